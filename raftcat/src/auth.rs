@@ -0,0 +1,463 @@
+use actix_web::{
+    http::{header::AUTHORIZATION, HeaderMap},
+    HttpRequest,
+};
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use shipcat_definitions::{teams::Owners, Manifest};
+use std::{
+    collections::BTreeSet,
+    env, fs,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use crate::{kompass::KOMPASS_AUTH_TOKEN, Result};
+
+/// How often signing keys are refetched from the identity provider
+const JWKS_REFRESH_SECS: u64 = 600;
+
+/// What a caller is allowed to do
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// Overviews, search, dashboards, and manifests of the caller's squads
+    Read,
+    /// Full manifests of every service regardless of team
+    Manifests,
+    /// The kompass hub service listing
+    Kompass,
+}
+
+/// Scope required for a path (None if it is public)
+pub fn required_scope(path: &str) -> Option<Scope> {
+    let public = ["/health", "/raftcat/health", "/metrics", "/raftcat/metrics"];
+    if public.contains(&path) || path.starts_with("/raftcat/static/") {
+        None
+    } else if path == "/raftcat/kompass-hub" {
+        Some(Scope::Kompass)
+    } else {
+        Some(Scope::Read)
+    }
+}
+
+/// An authenticated caller
+#[derive(Clone, Debug)]
+pub struct Principal {
+    /// Subject of an identity token, or the name of a static token
+    pub name: String,
+    /// Name of the provider that authenticated the caller
+    pub provider: &'static str,
+    pub email: Option<String>,
+    /// Groups from the identity provider (matched against github teams)
+    pub groups: Vec<String>,
+    /// Squads granted explicitly
+    pub squads: Vec<String>,
+    pub scopes: BTreeSet<Scope>,
+}
+
+impl Principal {
+    /// The caller when no authentication is configured
+    pub fn anonymous() -> Self {
+        Principal {
+            name: "anonymous".into(),
+            provider: "none",
+            email: None,
+            groups: vec![],
+            squads: vec![],
+            scopes: vec![Scope::Read, Scope::Manifests].into_iter().collect(),
+        }
+    }
+
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.scopes.contains(&scope)
+    }
+
+    /// Squad slugs the caller belongs to
+    ///
+    /// From explicit grants, squad membership of the person with the caller's email,
+    /// and groups matching the github teams of squads or tribes.
+    pub fn squads_in(&self, owners: &Owners) -> BTreeSet<String> {
+        let mut res = self.squads.iter().cloned().collect::<BTreeSet<_>>();
+        let person = self
+            .email
+            .as_ref()
+            .and_then(|e| owners.people.values().find(|p| p.email.eq_ignore_ascii_case(e)));
+        let in_group = |team: &str| self.groups.iter().any(|g| g.eq_ignore_ascii_case(team));
+        for (slug, sq) in &owners.squads {
+            let member = person.map_or(false, |p| {
+                sq.members.contains(&p.name) || sq.owners.contains(&p.name)
+            });
+            let admins = sq.github.admins.as_ref().map_or(false, |a| in_group(a));
+            if member || in_group(&sq.github.team) || admins {
+                res.insert(slug.clone());
+            }
+        }
+        for t in owners.tribes.values() {
+            if t.github.as_ref().map_or(false, |gh| in_group(&gh.team)) {
+                res.extend(t.squads.iter().cloned());
+            }
+        }
+        res
+    }
+
+    /// Whether the caller can see the full manifest (env, configs) of a service
+    pub fn can_view(&self, mf: &Manifest, owners: &Owners) -> bool {
+        if self.has_scope(Scope::Manifests) {
+            return true;
+        }
+        match mf
+            .metadata
+            .as_ref()
+            .and_then(|md| crate::search::squad_for(owners, &md.team))
+        {
+            Some(squad) => self.has_scope(Scope::Read) && self.squads_in(owners).contains(&squad),
+            None => false,
+        }
+    }
+}
+
+/// The principal the auth middleware attached to a request
+pub fn principal(req: &HttpRequest) -> Option<Principal> {
+    req.extensions().get::<Principal>().cloned()
+}
+
+/// A way of authenticating callers
+pub trait AuthProvider: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Authenticate a request from its headers
+    ///
+    /// Returns None if the request carries no credentials this provider understands,
+    /// and an error if it carries credentials that are not valid.
+    fn authenticate(&self, headers: &HeaderMap) -> Result<Option<Principal>>;
+}
+
+fn credentials<'a>(headers: &'a HeaderMap, scheme: &str) -> Option<&'a str> {
+    let value = headers.get(AUTHORIZATION)?.to_str().ok()?;
+    let mut parts = value.splitn(2, ' ');
+    match parts.next() {
+        Some(s) if s.eq_ignore_ascii_case(scheme) => parts.next().map(str::trim),
+        _ => None,
+    }
+}
+
+/// Compare secrets without leaking their common prefix through timing
+fn secret_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Basic auth with the token kompass registers with
+pub struct KompassProvider {
+    token: String,
+}
+
+impl KompassProvider {
+    pub fn new(token: String) -> Self {
+        KompassProvider { token }
+    }
+}
+
+impl AuthProvider for KompassProvider {
+    fn name(&self) -> &'static str {
+        "kompass"
+    }
+
+    fn authenticate(&self, headers: &HeaderMap) -> Result<Option<Principal>> {
+        let encoded = match credentials(headers, "Basic") {
+            Some(c) => c,
+            None => return Ok(None),
+        };
+        let decoded = String::from_utf8(base64::decode(encoded)?)?;
+        let token = decoded
+            .splitn(2, ':')
+            .nth(1)
+            .ok_or_else(|| format_err!("Error parsing auth header"))?;
+        if !secret_eq(token, &self.token) {
+            bail!("Invalid kompass token");
+        }
+        Ok(Some(Principal {
+            name: "kompass".into(),
+            provider: self.name(),
+            email: None,
+            groups: vec![],
+            squads: vec![],
+            scopes: vec![Scope::Kompass].into_iter().collect(),
+        }))
+    }
+}
+
+/// A static api token
+///
+/// ```yaml
+/// - name: backstage
+///   token: s3cr3t
+///   scopes: [read]
+///   squads: [devops]
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ApiToken {
+    /// Name of the consumer (for logs)
+    pub name: String,
+    pub token: String,
+    pub scopes: BTreeSet<Scope>,
+    /// Squads whose manifests the token can read
+    #[serde(default)]
+    pub squads: Vec<String>,
+}
+
+/// Bearer auth with static api tokens
+pub struct TokenProvider {
+    tokens: Vec<ApiToken>,
+}
+
+impl TokenProvider {
+    pub fn new(tokens: Vec<ApiToken>) -> Self {
+        TokenProvider { tokens }
+    }
+
+    /// Read tokens from a yaml file (typically a mounted secret)
+    pub fn from_file(path: &str) -> Result<Self> {
+        let data = fs::read_to_string(path)?;
+        Ok(TokenProvider::new(serde_yaml::from_str(&data)?))
+    }
+}
+
+impl AuthProvider for TokenProvider {
+    fn name(&self) -> &'static str {
+        "token"
+    }
+
+    fn authenticate(&self, headers: &HeaderMap) -> Result<Option<Principal>> {
+        let bearer = match credentials(headers, "Bearer") {
+            Some(b) => b,
+            None => return Ok(None),
+        };
+        // unknown tokens may be for another provider
+        Ok(self
+            .tokens
+            .iter()
+            .find(|t| secret_eq(&t.token, bearer))
+            .map(|t| Principal {
+                name: t.name.clone(),
+                provider: self.name(),
+                email: None,
+                groups: vec![],
+                squads: t.squads.clone(),
+                scopes: t.scopes.clone(),
+            }))
+    }
+}
+
+/// A JSON Web Key (only RSA keys are used)
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Jwk {
+    pub kty: String,
+    pub kid: Option<String>,
+    pub n: Option<String>,
+    pub e: Option<String>,
+}
+
+/// A JSON Web Key Set as served by an identity provider
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Jwks {
+    pub keys: Vec<Jwk>,
+}
+
+/// Settings for validating identity tokens
+#[derive(Clone, Debug)]
+pub struct OidcConfig {
+    pub issuer: String,
+    pub audience: String,
+    pub jwks_url: String,
+    /// Groups granted the manifests scope
+    pub admin_groups: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct Claims {
+    sub: String,
+    email: Option<String>,
+    #[serde(default)]
+    groups: Vec<String>,
+}
+
+/// Bearer auth with identity tokens from an OIDC provider
+///
+/// Signing keys are cached and refreshed in the background.
+#[derive(Clone)]
+pub struct OidcProvider {
+    config: OidcConfig,
+    jwks: Arc<RwLock<Jwks>>,
+}
+
+impl OidcProvider {
+    pub fn new(config: OidcConfig) -> Self {
+        OidcProvider {
+            config,
+            jwks: Arc::new(RwLock::new(Jwks::default())),
+        }
+    }
+
+    /// Replace the cached signing keys
+    pub fn set_jwks(&self, jwks: Jwks) {
+        *self.jwks.write().unwrap() = jwks;
+    }
+
+    /// Fetch the signing keys from the identity provider
+    pub async fn refresh(&self) -> Result<()> {
+        let jwks: Jwks = reqwest::get(&self.config.jwks_url)
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        debug!(
+            "Loaded {} signing keys from {}",
+            jwks.keys.len(),
+            self.config.jwks_url
+        );
+        self.set_jwks(jwks);
+        Ok(())
+    }
+
+    /// Keep refreshing the signing keys (keys are rotated by the provider)
+    pub async fn refresh_loop(self) {
+        loop {
+            tokio::time::delay_for(Duration::from_secs(JWKS_REFRESH_SECS)).await;
+            if let Err(e) = self.refresh().await {
+                warn!("Unable to refresh signing keys: {}", e);
+            }
+        }
+    }
+
+    fn key_for(&self, kid: Option<&str>) -> Result<Jwk> {
+        let jwks = self.jwks.read().unwrap();
+        let mut rsa = jwks.keys.iter().filter(|k| k.kty == "RSA");
+        let key = match kid {
+            Some(kid) => rsa.find(|k| k.kid.as_deref() == Some(kid)),
+            None => rsa.next(),
+        };
+        key.cloned()
+            .ok_or_else(|| format_err!("No signing key found for {:?}", kid))
+    }
+}
+
+impl AuthProvider for OidcProvider {
+    fn name(&self) -> &'static str {
+        "oidc"
+    }
+
+    fn authenticate(&self, headers: &HeaderMap) -> Result<Option<Principal>> {
+        let token = match credentials(headers, "Bearer") {
+            Some(t) if t.split('.').count() == 3 => t,
+            _ => return Ok(None),
+        };
+        let header = decode_header(token)?;
+        let jwk = self.key_for(header.kid.as_deref())?;
+        let (n, e) = match (&jwk.n, &jwk.e) {
+            (Some(n), Some(e)) => (n, e),
+            _ => bail!("Signing key is missing its modulus or exponent"),
+        };
+        let mut validation = Validation::new(Algorithm::RS256);
+        validation.iss = Some(self.config.issuer.clone());
+        validation.set_audience(&[&self.config.audience]);
+        let claims = decode::<Claims>(token, &DecodingKey::from_rsa_components(n, e), &validation)?.claims;
+
+        let mut scopes = vec![Scope::Read].into_iter().collect::<BTreeSet<_>>();
+        if claims.groups.iter().any(|g| self.config.admin_groups.contains(g)) {
+            scopes.insert(Scope::Manifests);
+        }
+        Ok(Some(Principal {
+            name: claims.sub,
+            provider: self.name(),
+            email: claims.email,
+            groups: claims.groups,
+            squads: vec![],
+            scopes,
+        }))
+    }
+}
+
+/// The set of configured providers
+///
+/// Providers are tried in order until one recognises the credentials.
+#[derive(Clone, Default)]
+pub struct Authenticator {
+    providers: Vec<Arc<dyn AuthProvider>>,
+    /// Whether requests without credentials are rejected
+    required: bool,
+}
+
+impl Authenticator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn provider(mut self, p: impl AuthProvider + 'static) -> Self {
+        self.providers.push(Arc::new(p));
+        self
+    }
+
+    pub fn required(mut self, required: bool) -> Self {
+        self.required = required;
+        self
+    }
+
+    /// Configure providers from evars
+    ///
+    /// - `KOMPASS_AUTH_TOKEN` enables basic auth for kompass
+    /// - `RAFTCAT_TOKENS_FILE` points to a yaml list of static api tokens
+    /// - `OIDC_ISSUER` and `OIDC_AUDIENCE` enable identity tokens (keys from `OIDC_JWKS_URL`)
+    /// - `OIDC_ADMIN_GROUPS` is a comma separated list of groups that can read all manifests
+    ///
+    /// Authentication is only required when api tokens or OIDC are configured.
+    pub async fn from_env() -> Result<Self> {
+        let mut res = Authenticator::new();
+        if let Ok(token) = env::var(KOMPASS_AUTH_TOKEN) {
+            res = res.provider(KompassProvider::new(token));
+        }
+        if let Ok(path) = env::var("RAFTCAT_TOKENS_FILE") {
+            let tokens = TokenProvider::from_file(&path)?;
+            info!("Loaded {} api tokens", tokens.tokens.len());
+            res = res.provider(tokens).required(true);
+        }
+        if let Ok(issuer) = env::var("OIDC_ISSUER") {
+            let audience = env::var("OIDC_AUDIENCE").map_err(|_| format_err!("Need OIDC_AUDIENCE evar"))?;
+            let jwks_url = env::var("OIDC_JWKS_URL")
+                .unwrap_or_else(|_| format!("{}/.well-known/jwks.json", issuer.trim_end_matches('/')));
+            let admin_groups = env::var("OIDC_ADMIN_GROUPS")
+                .map(|g| g.split(',').map(|s| s.trim().to_string()).collect())
+                .unwrap_or_default();
+            let oidc = OidcProvider::new(OidcConfig {
+                issuer,
+                audience,
+                jwks_url,
+                admin_groups,
+            });
+            oidc.refresh().await?;
+            tokio::spawn(oidc.clone().refresh_loop());
+            res = res.provider(oidc).required(true);
+        }
+        if !res.required {
+            warn!("No api tokens or OIDC configured; raftcat is readable without authentication");
+        }
+        Ok(res)
+    }
+
+    /// Find the caller of a request
+    pub fn authenticate(&self, headers: &HeaderMap) -> Result<Principal> {
+        for p in &self.providers {
+            if let Some(principal) = p.authenticate(headers)? {
+                debug!("Authenticated {} via {}", principal.name, p.name());
+                return Ok(principal);
+            }
+        }
+        if self.required {
+            if headers.contains_key(AUTHORIZATION) {
+                bail!("Unrecognised credentials");
+            }
+            bail!("Missing credentials");
+        }
+        // e.g. credentials for a proxy in front of raftcat
+        Ok(Principal::anonymous())
+    }
+}
@@ -0,0 +1,326 @@
+use chrono::{DateTime, Utc};
+use shipcat_definitions::ShipcatManifest;
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::rollouts::{RolloutHealth, RolloutRow};
+
+/// Upper bound on hops in either direction
+pub const MAX_HOPS: usize = 5;
+
+const COLUMN_WIDTH: i32 = 220;
+const ROW_HEIGHT: i32 = 50;
+const NODE_WIDTH: i32 = 180;
+const NODE_HEIGHT: i32 = 30;
+const MARGIN: i32 = 20;
+
+/// A service in a dependency graph
+#[derive(Serialize, Clone, Debug)]
+pub struct GraphNode {
+    pub name: String,
+    /// Team as set in metadata (None for services not in the region)
+    pub team: Option<String>,
+    pub health: Option<RolloutHealth>,
+    /// Hops from the root: negative for dependents, positive for dependencies
+    pub layer: i32,
+}
+
+/// A dependency from one service to another
+#[derive(Serialize, Clone, Debug)]
+pub struct GraphEdge {
+    pub from: String,
+    pub to: String,
+    pub protocol: String,
+    pub api: String,
+    pub intent: Option<String>,
+}
+
+/// The neighbourhood of a service in the dependency graph
+#[derive(Serialize, Clone, Debug)]
+pub struct DepGraph {
+    pub root: String,
+    pub nodes: Vec<GraphNode>,
+    pub edges: Vec<GraphEdge>,
+}
+
+/// Breadth first search from the root, recording the distance of each service
+fn walk<'a, F>(root: &'a str, hops: usize, next: F) -> BTreeMap<&'a str, usize>
+where
+    F: Fn(&str) -> Vec<&'a str>,
+{
+    let mut seen = BTreeMap::new();
+    seen.insert(root, 0);
+    let mut queue = VecDeque::from(vec![root]);
+    while let Some(current) = queue.pop_front() {
+        let dist = seen[current];
+        if dist == hops {
+            continue;
+        }
+        for n in next(current) {
+            if !seen.contains_key(n) {
+                seen.insert(n, dist + 1);
+                queue.push_back(n);
+            }
+        }
+    }
+    seen
+}
+
+/// Compute the graph around a service
+///
+/// Follows dependencies `down` hops and dependents `up` hops.
+/// Returns None if the service is not in the cache.
+pub fn build(
+    crds: &[ShipcatManifest],
+    root: &str,
+    up: usize,
+    down: usize,
+    now: DateTime<Utc>,
+) -> Option<DepGraph> {
+    let by_name = crds
+        .iter()
+        .map(|crd| (crd.spec.name.as_str(), crd))
+        .collect::<BTreeMap<_, _>>();
+    let root = by_name.get(root)?.spec.name.as_str();
+
+    let downs = walk(root, down.min(MAX_HOPS), |svc| match by_name.get(svc) {
+        Some(crd) => crd.spec.dependencies.iter().map(|d| d.name.as_str()).collect(),
+        None => vec![],
+    });
+    let ups = walk(root, up.min(MAX_HOPS), |svc| {
+        crds.iter()
+            .filter(|crd| crd.spec.dependencies.iter().any(|d| d.name == svc))
+            .map(|crd| crd.spec.name.as_str())
+            .collect()
+    });
+
+    // services on both sides stay on the closer one (dependencies on ties)
+    let mut layers = BTreeMap::new();
+    for (name, d) in &ups {
+        layers.insert(*name, -(*d as i32));
+    }
+    for (name, d) in &downs {
+        if ups.get(name).map_or(true, |u| d <= u) {
+            layers.insert(*name, *d as i32);
+        }
+    }
+
+    let nodes = layers
+        .iter()
+        .map(|(name, layer)| {
+            let crd = by_name.get(name);
+            GraphNode {
+                name: name.to_string(),
+                team: crd
+                    .and_then(|c| c.spec.metadata.as_ref())
+                    .map(|md| md.team.clone()),
+                health: crd.map(|c| RolloutRow::new(c, now).health),
+                layer: *layer,
+            }
+        })
+        .collect();
+    let mut edges = vec![];
+    for name in layers.keys() {
+        if let Some(crd) = by_name.get(name) {
+            for d in crd
+                .spec
+                .dependencies
+                .iter()
+                .filter(|d| layers.contains_key(d.name.as_str()))
+            {
+                edges.push(GraphEdge {
+                    from: name.to_string(),
+                    to: d.name.clone(),
+                    protocol: serde_json::to_value(&d.protocol)
+                        .ok()
+                        .and_then(|v| v.as_str().map(String::from))
+                        .unwrap_or_default(),
+                    api: d.api.clone(),
+                    intent: d.intent.clone(),
+                });
+            }
+        }
+    }
+    Some(DepGraph {
+        root: root.to_string(),
+        nodes,
+        edges,
+    })
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// A stable colour for a team
+fn team_colour(team: Option<&String>) -> String {
+    match team {
+        Some(t) => {
+            let hue = t
+                .bytes()
+                .fold(0u32, |acc, b| acc.wrapping_mul(31).wrapping_add(b.into()))
+                % 360;
+            format!("hsl({}, 60%, 85%)", hue)
+        }
+        None => "#eeeeee".into(),
+    }
+}
+
+fn health_colour(health: Option<&RolloutHealth>) -> &'static str {
+    match health {
+        Some(RolloutHealth::Failed) => "#d0021b",
+        Some(RolloutHealth::Stale) => "#f5a623",
+        Some(RolloutHealth::Rolling) => "#4a90e2",
+        Some(RolloutHealth::Healthy) => "#417505",
+        None => "#9b9b9b",
+    }
+}
+
+/// Render a graph as a standalone svg
+///
+/// Dependents are drawn left of the root and dependencies right of it.
+/// Nodes link to their service pages, are filled by team, and outlined by rollout health.
+pub fn render_svg(graph: &DepGraph) -> String {
+    let min_layer = graph.nodes.iter().map(|n| n.layer).min().unwrap_or(0);
+    let max_layer = graph.nodes.iter().map(|n| n.layer).max().unwrap_or(0);
+
+    let mut positions = BTreeMap::new();
+    let mut rows = 0;
+    for layer in min_layer..=max_layer {
+        let column = graph.nodes.iter().filter(|n| n.layer == layer);
+        for (i, n) in column.enumerate() {
+            let x = MARGIN + (layer - min_layer) * COLUMN_WIDTH;
+            let y = MARGIN + i as i32 * ROW_HEIGHT;
+            positions.insert(n.name.as_str(), (x, y));
+            rows = rows.max(i as i32 + 1);
+        }
+    }
+    let width = 2 * MARGIN + (max_layer - min_layer) * COLUMN_WIDTH + NODE_WIDTH;
+    let height = 2 * MARGIN + (rows - 1).max(0) * ROW_HEIGHT + NODE_HEIGHT;
+
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" xmlns:xlink=\"http://www.w3.org/1999/xlink\" \
+         width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\" font-family=\"monospace\" font-size=\"12\">\n",
+        w = width,
+        h = height
+    );
+    svg += "<style>g.node:hover rect { stroke-width: 4; } line:hover { stroke: #000; stroke-width: 3; }</style>\n";
+    svg += "<defs><marker id=\"arrow\" viewBox=\"0 0 10 10\" refX=\"10\" refY=\"5\" markerWidth=\"6\" \
+            markerHeight=\"6\" orient=\"auto\"><path d=\"M 0 0 L 10 5 L 0 10 z\" fill=\"#777\"/></marker></defs>\n";
+
+    for e in &graph.edges {
+        let (fx, fy) = positions[e.from.as_str()];
+        let (tx, ty) = positions[e.to.as_str()];
+        let (x1, x2) = if fx <= tx {
+            (fx + NODE_WIDTH, tx)
+        } else {
+            (fx, tx + NODE_WIDTH)
+        };
+        let title = match &e.intent {
+            Some(i) => format!("{} -> {} ({} {}): {}", e.from, e.to, e.protocol, e.api, i),
+            None => format!("{} -> {} ({} {})", e.from, e.to, e.protocol, e.api),
+        };
+        svg += &format!(
+            "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"#777\" marker-end=\"url(#arrow)\"><title>{}</title></line>\n",
+            x1,
+            fy + NODE_HEIGHT / 2,
+            x2,
+            ty + NODE_HEIGHT / 2,
+            escape(&title)
+        );
+    }
+
+    for n in &graph.nodes {
+        let (x, y) = positions[n.name.as_str()];
+        let health = n
+            .health
+            .as_ref()
+            .and_then(|h| serde_json::to_value(h).ok())
+            .and_then(|v| v.as_str().map(String::from))
+            .unwrap_or_else(|| "not in region".into());
+        let title = format!(
+            "{} (team: {}, {})",
+            n.name,
+            n.team.clone().unwrap_or_else(|| "unknown".into()),
+            health
+        );
+        let weight = if n.name == graph.root { "bold" } else { "normal" };
+        svg += &format!(
+            "<a xlink:href=\"/raftcat/services/{name}\" target=\"_top\"><g class=\"node\"><title>{title}</title>\
+             <rect x=\"{x}\" y=\"{y}\" width=\"{w}\" height=\"{h}\" rx=\"4\" fill=\"{fill}\" stroke=\"{stroke}\" stroke-width=\"2\"/>\
+             <text x=\"{tx}\" y=\"{ty}\" text-anchor=\"middle\" font-weight=\"{weight}\">{name}</text></g></a>\n",
+            name = escape(&n.name),
+            title = escape(&title),
+            x = x,
+            y = y,
+            w = NODE_WIDTH,
+            h = NODE_HEIGHT,
+            fill = team_colour(n.team.as_ref()),
+            stroke = health_colour(n.health.as_ref()),
+            tx = x + NODE_WIDTH / 2,
+            ty = y + NODE_HEIGHT / 2 + 4,
+            weight = weight,
+        );
+    }
+    svg += "</svg>\n";
+    svg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build, render_svg};
+    use chrono::Utc;
+    use shipcat_definitions::ShipcatManifest;
+
+    fn crd(name: &str, deps: &[&str]) -> ShipcatManifest {
+        let deps = deps
+            .iter()
+            .map(|d| serde_json::json!({ "name": d }))
+            .collect::<Vec<_>>();
+        serde_json::from_value(serde_json::json!({
+            "apiVersion": "babylontech.co.uk/v1",
+            "kind": "ShipcatManifest",
+            "metadata": { "name": name },
+            "spec": {
+                "name": name,
+                "version": "1.0.0",
+                "metadata": { "repo": "https://github.com/babylonhealth/x", "team": "devops" },
+                "dependencies": deps,
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn depgraph_hops() {
+        // gateway -> webapp -> auth -> db
+        let crds = vec![
+            crd("gateway", &["webapp"]),
+            crd("webapp", &["auth"]),
+            crd("auth", &["db"]),
+            crd("db", &[]),
+            crd("other", &[]),
+        ];
+        let g = build(&crds, "webapp", 1, 1, Utc::now()).unwrap();
+        let layers = g
+            .nodes
+            .iter()
+            .map(|n| (n.name.as_str(), n.layer))
+            .collect::<Vec<_>>();
+        assert_eq!(layers, vec![("auth", 1), ("gateway", -1), ("webapp", 0)]);
+        assert_eq!(g.edges.len(), 2);
+        assert_eq!(g.edges[0].protocol, "http");
+
+        let g = build(&crds, "webapp", 0, 2, Utc::now()).unwrap();
+        assert!(g.nodes.iter().any(|n| n.name == "db" && n.layer == 2));
+        assert!(!g.nodes.iter().any(|n| n.name == "gateway"));
+
+        assert!(build(&crds, "missing", 1, 1, Utc::now()).is_none());
+
+        let svg = render_svg(&build(&crds, "webapp", 2, 2, Utc::now()).unwrap());
+        assert!(svg.contains("xlink:href=\"/raftcat/services/db\""));
+        assert_eq!(svg.matches("<line").count(), 3);
+    }
+}
@@ -0,0 +1,183 @@
+use chrono::{DateTime, Utc};
+use shipcat_definitions::{Region, ShipcatManifest};
+use std::{env, time::Duration};
+
+use crate::{
+    rollouts::{RolloutHealth, RolloutRow},
+    Result,
+};
+
+/// How long to wait for a peer raftcat
+const PEER_TIMEOUT_SECS: u64 = 3;
+
+/// State of a service in one region
+///
+/// Served by every raftcat for its own region, and aggregated from peers.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[allow(non_snake_case)]
+pub struct RegionalSummary {
+    pub region: String,
+    /// Version in the manifest spec
+    pub version: Option<String>,
+    /// Last version that was successfully rolled out
+    pub currentVersion: Option<String>,
+    pub replicas: Option<u32>,
+    pub minReplicas: Option<u32>,
+    pub maxReplicas: Option<u32>,
+    pub health: RolloutHealth,
+    pub lastFailureReason: Option<String>,
+}
+
+impl RegionalSummary {
+    pub fn new(crd: &ShipcatManifest, region: &str, now: DateTime<Utc>) -> Self {
+        let mf = &crd.spec;
+        let row = RolloutRow::new(crd, now);
+        RegionalSummary {
+            region: region.to_string(),
+            version: row.desiredVersion,
+            currentVersion: row.currentVersion,
+            replicas: mf.replicaCount,
+            minReplicas: mf.autoScaling.as_ref().map(|a| a.minReplicas),
+            maxReplicas: mf.autoScaling.as_ref().map(|a| a.maxReplicas),
+            health: row.health,
+            lastFailureReason: row.lastFailureReason,
+        }
+    }
+}
+
+/// A service in a federated region (missing if not deployed there)
+#[derive(Serialize, Clone, Debug)]
+pub struct FederatedService {
+    pub region: String,
+    /// Service page on the raftcat of the region
+    pub link: Option<String>,
+    pub summary: Option<RegionalSummary>,
+    /// Why the region could not be queried
+    pub error: Option<String>,
+    /// Whether the version differs from the majority version across regions
+    pub skewed: bool,
+}
+
+/// Api token presented to peers (when they require authentication)
+const PEER_TOKEN: &str = "RAFTCAT_PEER_TOKEN";
+
+/// Fetch the summary of a service from the raftcat of another region
+async fn fetch_peer(client: &reqwest::Client, base: &str, service: &str) -> Result<Option<RegionalSummary>> {
+    let url = format!("{}manifests/{}/summary", base, service);
+    debug!("Fetching {}", url);
+    let mut req = client.get(&url);
+    if let Ok(token) = env::var(PEER_TOKEN) {
+        req = req.bearer_auth(token);
+    }
+    let res = req.send().await?;
+    if res.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    if !res.status().is_success() {
+        bail!("{} returned {}", url, res.status());
+    }
+    Ok(Some(res.json().await?))
+}
+
+/// Query a service in every federated region
+///
+/// The local summary is passed in, the others are fetched concurrently from peers.
+pub async fn federate(
+    regions: Vec<Region>,
+    local_region: &str,
+    local: Option<RegionalSummary>,
+    service: &str,
+) -> Result<Vec<FederatedService>> {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(PEER_TIMEOUT_SECS))
+        .build()?;
+    let queries = regions.into_iter().map(|r| {
+        let client = &client;
+        let local = local.clone();
+        async move {
+            let base = r.raftcat_url();
+            let link = base.as_ref().map(|b| format!("{}services/{}", b, service));
+            let (summary, error) = if r.name == local_region {
+                (local, None)
+            } else if let Some(b) = &base {
+                match fetch_peer(client, b, service).await {
+                    Ok(s) => (s, None),
+                    Err(e) => {
+                        warn!("Failed to query raftcat in {}: {}", r.name, e);
+                        (None, Some(e.to_string()))
+                    }
+                }
+            } else {
+                (None, Some("no raftcat url".to_string()))
+            };
+            FederatedService {
+                region: r.name,
+                link,
+                summary,
+                error,
+                skewed: false,
+            }
+        }
+    });
+    let mut res = futures::future::join_all(queries).await;
+    mark_skew(&mut res);
+    Ok(res)
+}
+
+/// Flag regions that run a different version to the most common one
+pub fn mark_skew(services: &mut [FederatedService]) {
+    let versions = services
+        .iter()
+        .filter_map(|s| s.summary.as_ref().and_then(|s| s.version.clone()))
+        .collect::<Vec<_>>();
+    let majority = versions
+        .iter()
+        .max_by_key(|v| versions.iter().filter(|x| x == v).count())
+        .cloned();
+    for s in services.iter_mut() {
+        let version = s.summary.as_ref().and_then(|s| s.version.clone());
+        s.skewed = version.is_some() && version != majority;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{mark_skew, FederatedService, RegionalSummary};
+    use crate::rollouts::RolloutHealth;
+
+    fn service(region: &str, version: Option<&str>) -> FederatedService {
+        FederatedService {
+            region: region.into(),
+            link: None,
+            summary: version.map(|v| RegionalSummary {
+                region: region.into(),
+                version: Some(v.into()),
+                currentVersion: Some(v.into()),
+                replicas: Some(2),
+                minReplicas: None,
+                maxReplicas: None,
+                health: RolloutHealth::Healthy,
+                lastFailureReason: None,
+            }),
+            error: None,
+            skewed: false,
+        }
+    }
+
+    #[test]
+    fn federation_version_skew() {
+        let mut svcs = vec![
+            service("dev-uk", Some("1.3.0")),
+            service("staging-uk", Some("1.2.0")),
+            service("prod-uk", Some("1.2.0")),
+            service("prod-us", None),
+        ];
+        mark_skew(&mut svcs);
+        let skewed = svcs
+            .iter()
+            .filter(|s| s.skewed)
+            .map(|s| s.region.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(skewed, vec!["dev-uk"]);
+    }
+}
@@ -0,0 +1,635 @@
+// juniper 0.14 macros expand resolver bodies into braces
+#![allow(unused_braces)]
+
+use juniper::{EmptyMutation, FieldResult, IntrospectionFormat, RootNode};
+use serde::Serialize;
+use serde_json::Value;
+use shipcat_definitions::{
+    math::ResourceTotals,
+    structs::{resources::Resources, Kong},
+    teams::{Squad, Tribe},
+    Cluster, Config, Manifest, Region,
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+};
+
+use crate::{
+    auth::Principal,
+    integrations::{IntegrationLink, Integrations},
+    search::{squad_for, tribe_for},
+    Result,
+};
+
+/// How many relations (dependencies, squads, services..) a query can follow
+///
+/// Relations are cyclic, so this bounds the work of a single query.
+pub const MAX_DEPTH: usize = 8;
+
+/// Depth of the nodes of a relation followed from a node at `depth`
+fn deeper(depth: usize) -> FieldResult<usize> {
+    if depth >= MAX_DEPTH {
+        return Err(format!("Query follows relations deeper than {} levels", MAX_DEPTH).into());
+    }
+    Ok(depth + 1)
+}
+
+/// Snapshot of the State caches that resolvers read from
+///
+/// Shared with the State rather than copied, so a query sees a consistent view.
+pub struct GraphContext {
+    manifests: Arc<BTreeMap<String, Manifest>>,
+    /// Names of the manifests the caller can see (all if unrestricted)
+    visible: Option<BTreeSet<String>>,
+    pub config: Arc<Config>,
+    pub region: Region,
+    pub integrations: Integrations,
+}
+impl juniper::Context for GraphContext {}
+
+impl GraphContext {
+    pub fn new(
+        manifests: Arc<BTreeMap<String, Manifest>>,
+        config: Arc<Config>,
+        region: Region,
+        integrations: Integrations,
+    ) -> Self {
+        GraphContext {
+            manifests,
+            visible: None,
+            config,
+            region,
+            integrations,
+        }
+    }
+
+    /// Only expose the manifests a caller can view
+    pub fn restrict_to(&mut self, principal: &Principal) {
+        let visible = self
+            .manifests
+            .values()
+            .filter(|mf| principal.can_view(mf, &self.config.owners))
+            .map(|mf| mf.name.clone())
+            .collect();
+        self.visible = Some(visible);
+    }
+
+    /// Visible manifests in name order
+    fn manifests(&self) -> impl Iterator<Item = &Manifest> {
+        let visible = self.visible.as_ref();
+        self.manifests
+            .values()
+            .filter(move |mf| visible.map_or(true, |v| v.contains(&mf.name)))
+    }
+
+    fn manifest(&self, name: &str, depth: usize) -> Option<ManifestNode> {
+        if self.visible.as_ref().map_or(false, |v| !v.contains(name)) {
+            return None;
+        }
+        self.manifests.get(name).map(|mf| ManifestNode(mf.clone(), depth))
+    }
+
+    fn squad(&self, slug: &str, depth: usize) -> Option<SquadNode> {
+        self.config.owners.squads.get(slug).map(|s| SquadNode {
+            slug: slug.to_string(),
+            squad: s.clone(),
+            depth,
+        })
+    }
+
+    fn tribe(&self, slug: &str, depth: usize) -> Option<TribeNode> {
+        self.config.owners.tribes.get(slug).map(|t| TribeNode {
+            slug: slug.to_string(),
+            tribe: t.clone(),
+            depth,
+        })
+    }
+}
+
+/// Serialized name of a unit enum (e.g. `Language::Rust` -> rust)
+fn enum_name<T: Serialize>(x: &T) -> Option<String> {
+    serde_json::to_value(x)
+        .ok()
+        .and_then(|v| v.as_str().map(String::from))
+}
+
+/// A manifest and how many relations were followed to reach it
+pub struct ManifestNode(Manifest, usize);
+
+/// A service deployed in the region
+#[juniper::object(Context = GraphContext, name = "Manifest")]
+impl ManifestNode {
+    fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    /// Version in the manifest spec
+    fn version(&self) -> Option<&str> {
+        self.0.version.as_deref()
+    }
+
+    fn image(&self) -> Option<&str> {
+        self.0.image.as_deref()
+    }
+
+    /// Team as set in metadata
+    fn team(&self) -> Option<&str> {
+        self.0.metadata.as_ref().map(|md| md.team.as_str())
+    }
+
+    fn description(&self) -> Option<&str> {
+        self.0.metadata.as_ref().and_then(|md| md.description.as_deref())
+    }
+
+    fn language(&self) -> Option<String> {
+        self.0
+            .metadata
+            .as_ref()
+            .and_then(|md| md.language.as_ref())
+            .and_then(enum_name)
+    }
+
+    fn repo(&self) -> Option<&str> {
+        self.0.metadata.as_ref().map(|md| md.repo.as_str())
+    }
+
+    /// Owning squad from teams.yml
+    fn squad(&self, context: &GraphContext) -> FieldResult<Option<SquadNode>> {
+        let depth = deeper(self.1)?;
+        let team = self.0.metadata.as_ref().map(|md| md.team.as_str());
+        Ok(team
+            .and_then(|t| squad_for(&context.config.owners, t))
+            .and_then(|s| context.squad(&s, depth)))
+    }
+
+    fn labels(&self) -> Vec<Label> {
+        self.0
+            .labels
+            .iter()
+            .map(|(k, v)| Label {
+                key: k.clone(),
+                value: v.clone(),
+            })
+            .collect()
+    }
+
+    /// Environment variables (values of secrets are omitted)
+    fn env(&self) -> Vec<EnvVar> {
+        let plain = self.0.env.plain.iter().map(|(k, v)| EnvVar {
+            name: k.clone(),
+            value: Some(v.clone()),
+            secret: false,
+        });
+        let secrets = self.0.env.secrets.iter().map(|k| EnvVar {
+            name: k.clone(),
+            value: None,
+            secret: true,
+        });
+        plain.chain(secrets).collect()
+    }
+
+    fn kong_apis(&self) -> Vec<KongNode> {
+        self.0.kongApis.iter().cloned().map(KongNode).collect()
+    }
+
+    /// Names of the services this service depends on
+    fn dependency_names(&self) -> Vec<String> {
+        self.0.dependencies.iter().map(|d| d.name.clone()).collect()
+    }
+
+    /// Services this service depends on (that exist in the region)
+    fn dependencies(&self, context: &GraphContext) -> FieldResult<Vec<ManifestNode>> {
+        let depth = deeper(self.1)?;
+        Ok(self
+            .0
+            .dependencies
+            .iter()
+            .filter_map(|d| context.manifest(&d.name, depth))
+            .collect())
+    }
+
+    /// Services that depend on this service
+    fn reverse_dependencies(&self, context: &GraphContext) -> FieldResult<Vec<ManifestNode>> {
+        let depth = deeper(self.1)?;
+        Ok(context
+            .manifests()
+            .filter(|mf| mf.dependencies.iter().any(|d| d.name == self.0.name))
+            .map(|mf| ManifestNode(mf.clone(), depth))
+            .collect())
+    }
+
+    /// Resources required by all replicas of the service
+    fn resource_totals(&self) -> FieldResult<Option<ResourceTotalsNode>> {
+        if self.0.resources.is_none() {
+            return Ok(None);
+        }
+        let totals = self.0.compute_resource_totals()?;
+        Ok(Some(ResourceTotalsNode(totals)))
+    }
+
+    /// Links to the integrations enabled in the region
+    fn links(&self, context: &GraphContext) -> Vec<IntegrationLink> {
+        context.integrations.links(&self.0, &context.region)
+    }
+
+    fn newrelic_link(&self, context: &GraphContext) -> Option<String> {
+        context.integrations.link("newrelic", &self.0, &context.region)
+    }
+
+    fn sentry_link(&self, context: &GraphContext) -> Option<String> {
+        context.integrations.link("sentry", &self.0, &context.region)
+    }
+
+    fn vault_link(&self, context: &GraphContext) -> String {
+        context.region.vault_url(&self.0.name)
+    }
+
+    /// The full manifest spec as json
+    fn spec(&self) -> FieldResult<String> {
+        Ok(serde_json::to_string(&self.0)?)
+    }
+}
+
+pub struct Label {
+    key: String,
+    value: String,
+}
+
+#[juniper::object(Context = GraphContext)]
+impl Label {
+    fn key(&self) -> &str {
+        &self.key
+    }
+
+    fn value(&self) -> &str {
+        &self.value
+    }
+}
+
+pub struct EnvVar {
+    name: String,
+    value: Option<String>,
+    secret: bool,
+}
+
+#[juniper::object(Context = GraphContext)]
+impl EnvVar {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn value(&self) -> Option<&str> {
+        self.value.as_deref()
+    }
+
+    fn secret(&self) -> bool {
+        self.secret
+    }
+}
+
+pub struct KongNode(Kong);
+
+/// Kong api exposing a service
+#[juniper::object(Context = GraphContext, name = "Kong")]
+impl KongNode {
+    fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    fn hosts(&self) -> Vec<String> {
+        self.0.hosts.clone()
+    }
+
+    fn uris(&self) -> Option<&str> {
+        self.0.uris.as_deref()
+    }
+
+    fn internal(&self) -> bool {
+        self.0.internal
+    }
+
+    fn publicly_accessible(&self) -> bool {
+        self.0.publiclyAccessible
+    }
+}
+
+pub struct ResourcesNode(Resources<f64>);
+
+/// Cores and bytes of memory
+#[juniper::object(Context = GraphContext, name = "Resources")]
+impl ResourcesNode {
+    fn cpu(&self) -> f64 {
+        self.0.cpu
+    }
+
+    fn memory(&self) -> f64 {
+        self.0.memory
+    }
+}
+
+pub struct ResourceTotalsNode(ResourceTotals);
+
+/// Sum of resources across replicas
+#[juniper::object(Context = GraphContext, name = "ResourceTotals")]
+impl ResourceTotalsNode {
+    /// Requests at minimum replicas
+    fn requests(&self) -> ResourcesNode {
+        ResourcesNode(self.0.base.requests.clone())
+    }
+
+    /// Limits at minimum replicas
+    fn limits(&self) -> ResourcesNode {
+        ResourcesNode(self.0.base.limits.clone())
+    }
+
+    /// Additional requests at maximum autoscaling
+    fn extra_requests(&self) -> ResourcesNode {
+        ResourcesNode(self.0.extra.requests.clone())
+    }
+
+    /// Additional limits at maximum autoscaling
+    fn extra_limits(&self) -> ResourcesNode {
+        ResourcesNode(self.0.extra.limits.clone())
+    }
+
+    /// Estimated daily cost in dollars (cpu, memory)
+    fn daily_cost(&self) -> Vec<f64> {
+        let (cpu, mem) = self.0.clone().normalise().daily_cost();
+        vec![cpu, mem]
+    }
+}
+
+pub struct SquadNode {
+    slug: String,
+    squad: Squad,
+    depth: usize,
+}
+
+/// A squad from teams.yml
+#[juniper::object(Context = GraphContext, name = "Squad")]
+impl SquadNode {
+    fn slug(&self) -> &str {
+        &self.slug
+    }
+
+    fn name(&self) -> &str {
+        &self.squad.name
+    }
+
+    fn members(&self) -> Vec<String> {
+        self.squad.members.clone()
+    }
+
+    fn owners(&self) -> Vec<String> {
+        self.squad.owners.clone()
+    }
+
+    fn tribe(&self, context: &GraphContext) -> FieldResult<Option<TribeNode>> {
+        let depth = deeper(self.depth)?;
+        Ok(tribe_for(&context.config.owners, &self.slug).and_then(|t| context.tribe(&t, depth)))
+    }
+
+    /// Services owned by the squad
+    fn services(&self, context: &GraphContext) -> FieldResult<Vec<ManifestNode>> {
+        let depth = deeper(self.depth)?;
+        Ok(context
+            .manifests()
+            .filter(|mf| {
+                mf.metadata
+                    .as_ref()
+                    .and_then(|md| squad_for(&context.config.owners, &md.team))
+                    .as_ref()
+                    == Some(&self.slug)
+            })
+            .map(|mf| ManifestNode(mf.clone(), depth))
+            .collect())
+    }
+}
+
+pub struct TribeNode {
+    slug: String,
+    tribe: Tribe,
+    depth: usize,
+}
+
+/// A tribe of squads from teams.yml
+#[juniper::object(Context = GraphContext, name = "Tribe")]
+impl TribeNode {
+    fn slug(&self) -> &str {
+        &self.slug
+    }
+
+    fn name(&self) -> &str {
+        &self.tribe.name
+    }
+
+    fn squads(&self, context: &GraphContext) -> FieldResult<Vec<SquadNode>> {
+        let depth = deeper(self.depth)?;
+        Ok(self
+            .tribe
+            .squads
+            .iter()
+            .filter_map(|s| context.squad(s, depth))
+            .collect())
+    }
+}
+
+pub struct RegionNode(Region);
+
+/// A kubernetes namespace in a cluster
+#[juniper::object(Context = GraphContext, name = "Region")]
+impl RegionNode {
+    fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    fn namespace(&self) -> &str {
+        &self.0.namespace
+    }
+
+    fn environment(&self) -> String {
+        self.0.environment.to_string()
+    }
+
+    fn cluster(&self) -> &str {
+        &self.0.cluster
+    }
+
+    fn versioning_scheme(&self) -> Option<String> {
+        enum_name(&self.0.versioningScheme)
+    }
+
+    fn locations(&self) -> Vec<String> {
+        self.0.locations.clone()
+    }
+
+    fn raftcat_url(&self) -> Option<String> {
+        self.0.raftcat_url()
+    }
+}
+
+pub struct ClusterNode(Cluster);
+
+#[juniper::object(Context = GraphContext, name = "Cluster")]
+impl ClusterNode {
+    fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    fn api(&self) -> &str {
+        &self.0.api
+    }
+
+    fn regions(&self) -> Vec<String> {
+        self.0.regions.clone()
+    }
+}
+
+pub struct ConfigNode(Arc<Config>);
+
+/// The shipcat config served to raftcat
+#[juniper::object(Context = GraphContext, name = "Config")]
+impl ConfigNode {
+    fn regions(&self) -> Vec<RegionNode> {
+        self.0.get_regions().into_iter().map(RegionNode).collect()
+    }
+
+    fn clusters(&self) -> Vec<ClusterNode> {
+        self.0.clusters.values().cloned().map(ClusterNode).collect()
+    }
+
+    fn allowed_labels(&self) -> Vec<String> {
+        self.0.allowedLabels.clone()
+    }
+}
+
+pub struct Query;
+
+#[juniper::object(Context = GraphContext)]
+impl Query {
+    /// A single service by name
+    fn manifest(context: &GraphContext, name: String) -> Option<ManifestNode> {
+        context.manifest(&name, 0)
+    }
+
+    /// All services, optionally for a team
+    fn manifests(context: &GraphContext, team: Option<String>) -> Vec<ManifestNode> {
+        context
+            .manifests()
+            .filter(|mf| match &team {
+                Some(t) => mf.metadata.as_ref().map_or(false, |md| &md.team == t),
+                None => true,
+            })
+            .map(|mf| ManifestNode(mf.clone(), 0))
+            .collect()
+    }
+
+    /// The region raftcat is running in
+    fn region(context: &GraphContext) -> RegionNode {
+        RegionNode(context.region.clone())
+    }
+
+    fn config(context: &GraphContext) -> ConfigNode {
+        ConfigNode(context.config.clone())
+    }
+
+    fn squads(context: &GraphContext) -> Vec<SquadNode> {
+        context
+            .config
+            .owners
+            .squads
+            .keys()
+            .filter_map(|s| context.squad(s, 0))
+            .collect()
+    }
+
+    fn squad(context: &GraphContext, slug: String) -> Option<SquadNode> {
+        context.squad(&slug, 0)
+    }
+
+    fn tribes(context: &GraphContext) -> Vec<TribeNode> {
+        context
+            .config
+            .owners
+            .tribes
+            .keys()
+            .filter_map(|t| context.tribe(t, 0))
+            .collect()
+    }
+
+    fn tribe(context: &GraphContext, slug: String) -> Option<TribeNode> {
+        context.tribe(&slug, 0)
+    }
+}
+
+pub type Schema = RootNode<'static, Query, EmptyMutation<GraphContext>>;
+
+pub fn schema() -> Schema {
+    Schema::new(Query, EmptyMutation::new())
+}
+
+/// Render a type reference from introspection (e.g. `[Manifest!]!`)
+fn type_ref(t: &Value) -> String {
+    match t["kind"].as_str() {
+        Some("NON_NULL") => format!("{}!", type_ref(&t["ofType"])),
+        Some("LIST") => format!("[{}]", type_ref(&t["ofType"])),
+        _ => t["name"].as_str().unwrap_or_default().to_string(),
+    }
+}
+
+/// The schema in the GraphQL schema language
+///
+/// Generated from introspection, without descriptions or builtin types.
+pub fn schema_language(schema: &Schema, ctx: &GraphContext) -> Result<String> {
+    let (res, errs) = juniper::introspect(schema, ctx, IntrospectionFormat::default())
+        .map_err(|e| failure::err_msg(format!("introspection failed: {:?}", e)))?;
+    if !errs.is_empty() {
+        bail!("introspection failed: {:?}", errs);
+    }
+    let json = serde_json::to_value(&res)?;
+    let mut types = json["__schema"]["types"]
+        .as_array()
+        .cloned()
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|t| {
+            let name = t["name"].as_str().unwrap_or_default();
+            !name.starts_with("__") && t["kind"] != "SCALAR"
+        })
+        .collect::<Vec<_>>();
+    types.sort_by_key(|t| t["name"].as_str().unwrap_or_default().to_string());
+
+    let mut out = String::new();
+    for t in types {
+        out += &format!("type {} {{\n", t["name"].as_str().unwrap_or_default());
+        for f in t["fields"].as_array().cloned().unwrap_or_default() {
+            let args = f["args"]
+                .as_array()
+                .cloned()
+                .unwrap_or_default()
+                .iter()
+                .map(|a| {
+                    format!(
+                        "{}: {}",
+                        a["name"].as_str().unwrap_or_default(),
+                        type_ref(&a["type"])
+                    )
+                })
+                .collect::<Vec<_>>();
+            let args = if args.is_empty() {
+                String::new()
+            } else {
+                format!("({})", args.join(", "))
+            };
+            out += &format!(
+                "  {}{}: {}\n",
+                f["name"].as_str().unwrap_or_default(),
+                args,
+                type_ref(&f["type"])
+            );
+        }
+        out += "}\n\n";
+    }
+    Ok(out.trim_end().to_string() + "\n")
+}
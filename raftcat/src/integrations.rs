@@ -1,3 +1,377 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, RwLock},
+};
+
+use crate::{Manifest, Region, Result};
+
+/// A link from a service to an external integration
+#[derive(Serialize, Clone, Debug, juniper::GraphQLObject)]
+pub struct IntegrationLink {
+    /// Provider name (e.g. `sentry`)
+    pub provider: String,
+    pub title: String,
+    /// Image under /raftcat/static/images
+    pub icon: Option<String>,
+    pub url: String,
+}
+
+/// Last known state of an integration provider
+#[derive(Serialize, Clone, Debug)]
+#[allow(non_snake_case)]
+pub struct IntegrationHealth {
+    pub name: String,
+    pub healthy: bool,
+    /// Whether the provider caches data from an external api
+    pub refreshes: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lastRefresh: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lastError: Option<String>,
+    pub entries: usize,
+}
+
+/// A source of external links for services
+///
+/// Providers that need data from an external api cache it on `refresh`,
+/// and `link` must only read from that cache.
+#[async_trait]
+pub trait Integration: Send + Sync {
+    /// Name used in region config, metrics and health reports
+    fn name(&self) -> &'static str;
+    fn title(&self) -> &'static str;
+    fn icon(&self) -> Option<&'static str> {
+        None
+    }
+    /// Whether the region has the config this provider needs
+    fn configured(&self, region: &Region) -> bool;
+    /// Whether `refresh` fetches from an external api
+    fn refreshes(&self) -> bool {
+        false
+    }
+    /// Refresh cached data, returning the number of entries cached
+    async fn refresh(&self, _region: &Region) -> Result<usize> {
+        Ok(0)
+    }
+    fn link(&self, mf: &Manifest, region: &Region) -> Option<String>;
+}
+
+/// The integration providers enabled for a region
+///
+/// Cheap to clone; all clones share caches and health.
+#[derive(Clone, Default)]
+pub struct Integrations {
+    providers: Vec<Arc<dyn Integration>>,
+    health: Arc<RwLock<BTreeMap<String, IntegrationHealth>>>,
+}
+
+impl Integrations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a provider
+    pub fn provider(mut self, p: impl Integration + 'static) -> Self {
+        self.health
+            .write()
+            .unwrap()
+            .insert(p.name().to_string(), IntegrationHealth {
+                name: p.name().to_string(),
+                healthy: !p.refreshes(), // unknown until the first refresh
+                refreshes: p.refreshes(),
+                lastRefresh: None,
+                lastError: None,
+                entries: 0,
+            });
+        self.providers.push(Arc::new(p));
+        self
+    }
+
+    /// Built-in providers configured and enabled for a region
+    pub fn for_region(region: &Region) -> Self {
+        Self::new()
+            .provider_in(NewRelic::default(), region)
+            .provider_in(Grafana, region)
+            .provider_in(LogzIo, region)
+            .provider_in(Sentry::default(), region)
+            .provider_in(PagerDuty::default(), region)
+            .provider_in(Runbook, region)
+    }
+
+    fn provider_in(self, p: impl Integration + 'static, region: &Region) -> Self {
+        if p.configured(region) && region.integrations.enabled(p.name()) {
+            self.provider(p)
+        } else {
+            debug!("Integration {} not enabled for {}", p.name(), region.name);
+            self
+        }
+    }
+
+    /// Refresh providers backed by an external api
+    ///
+    /// A failed refresh keeps the previous cache, and is reported in the health.
+    /// Returns the health of the refreshed providers.
+    pub async fn refresh(&self, region: &Region) -> Vec<IntegrationHealth> {
+        let mut res = vec![];
+        for p in self.providers.iter().filter(|p| p.refreshes()) {
+            let outcome = p.refresh(region).await;
+            let mut health = self.health.write().unwrap();
+            let h = health.get_mut(p.name()).unwrap();
+            match outcome {
+                Ok(n) => {
+                    info!("Loaded {} {} entries", n, p.name());
+                    h.healthy = true;
+                    h.lastRefresh = Some(Utc::now());
+                    h.lastError = None;
+                    h.entries = n;
+                }
+                Err(e) => {
+                    warn!("Unable to refresh {}: {}", p.name(), e);
+                    h.healthy = false;
+                    h.lastError = Some(e.to_string());
+                }
+            }
+            res.push(h.clone());
+        }
+        res
+    }
+
+    /// Links for a service from every provider
+    pub fn links(&self, mf: &Manifest, region: &Region) -> Vec<IntegrationLink> {
+        self.providers
+            .iter()
+            .filter_map(|p| {
+                p.link(mf, region).map(|url| IntegrationLink {
+                    provider: p.name().to_string(),
+                    title: p.title().to_string(),
+                    icon: p.icon().map(String::from),
+                    url,
+                })
+            })
+            .collect()
+    }
+
+    /// Link for a service from a single provider
+    pub fn link(&self, provider: &str, mf: &Manifest, region: &Region) -> Option<String> {
+        self.providers
+            .iter()
+            .find(|p| p.name() == provider)
+            .and_then(|p| p.link(mf, region))
+    }
+
+    pub fn health(&self) -> Vec<IntegrationHealth> {
+        self.health.read().unwrap().values().cloned().collect()
+    }
+}
+
+/// NewRelic application pages (named after the service)
+#[derive(Default)]
+pub struct NewRelic {
+    links: RwLock<newrelic::RelicMap>,
+}
+
+#[async_trait]
+impl Integration for NewRelic {
+    fn name(&self) -> &'static str {
+        "newrelic"
+    }
+
+    fn title(&self) -> &'static str {
+        "NewRelic"
+    }
+
+    fn icon(&self) -> Option<&'static str> {
+        Some("newrelic.png")
+    }
+
+    fn configured(&self, _: &Region) -> bool {
+        std::env::var("NEWRELIC_API_KEY").is_ok()
+    }
+
+    fn refreshes(&self) -> bool {
+        true
+    }
+
+    async fn refresh(&self, region: &Region) -> Result<usize> {
+        let links = newrelic::get_links(&region.name).await?;
+        let n = links.len();
+        *self.links.write().unwrap() = links;
+        Ok(n)
+    }
+
+    fn link(&self, mf: &Manifest, _: &Region) -> Option<String> {
+        self.links.read().unwrap().get(&mf.name).cloned()
+    }
+}
+
+/// The services dashboard in Grafana
+pub struct Grafana;
+
+impl Integration for Grafana {
+    fn name(&self) -> &'static str {
+        "grafana"
+    }
+
+    fn title(&self) -> &'static str {
+        "Grafana"
+    }
+
+    fn icon(&self) -> Option<&'static str> {
+        Some("grafana.png")
+    }
+
+    fn configured(&self, region: &Region) -> bool {
+        region.grafana.is_some()
+    }
+
+    fn link(&self, mf: &Manifest, region: &Region) -> Option<String> {
+        region.grafana_url(&mf.name)
+    }
+}
+
+/// The service dashboard in Logz.io
+pub struct LogzIo;
+
+impl Integration for LogzIo {
+    fn name(&self) -> &'static str {
+        "logzio"
+    }
+
+    fn title(&self) -> &'static str {
+        "Logz.io"
+    }
+
+    fn icon(&self) -> Option<&'static str> {
+        Some("logz.io.png")
+    }
+
+    fn configured(&self, region: &Region) -> bool {
+        region.logzio.is_some()
+    }
+
+    fn link(&self, mf: &Manifest, region: &Region) -> Option<String> {
+        region.logzio_url(&mf.name)
+    }
+}
+
+/// Sentry projects (named after the service)
+#[derive(Default)]
+pub struct Sentry {
+    slugs: RwLock<sentryapi::SentryMap>,
+}
+
+#[async_trait]
+impl Integration for Sentry {
+    fn name(&self) -> &'static str {
+        "sentry"
+    }
+
+    fn title(&self) -> &'static str {
+        "Sentry"
+    }
+
+    fn icon(&self) -> Option<&'static str> {
+        Some("sentry.png")
+    }
+
+    fn configured(&self, region: &Region) -> bool {
+        region.sentry.is_some()
+    }
+
+    fn refreshes(&self) -> bool {
+        true
+    }
+
+    async fn refresh(&self, region: &Region) -> Result<usize> {
+        let url = match &region.sentry {
+            Some(s) => s.url.clone(),
+            None => bail!("No sentry url configured for {}", region.name),
+        };
+        let slugs = sentryapi::get_slugs(&url, &region.environment.to_string()).await?;
+        let n = slugs.len();
+        *self.slugs.write().unwrap() = slugs;
+        Ok(n)
+    }
+
+    fn link(&self, mf: &Manifest, region: &Region) -> Option<String> {
+        let slug = self.slugs.read().unwrap().get(&mf.name).cloned()?;
+        region.sentry_url(&slug)
+    }
+}
+
+/// PagerDuty services (named after the service)
+#[derive(Default)]
+pub struct PagerDuty {
+    ids: RwLock<pagerduty::ServiceMap>,
+}
+
+#[async_trait]
+impl Integration for PagerDuty {
+    fn name(&self) -> &'static str {
+        "pagerduty"
+    }
+
+    fn title(&self) -> &'static str {
+        "PagerDuty"
+    }
+
+    fn configured(&self, region: &Region) -> bool {
+        region.pagerduty.is_some()
+    }
+
+    fn refreshes(&self) -> bool {
+        true
+    }
+
+    async fn refresh(&self, _: &Region) -> Result<usize> {
+        let ids = pagerduty::get_services().await?;
+        let n = ids.len();
+        *self.ids.write().unwrap() = ids;
+        Ok(n)
+    }
+
+    fn link(&self, mf: &Manifest, region: &Region) -> Option<String> {
+        let id = self.ids.read().unwrap().get(&mf.name).cloned()?;
+        region.pagerduty_url(&id)
+    }
+}
+
+/// The runbook in the metadata of the service
+pub struct Runbook;
+
+impl Integration for Runbook {
+    fn name(&self) -> &'static str {
+        "runbook"
+    }
+
+    fn title(&self) -> &'static str {
+        "Runbook"
+    }
+
+    fn configured(&self, _: &Region) -> bool {
+        true
+    }
+
+    fn link(&self, mf: &Manifest, _: &Region) -> Option<String> {
+        let md = mf.metadata.as_ref()?;
+        let runbook = md.runbook.as_ref()?;
+        if runbook.contains("://") {
+            Some(runbook.clone())
+        } else if md.repo.contains("/tree/") {
+            // repo is already a subfolder link
+            Some(format!("{}/{}", md.repo.trim_end_matches('/'), runbook))
+        } else {
+            Some(format!(
+                "{}/blob/master/{}",
+                md.repo.trim_end_matches('/'),
+                runbook
+            ))
+        }
+    }
+}
+
 pub mod sentryapi {
     use crate::Result;
     use std::collections::BTreeMap;
@@ -94,3 +468,56 @@ pub mod newrelic {
         Ok(res)
     }
 }
+
+pub mod pagerduty {
+    use crate::Result;
+    use std::collections::BTreeMap;
+
+    // PagerDuty service info
+    #[derive(Deserialize)]
+    struct Service {
+        id: String,
+        name: String,
+    }
+    #[derive(Deserialize)]
+    struct Services {
+        services: Vec<Service>,
+        more: bool,
+    }
+
+    /// Service -> PagerDuty service id
+    pub type ServiceMap = BTreeMap<String, String>;
+
+    // Get all PagerDuty services
+    pub async fn get_services() -> Result<ServiceMap> {
+        let client = reqwest::Client::new();
+        let token = std::env::var("PAGERDUTY_TOKEN")?;
+        let limit = 100;
+
+        let mut res = BTreeMap::new();
+        let mut offset = 0;
+        loop {
+            let page = client
+                .get("https://api.pagerduty.com/services")
+                .query(&[("limit", limit), ("offset", offset)])
+                .header("Authorization", format!("Token token={}", token))
+                .header("Accept", "application/vnd.pagerduty+json;version=2")
+                .send()
+                .await?;
+
+            if !page.status().is_success() {
+                bail!("Failed to fetch services: {}", page.status());
+            }
+            let data: Services = serde_json::from_str(&page.text().await?)?;
+            debug!("Got {} PagerDuty services", data.services.len());
+            for s in data.services {
+                res.insert(s.name, s.id);
+            }
+            if !data.more {
+                break;
+            }
+            offset += limit;
+        }
+        Ok(res)
+    }
+}
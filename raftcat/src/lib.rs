@@ -20,5 +20,26 @@ pub mod integrations;
 pub mod state;
 pub use state::State;
 
+/// Search and filtering over the manifest cache
+pub mod search;
+
+/// Rollout overview from manifest statuses
+pub mod rollouts;
+
+/// GraphQL schema over the State caches
+pub mod graphql;
+
+/// Aggregation of services across regions
+pub mod federation;
+
+/// Dependency graphs from the manifest cache
+pub mod depgraph;
+
+/// Authentication providers and team visibility rules
+pub mod auth;
+
+/// Prometheus metrics
+pub mod metrics;
+
 pub mod kompass;
 pub mod protos;
@@ -1,10 +1,11 @@
 #![allow(unused_imports, unused_variables)]
 #[macro_use] extern crate log;
 
-use serde_derive::Serialize;
+use serde_derive::{Deserialize, Serialize};
 use std::{
     collections::{BTreeMap, HashMap},
     io,
+    time::Instant,
 };
 
 use chrono::Local;
@@ -12,7 +13,13 @@ use reqwest::Url;
 use shipcat_definitions::Manifest;
 use std::env;
 
-pub use raftcat::*;
+pub use raftcat::{
+    auth::{self, Authenticator, Principal},
+    depgraph,
+    graphql::{self, Schema},
+    search::{SearchQuery, SearchResults},
+    *,
+};
 
 fn find_team(owners: &Owners, slug: &str) -> Option<Squad> {
     owners.squads.get(slug).cloned()
@@ -22,23 +29,77 @@ fn find_team(owners: &Owners, slug: &str) -> Option<Squad> {
 // Web server interface
 use actix_files as fs;
 use actix_web::{
+    dev::{Service, ServiceRequest},
     http::HeaderValue,
     middleware,
     web::{self, Data},
-    App, HttpRequest, HttpResponse, HttpServer, Responder,
+    App, HttpMessage, HttpRequest, HttpResponse, HttpServer, Responder,
 };
+use futures::future::{ok, Either};
+
+/// Authenticate a request and check the scope its path requires
+///
+/// The caller is attached to the request for handlers that filter by team.
+fn authorize(authn: &Authenticator, req: &ServiceRequest) -> std::result::Result<(), HttpResponse> {
+    let scope = match auth::required_scope(req.path()) {
+        Some(s) => s,
+        None => return Ok(()),
+    };
+    let res = match authn.authenticate(req.headers()) {
+        Ok(p) if p.has_scope(scope) => {
+            req.extensions_mut().insert(p);
+            Ok(())
+        }
+        Ok(p) => {
+            warn!(
+                "{} ({}) lacks {:?} scope for {}",
+                p.name,
+                p.provider,
+                scope,
+                req.path()
+            );
+            Err(HttpResponse::Forbidden().finish())
+        }
+        Err(e) => {
+            debug!("Rejected request for {}: {}", req.path(), e);
+            Err(HttpResponse::Unauthorized()
+                .header("WWW-Authenticate", "Bearer")
+                .finish())
+        }
+    };
+    if res.is_err() {
+        req.extensions_mut().insert(metrics::Unrouted);
+    }
+    res
+}
+
+/// Caller of a request that passed the auth middleware
+fn caller(req: &HttpRequest) -> Principal {
+    auth::principal(req).expect("auth middleware attaches a principal")
+}
 
 // Route entrypoints
 async fn get_single_manifest(c: Data<State>, req: HttpRequest) -> Result<HttpResponse> {
     let name = req.match_info().get("name").unwrap();
     if let Some(mf) = c.get_manifest(name).await? {
+        let cfg = c.get_config().await?;
+        if !caller(&req).can_view(&mf.spec, &cfg.owners) {
+            return Ok(HttpResponse::Forbidden().finish());
+        }
         Ok(HttpResponse::Ok().json(mf.spec))
     } else {
         Ok(HttpResponse::NotFound().finish())
     }
 }
-async fn get_all_manifests(c: Data<State>, _req: HttpRequest) -> Result<HttpResponse> {
-    let mfs: BTreeMap<String, Manifest> = c.get_manifests().await?;
+async fn get_all_manifests(c: Data<State>, req: HttpRequest) -> Result<HttpResponse> {
+    let cfg = c.get_config().await?;
+    let principal = caller(&req);
+    let mfs = c
+        .get_manifests()
+        .await?
+        .into_iter()
+        .filter(|(_, mf)| principal.can_view(mf, &cfg.owners))
+        .collect::<BTreeMap<_, _>>();
     Ok(HttpResponse::Ok().json(mfs))
 }
 async fn get_resource_usage(c: Data<State>, req: HttpRequest) -> Result<HttpResponse> {
@@ -50,6 +111,51 @@ async fn get_resource_usage(c: Data<State>, req: HttpRequest) -> Result<HttpResp
         Ok(HttpResponse::NotFound().finish())
     }
 }
+async fn get_manifest_summary(c: Data<State>, req: HttpRequest) -> Result<HttpResponse> {
+    let name = req.match_info().get("name").unwrap();
+    if let Some(summary) = c.get_summary(name).await? {
+        Ok(HttpResponse::Ok().json(summary))
+    } else {
+        Ok(HttpResponse::NotFound().finish())
+    }
+}
+async fn get_manifest_regions(c: Data<State>, req: HttpRequest) -> Result<HttpResponse> {
+    let name = req.match_info().get("name").unwrap();
+    let federated = c.get_federated(name).await?;
+    Ok(HttpResponse::Ok().json(federated))
+}
+#[derive(Deserialize)]
+struct GraphQuery {
+    up: Option<usize>,
+    down: Option<usize>,
+}
+async fn get_dependency_graph(
+    c: Data<State>,
+    req: HttpRequest,
+    query: web::Query<GraphQuery>,
+) -> Result<HttpResponse> {
+    let name = req.match_info().get("name").unwrap();
+    let (up, down) = (query.up.unwrap_or(1), query.down.unwrap_or(1));
+    if let Some(graph) = c.get_dependency_graph(name, up, down).await? {
+        Ok(HttpResponse::Ok().json(graph))
+    } else {
+        Ok(HttpResponse::NotFound().finish())
+    }
+}
+async fn get_dependency_graph_svg(
+    c: Data<State>,
+    req: HttpRequest,
+    query: web::Query<GraphQuery>,
+) -> Result<HttpResponse> {
+    let name = req.match_info().get("name").unwrap();
+    let (up, down) = (query.up.unwrap_or(1), query.down.unwrap_or(1));
+    if let Some(graph) = c.get_dependency_graph(name, up, down).await? {
+        let svg = depgraph::render_svg(&graph);
+        Ok(HttpResponse::Ok().content_type("image/svg+xml").body(svg))
+    } else {
+        Ok(HttpResponse::NotFound().finish())
+    }
+}
 async fn get_manifests_for_team(c: Data<State>, req: HttpRequest) -> Result<HttpResponse> {
     let name = req.match_info().get("name").unwrap();
     let cfg = c.get_config().await?;
@@ -65,28 +171,156 @@ async fn get_teams(c: Data<State>) -> Result<HttpResponse> {
     Ok(HttpResponse::Ok().json(cfg.owners.squads))
 }
 
-async fn get_versions(c: Data<State>) -> Result<HttpResponse> {
-    let vers = c.get_versions().await?;
-    Ok(HttpResponse::Ok().json(vers))
+async fn search_manifests(
+    c: Data<State>,
+    query: web::Query<SearchQuery>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    let res = c.search_manifests(&query, &caller(&req)).await?;
+    Ok(HttpResponse::Ok().json(res))
 }
 
-async fn get_kompass_hub_services(c: Data<State>, req: HttpRequest) -> Result<HttpResponse> {
-    let req_token = req.headers().get("Authorization");
-    if req_token.is_none() {
-        return Ok(HttpResponse::Unauthorized().finish());
+/// Query string of a search without the page (for page links)
+fn search_base_query(query: &SearchQuery, filters: &[SearchFilter]) -> String {
+    let mut qs = url::form_urlencoded::Serializer::new(String::new());
+    if let Some(q) = &query.q {
+        qs.append_pair("q", q);
     }
-    let req_token = parse_basic_auth(req_token.expect("request token"))?;
-
-    let auth_token = env::var(kompass::KOMPASS_AUTH_TOKEN).ok();
-    if auth_token.is_none() {
-        return Ok(HttpResponse::InternalServerError().finish());
+    for f in filters.iter().filter(|f| !f.value.is_empty()) {
+        qs.append_pair(f.name, &f.value);
+    }
+    if let Some(n) = query.per_page {
+        qs.append_pair("perPage", &n.to_string());
     }
-    let auth_token = auth_token.expect("auth token");
+    qs.finish()
+}
+
+#[derive(Serialize)]
+struct SearchFilter {
+    name: &'static str,
+    value: String,
+}
+
+async fn search_services(
+    c: Data<State>,
+    query: web::Query<SearchQuery>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    let res: SearchResults = c.search_manifests(&query, &caller(&req)).await?;
+    let region = c.get_region().await?;
+
+    let mut ctx = tera::Context::new();
+    ctx.insert("raftcat", env!("CARGO_PKG_VERSION"));
+    ctx.insert("region", &region);
+    let query = query.into_inner();
+    let filters = vec![
+        ("team", &query.team),
+        ("squad", &query.squad),
+        ("tribe", &query.tribe),
+        ("language", &query.language),
+        ("host", &query.host),
+        ("uri", &query.uri),
+        ("env", &query.env),
+        ("image", &query.image),
+        ("label", &query.label),
+        ("dependency", &query.dependency),
+    ]
+    .into_iter()
+    .map(|(name, value)| SearchFilter {
+        name,
+        value: value.clone().unwrap_or_default(),
+    })
+    .collect::<Vec<_>>();
+    ctx.insert("query", &query);
+    ctx.insert("filters", &filters);
+    ctx.insert("base_query", &search_base_query(&query, &filters));
+    ctx.insert("results", &res);
+    let s = c.render_template("search.tera", ctx);
+    Ok(HttpResponse::Ok().content_type("text/html").body(s))
+}
 
-    if req_token != auth_token {
-        return Ok(HttpResponse::Unauthorized().finish());
+async fn get_rollouts(c: Data<State>) -> Result<HttpResponse> {
+    let rollouts = c.get_rollouts().await?;
+    Ok(HttpResponse::Ok().json(rollouts))
+}
+
+/// Server-sent events with the rollout overview
+///
+/// Sends the current overview on connect, then again whenever it changes.
+async fn get_rollout_events(c: Data<State>) -> Result<HttpResponse> {
+    use tokio::stream::{self as ts, StreamExt};
+    let initial = serde_json::to_string(&c.get_rollouts().await?)?;
+    let updates = c.subscribe_rollouts().filter_map(|r| r.ok()); // skip lagged receivers
+    let events = ts::once(initial).chain(updates).map(|data| {
+        let event = format!("event: rollouts\ndata: {}\n\n", data);
+        Ok::<_, actix_web::Error>(web::Bytes::from(event))
+    });
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .header("cache-control", "no-cache")
+        .streaming(events))
+}
+
+async fn rollout_dashboard(c: Data<State>) -> Result<HttpResponse> {
+    let rollouts = c.get_rollouts().await?;
+    let region = c.get_region().await?;
+
+    let mut ctx = tera::Context::new();
+    ctx.insert("raftcat", env!("CARGO_PKG_VERSION"));
+    ctx.insert("region", &region);
+    // embedded in a script tag; failure reasons are arbitrary text
+    let data = serde_json::to_string(&rollouts)?.replace("</", "<\\/");
+    ctx.insert("rollouts", &data);
+    let s = c.render_template("rollouts.tera", ctx);
+    Ok(HttpResponse::Ok().content_type("text/html").body(s))
+}
+
+async fn graphql_query(
+    c: Data<State>,
+    schema: Data<Schema>,
+    http: HttpRequest,
+    req: web::Json<juniper::http::GraphQLRequest>,
+) -> Result<HttpResponse> {
+    let mut ctx = c.graphql_context().await?;
+    // manifests expose env and configs, so only those of the caller's squads are queryable
+    ctx.restrict_to(&caller(&http));
+    let res = req.execute(&schema, &ctx);
+    if res.is_ok() {
+        Ok(HttpResponse::Ok().json(res))
+    } else {
+        Ok(HttpResponse::BadRequest().json(res))
     }
+}
+
+async fn graphiql() -> Result<HttpResponse> {
+    let html = juniper::http::graphiql::graphiql_source("/raftcat/graphql");
+    Ok(HttpResponse::Ok().content_type("text/html").body(html))
+}
+
+async fn graphql_schema(c: Data<State>, schema: Data<Schema>) -> Result<HttpResponse> {
+    let ctx = c.graphql_context().await?;
+    let sdl = graphql::schema_language(&schema, &ctx)?;
+    Ok(HttpResponse::Ok().content_type("text/plain").body(sdl))
+}
+
+async fn get_metrics(c: Data<State>) -> Result<HttpResponse> {
+    let text = c.render_metrics()?;
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(text))
+}
+
+/// Health of the integration providers
+async fn get_integrations(c: Data<State>) -> Result<HttpResponse> {
+    Ok(HttpResponse::Ok().json(c.get_integration_health()))
+}
+
+async fn get_versions(c: Data<State>) -> Result<HttpResponse> {
+    let vers = c.get_versions().await?;
+    Ok(HttpResponse::Ok().json(vers))
+}
 
+async fn get_kompass_hub_services(c: Data<State>, req: HttpRequest) -> Result<HttpResponse> {
     let manifests = c.get_manifests().await?;
     let mut services = HashMap::new();
     let path = match req.headers().get("host") {
@@ -102,39 +336,17 @@ async fn get_kompass_hub_services(c: Data<State>, req: HttpRequest) -> Result<Ht
     }))
 }
 
-fn parse_basic_auth(value: &HeaderValue) -> Result<String> {
-    let parse_err = failure::format_err!("Error parsing auth header");
-    let mut tokens = value.to_str()?.splitn(2, ' ');
-    match tokens.next() {
-        Some(scheme) if scheme == "Basic" => (),
-        _ => return Err(parse_err),
-    }
-
-    let decoded = base64::decode(
-        tokens
-            .next()
-            .ok_or(failure::format_err!("Error decoding base64"))?,
-    )?;
-    let auth_token = String::from(
-        String::from_utf8(decoded)?
-            .splitn(2, ':')
-            .nth(1)
-            .ok_or(parse_err)?,
-    );
-    Ok(auth_token)
-}
-
 async fn get_service(c: Data<State>, req: HttpRequest) -> Result<HttpResponse> {
     let name = req.match_info().get("name").unwrap();
     let cfg = c.get_config().await?;
     let region = c.get_region().await?;
 
     let revdeps = c.get_reverse_deps(name).await.ok();
-    let newrelic_link = c.get_newrelic_link(name);
-    let sentry_slug = c.get_sentry_slug(name);
+    let integrations = c.get_integrations();
 
     if let Some(mfobj) = c.get_manifest(name).await? {
         let mf = mfobj.spec;
+        let visible = caller(&req).can_view(&mf, &cfg.owners);
         let pretty = serde_yaml::to_string(&mf)?;
         let mfstub = mf.clone().stub(&region).await.unwrap();
 
@@ -156,13 +368,16 @@ async fn get_service(c: Data<State>, req: HttpRequest) -> Result<HttpResponse> {
         let quaylink = format!("https://{}/?tab=tags", mf.image.clone().unwrap());
 
         let (team, teamlink) = (md.team.clone(), format!("/raftcat/teams/{}", &md.team));
-        // TODO: runbook
 
         let mut ctx = tera::Context::new();
         ctx.insert("raftcat", env!("CARGO_PKG_VERSION"));
         ctx.insert("manifest", &mf);
-        ctx.insert("pretty_manifest", &pretty);
-        ctx.insert("pretty_manifest_stub", &pretty);
+        if visible {
+            ctx.insert("pretty_manifest", &pretty);
+            ctx.insert("pretty_manifest_stub", &pretty);
+            ctx.insert("mfenv", &mf.env);
+            ctx.insert("mfenvstub", &mfstub.env);
+        }
         ctx.insert("region", &region);
         ctx.insert("version_link", &vlink);
         ctx.insert("version", &version);
@@ -173,8 +388,6 @@ async fn get_service(c: Data<State>, req: HttpRequest) -> Result<HttpResponse> {
         ctx.insert("quay_link", &quaylink);
         ctx.insert("team", &team);
         ctx.insert("team_link", &teamlink);
-        ctx.insert("mfenv", &mf.env);
-        ctx.insert("mfenvstub", &mfstub.env);
         ctx.insert("mfdeps", &mf.dependencies);
 
         if let Some(status) = mfobj.status {
@@ -189,25 +402,14 @@ async fn get_service(c: Data<State>, req: HttpRequest) -> Result<HttpResponse> {
             if let Some(r) = &conds.rolledout {
                 cvec.push(format!("RolledOut: {}", r.html_list_item().unwrap()));
             }
+            if let Some(s) = &conds.smoketested {
+                cvec.push(format!("SmokeTested: {}", s.html_list_item().unwrap()));
+            }
             ctx.insert("conditions", &cvec);
         }
 
-        // integration insert if found in the big query
-        if let Some(lio_link) = region.logzio_url(&mf.name) {
-            ctx.insert("logzio_link", &lio_link);
-        }
-        if let Some(gf_link) = region.grafana_url(&mf.name) {
-            ctx.insert("grafana_link", &gf_link);
-        }
+        ctx.insert("integrations", &integrations.links(&mf, &region));
         ctx.insert("vault_link", &region.vault_url(&mf.name));
-        if let Some(slug) = sentry_slug {
-            if let Some(sentry_link) = region.sentry_url(&slug) {
-                ctx.insert("sentry_link", &sentry_link);
-            }
-        }
-        if let Some(nr) = newrelic_link {
-            ctx.insert("newrelic_link", &nr);
-        }
 
         // stats
         if let Ok(_usage) = mf.compute_resource_totals() {
@@ -221,6 +423,10 @@ async fn get_service(c: Data<State>, req: HttpRequest) -> Result<HttpResponse> {
         }
 
         ctx.insert("revdeps", &revdeps);
+        let federated = c.get_federated(name).await?;
+        if !federated.is_empty() {
+            ctx.insert("federated", &federated);
+        }
 
         let date = Local::now();
         let time = date.format("%Y-%m-%d %H:%M:%S").to_string();
@@ -324,14 +530,43 @@ async fn main() -> io::Result<()> {
         tokio::spawn(kompass::register(kompass_url, region_url));
     }
 
+    let schema = Data::new(graphql::schema());
+    let authn = Authenticator::from_env().await.expect("Failed to configure auth");
+
     info!("Starting listening on 0.0.0.0:8080");
     HttpServer::new(move || {
         App::new()
             .data(shared_state.clone())
+            .app_data(schema.clone())
+            .wrap_fn({
+                let authn = authn.clone();
+                move |req, srv| match authorize(&authn, &req) {
+                    Ok(()) => Either::Left(srv.call(req)),
+                    Err(res) => Either::Right(ok(req.into_response(res))),
+                }
+            })
+            .wrap_fn({
+                let metrics = shared_state.metrics();
+                move |req, srv| {
+                    let method = req.method().to_string();
+                    let start = Instant::now();
+                    let res = srv.call(req);
+                    let metrics = metrics.clone();
+                    async move {
+                        let res = res.await?;
+                        let elapsed = start.elapsed().as_secs_f64();
+                        let route = metrics::route_label(res.request());
+                        metrics.observe_request(&method, &route, res.status().as_u16(), elapsed);
+                        Ok(res)
+                    }
+                }
+            })
             .wrap(
                 middleware::Logger::default()
                     .exclude("/health")
                     .exclude("/raftcat/health")
+                    .exclude("/metrics")
+                    .exclude("/raftcat/metrics")
                     .exclude("/favicon.ico")
                     .exclude("/raftcat/static/*.png")
                     .exclude("/raftcat/static/images/*.png"),
@@ -343,15 +578,42 @@ async fn main() -> io::Result<()> {
             .service(
                 web::resource("/raftcat/manifests/{name}/resources").route(web::get().to(get_resource_usage)),
             )
+            .service(
+                web::resource("/raftcat/manifests/{name}/graph.svg")
+                    .route(web::get().to(get_dependency_graph_svg)),
+            )
+            .service(
+                web::resource("/raftcat/manifests/{name}/graph").route(web::get().to(get_dependency_graph)),
+            )
+            .service(
+                web::resource("/raftcat/manifests/{name}/summary").route(web::get().to(get_manifest_summary)),
+            )
+            .service(
+                web::resource("/raftcat/manifests/{name}/regions").route(web::get().to(get_manifest_regions)),
+            )
             .service(web::resource("/raftcat/manifests/{name}").route(web::get().to(get_single_manifest)))
             .service(web::resource("/raftcat/manifests").route(web::get().to(get_all_manifests)))
+            .service(web::resource("/raftcat/search").route(web::get().to(search_manifests)))
+            .service(web::resource("/raftcat/services").route(web::get().to(search_services)))
             .service(web::resource("/raftcat/services/{name}").route(web::get().to(get_service)))
             .service(web::resource("/raftcat/teams/{name}").route(web::get().to(get_manifests_for_team)))
             .service(web::resource("/raftcat/teams").route(web::get().to(get_teams)))
+            .service(web::resource("/raftcat/rollouts/events").route(web::get().to(get_rollout_events)))
+            .service(web::resource("/raftcat/rollouts").route(web::get().to(get_rollouts)))
+            .service(web::resource("/raftcat/dashboard").route(web::get().to(rollout_dashboard)))
+            .service(web::resource("/raftcat/graphql/schema").route(web::get().to(graphql_schema)))
+            .service(
+                web::resource("/raftcat/graphql")
+                    .route(web::post().to(graphql_query))
+                    .route(web::get().to(graphiql)),
+            )
             .service(web::resource("/raftcat/health").route(web::get().to(health)))
             .service(web::resource("/raftcat/versions").route(web::get().to(get_versions)))
+            .service(web::resource("/raftcat/integrations").route(web::get().to(get_integrations)))
             .service(web::resource("/raftcat/kompass-hub").route(web::get().to(get_kompass_hub_services)))
+            .service(web::resource("/raftcat/metrics").route(web::get().to(get_metrics)))
             .service(web::resource("/health").route(web::get().to(health))) // redundancy
+            .service(web::resource("/metrics").route(web::get().to(get_metrics)))
             .service(web::resource("/raftcat/").route(web::get().to(index)))
     })
     .bind("0.0.0.0:8080")
@@ -0,0 +1,231 @@
+use actix_web::HttpRequest;
+use chrono::{DateTime, Utc};
+use prometheus::{
+    Encoder, Gauge, GaugeVec, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, RwLock},
+};
+
+use crate::{rollouts::RolloutRow, Result};
+
+/// Marks requests answered by middleware before reaching the router
+pub struct Unrouted;
+
+/// The route pattern a request matched (keeps label cardinality bounded)
+///
+/// Rebuilt from the router's match info by putting the parameter names back in place
+/// of their matched segments, so every registered route is labelled by its pattern.
+pub fn route_label(req: &HttpRequest) -> String {
+    let info = req.match_info();
+    let path = info.get_ref().path();
+    if path.starts_with("/raftcat/static/") {
+        return "/raftcat/static".into();
+    }
+    if req.extensions().get::<Unrouted>().is_some() {
+        return "unrouted".into();
+    }
+    if !req.resource_map().has_resource(path) {
+        return "unmatched".into();
+    }
+    // matched parameters are slices of the path
+    let mut params = info
+        .iter()
+        .filter_map(|(name, value)| {
+            let start = (value.as_ptr() as usize).checked_sub(path.as_ptr() as usize)?;
+            let end = start + value.len();
+            if end <= path.len() {
+                Some((start, end, name))
+            } else {
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+    params.sort();
+    let mut label = String::new();
+    let mut done = 0;
+    for (start, end, name) in params {
+        if start >= done {
+            label.push_str(&path[done..start]);
+            label.push_str(&format!("{{{}}}", name));
+            done = end;
+        }
+    }
+    label.push_str(&path[done..]);
+    label
+}
+
+/// Prometheus metrics for raftcat
+///
+/// Cheap to clone; all clones update the same registry.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    requests: HistogramVec,
+    reflector_staleness: GaugeVec,
+    cache_refreshes: IntCounterVec,
+    cache_entries: IntGaugeVec,
+    manifests: IntGaugeVec,
+    last_poll: Arc<RwLock<BTreeMap<String, DateTime<Utc>>>>,
+}
+
+impl Metrics {
+    pub fn new() -> Result<Self> {
+        let registry = Registry::new();
+        let requests = HistogramVec::new(
+            HistogramOpts::new(
+                "raftcat_http_request_duration_seconds",
+                "Latency of http requests",
+            ),
+            &["method", "path", "status"],
+        )?;
+        let reflector_staleness = GaugeVec::new(
+            Opts::new(
+                "raftcat_reflector_staleness_seconds",
+                "Seconds since a reflector last polled successfully",
+            ),
+            &["resource"],
+        )?;
+        let cache_refreshes = IntCounterVec::new(
+            Opts::new(
+                "raftcat_cache_refresh_total",
+                "Refreshes of integration caches by result",
+            ),
+            &["cache", "result"],
+        )?;
+        let cache_entries = IntGaugeVec::new(
+            Opts::new("raftcat_cache_entries", "Entries in integration caches"),
+            &["cache"],
+        )?;
+        let manifests = IntGaugeVec::new(
+            Opts::new("raftcat_manifests", "Manifests in the region by rollout health"),
+            &["health"],
+        )?;
+        let start_time = Gauge::new("raftcat_start_time_seconds", "Start time of raftcat")?;
+        start_time.set(Utc::now().timestamp() as f64);
+
+        registry.register(Box::new(requests.clone()))?;
+        registry.register(Box::new(reflector_staleness.clone()))?;
+        registry.register(Box::new(cache_refreshes.clone()))?;
+        registry.register(Box::new(cache_entries.clone()))?;
+        registry.register(Box::new(manifests.clone()))?;
+        registry.register(Box::new(start_time))?;
+        Ok(Metrics {
+            registry,
+            requests,
+            reflector_staleness,
+            cache_refreshes,
+            cache_entries,
+            manifests,
+            last_poll: Arc::new(RwLock::new(BTreeMap::new())),
+        })
+    }
+
+    /// Record a served request
+    ///
+    /// The `route` should come from `route_label` once the request has been served.
+    pub fn observe_request(&self, method: &str, route: &str, status: u16, seconds: f64) {
+        self.requests
+            .with_label_values(&[method, route, &status.to_string()])
+            .observe(seconds);
+    }
+
+    /// Record a successful reflector poll
+    pub fn reflector_polled(&self, resource: &str, at: DateTime<Utc>) {
+        self.last_poll.write().unwrap().insert(resource.to_string(), at);
+    }
+
+    /// Record the result of refreshing an integration cache
+    pub fn cache_refreshed(&self, cache: &str, entries: Option<usize>) {
+        let result = if entries.is_some() { "success" } else { "failure" };
+        self.cache_refreshes.with_label_values(&[cache, result]).inc();
+        if let Some(n) = entries {
+            self.cache_entries.with_label_values(&[cache]).set(n as i64);
+        }
+    }
+
+    /// Count manifests by rollout health
+    pub fn set_rollouts(&self, rows: &[RolloutRow]) {
+        let mut counts = BTreeMap::new();
+        for r in rows {
+            let health = serde_json::to_value(&r.health)
+                .ok()
+                .and_then(|v| v.as_str().map(String::from))
+                .unwrap_or_default();
+            *counts.entry(health).or_insert(0) += 1;
+        }
+        for h in &["healthy", "rolling", "stale", "failed"] {
+            let n = counts.get(*h).copied().unwrap_or(0);
+            self.manifests.with_label_values(&[h]).set(n);
+        }
+    }
+
+    /// Render all metrics in the prometheus text format
+    pub fn render(&self, now: DateTime<Utc>) -> Result<String> {
+        for (resource, at) in self.last_poll.read().unwrap().iter() {
+            let age = (now - *at).num_milliseconds() as f64 / 1000.0;
+            self.reflector_staleness.with_label_values(&[resource]).set(age);
+        }
+        let mut buf = vec![];
+        TextEncoder::new().encode(&self.registry.gather(), &mut buf)?;
+        Ok(String::from_utf8(buf)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{route_label, Metrics, Unrouted};
+    use actix_web::{dev::Service, test, web, App, HttpResponse};
+    use chrono::{Duration, Utc};
+
+    #[actix_rt::test]
+    async fn metrics_route_labels() {
+        let mut app = test::init_service(
+            App::new()
+                .service(web::resource("/raftcat/versions").to(HttpResponse::Ok))
+                .service(web::resource("/raftcat/manifests/{name}/summary").to(HttpResponse::Ok))
+                .service(web::resource("/raftcat/services/{name}").to(HttpResponse::Ok)),
+        )
+        .await;
+        let cases = vec![
+            ("/raftcat/versions", "/raftcat/versions"),
+            (
+                "/raftcat/manifests/webapp/summary",
+                "/raftcat/manifests/{name}/summary",
+            ),
+            ("/raftcat/services/services", "/raftcat/services/{name}"),
+            ("/raftcat/static/raftcat.css", "/raftcat/static"),
+            ("/wp-admin/login.php", "unmatched"),
+        ];
+        for (path, label) in cases {
+            let req = test::TestRequest::with_uri(path).to_request();
+            let res = app.call(req).await.unwrap();
+            assert_eq!(route_label(res.request()), label);
+        }
+
+        // requests rejected before routing
+        let req = test::TestRequest::with_uri("/raftcat/services/webapp").to_http_request();
+        req.extensions_mut().insert(Unrouted);
+        assert_eq!(route_label(&req), "unrouted");
+    }
+
+    #[test]
+    fn metrics_render() {
+        let m = Metrics::new().unwrap();
+        m.observe_request("GET", "/raftcat/services/{name}", 200, 0.02);
+        m.cache_refreshed("sentry", Some(12));
+        m.cache_refreshed("newrelic", None);
+        let now = Utc::now();
+        m.reflector_polled("shipcatmanifests", now - Duration::seconds(30));
+
+        let text = m.render(now).unwrap();
+        assert!(text.contains(
+            "raftcat_http_request_duration_seconds_count{method=\"GET\",path=\"/raftcat/services/{name}\",status=\"200\"} 1"
+        ));
+        assert!(text.contains("raftcat_cache_entries{cache=\"sentry\"} 12"));
+        assert!(text.contains("raftcat_cache_refresh_total{cache=\"newrelic\",result=\"failure\"} 1"));
+        assert!(text.contains("raftcat_reflector_staleness_seconds{resource=\"shipcatmanifests\"} 30"));
+    }
+}
@@ -0,0 +1,198 @@
+use chrono::{DateTime, Duration, Utc};
+use shipcat_definitions::{status::Condition, ShipcatManifest};
+
+/// How long a version can be pending a rollout before it is considered stale
+const STALE_AFTER_MINUTES: i64 = 60;
+
+/// Overall state of a service in a region
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RolloutHealth {
+    /// Desired version rolled out
+    Healthy,
+    /// Desired version applied recently and not rolled out yet
+    Rolling,
+    /// Desired version not rolled out long after it was applied (or never reconciled)
+    Stale,
+    /// Last generate, apply, rollout or smoke test failed
+    Failed,
+}
+
+/// Rollout state of a single service
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[allow(non_snake_case)]
+pub struct RolloutRow {
+    pub name: String,
+    pub team: String,
+    /// Version in the manifest spec
+    pub desiredVersion: Option<String>,
+    /// Last version that was successfully rolled out
+    pub currentVersion: Option<String>,
+    /// Date string (RFC3339) of the last apply
+    pub lastApply: Option<String>,
+    /// Reason for the last failure (if still failing)
+    pub lastFailureReason: Option<String>,
+    pub health: RolloutHealth,
+}
+
+fn failed(c: &Option<Condition>) -> bool {
+    c.as_ref().map_or(false, |c| !c.status)
+}
+
+impl RolloutRow {
+    /// Summarise the status of a ShipcatManifest at a point in time
+    pub fn new(crd: &ShipcatManifest, now: DateTime<Utc>) -> Self {
+        let mf = &crd.spec;
+        let status = crd.status.clone().unwrap_or_default();
+        let summary = status.summary.clone();
+        let conds = &status.conditions;
+
+        let current = summary
+            .as_ref()
+            .and_then(|s| s.last_successful_rollout_version.clone());
+        let last_apply = summary.as_ref().and_then(|s| s.last_apply.clone());
+        let is_failed = failed(&conds.generated)
+            || failed(&conds.applied)
+            || failed(&conds.rolledout)
+            || (mf.smokeTests.is_some() && failed(&conds.smoketested));
+
+        let health = if is_failed {
+            RolloutHealth::Failed
+        } else if current.is_some() && current == mf.version {
+            RolloutHealth::Healthy
+        } else {
+            let applied = last_apply.as_ref().and_then(|d| d.parse::<DateTime<Utc>>().ok());
+            match applied {
+                Some(t) if now - t < Duration::minutes(STALE_AFTER_MINUTES) => RolloutHealth::Rolling,
+                _ => RolloutHealth::Stale,
+            }
+        };
+        let reason = if is_failed {
+            summary.and_then(|s| s.last_failure_reason)
+        } else {
+            None
+        };
+        RolloutRow {
+            name: mf.name.clone(),
+            team: mf.metadata.as_ref().map(|md| md.team.clone()).unwrap_or_default(),
+            desiredVersion: mf.version.clone(),
+            currentVersion: current,
+            lastApply: last_apply,
+            lastFailureReason: reason,
+            health,
+        }
+    }
+}
+
+/// Rollout state of every service, ordered by name
+pub fn overview(crds: &[ShipcatManifest], now: DateTime<Utc>) -> Vec<RolloutRow> {
+    let mut rows = crds
+        .iter()
+        .map(|crd| RolloutRow::new(crd, now))
+        .collect::<Vec<_>>();
+    rows.sort_by(|a, b| a.name.cmp(&b.name));
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{overview, RolloutHealth};
+    use chrono::{DateTime, Duration, Utc};
+    use serde_json::json;
+    use shipcat_definitions::ShipcatManifest;
+
+    fn cond(ok: bool) -> serde_json::Value {
+        json!({ "status": ok, "lastTransitionTime": "2020-03-01T12:00:00Z" })
+    }
+
+    fn crd(name: &str, version: &str, status: serde_json::Value) -> ShipcatManifest {
+        serde_json::from_value(json!({
+            "apiVersion": "babylontech.co.uk/v1",
+            "kind": "ShipcatManifest",
+            "metadata": { "name": name },
+            "spec": {
+                "name": name,
+                "version": version,
+                "metadata": { "repo": "https://github.com/babylonhealth/x", "team": "devops" },
+                "smokeTests": { "checks": [{ "name": "health", "path": "/health" }] },
+            },
+            "status": status,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn rollout_health() {
+        let now: DateTime<Utc> = "2020-03-01T12:00:00Z".parse().unwrap();
+        let recent = (now - Duration::minutes(5)).to_rfc3339();
+        let old = (now - Duration::hours(3)).to_rfc3339();
+        let crds = vec![
+            crd(
+                "healthy",
+                "1.0.0",
+                json!({
+                    "conditions": { "rolledout": cond(true), "smoketested": cond(true) },
+                    "summary": { "lastSuccessfulRolloutVersion": "1.0.0", "lastApply": old },
+                }),
+            ),
+            crd(
+                "rolling",
+                "1.1.0",
+                json!({
+                    "conditions": { "applied": cond(true) },
+                    "summary": { "lastSuccessfulRolloutVersion": "1.0.0", "lastApply": recent },
+                }),
+            ),
+            crd(
+                "stale",
+                "1.1.0",
+                json!({
+                    "summary": { "lastSuccessfulRolloutVersion": "1.0.0", "lastApply": old },
+                }),
+            ),
+            crd("unreconciled", "1.0.0", json!({})),
+            crd(
+                "broken",
+                "1.1.0",
+                json!({
+                    "conditions": { "applied": cond(false) },
+                    "summary": { "lastApply": recent, "lastFailureReason": "invalid yaml" },
+                }),
+            ),
+            crd(
+                "smoky",
+                "1.1.0",
+                json!({
+                    "conditions": { "rolledout": cond(true), "smoketested": cond(false) },
+                    "summary": {
+                        "lastSuccessfulRolloutVersion": "1.1.0",
+                        "lastFailureReason": "health check failed",
+                    },
+                }),
+            ),
+        ];
+        let rows = overview(&crds, now);
+        let health = rows
+            .iter()
+            .map(|r| (r.name.as_str(), r.health.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(health, vec![
+            ("broken", RolloutHealth::Failed),
+            ("healthy", RolloutHealth::Healthy),
+            ("rolling", RolloutHealth::Rolling),
+            ("smoky", RolloutHealth::Failed),
+            ("stale", RolloutHealth::Stale),
+            ("unreconciled", RolloutHealth::Stale),
+        ]);
+        // failure reasons are only shown while failing
+        assert_eq!(rows[0].lastFailureReason.as_deref(), Some("invalid yaml"));
+        assert_eq!(rows[3].lastFailureReason.as_deref(), Some("health check failed"));
+        assert_eq!(rows[1].lastFailureReason, None);
+        assert_eq!(rows[2].currentVersion.as_deref(), Some("1.0.0"));
+
+        // smoke test conditions left over from removed smoke tests are ignored
+        let mut removed = crds[5].clone();
+        removed.spec.smokeTests = None;
+        assert_eq!(overview(&[removed], now)[0].health, RolloutHealth::Healthy);
+    }
+}
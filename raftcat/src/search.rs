@@ -0,0 +1,337 @@
+use shipcat_definitions::{teams::Owners, Manifest};
+
+/// Default number of results per page
+const DEFAULT_PER_PAGE: usize = 25;
+/// Upper bound on results per page
+const MAX_PER_PAGE: usize = 200;
+
+/// Query parameters for manifest search
+///
+/// All filters are optional and combined. String matches are case insensitive,
+/// and exact unless stated otherwise.
+#[derive(Deserialize, Serialize, Default, Clone, Debug)]
+pub struct SearchQuery {
+    /// Full text search over name and description
+    pub q: Option<String>,
+    /// Team as set in the manifest metadata
+    pub team: Option<String>,
+    /// Squad slug from teams.yml
+    pub squad: Option<String>,
+    /// Tribe slug from teams.yml
+    pub tribe: Option<String>,
+    /// Language the service is written in
+    pub language: Option<String>,
+    /// Substring of a kong host
+    pub host: Option<String>,
+    /// Prefix of a kong uri
+    pub uri: Option<String>,
+    /// Name of a plain or secret environment variable
+    pub env: Option<String>,
+    /// Substring of the image
+    pub image: Option<String>,
+    /// Label as `key` or `key=value`
+    pub label: Option<String>,
+    /// Name of a service this service depends on
+    pub dependency: Option<String>,
+    /// Page to return (starting at 1)
+    pub page: Option<usize>,
+    #[serde(rename = "perPage")]
+    pub per_page: Option<usize>,
+}
+
+/// Summary of a manifest matching a search
+#[derive(Serialize, Clone, Debug)]
+#[allow(non_snake_case)]
+pub struct SearchHit {
+    pub name: String,
+    pub team: String,
+    pub squad: Option<String>,
+    pub tribe: Option<String>,
+    pub version: Option<String>,
+    pub description: Option<String>,
+    pub dependencies: Vec<String>,
+    pub kongHosts: Vec<String>,
+}
+
+/// A page of search results
+#[derive(Serialize, Clone, Debug)]
+#[allow(non_snake_case)]
+pub struct SearchResults {
+    /// Total number of matches across all pages
+    pub total: usize,
+    pub page: usize,
+    pub perPage: usize,
+    pub pages: usize,
+    pub results: Vec<SearchHit>,
+}
+
+fn contains_ci(haystack: &str, needle: &str) -> bool {
+    haystack.to_lowercase().contains(&needle.to_lowercase())
+}
+
+/// Squad slug owning a team name (teams are either slugs or squad names)
+pub fn squad_for(owners: &Owners, team: &str) -> Option<String> {
+    owners
+        .squads
+        .iter()
+        .find(|(slug, sq)| slug.eq_ignore_ascii_case(team) || sq.name.eq_ignore_ascii_case(team))
+        .map(|(slug, _)| slug.clone())
+}
+
+/// Tribe slug containing a squad slug
+pub fn tribe_for(owners: &Owners, squad: &str) -> Option<String> {
+    owners
+        .tribes
+        .iter()
+        .find(|(_, t)| t.squads.iter().any(|s| s == squad))
+        .map(|(slug, _)| slug.clone())
+}
+
+impl SearchQuery {
+    /// Drop filters left empty (as submitted by the search form)
+    fn without_blanks(&self) -> Self {
+        let keep = |f: &Option<String>| f.clone().filter(|s| !s.trim().is_empty());
+        SearchQuery {
+            q: keep(&self.q),
+            team: keep(&self.team),
+            squad: keep(&self.squad),
+            tribe: keep(&self.tribe),
+            language: keep(&self.language),
+            host: keep(&self.host),
+            uri: keep(&self.uri),
+            env: keep(&self.env),
+            image: keep(&self.image),
+            label: keep(&self.label),
+            dependency: keep(&self.dependency),
+            ..self.clone()
+        }
+    }
+
+    /// Whether a manifest satisfies every filter in the query
+    fn matches(&self, mf: &Manifest, squad: Option<&String>, tribe: Option<&String>) -> bool {
+        let md = mf.metadata.as_ref();
+        let team = md.map(|m| m.team.as_str()).unwrap_or_default();
+        let description = md.and_then(|m| m.description.as_deref()).unwrap_or_default();
+
+        if let Some(q) = &self.q {
+            let words = q.split_whitespace().collect::<Vec<_>>();
+            if !words
+                .iter()
+                .all(|w| contains_ci(&mf.name, w) || contains_ci(description, w))
+            {
+                return false;
+            }
+        }
+        if let Some(t) = &self.team {
+            if !team.eq_ignore_ascii_case(t) {
+                return false;
+            }
+        }
+        if let Some(s) = &self.squad {
+            if !squad.map_or(false, |x| x.eq_ignore_ascii_case(s)) {
+                return false;
+            }
+        }
+        if let Some(t) = &self.tribe {
+            if !tribe.map_or(false, |x| x.eq_ignore_ascii_case(t)) {
+                return false;
+            }
+        }
+        if let Some(l) = &self.language {
+            let lang = md
+                .and_then(|m| m.language.as_ref())
+                .and_then(|l| serde_json::to_value(l).ok())
+                .and_then(|v| v.as_str().map(String::from));
+            if !lang.map_or(false, |x| x.eq_ignore_ascii_case(l)) {
+                return false;
+            }
+        }
+        if let Some(h) = &self.host {
+            if !mf
+                .kongApis
+                .iter()
+                .any(|k| k.hosts.iter().any(|x| contains_ci(x, h)))
+            {
+                return false;
+            }
+        }
+        if let Some(u) = &self.uri {
+            let u = u.to_lowercase();
+            if !mf
+                .kongApis
+                .iter()
+                .filter_map(|k| k.uris.as_ref())
+                .any(|x| x.to_lowercase().starts_with(&u))
+            {
+                return false;
+            }
+        }
+        if let Some(e) = &self.env {
+            let found = mf.env.plain.keys().chain(mf.env.secrets.iter()).any(|k| k == e);
+            if !found {
+                return false;
+            }
+        }
+        if let Some(i) = &self.image {
+            if !mf.image.as_ref().map_or(false, |x| contains_ci(x, i)) {
+                return false;
+            }
+        }
+        if let Some(l) = &self.label {
+            let found = match l.find('=') {
+                Some(i) => mf.labels.get(&l[..i]).map_or(false, |v| v == &l[i + 1..]),
+                None => mf.labels.contains_key(l.as_str()),
+            };
+            if !found {
+                return false;
+            }
+        }
+        if let Some(d) = &self.dependency {
+            if !mf.dependencies.iter().any(|x| x.name == *d) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Filter and paginate manifests
+///
+/// Manifests are expected in name order (as returned by the State).
+pub fn search(
+    mfs: impl IntoIterator<Item = Manifest>,
+    owners: &Owners,
+    query: &SearchQuery,
+) -> SearchResults {
+    let query = query.without_blanks();
+    let per_page = query
+        .per_page
+        .unwrap_or(DEFAULT_PER_PAGE)
+        .max(1)
+        .min(MAX_PER_PAGE);
+    let page = query.page.unwrap_or(1).max(1);
+
+    let hits = mfs
+        .into_iter()
+        .filter_map(|mf| {
+            let team = mf.metadata.as_ref().map(|m| m.team.clone()).unwrap_or_default();
+            let squad = squad_for(owners, &team);
+            let tribe = squad.as_ref().and_then(|s| tribe_for(owners, s));
+            if !query.matches(&mf, squad.as_ref(), tribe.as_ref()) {
+                return None;
+            }
+            Some(SearchHit {
+                description: mf.metadata.as_ref().and_then(|m| m.description.clone()),
+                dependencies: mf.dependencies.iter().map(|d| d.name.clone()).collect(),
+                kongHosts: mf.kongApis.iter().flat_map(|k| k.hosts.clone()).collect(),
+                version: mf.version,
+                name: mf.name,
+                team,
+                squad,
+                tribe,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let total = hits.len();
+    let pages = (total + per_page - 1) / per_page;
+    let results = hits
+        .into_iter()
+        .skip((page - 1) * per_page)
+        .take(per_page)
+        .collect();
+    SearchResults {
+        total,
+        page,
+        perPage: per_page,
+        pages,
+        results,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{search, SearchQuery, MAX_PER_PAGE};
+    use shipcat_definitions::{teams::Owners, Manifest};
+
+    fn manifest(name: &str) -> Manifest {
+        serde_json::from_value(serde_json::json!({
+            "name": name,
+            "metadata": { "repo": "https://github.com/babylonhealth/x", "team": "devops" },
+        }))
+        .unwrap()
+    }
+
+    fn query(f: impl FnOnce(&mut SearchQuery)) -> SearchQuery {
+        let mut q = SearchQuery::default();
+        f(&mut q);
+        q
+    }
+
+    #[test]
+    fn search_filters() {
+        let mut mf = manifest("webapp");
+        mf.env.plain.insert("LOG_LEVEL".into(), "info".into());
+        mf.env.secrets.insert("DB_PASSWORD".into());
+        mf.image = Some("quay.io/babylonhealth/webapp".into());
+        mf.labels.insert("tier".into(), "frontend".into());
+        mf.labels.insert("pci".into(), "".into());
+
+        let matches = |q: SearchQuery| q.matches(&mf, None, None);
+        assert!(matches(SearchQuery::default()));
+
+        assert!(matches(query(|q| q.env = Some("LOG_LEVEL".into()))));
+        assert!(matches(query(|q| q.env = Some("DB_PASSWORD".into()))));
+        assert!(!matches(query(|q| q.env = Some("LOG".into()))));
+
+        assert!(matches(query(|q| q.label = Some("tier".into()))));
+        assert!(matches(query(|q| q.label = Some("tier=frontend".into()))));
+        assert!(matches(query(|q| q.label = Some("pci=".into()))));
+        assert!(!matches(query(|q| q.label = Some("tier=backend".into()))));
+        assert!(!matches(query(|q| q.label = Some("frontend".into()))));
+
+        assert!(matches(query(|q| q.image = Some("BabylonHealth/web".into()))));
+        assert!(!matches(query(|q| q.image = Some("docker.io".into()))));
+        let mut no_image = mf.clone();
+        no_image.image = None;
+        assert!(!query(|q| q.image = Some("webapp".into())).matches(&no_image, None, None));
+
+        // filters combine
+        let both = query(|q| {
+            q.env = Some("LOG_LEVEL".into());
+            q.label = Some("tier=backend".into());
+        });
+        assert!(!matches(both));
+    }
+
+    #[test]
+    fn search_pagination() {
+        let mfs = (0..30)
+            .map(|i| manifest(&format!("svc-{:02}", i)))
+            .collect::<Vec<_>>();
+        let owners = Owners::default();
+
+        let res = search(mfs.clone(), &owners, &SearchQuery::default());
+        assert_eq!((res.total, res.page, res.perPage, res.pages), (30, 1, 25, 2));
+        assert_eq!(res.results.len(), 25);
+
+        let res = search(mfs.clone(), &owners, &query(|q| q.page = Some(2)));
+        assert_eq!(res.results.len(), 5);
+        assert_eq!(res.results[0].name, "svc-25");
+
+        // out of range pages are empty, and page 0 is the first page
+        let res = search(mfs.clone(), &owners, &query(|q| q.page = Some(3)));
+        assert!(res.results.is_empty());
+        let res = search(mfs.clone(), &owners, &query(|q| q.page = Some(0)));
+        assert_eq!((res.page, res.results[0].name.as_str()), (1, "svc-00"));
+
+        // per page is bounded
+        let res = search(mfs.clone(), &owners, &query(|q| q.per_page = Some(0)));
+        assert_eq!((res.perPage, res.pages), (1, 30));
+        let res = search(mfs.clone(), &owners, &query(|q| q.per_page = Some(10_000)));
+        assert_eq!((res.perPage, res.pages), (MAX_PER_PAGE, 1));
+
+        let res = search(vec![], &owners, &SearchQuery::default());
+        assert_eq!((res.total, res.pages), (0, 0));
+    }
+}
@@ -1,3 +1,4 @@
+use chrono::Utc;
 use failure::err_msg;
 use kube::{
     api::{ListParams, Meta, Resource},
@@ -5,26 +6,35 @@ use kube::{
     config::Configuration,
     runtime::Reflector,
 };
-use shipcat_definitions::{ShipcatConfig, ShipcatManifest};
+use shipcat_definitions::{region::IntegrationsConfig, ShipcatConfig, ShipcatManifest};
 use tera::compile_templates;
 
 use std::{
     collections::BTreeMap,
     env,
     sync::{Arc, RwLock},
+    time::Duration,
 };
+use tokio::{sync::broadcast, time::delay_for};
 
 use crate::{
-    integrations::{
-        newrelic::{self, RelicMap},
-        sentryapi::{self, SentryMap},
-    },
+    auth::Principal,
+    depgraph::{self, DepGraph},
+    federation::{self, FederatedService, RegionalSummary},
+    graphql::GraphContext,
+    integrations::{IntegrationHealth, Integrations},
+    metrics::Metrics,
+    rollouts::{self, RolloutRow},
+    search::{self, SearchQuery, SearchResults},
     *,
 };
 
 /// Map of service -> versions
 pub type VersionMap = BTreeMap<String, String>;
 
+/// Max seconds between reflector polls (bounds the latency of rollout events)
+const POLL_TIMEOUT_SECS: u32 = 30;
+
 /// The canonical shared state for actix
 ///
 /// Consumers of these (http handlers) should use public impls on this struct only.
@@ -34,10 +44,15 @@ pub type VersionMap = BTreeMap<String, String>;
 pub struct State {
     manifests: Reflector<ShipcatManifest>,
     configs: Reflector<ShipcatConfig>,
-    relics: RelicMap,
-    sentries: SentryMap,
+    /// Manifests by name, rebuilt after every poll and shared by GraphQL queries
+    manifest_cache: Arc<RwLock<Arc<BTreeMap<String, Manifest>>>>,
+    /// External links for services
+    integrations: Integrations,
     /// Templates via tera which do not implement clone
     template: Arc<RwLock<tera::Tera>>,
+    /// Serialized rollout overviews sent whenever they change
+    rollouts: broadcast::Sender<String>,
+    metrics: Metrics,
     region: String,
     config_name: String,
 }
@@ -61,9 +76,8 @@ impl State {
         let cfgresource = Resource::namespaced::<ShipcatConfig>(&ns);
 
         let lp = ListParams::default();
-        let manifests = Reflector::new(client.clone(), lp.clone(), mfresource)
-            .init()
-            .await?;
+        let mflp = lp.clone().timeout(POLL_TIMEOUT_SECS);
+        let manifests = Reflector::new(client.clone(), mflp, mfresource).init().await?;
         let configs = Reflector::new(client, lp, cfgresource).init().await?;
         // Use federated config if available:
         let is_federated = configs
@@ -79,13 +93,20 @@ impl State {
         let mut res = State {
             manifests,
             configs,
+            manifest_cache: Arc::new(RwLock::new(Arc::new(BTreeMap::new()))),
             region,
             config_name,
-            relics: BTreeMap::new(),
-            sentries: BTreeMap::new(),
+            integrations: Integrations::new(),
             template: Arc::new(RwLock::new(t)),
+            rollouts: broadcast::channel(16).0,
+            metrics: Metrics::new()?,
         };
-        res.update_slow_cache().await?;
+        let now = Utc::now();
+        res.metrics.reflector_polled("shipcatmanifests", now);
+        res.metrics.reflector_polled("shipcatconfigs", now);
+        res.refresh_manifest_cache().await?;
+        res.integrations = Integrations::for_region(&res.get_region().await?);
+        res.refresh_integrations().await?;
         Ok(res)
     }
 
@@ -169,12 +190,93 @@ impl State {
         Ok(res)
     }
 
-    pub fn get_newrelic_link(&self, service: &str) -> Option<String> {
-        self.relics.get(service).map(String::to_owned)
+    /// Search the manifests a caller can view
+    ///
+    /// Filters like env and image reveal manifest contents, so other manifests are never matched.
+    pub async fn search_manifests(
+        &self,
+        query: &SearchQuery,
+        principal: &Principal,
+    ) -> Result<SearchResults> {
+        let cfg = self.get_config().await?;
+        let mfs = self.manifest_cache.read().unwrap().clone();
+        let visible = mfs.values().filter(|mf| principal.can_view(mf, &cfg.owners));
+        Ok(search::search(visible.cloned(), &cfg.owners, query))
+    }
+
+    pub async fn get_rollouts(&self) -> Result<Vec<RolloutRow>> {
+        let crds = self.manifests.state().await?;
+        Ok(rollouts::overview(&crds, Utc::now()))
+    }
+
+    /// Subscribe to serialized rollout overviews
+    ///
+    /// A new overview is sent after a reflector poll if anything changed.
+    pub fn subscribe_rollouts(&self) -> broadcast::Receiver<String> {
+        self.rollouts.subscribe()
+    }
+
+    /// Dependency graph around a service, `up` hops of dependents and `down` hops of dependencies
+    pub async fn get_dependency_graph(
+        &self,
+        service: &str,
+        up: usize,
+        down: usize,
+    ) -> Result<Option<DepGraph>> {
+        let crds = self.manifests.state().await?;
+        Ok(depgraph::build(&crds, service, up, down, Utc::now()))
+    }
+
+    /// Summary of a service in this region
+    pub async fn get_summary(&self, service: &str) -> Result<Option<RegionalSummary>> {
+        let crd = self.get_manifest(service).await?;
+        Ok(crd.map(|crd| RegionalSummary::new(&crd, &self.region, Utc::now())))
+    }
+
+    /// A service across the federatedRegions of the config
+    ///
+    /// Empty when the config has no federatedRegions.
+    pub async fn get_federated(&self, service: &str) -> Result<Vec<FederatedService>> {
+        let cfg = self.get_config().await?;
+        let regions = cfg
+            .federatedRegions
+            .iter()
+            .filter_map(|name| cfg.get_regions().into_iter().find(|r| &r.name == name))
+            .collect::<Vec<_>>();
+        if regions.is_empty() {
+            return Ok(vec![]);
+        }
+        let local = self.get_summary(service).await?;
+        federation::federate(regions, &self.region, local, service).await
+    }
+
+    /// Snapshot of the caches for a GraphQL query
+    pub async fn graphql_context(&self) -> Result<GraphContext> {
+        let manifests = self.manifest_cache.read().unwrap().clone();
+        Ok(GraphContext::new(
+            manifests,
+            Arc::new(self.get_config().await?),
+            self.get_region().await?,
+            self.integrations.clone(),
+        ))
+    }
+
+    /// Handle for recording request metrics
+    pub fn metrics(&self) -> Metrics {
+        self.metrics.clone()
     }
 
-    pub fn get_sentry_slug(&self, service: &str) -> Option<String> {
-        self.sentries.get(service).map(String::to_owned)
+    /// All metrics in the prometheus text format
+    pub fn render_metrics(&self) -> Result<String> {
+        self.metrics.render(Utc::now())
+    }
+
+    pub fn get_integrations(&self) -> Integrations {
+        self.integrations.clone()
+    }
+
+    pub fn get_integration_health(&self) -> Vec<IntegrationHealth> {
+        self.integrations.health()
     }
 
     // Interface for internal thread
@@ -183,11 +285,30 @@ impl State {
         // Make sure we always keep polling the reflectors.
         // If any of them fail, boot to let kubernete's backoff to hopefully fix it
         tokio::spawn(async move {
+            let mut last = String::new();
             loop {
                 if let Err(e) = c.manifests.poll().await {
                     error!("Kube state failed to recover: {}", e);
                     std::process::exit(1);
                 }
+                c.metrics.reflector_polled("shipcatmanifests", Utc::now());
+                if let Err(e) = c.refresh_manifest_cache().await {
+                    warn!("Failed to refresh manifest cache: {}", e);
+                }
+                let rollouts = c.get_rollouts().await;
+                if let Ok(rows) = &rollouts {
+                    c.metrics.set_rollouts(rows);
+                }
+                match rollouts.map(|r| serde_json::to_string(&r)) {
+                    Ok(Ok(data)) if data != last => {
+                        // no receivers is fine; nobody is looking at the dashboard
+                        let _ = c.rollouts.send(data.clone());
+                        last = data;
+                    }
+                    Ok(Ok(_)) => {}
+                    Ok(Err(e)) => warn!("Failed to serialize rollouts: {}", e),
+                    Err(e) => warn!("Failed to compute rollouts: {}", e),
+                }
             }
         });
         let c2 = self.clone();
@@ -197,30 +318,39 @@ impl State {
                     error!("Kube state failed to recover: {}", e);
                     std::process::exit(1);
                 }
+                c2.metrics.reflector_polled("shipcatconfigs", Utc::now());
+            }
+        });
+        let c3 = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let secs = match c3.get_region().await {
+                    Ok(r) => r.integrations.refreshSeconds,
+                    Err(e) => {
+                        warn!("Failed to get region for integration refresh: {}", e);
+                        IntegrationsConfig::default().refreshSeconds
+                    }
+                };
+                delay_for(Duration::from_secs(secs)).await;
+                if let Err(e) = c3.refresh_integrations().await {
+                    warn!("Failed to refresh integrations: {}", e);
+                }
             }
         });
         Ok(())
     }
 
-    async fn update_slow_cache(&mut self) -> Result<()> {
+    async fn refresh_manifest_cache(&self) -> Result<()> {
+        let mfs = self.get_manifests().await?;
+        *self.manifest_cache.write().unwrap() = Arc::new(mfs);
+        Ok(())
+    }
+
+    async fn refresh_integrations(&self) -> Result<()> {
         let region = self.get_region().await?;
-        if let Some(s) = region.sentry {
-            match sentryapi::get_slugs(&s.url, &region.environment.to_string()).await {
-                Ok(res) => {
-                    self.sentries = res;
-                    info!("Loaded {} sentry slugs", self.sentries.len());
-                }
-                Err(e) => warn!("Unable to load sentry slugs: {}", err_msg(e)),
-            }
-        } else {
-            warn!("No sentry url configured for this region");
-        }
-        match newrelic::get_links(&region.name).await {
-            Ok(res) => {
-                self.relics = res;
-                info!("Loaded {} newrelic links", self.relics.len());
-            }
-            Err(e) => warn!("Unable to load newrelic projects. {}", err_msg(e)),
+        for h in self.integrations.refresh(&region).await {
+            let entries = if h.healthy { Some(h.entries) } else { None };
+            self.metrics.cache_refreshed(&h.name, entries);
         }
         Ok(())
     }
@@ -0,0 +1,199 @@
+use actix_web::http::{header::AUTHORIZATION, HeaderMap, HeaderValue};
+use jsonwebtoken::{encode, EncodingKey, Header};
+use raftcat::{
+    auth::{
+        required_scope, ApiToken, Authenticator, Jwks, KompassProvider, OidcConfig, OidcProvider, Scope,
+        TokenProvider,
+    },
+    Manifest, Owners,
+};
+use serde_json::json;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const ISSUER: &str = "https://login.example.com/";
+const AUDIENCE: &str = "raftcat";
+
+fn oidc() -> OidcProvider {
+    let provider = OidcProvider::new(OidcConfig {
+        issuer: ISSUER.into(),
+        audience: AUDIENCE.into(),
+        jwks_url: "http://localhost/unused".into(),
+        admin_groups: vec!["platform-admins".into()],
+    });
+    let jwks: Jwks = serde_json::from_str(include_str!("fixtures/jwks.json")).unwrap();
+    provider.set_jwks(jwks);
+    provider
+}
+
+fn sign(claims: serde_json::Value, kid: &str) -> String {
+    let key = EncodingKey::from_rsa_pem(include_bytes!("fixtures/oidc.key")).unwrap();
+    let mut header = Header::new(jsonwebtoken::Algorithm::RS256);
+    header.kid = Some(kid.into());
+    encode(&header, &claims, &key).unwrap()
+}
+
+fn claims(extra: serde_json::Value) -> serde_json::Value {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    let mut base = json!({
+        "iss": ISSUER,
+        "aud": AUDIENCE,
+        "sub": "00u1abcd",
+        "exp": now + 300,
+    });
+    base.as_object_mut()
+        .unwrap()
+        .extend(extra.as_object().unwrap().clone());
+    base
+}
+
+fn headers(auth: &str) -> HeaderMap {
+    let mut h = HeaderMap::new();
+    h.insert(AUTHORIZATION, HeaderValue::from_str(auth).unwrap());
+    h
+}
+
+fn owners() -> Owners {
+    serde_json::from_value(json!({
+        "people": {
+            "clux": { "name": "clux", "slack": "U1234ABCD", "email": "clux@example.com" },
+        },
+        "squads": {
+            "devops": {
+                "name": "devops",
+                "members": ["clux"],
+                "github": { "team": "devops-team" },
+                "slack": {},
+            },
+            "payments": {
+                "name": "payments",
+                "members": [],
+                "github": { "team": "payments-team" },
+                "slack": {},
+            },
+        },
+        "tribes": {},
+    }))
+    .unwrap()
+}
+
+fn manifest(team: &str) -> Manifest {
+    serde_json::from_value(json!({
+        "name": format!("{}-api", team),
+        "metadata": { "repo": "https://github.com/babylonhealth/x", "team": team },
+    }))
+    .unwrap()
+}
+
+#[test]
+fn oidc_token_validation() {
+    let authn = Authenticator::new().provider(oidc()).required(true);
+
+    let token = sign(claims(json!({ "email": "clux@example.com" })), "raftcat-test");
+    let p = authn
+        .authenticate(&headers(&format!("Bearer {}", token)))
+        .unwrap();
+    assert_eq!(p.name, "00u1abcd");
+    assert_eq!(p.provider, "oidc");
+    assert!(p.has_scope(Scope::Read));
+    assert!(!p.has_scope(Scope::Manifests));
+
+    let admin = sign(claims(json!({ "groups": ["platform-admins"] })), "raftcat-test");
+    let p = authn
+        .authenticate(&headers(&format!("Bearer {}", admin)))
+        .unwrap();
+    assert!(p.has_scope(Scope::Manifests));
+
+    let expired = sign(claims(json!({ "exp": 1_500_000_000 })), "raftcat-test");
+    assert!(authn
+        .authenticate(&headers(&format!("Bearer {}", expired)))
+        .is_err());
+
+    let wrong_aud = sign(claims(json!({ "aud": "kompass" })), "raftcat-test");
+    assert!(authn
+        .authenticate(&headers(&format!("Bearer {}", wrong_aud)))
+        .is_err());
+
+    let unknown_kid = sign(claims(json!({})), "rotated-away");
+    assert!(authn
+        .authenticate(&headers(&format!("Bearer {}", unknown_kid)))
+        .is_err());
+
+    assert!(authn.authenticate(&HeaderMap::new()).is_err());
+}
+
+#[test]
+fn static_tokens_and_kompass() {
+    let tokens = TokenProvider::new(vec![ApiToken {
+        name: "backstage".into(),
+        token: "s3cr3t".into(),
+        scopes: vec![Scope::Read].into_iter().collect(),
+        squads: vec!["payments".into()],
+    }]);
+    let authn = Authenticator::new()
+        .provider(KompassProvider::new("k0mpass".into()))
+        .provider(tokens)
+        .provider(oidc())
+        .required(true);
+
+    let p = authn.authenticate(&headers("Bearer s3cr3t")).unwrap();
+    assert_eq!(p.name, "backstage");
+    assert_eq!(p.squads, vec!["payments".to_string()]);
+    assert!(!p.has_scope(Scope::Kompass));
+    assert!(authn.authenticate(&headers("Bearer nope")).is_err());
+
+    let basic = format!("Basic {}", base64::encode("kompass:k0mpass"));
+    let p = authn.authenticate(&headers(&basic)).unwrap();
+    assert!(p.has_scope(Scope::Kompass));
+    assert!(!p.has_scope(Scope::Read));
+    let wrong = format!("Basic {}", base64::encode("kompass:guess"));
+    assert!(authn.authenticate(&headers(&wrong)).is_err());
+
+    assert_eq!(required_scope("/raftcat/kompass-hub"), Some(Scope::Kompass));
+    assert_eq!(required_scope("/raftcat/manifests"), Some(Scope::Read));
+    assert_eq!(required_scope("/raftcat/health"), None);
+}
+
+#[test]
+fn optional_auth_is_anonymous() {
+    let authn = Authenticator::new().provider(KompassProvider::new("k0mpass".into()));
+    let p = authn.authenticate(&HeaderMap::new()).unwrap();
+    assert!(p.has_scope(Scope::Manifests));
+    assert!(!p.has_scope(Scope::Kompass));
+    // credentials meant for something else are ignored
+    let p = authn.authenticate(&headers("Bearer for-the-proxy")).unwrap();
+    assert_eq!(p.provider, "none");
+    let basic = format!("Basic {}", base64::encode("kompass:k0mpass"));
+    assert!(authn
+        .authenticate(&headers(&basic))
+        .unwrap()
+        .has_scope(Scope::Kompass));
+}
+
+#[test]
+fn team_visibility() {
+    let owners = owners();
+    let authn = Authenticator::new().provider(oidc()).required(true);
+    let (devops, payments) = (manifest("devops"), manifest("payments"));
+
+    // by squad membership of the person with the token's email
+    let token = sign(claims(json!({ "email": "clux@example.com" })), "raftcat-test");
+    let p = authn
+        .authenticate(&headers(&format!("Bearer {}", token)))
+        .unwrap();
+    assert!(p.can_view(&devops, &owners));
+    assert!(!p.can_view(&payments, &owners));
+
+    // by github team groups
+    let token = sign(claims(json!({ "groups": ["payments-team"] })), "raftcat-test");
+    let p = authn
+        .authenticate(&headers(&format!("Bearer {}", token)))
+        .unwrap();
+    assert!(!p.can_view(&devops, &owners));
+    assert!(p.can_view(&payments, &owners));
+
+    let admin = sign(claims(json!({ "groups": ["platform-admins"] })), "raftcat-test");
+    let p = authn
+        .authenticate(&headers(&format!("Bearer {}", admin)))
+        .unwrap();
+    assert!(p.can_view(&devops, &owners) && p.can_view(&payments, &owners));
+}
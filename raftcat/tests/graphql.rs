@@ -0,0 +1,87 @@
+use juniper::http::GraphQLRequest;
+use raftcat::{
+    graphql::{schema, schema_language, GraphContext, MAX_DEPTH},
+    integrations::Integrations,
+};
+use serde_json::json;
+use std::{collections::BTreeMap, env, fs, sync::Arc};
+
+fn context() -> GraphContext {
+    let region = serde_json::from_value(json!({
+        "name": "dev-uk",
+        "namespace": "dev",
+        "environment": "dev",
+        "cluster": "kind-shipcat",
+        "versioningScheme": "GitShaOrSemver",
+        "vault": { "url": "http://localhost:8200", "folder": "dev-uk" },
+    }))
+    .unwrap();
+    let config = serde_json::from_value(json!({
+        "defaults": {},
+        "clusters": {},
+        "regions": [],
+        "slack": { "team": "T1234ABCD" },
+        "github": { "organisation": "babylonhealth" },
+        "versions": {},
+    }))
+    .unwrap();
+    GraphContext::new(
+        Arc::new(BTreeMap::new()),
+        Arc::new(config),
+        region,
+        Integrations::new(),
+    )
+}
+
+#[test]
+fn graphql_schema_snapshot() {
+    let sdl = schema_language(&schema(), &context()).unwrap();
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/schema.graphql");
+    // UPDATE_SNAPSHOTS=1 cargo test -p raftcat to accept schema changes
+    if env::var("UPDATE_SNAPSHOTS").is_ok() {
+        fs::write(path, &sdl).unwrap();
+    }
+    let snapshot = fs::read_to_string(path).unwrap();
+    assert_eq!(
+        sdl, snapshot,
+        "schema changed, update tests/schema.graphql if intended"
+    );
+}
+
+fn manifest(name: &str, deps: &[&str]) -> (String, raftcat::Manifest) {
+    let deps = deps.iter().map(|d| json!({ "name": d })).collect::<Vec<_>>();
+    let mf = serde_json::from_value(json!({
+        "name": name,
+        "metadata": { "repo": "https://github.com/babylonhealth/x", "team": "devops" },
+        "dependencies": deps,
+    }))
+    .unwrap();
+    (name.to_string(), mf)
+}
+
+#[test]
+fn graphql_depth_limit() {
+    let base = context();
+    let manifests = vec![manifest("webapp", &["auth"]), manifest("auth", &["webapp"])];
+    let ctx = GraphContext::new(
+        Arc::new(manifests.into_iter().collect()),
+        base.config.clone(),
+        base.region.clone(),
+        Integrations::new(),
+    );
+    // webapp -> auth -> webapp -> ... following dependencies `levels` times
+    let query = |levels: usize| {
+        let nested = (0..levels).fold("name".to_string(), |acc, _| format!("dependencies {{ {} }}", acc));
+        GraphQLRequest::new(
+            format!("{{ manifest(name: \"webapp\") {{ {} }} }}", nested),
+            None,
+            None,
+        )
+    };
+    let schema = schema();
+    let (deepest, too_deep) = (query(MAX_DEPTH), query(MAX_DEPTH + 1));
+    assert!(deepest.execute(&schema, &ctx).is_ok());
+    let res = serde_json::to_value(too_deep.execute(&schema, &ctx)).unwrap();
+    let err = res["errors"][0]["message"].as_str().unwrap();
+    assert!(err.contains("deeper than"), "unexpected error {}", err);
+}
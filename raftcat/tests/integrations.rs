@@ -0,0 +1,122 @@
+use async_trait::async_trait;
+use raftcat::{
+    integrations::{Integration, Integrations},
+    Manifest, Region, Result,
+};
+use serde_json::json;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+fn region(extra: serde_json::Value) -> Region {
+    let mut base = json!({
+        "name": "dev-uk",
+        "namespace": "dev",
+        "environment": "dev",
+        "cluster": "kind-shipcat",
+        "versioningScheme": "GitShaOrSemver",
+        "vault": { "url": "http://localhost:8200", "folder": "dev-uk" },
+    });
+    base.as_object_mut()
+        .unwrap()
+        .extend(extra.as_object().unwrap().clone());
+    serde_json::from_value(base).unwrap()
+}
+
+fn manifest(runbook: Option<&str>) -> Manifest {
+    serde_json::from_value(json!({
+        "name": "webapp",
+        "metadata": {
+            "repo": "https://github.com/babylonhealth/webapp",
+            "team": "devops",
+            "runbook": runbook,
+        },
+    }))
+    .unwrap()
+}
+
+/// Provider whose api can be made to fail
+#[derive(Default)]
+struct Flaky {
+    down: AtomicBool,
+}
+
+#[async_trait]
+impl Integration for Flaky {
+    fn name(&self) -> &'static str {
+        "flaky"
+    }
+
+    fn title(&self) -> &'static str {
+        "Flaky"
+    }
+
+    fn configured(&self, _: &Region) -> bool {
+        true
+    }
+
+    fn refreshes(&self) -> bool {
+        true
+    }
+
+    async fn refresh(&self, _: &Region) -> Result<usize> {
+        if self.down.swap(true, Ordering::SeqCst) {
+            Err(failure::format_err!("503 Service Unavailable"))
+        } else {
+            Ok(3)
+        }
+    }
+
+    fn link(&self, mf: &Manifest, _: &Region) -> Option<String> {
+        Some(format!("https://flaky.example.com/{}", mf.name))
+    }
+}
+
+#[test]
+fn region_integrations() {
+    let r = region(json!({
+        "grafana": { "url": "https://grafana.example.com/", "services_dashboard_id": "abc" },
+        "logzio": { "url": "https://app-eu.logz.io", "account_id": "1337" },
+        "pagerduty": { "url": "https://example.pagerduty.com" },
+        "integrations": { "providers": ["grafana", "runbook", "sentry"] },
+    }));
+    let integrations = Integrations::for_region(&r);
+    let names = integrations
+        .health()
+        .into_iter()
+        .map(|h| h.name)
+        .collect::<Vec<_>>();
+    // sentry is enabled but not configured, logzio and pagerduty are configured but not enabled
+    assert_eq!(names, vec!["grafana".to_string(), "runbook".to_string()]);
+
+    let links = integrations.links(&manifest(Some("docs/runbook.md")), &r);
+    assert_eq!(links.len(), 2);
+    assert_eq!(links[0].provider, "grafana");
+    assert_eq!(links[0].icon.as_deref(), Some("grafana.png"));
+    assert!(links[0].url.starts_with("https://grafana.example.com/d/abc/"));
+    assert_eq!(
+        links[1].url,
+        "https://github.com/babylonhealth/webapp/blob/master/docs/runbook.md"
+    );
+    assert!(integrations.link("runbook", &manifest(None), &r).is_none());
+    assert!(integrations.link("logzio", &manifest(None), &r).is_none());
+}
+
+#[tokio::test]
+async fn integration_refresh_health() {
+    let r = region(json!({}));
+    let integrations = Integrations::new().provider(Flaky::default());
+    assert!(!integrations.health()[0].healthy);
+
+    let refreshed = integrations.refresh(&r).await;
+    assert_eq!(refreshed.len(), 1);
+    assert!(refreshed[0].healthy && refreshed[0].lastRefresh.is_some());
+    assert_eq!(refreshed[0].entries, 3);
+
+    // failures keep the last refresh and entries
+    let refreshed = integrations.refresh(&r).await;
+    let h = &integrations.health()[0];
+    assert_eq!(refreshed[0].name, h.name);
+    assert!(!h.healthy);
+    assert_eq!(h.entries, 3);
+    assert_eq!(h.lastError.as_deref(), Some("503 Service Unavailable"));
+    assert!(integrations.link("flaky", &manifest(None), &r).is_some());
+}
@@ -3,15 +3,19 @@ use tokio::fs;
 
 use crate::{
     diff, helm,
+    history::HistoryAction,
+    hooks::{self, HookStage},
     kubeapi::ShipKube,
-    kubectl, track,
+    kubectl, slack, smoke, track,
     webhooks::{self, UpgradeState},
 };
 use serde_json::json;
 
+use chrono::Utc;
 use shipcat_definitions::{
+    freeze::FreezeOverride,
     status::{make_date, Condition},
-    structs::{Metadata, NotificationMode},
+    structs::{statefulset::StatefulUpdateStrategy, Metadata, NotificationMode},
     Config, Manifest, PrimaryWorkload, ReconciliationMode, Region,
 };
 
@@ -37,6 +41,14 @@ pub struct UpgradeInfo {
     pub namespace: String,
     /// Computed diff string (if available)
     pub diff: Option<String>,
+    /// Reason for the upgrade (once determined)
+    pub reason: Option<String>,
+    /// Slack threads tracking this upgrade (Web API mode only)
+    pub slack: Vec<slack::Thread>,
+    /// Deploy freeze this upgrade was allowed through
+    pub freeze_override: Option<FreezeOverride>,
+    /// How this upgrade is recorded in the service history
+    pub action: HistoryAction,
 }
 
 impl UpgradeInfo {
@@ -58,6 +70,10 @@ impl UpgradeInfo {
             region: mf.region.clone(),
             namespace: mf.namespace.clone(),
             diff: None,
+            reason: None,
+            slack: vec![],
+            freeze_override: None,
+            action: HistoryAction::Deployment,
         }
     }
 }
@@ -89,9 +105,71 @@ pub async fn apply(
     conf: &Config,
     wait: bool,
     passed_version: Option<String>,
+    freeze_override: Option<String>,
 ) -> Result<Option<UpgradeInfo>> {
     match region.reconciliationMode {
-        ReconciliationMode::CrdOwned => apply_kubectl(&svc, force, region, conf, wait, passed_version).await,
+        ReconciliationMode::CrdOwned => {
+            let action = HistoryAction::Deployment;
+            apply_kubectl(
+                &svc,
+                force,
+                region,
+                conf,
+                wait,
+                passed_version,
+                freeze_override,
+                action,
+            )
+            .await
+        }
+    }
+}
+
+/// shipcat apply as part of a cluster reconcile
+///
+/// Always waits for the rollout of the version in the manifests,
+/// and is recorded as a reconciliation in the service history.
+pub async fn reconcile(
+    svc: String,
+    force: bool,
+    region: &Region,
+    conf: &Config,
+    freeze_override: Option<String>,
+) -> Result<Option<UpgradeInfo>> {
+    match region.reconciliationMode {
+        ReconciliationMode::CrdOwned => {
+            let action = HistoryAction::Reconciliation;
+            apply_kubectl(&svc, force, region, conf, true, None, freeze_override, action).await
+        }
+    }
+}
+
+/// Refuse upgrades during a deploy freeze unless overridden with a reason
+///
+/// Returns the override that was used (if any) so that it can be recorded.
+pub fn check_freeze(
+    conf: &Config,
+    region: &Region,
+    override_reason: Option<&str>,
+) -> Result<Option<FreezeOverride>> {
+    let freeze = match conf.active_freeze(region, Utc::now())? {
+        Some(f) => f,
+        None => return Ok(None),
+    };
+    match override_reason.map(str::trim) {
+        Some(reason) if !reason.is_empty() => {
+            warn!("Overriding freeze {} in {}: {}", freeze.name, region.name, reason);
+            Ok(Some(FreezeOverride {
+                freeze: freeze.name.clone(),
+                reason: reason.to_string(),
+            }))
+        }
+        _ => Err(ErrorKind::DeployFreeze(
+            region.name.clone(),
+            freeze.name.clone(),
+            freeze.reason.clone().unwrap_or_else(|| "no reason given".into()),
+        )
+        .into()),
     }
 }
 
@@ -123,7 +201,7 @@ impl ToString for UpgradeReason {
 /// First version of apply that does not use tiller
 ///
 /// This writes events to uses the shipcatmanifest crd
-#[allow(clippy::cognitive_complexity)] // TODO: refactor this!
+#[allow(clippy::cognitive_complexity, clippy::too_many_arguments)] // TODO: refactor this!
 async fn apply_kubectl(
     svc: &str,
     force: bool,
@@ -131,6 +209,8 @@ async fn apply_kubectl(
     conf: &Config,
     wait: bool,
     passed_version: Option<String>,
+    freeze_override: Option<String>,
+    action: HistoryAction,
 ) -> Result<Option<UpgradeInfo>> {
     if let Err(e) = webhooks::ensure_requirements(&region) {
         warn!("Could not ensure webhook requirements: {}", e);
@@ -202,21 +282,32 @@ async fn apply_kubectl(
     // no shoehorning in illegal versions in the crd!
     region.versioningScheme.verify(&actual_version)?;
 
+    // A deploy freeze only refuses actual upgrades, so it is enforced once the diff is known.
+    // Until then nothing is changed (not even the crd) and no webhooks fire,
+    // and new services are always refused.
+    let freeze = match check_freeze(conf, region, freeze_override.as_deref()) {
+        Err(e) if !can_diff => return Err(e),
+        f => f,
+    };
+    let frozen = freeze.is_err();
+
     // Complete and apply the CRD
     let mfcrd = mfbase.version(actual_version.clone());
-    let crd_changed = s.apply(mfcrd.clone()).await?;
-    // Cheap reconcile ends here if !changed && !force
-    if crd_changed {
+    if !frozen && s.apply(mfcrd.clone()).await? {
         reason = reason.or(Some(UpgradeReason::ManifestChange));
     }
-    if reason.is_none() && !force {
+    // Cheap reconcile ends here if !changed && !force (only the full diff can tell when frozen)
+    if reason.is_none() && !force && !frozen {
         info!("{} up to date (crd check)", svc);
         return Ok(None);
     }
 
     // Prepare for an actual upgrade now..
     let mut ui = UpgradeInfo::new(&mfcrd);
-    webhooks::apply_event(UpgradeState::Pending, &ui, &region, &conf).await;
+    ui.action = action;
+    if !frozen {
+        webhooks::apply_event(UpgradeState::Pending, &mut ui, &region, &conf, &s).await;
+    }
 
     // Fetch all the secrets so we can create a completed manifest
     // TODO: check scp.status.secretChecksum against secret-manager instead
@@ -224,7 +315,9 @@ async fn apply_kubectl(
         Ok(m) => m,
         Err(e) => {
             // Fire failed events if secrets fail to resolve
-            webhooks::apply_event(UpgradeState::Failed, &ui, &region, &conf).await;
+            if !frozen {
+                webhooks::apply_event(UpgradeState::Failed, &mut ui, &region, &conf, &s).await;
+            }
             s.update_generate_false("SecretFailure", e.description().to_string())
                 .await?;
             return Err(e.into());
@@ -240,7 +333,9 @@ async fn apply_kubectl(
             Err(e) => {
                 debug!("{:?}", e);
                 // Fire failed events if crd could not be fetched after its creation
-                webhooks::apply_event(UpgradeState::Failed, &ui, &region, &conf).await;
+                if !frozen {
+                    webhooks::apply_event(UpgradeState::Failed, &mut ui, &region, &conf, &s).await;
+                }
                 s.update_generate_false("CrdFailure", e.description().to_string())
                     .await?;
                 return Err(e);
@@ -253,7 +348,9 @@ async fn apply_kubectl(
     let tpth = Path::new(".").join(tfile.clone());
     if let Err(e) = helm::template(&mf, Some(tpth)).await {
         // Errors here are obscure, and should not happen, but pass them up anyway
-        webhooks::apply_event(UpgradeState::Failed, &ui, &region, &conf).await;
+        if !frozen {
+            webhooks::apply_event(UpgradeState::Failed, &mut ui, &region, &conf, &s).await;
+        }
         s.update_generate_false("ResolveFailure", e.description().to_string())
             .await?;
         return Err(e);
@@ -271,7 +368,9 @@ async fn apply_kubectl(
                 // If we explicitly received no diff, don't try to upgrade
                 // This is a stronger diff than CRD-only if this succeeds; STOP.
                 info!("{} up to date (full diff check)", svc);
-                webhooks::apply_event(UpgradeState::Cancelled, &ui, &region, &conf).await;
+                if !frozen {
+                    webhooks::apply_event(UpgradeState::Cancelled, &mut ui, &region, &conf, &s).await;
+                }
                 s.update_generate_true().await?; // every force reconcile makes one generate cond
                 return Ok(None);
             }
@@ -280,7 +379,9 @@ async fn apply_kubectl(
                 warn!("Unable to diff against {}: {}", svc, e);
                 if !force && reason.is_none() {
                     // pass on a diff failure
-                    webhooks::apply_event(UpgradeState::Cancelled, &ui, &region, &conf).await;
+                    if !frozen {
+                        webhooks::apply_event(UpgradeState::Cancelled, &mut ui, &region, &conf, &s).await;
+                    }
                     s.update_generate_false("DiffFailure", e.description().to_string())
                         .await?;
                     return Ok(None); // but ultimately ignore this in fast reconciles
@@ -292,13 +393,37 @@ async fn apply_kubectl(
 
     // We cannot be here without a reason now, although you have to convince yourself.
     let ureason = reason.expect("cannot apply without a reason");
-    webhooks::apply_event(UpgradeState::Started, &ui, &region, &conf).await;
+    ui.reason = Some(ureason.to_string());
+    let freeze = match freeze {
+        Ok(f) => f,
+        Err(e) => {
+            // a refused upgrade is announced like any other failure
+            ui.reason = Some(e.to_string());
+            webhooks::apply_event(UpgradeState::Pending, &mut ui, &region, &conf, &s).await;
+            webhooks::apply_event(UpgradeState::Failed, &mut ui, &region, &conf, &s).await;
+            return Err(e);
+        }
+    };
+    ui.freeze_override = freeze.clone();
+    webhooks::apply_event(UpgradeState::Started, &mut ui, &region, &conf, &s).await;
     s.update_generate_true().await?; // if this fails, stop, want .status to be correct
+    s.update_freeze_override(freeze.as_ref()).await?;
+
+    // Jobs like migrations that must succeed before we upgrade
+    if let Err(e) = hooks::run(&mf, &s, HookStage::PreApply).await {
+        error!("{} from {}", e, ui.name);
+        ui.reason = Some(e.to_string());
+        webhooks::apply_event(UpgradeState::Failed, &mut ui, &region, &conf, &s).await;
+        let reason = HookStage::PreApply.failure_reason();
+        s.update_apply_false(ureason.to_string(), reason, e.to_string())
+            .await?;
+        return Err(e);
+    }
 
     match upgrade_kubectl(&mf, &tfile).await {
         Err(e) => {
             error!("{} from {}", e, ui.name);
-            webhooks::apply_event(UpgradeState::Failed, &ui, &region, &conf).await;
+            webhooks::apply_event(UpgradeState::Failed, &mut ui, &region, &conf, &s).await;
             let reason = e.description().to_string();
             s.update_apply_false(ureason.to_string(), "ApplyFailure", reason)
                 .await?; // TODO: chain
@@ -307,12 +432,44 @@ async fn apply_kubectl(
         Ok(_) => {
             let _ = s.update_apply_true(ureason.to_string()).await;
             if !wait {
+                if !mf.hooks.postApply.is_empty() {
+                    warn!("skipping postApply hooks for {} (without waiting)", ui.name);
+                }
+                if mf.smokeTests.is_some() {
+                    warn!("skipping smoke tests for {} (without waiting)", ui.name);
+                }
                 info!("successfully applied {} (without waiting)", ui.name);
             } else {
                 match track::workload_rollout(&mf, &s).await {
                     Ok(true) => {
                         info!("successfully rolled out {}", &ui.name);
-                        webhooks::apply_event(UpgradeState::Completed, &ui, &region, &conf).await;
+                        if let Err(e) = hooks::run(&mf, &s, HookStage::PostApply).await {
+                            error!("{} from {}", e, ui.name);
+                            ui.reason = Some(e.to_string());
+                            webhooks::apply_event(UpgradeState::Failed, &mut ui, &region, &conf, &s).await;
+                            let reason = HookStage::PostApply.failure_reason();
+                            s.update_rollout_false(reason, e.to_string()).await?;
+                            return Err(e);
+                        }
+                        if mf.smokeTests.is_some() {
+                            if let Err(e) = smoke::run(&mf, &s).await {
+                                error!("{} from {}", e, ui.name);
+                                let mut reason = e.to_string();
+                                if mf.smokeTests.as_ref().map_or(false, |st| st.rollback) {
+                                    match rollback(&mf, &s).await {
+                                        Ok(true) => reason += " (rolled back)",
+                                        Ok(false) => reason += " (rollback did not complete)",
+                                        Err(re) => warn!("failed to roll back {}: {}", ui.name, re),
+                                    }
+                                }
+                                webhooks::apply_event(UpgradeState::Failed, &mut ui, &region, &conf, &s)
+                                    .await;
+                                s.update_smoketest_false("SmokeTestFailure", reason).await?;
+                                return Err(e);
+                            }
+                            s.update_smoketest_true().await?;
+                        }
+                        webhooks::apply_event(UpgradeState::Completed, &mut ui, &region, &conf, &s).await;
                         s.update_rollout_true(&actual_version).await?;
                     }
                     Ok(false) => {
@@ -320,14 +477,19 @@ async fn apply_kubectl(
                         let reason = format!("timed out waiting {}s for rollout", time);
                         //let _ = kubectl::debug_rollout_status(&mf).await;
                         let _ = track::debug(&mf, &s).await;
+                        if !ui.slack.is_empty() {
+                            for failure in track::pod_failures(&s).await.unwrap_or_default() {
+                                let _ = slack::reply_all(&ui.slack, &format!("```{}```", failure)).await;
+                            }
+                        }
                         // TODO: collect these for .status call ^?
                         warn!("failed to roll out {}", &ui.name);
-                        webhooks::apply_event(UpgradeState::Failed, &ui, &region, &conf).await;
+                        webhooks::apply_event(UpgradeState::Failed, &mut ui, &region, &conf, &s).await;
                         s.update_rollout_false("Timeout", reason).await?; // TODO: chain
                         return Err(ErrorKind::UpgradeTimeout(mf.name.clone(), time).into());
                     }
                     Err(e) => {
-                        webhooks::apply_event(UpgradeState::Failed, &ui, &region, &conf).await;
+                        webhooks::apply_event(UpgradeState::Failed, &mut ui, &region, &conf, &s).await;
                         s.update_rollout_false("RolloutTrackFailure", e.description().to_string())
                             .await?; // TODO: chain
                         return Err(e);
@@ -398,10 +560,44 @@ pub async fn diff_kubectl(mf: &Manifest, tfile: &str) -> Result<Option<String>>
     })
 }
 
+/// Undo the last rollout of the main workload and wait for the previous version to roll out
+async fn rollback(mf: &Manifest, s: &ShipKube) -> Result<bool> {
+    warn!("rolling back {}", mf.name);
+    kubectl::rollout_undo(mf).await?;
+    let ok = track::workload_rollout(mf, s).await?;
+    if !ok {
+        let _ = track::debug(mf, s).await;
+        warn!("timed out waiting for {} to roll back", mf.name);
+    }
+    Ok(ok)
+}
+
 /// Restart the workloads associated with a shipcatmanifest
 ///
 /// Optionally wait for the main resource
-pub async fn restart(mf: &Manifest, wait: bool) -> Result<()> {
+pub async fn restart(mf: &Manifest, wait: bool, force: bool) -> Result<()> {
+    if let (PrimaryWorkload::Statefulset, Some(ss)) = (&mf.workload, &mf.statefulSet) {
+        let unsafe_reason = if ss.partition() > 0 {
+            Some(format!(
+                "{} is partitioned at {}: only ordinals {} and above would restart",
+                mf.name,
+                ss.partition(),
+                ss.partition()
+            ))
+        } else if ss.updateStrategy == StatefulUpdateStrategy::OnDelete && !wait {
+            Some(format!(
+                "{} uses OnDelete: its pods are only replaced when waiting for the restart",
+                mf.name
+            ))
+        } else {
+            None
+        };
+        match unsafe_reason {
+            Some(r) if force => warn!("{} (forced)", r),
+            Some(r) => bail!("{} - use --force to restart anyway", r),
+            None => {}
+        }
+    }
     for w in &mf.workers {
         let r = Restartable {
             name: w.container.name.clone(),
@@ -468,18 +664,18 @@ pub async fn delete(svc: &str, reg: &Region, conf: &Config) -> Result<()> {
     match s.get().await {
         // audit all events if it's possible to deserialize current crd
         Ok(mfk) => {
-            let info = UpgradeInfo::new(&mfk.spec);
+            let mut info = UpgradeInfo::new(&mfk.spec);
             // We notify before we start, because this is potentially a "panic" type notification.
-            webhooks::delete_event(&UpgradeState::Started, &info, &reg, &conf).await;
+            webhooks::delete_event(&UpgradeState::Started, &mut info, &reg, &conf, &s).await;
             match s.delete().await {
                 Ok(_) => {
                     // NB: we say completed when the api is "done"
                     // This might still trigger finalizers ATM...
-                    webhooks::delete_event(&UpgradeState::Completed, &info, &reg, &conf).await;
+                    webhooks::delete_event(&UpgradeState::Completed, &mut info, &reg, &conf, &s).await;
                     Ok(())
                 }
                 Err(e) => {
-                    webhooks::delete_event(&UpgradeState::Failed, &info, &reg, &conf).await;
+                    webhooks::delete_event(&UpgradeState::Failed, &mut info, &reg, &conf, &s).await;
                     Err(e)
                 }
             }
@@ -616,6 +812,52 @@ impl ShipKube {
         self.patch(&data).await
     }
 
+    pub async fn update_smoketest_false(&self, err: &str, reason: String) -> Result<()> {
+        debug!("Setting smoketested and rolledout false");
+        let cond = Condition::bad(&self.applier, err, reason.clone());
+        let now = make_date();
+        let data = json!({
+            "status": {
+                "conditions": {
+                    "smoketested": cond,
+                    "rolledout": cond
+                },
+                "summary": {
+                    "lastRollout": now,
+                    "lastFailureReason": reason,
+                    "lastAction": "SmokeTest",
+                }
+            }
+        });
+        self.patch(&data).await
+    }
+
+    pub async fn update_smoketest_true(&self) -> Result<()> {
+        debug!("Setting smoketested true");
+        let cond = Condition::ok(&self.applier);
+        let data = json!({
+            "status": {
+                "conditions": {
+                    "smoketested": cond
+                },
+            }
+        });
+        self.patch(&data).await
+    }
+
+    /// Record the freeze override of an apply (clearing the last one on normal applies)
+    pub async fn update_freeze_override(&self, fo: Option<&FreezeOverride>) -> Result<()> {
+        debug!("Recording freeze override {:?}", fo);
+        let data = json!({
+            "status": {
+                "summary": {
+                    "lastFreezeOverride": fo,
+                }
+            }
+        });
+        self.patch(&data).await
+    }
+
     pub async fn update_rollout_true(&self, version: &str) -> Result<()> {
         debug!("Setting rolledout true");
         let now = make_date();
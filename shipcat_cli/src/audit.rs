@@ -7,6 +7,7 @@ use uuid::Uuid;
 
 use super::{AuditWebhook, ErrorKind, Result, ResultExt};
 use crate::{apply::UpgradeInfo, webhooks::UpgradeState};
+use shipcat_definitions::freeze::FreezeOverride;
 
 // Webhook Configuration Map
 type WHC = BTreeMap<String, String>;
@@ -96,6 +97,8 @@ struct DeploymentPayload {
     service: String,
     version: String,
     manifests_revision: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    freeze_override: Option<FreezeOverride>,
 }
 impl DeploymentPayload {
     fn new(whc: &WHC, info: &UpgradeInfo) -> Self {
@@ -105,6 +108,7 @@ impl DeploymentPayload {
             service: info.name.clone(),
             version: info.version.clone(),
             manifests_revision: whc["SHIPCAT_AUDIT_REVISION"].clone(),
+            freeze_override: info.freeze_override.clone(),
         }
     }
 }
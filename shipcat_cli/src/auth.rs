@@ -1,99 +1,883 @@
-use super::{Config, Region, Result};
-use crate::kubectl;
-use std::process::Command;
-
-/// Check if teleport expired
-fn need_teleport_login(url: &str) -> Result<bool> {
-    let args = vec!["status".to_string()]; // tsh status doesn't seem to have a nice filtering or yaml output :(
-                                           // https://github.com/gravitational/teleport/issues/2869
-    let s = Command::new("tsh").args(&args).output()?;
-
-    let tsh_out = String::from_utf8_lossy(&s.stdout);
-    let lines = tsh_out.lines().collect::<Vec<_>>();
-    if let Some(idx) = lines.iter().position(|l| l.contains(url)) {
-        let valid_ln = lines[idx + 5]; // idx+5 is Valid until line
-        debug!("Checking Valid line {}", valid_ln);
-        Ok(valid_ln.contains("EXPIRED"))
+use super::{Config, ErrorKind, Region, Result, ResultExt};
+use crate::{
+    config::{Cluster, ExecLogin, LoginConfig, OidcLogin, TeleportLogin},
+    kubectl,
+};
+use async_trait::async_trait;
+use chrono::{DateTime, TimeZone, Utc};
+use serde_yaml::{Mapping, Value};
+use std::{
+    fs,
+    io::Write,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+use tokio::process::Command;
+
+/// State of existing credentials for a cluster
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoginStatus {
+    /// Usable credentials (with expiry if known)
+    Valid(Option<DateTime<Utc>>),
+    /// Credentials that need a new login
+    Expired,
+    /// No credentials
+    Missing,
+}
+
+/// A way to get kubeconfig credentials for a cluster
+#[async_trait]
+pub trait LoginProvider: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Names of the kubeconfig cluster and user entries the provider manages
+    fn entries(&self) -> (String, String);
+
+    /// Check existing credentials without logging in
+    async fn status(&self, kubeconfig: &Kubeconfig) -> Result<LoginStatus>;
+
+    /// Forget cached credentials so the next login starts from scratch
+    fn logout(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Log in and write the cluster and user entries (may be interactive)
+    async fn login(&self, kubeconfig: &mut Kubeconfig) -> Result<()>;
+}
+
+/// The login provider configured for a cluster
+///
+/// None when the cluster relies on externally created kube contexts.
+pub fn provider_for(cluster: &Cluster) -> Option<Box<dyn LoginProvider>> {
+    match cluster.login_config()? {
+        LoginConfig::Teleport(t) => Some(Box::new(Teleport::new(t, cluster))),
+        LoginConfig::Oidc(o) => Some(Box::new(Oidc::new(o, cluster))),
+        LoginConfig::Exec(e) => Some(Box::new(Exec::new(e, cluster))),
+    }
+}
+
+/// Login to a region by going through its owning cluster
+///
+/// This uses the login provider of the cluster if one is set,
+/// otherwise it assumes you have already set a context with `region.cluster` externally.
+pub async fn login(conf: &Config, region: &Region, force: bool) -> Result<()> {
+    let cluster = match conf.find_owning_cluster(region) {
+        Some(c) => c,
+        None => bail!("Region {} does not have a cluster", region.name),
+    };
+    if let Some(provider) = provider_for(&cluster) {
+        let mut kubeconfig = Kubeconfig::load()?;
+        login_with(provider.as_ref(), &mut kubeconfig, region, force).await?;
+        kubeconfig.save()?;
     } else {
-        debug!("No {} found in tsh status", url);
-        Ok(true)
+        // We assume there's an external way to for users to create kube contexts
+        // if no login is configured on the owning cluster.
+        info!(
+            "Reusing {} context for region {} without login",
+            region.cluster, region.name
+        );
+        kubectl::use_context(&region.cluster).await?;
     }
+    Ok(())
 }
 
-fn ensure_teleport() -> Result<()> {
-    let s = Command::new("which").args(vec!["tsh"]).output()?;
-    let out = String::from_utf8_lossy(&s.stdout);
-    if out.is_empty() {
+/// Ensure valid credentials and point the region's context at them
+pub async fn login_with(
+    provider: &dyn LoginProvider,
+    kubeconfig: &mut Kubeconfig,
+    region: &Region,
+    force: bool,
+) -> Result<()> {
+    let status = if force {
+        provider.logout()?;
+        LoginStatus::Missing
+    } else {
+        provider.status(kubeconfig).await?
+    };
+    let (cluster, user) = provider.entries();
+    match status {
+        LoginStatus::Valid(Some(exp)) => info!(
+            "Reusing {} session for {} (until {})",
+            provider.name(),
+            cluster,
+            exp
+        ),
+        LoginStatus::Valid(None) => info!("Reusing {} session for {}", provider.name(), cluster),
+        s => {
+            debug!("{} status for {}: {:?}", provider.name(), cluster, s);
+            info!("Logging in to {} with {}", cluster, provider.name());
+            provider.login(kubeconfig).await?;
+        }
+    }
+    if kubeconfig.cluster(&cluster).is_none() {
         bail!(
-            "tsh not found. please install tsh --> https://gravitational.com/teleport/download/
-Download link for MacOS --> https://get.gravitational.com/teleport-v4.4.9-darwin-amd64-bin.tar.gz
-You must install version 4.4.9 and not higher versions"
+            "{} login did not create a kubeconfig cluster {}",
+            provider.name(),
+            cluster
         );
     }
-    // TODO: pin teleport url in cluster entry?
+    kubeconfig.set_context(&region.name, &cluster, &user, &region.namespace);
+    kubeconfig.use_context(&region.name);
     Ok(())
 }
 
-/// Login to a region by going through its owning cluster
+// ----------------------------------------------------------------------------------
+
+/// A kubeconfig file edited in place
 ///
-/// This will use teleport to login if a teleport url is set
-/// otherwise it assumes you have already set a context with `region.name` externally.
-pub async fn login(conf: &Config, region: &Region, force: bool) -> Result<()> {
-    if let Some(cluster) = conf.find_owning_cluster(&region) {
-        if let Some(teleport) = &cluster.teleport {
-            ensure_teleport()?;
-            let needs_login = need_teleport_login(&teleport)?;
-            if force {
-                let tsh_state_file = dirs::home_dir()
+/// Entries not touched by shipcat are preserved as is.
+#[derive(Debug, Clone, Default)]
+pub struct Kubeconfig {
+    path: Option<PathBuf>,
+    doc: Mapping,
+}
+
+impl Kubeconfig {
+    /// The kubeconfig kubectl uses (first `KUBECONFIG` entry or `~/.kube/config`)
+    pub fn default_path() -> PathBuf {
+        std::env::var_os("KUBECONFIG")
+            .and_then(|v| std::env::split_paths(&v).next())
+            .unwrap_or_else(|| {
+                dirs::home_dir()
                     .expect("need a homedir")
-                    .join(".tsh")
-                    .join(format!("{}.yaml", teleport));
-                debug!("Removing {}", tsh_state_file.display());
-                let _ = std::fs::remove_file(tsh_state_file); // don't care if the file is missing
+                    .join(".kube")
+                    .join("config")
+            })
+    }
+
+    pub fn load() -> Result<Self> {
+        Self::read(Self::default_path())
+    }
+
+    pub fn read(path: PathBuf) -> Result<Self> {
+        let mut res = if path.exists() {
+            Self::parse(&fs::read_to_string(&path)?)?
+        } else {
+            Self::default()
+        };
+        res.path = Some(path);
+        Ok(res)
+    }
+
+    pub fn parse(data: &str) -> Result<Self> {
+        let doc = match serde_yaml::from_str::<Option<Mapping>>(data)? {
+            Some(m) => m,
+            None => Mapping::new(),
+        };
+        Ok(Kubeconfig { path: None, doc })
+    }
+
+    /// Re-read the file after an external tool changed it
+    pub fn reload(&mut self) -> Result<()> {
+        if let Some(path) = self.path.clone() {
+            *self = Self::read(path)?;
+        }
+        Ok(())
+    }
+
+    /// Write the file atomically (other tools may read it at any time)
+    pub fn save(&self) -> Result<()> {
+        let path = match &self.path {
+            Some(p) => p,
+            None => bail!("Cannot save a kubeconfig without a path"),
+        };
+        let mut doc = self.doc.clone();
+        for (k, v) in &[("apiVersion", "v1"), ("kind", "Config")] {
+            if !doc.contains_key(&Value::from(*k)) {
+                doc.insert(Value::from(*k), Value::from(*v));
             }
-            if needs_login || force {
-                let tsh_args = vec![
-                    "login".into(),
-                    // NB: using default TTL here because there might be a hard limit
-                    format!("--proxy={url}:443", url = &teleport),
-                    "--auth=github".into(),
-                ];
-                info!("tsh {}", tsh_args.join(" "));
-                let s = Command::new("tsh").args(&tsh_args).output()?;
-                let out = String::from_utf8_lossy(&s.stdout);
-                let err = String::from_utf8_lossy(&s.stderr);
-                if !out.is_empty() {
-                    debug!("{}", out);
-                }
-                if !s.status.success() {
-                    bail!("tsh login: {}", err);
-                }
-            } else {
-                info!("Reusing active session for {}", teleport);
+        }
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let tmp = path.with_extension("shipcat.tmp");
+        let _ = fs::remove_file(&tmp); // left over from an interrupted save
+        let mut opts = fs::OpenOptions::new();
+        opts.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            // never readable by others, not even before the rename
+            use std::os::unix::fs::OpenOptionsExt;
+            opts.mode(0o600);
+        }
+        opts.open(&tmp)?
+            .write_all(serde_yaml::to_string(&doc)?.as_bytes())?;
+        fs::rename(&tmp, path)?;
+        debug!("Wrote kubeconfig {}", path.display());
+        Ok(())
+    }
+
+    fn named(&self, list: &str, kind: &str, name: &str) -> Option<&Value> {
+        self.doc
+            .get(&Value::from(list))?
+            .as_sequence()?
+            .iter()
+            .find(|e| e.get("name").and_then(Value::as_str) == Some(name))?
+            .get(kind)
+    }
+
+    fn set_named(&mut self, list: &str, kind: &str, name: &str, value: Value) {
+        let mut entry = Mapping::new();
+        entry.insert("name".into(), name.into());
+        entry.insert(kind.into(), value);
+        let key = Value::from(list);
+        if !self.doc.get(&key).map_or(false, Value::is_sequence) {
+            self.doc.insert(key.clone(), Value::Sequence(vec![]));
+        }
+        let seq = self.doc.get_mut(&key).and_then(Value::as_sequence_mut).unwrap();
+        match seq
+            .iter_mut()
+            .find(|e| e.get("name").and_then(Value::as_str) == Some(name))
+        {
+            Some(e) => *e = Value::Mapping(entry),
+            None => seq.push(Value::Mapping(entry)),
+        }
+    }
+
+    pub fn cluster(&self, name: &str) -> Option<&Value> {
+        self.named("clusters", "cluster", name)
+    }
+
+    pub fn user(&self, name: &str) -> Option<&Value> {
+        self.named("users", "user", name)
+    }
+
+    pub fn context(&self, name: &str) -> Option<&Value> {
+        self.named("contexts", "context", name)
+    }
+
+    pub fn current_context(&self) -> Option<&str> {
+        self.doc.get(&Value::from("current-context"))?.as_str()
+    }
+
+    pub fn set_cluster(&mut self, name: &str, cluster: Value) {
+        self.set_named("clusters", "cluster", name, cluster)
+    }
+
+    pub fn set_user(&mut self, name: &str, user: Value) {
+        self.set_named("users", "user", name, user)
+    }
+
+    pub fn set_context(&mut self, name: &str, cluster: &str, user: &str, namespace: &str) {
+        let mut ctx = Mapping::new();
+        ctx.insert("cluster".into(), cluster.into());
+        ctx.insert("user".into(), user.into());
+        ctx.insert("namespace".into(), namespace.into());
+        self.set_named("contexts", "context", name, Value::Mapping(ctx))
+    }
+
+    pub fn use_context(&mut self, name: &str) {
+        self.doc.insert("current-context".into(), name.into());
+    }
+}
+
+/// Cluster entry for an api server
+fn cluster_entry(api: &str, ca_data: &Option<String>) -> Value {
+    let mut c = Mapping::new();
+    c.insert("server".into(), api.into());
+    if let Some(ca) = ca_data {
+        c.insert("certificate-authority-data".into(), ca.as_str().into());
+    }
+    Value::Mapping(c)
+}
+
+// ----------------------------------------------------------------------------------
+
+/// Teleport login via `tsh`
+///
+/// tsh writes its own kubeconfig entries named after the proxy (or clustername).
+pub struct Teleport {
+    url: String,
+    entry: String,
+}
+
+impl Teleport {
+    pub fn new(cfg: TeleportLogin, cluster: &Cluster) -> Self {
+        let entry = cluster.clustername.clone().unwrap_or_else(|| cfg.url.clone());
+        Teleport { url: cfg.url, entry }
+    }
+
+    fn ensure_tsh() -> Result<()> {
+        if which::which("tsh").is_err() {
+            bail!(
+                "tsh not found. please install tsh --> https://gravitational.com/teleport/download/
+shipcat needs a version supporting `tsh status --format=json`"
+            );
+        }
+        Ok(())
+    }
+}
+
+/// A profile in `tsh status --format=json`
+#[derive(Deserialize, Debug)]
+struct TshProfile {
+    profile_url: String,
+    valid_until: DateTime<Utc>,
+}
+
+#[derive(Deserialize, Debug)]
+struct TshStatus {
+    active: Option<TshProfile>,
+    #[serde(default)]
+    profiles: Vec<TshProfile>,
+}
+
+/// Login status of a teleport proxy from `tsh status --format=json`
+pub fn teleport_status(json: &str, proxy: &str, now: DateTime<Utc>) -> Result<LoginStatus> {
+    let status: TshStatus = serde_json::from_str(json)?;
+    let host = |u: &str| {
+        let u = u.split("://").last().unwrap_or(u);
+        u.split(|c| c == ':' || c == '/').next().unwrap_or(u).to_string()
+    };
+    let profile = status
+        .active
+        .iter()
+        .chain(status.profiles.iter())
+        .find(|p| host(&p.profile_url) == host(proxy));
+    Ok(match profile {
+        Some(p) if p.valid_until > now => LoginStatus::Valid(Some(p.valid_until)),
+        Some(_) => LoginStatus::Expired,
+        None => LoginStatus::Missing,
+    })
+}
+
+#[async_trait]
+impl LoginProvider for Teleport {
+    fn name(&self) -> &'static str {
+        "teleport"
+    }
+
+    fn entries(&self) -> (String, String) {
+        (self.entry.clone(), self.entry.clone())
+    }
+
+    async fn status(&self, _: &Kubeconfig) -> Result<LoginStatus> {
+        Self::ensure_tsh()?;
+        let s = Command::new("tsh")
+            .args(&["status", "--format=json"])
+            .output()
+            .await?;
+        if !s.status.success() {
+            // tsh exits non-zero when not logged in to any proxy
+            debug!("tsh status: {}", String::from_utf8_lossy(&s.stderr));
+            return Ok(LoginStatus::Missing);
+        }
+        teleport_status(&String::from_utf8_lossy(&s.stdout), &self.url, Utc::now())
+    }
+
+    fn logout(&self) -> Result<()> {
+        let tsh_state_file = dirs::home_dir()
+            .expect("need a homedir")
+            .join(".tsh")
+            .join(format!("{}.yaml", self.url));
+        debug!("Removing {}", tsh_state_file.display());
+        let _ = fs::remove_file(tsh_state_file); // don't care if the file is missing
+        Ok(())
+    }
+
+    async fn login(&self, kubeconfig: &mut Kubeconfig) -> Result<()> {
+        Self::ensure_tsh()?;
+        let tsh_args = vec![
+            "login".into(),
+            // NB: using default TTL here because there might be a hard limit
+            format!("--proxy={url}:443", url = &self.url),
+            "--auth=github".into(),
+        ];
+        info!("tsh {}", tsh_args.join(" "));
+        let s = Command::new("tsh").args(&tsh_args).output().await?;
+        let out = String::from_utf8_lossy(&s.stdout);
+        let err = String::from_utf8_lossy(&s.stderr);
+        if !out.is_empty() {
+            debug!("{}", out);
+        }
+        if !s.status.success() {
+            bail!("tsh login: {}", err);
+        }
+        // pick up the entries tsh wrote
+        kubeconfig.reload()
+    }
+}
+
+// ----------------------------------------------------------------------------------
+
+/// OIDC login via the device authorization grant (RFC 8628)
+///
+/// Writes an oidc auth-provider user so kubectl refreshes the id token itself.
+pub struct Oidc {
+    cfg: OidcLogin,
+    cluster: String,
+    api: String,
+}
+
+impl Oidc {
+    pub fn new(cfg: OidcLogin, cluster: &Cluster) -> Self {
+        Oidc {
+            cfg,
+            cluster: cluster.name.clone(),
+            api: cluster.api.clone(),
+        }
+    }
+
+    fn user(&self) -> String {
+        format!("oidc-{}", self.cluster)
+    }
+
+    async fn discover(&self, client: &reqwest::Client) -> Result<OidcDiscovery> {
+        let url = reqwest::Url::parse(&format!(
+            "{}/.well-known/openid-configuration",
+            self.cfg.issuer.trim_end_matches('/')
+        ))?;
+        let res = client
+            .get(url.clone())
+            .send()
+            .await
+            .chain_err(|| ErrorKind::Url(url.clone()))?;
+        if !res.status().is_success() {
+            bail!("oidc discovery returned {}", res.status());
+        }
+        Ok(res.json().await?)
+    }
+
+    /// Run the device flow to get tokens for the user
+    pub async fn device_flow(&self) -> Result<OidcTokens> {
+        let client = reqwest::Client::new();
+        let disco = self.discover(&client).await?;
+        let device_url = match disco.device_authorization_endpoint {
+            Some(u) => u,
+            None => bail!(
+                "{} does not support the device authorization grant",
+                self.cfg.issuer
+            ),
+        };
+        let res = client
+            .post(&device_url)
+            .form(&[
+                ("client_id", self.cfg.clientId.as_str()),
+                ("scope", &self.cfg.scopes.join(" ")),
+            ])
+            .send()
+            .await?;
+        if !res.status().is_success() {
+            bail!("oidc device authorization returned {}", res.status());
+        }
+        let code: DeviceCode = res.json().await?;
+        println!(
+            "To login to {}, visit {} and enter the code {}",
+            self.cluster,
+            code.verification_uri_complete
+                .as_ref()
+                .unwrap_or(&code.verification_uri),
+            code.user_code
+        );
+
+        let deadline = Instant::now() + Duration::from_secs(code.expires_in);
+        let mut interval = code.interval;
+        while Instant::now() < deadline {
+            tokio::time::delay_for(Duration::from_secs(interval)).await;
+            let res = client
+                .post(&disco.token_endpoint)
+                .form(&[
+                    ("grant_type", "urn:ietf:params:oauth:grant-type:device_code"),
+                    ("device_code", code.device_code.as_str()),
+                    ("client_id", self.cfg.clientId.as_str()),
+                ])
+                .send()
+                .await?;
+            if res.status().is_success() {
+                return Ok(res.json().await?);
             }
+            let err: TokenError = res.json().await?;
+            match err.error.as_str() {
+                "authorization_pending" => debug!("Waiting for device authorization"),
+                "slow_down" => interval += 5,
+                _ => bail!(
+                    "oidc login failed: {}",
+                    err.error_description.unwrap_or(err.error)
+                ),
+            }
+        }
+        bail!("oidc device code expired before authorization")
+    }
+}
+
+#[derive(Deserialize)]
+struct OidcDiscovery {
+    token_endpoint: String,
+    device_authorization_endpoint: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DeviceCode {
+    device_code: String,
+    user_code: String,
+    #[serde(alias = "verification_url")]
+    verification_uri: String,
+    verification_uri_complete: Option<String>,
+    expires_in: u64,
+    #[serde(default = "default_device_interval")]
+    interval: u64,
+}
+fn default_device_interval() -> u64 {
+    5
+}
+
+#[derive(Deserialize)]
+struct TokenError {
+    error: String,
+    error_description: Option<String>,
+}
+
+/// Tokens from a successful oidc login
+#[derive(Deserialize, Debug)]
+pub struct OidcTokens {
+    pub id_token: String,
+    pub refresh_token: Option<String>,
+}
 
-            let mut args = vec![format!("--namespace={}", region.namespace)];
+/// Expiry of a jwt (the signature is for the api server to check)
+pub fn jwt_expiry(token: &str) -> Result<DateTime<Utc>> {
+    let payload = match token.split('.').nth(1) {
+        Some(p) => p,
+        None => bail!("id token is not a jwt"),
+    };
+    let claims: serde_json::Value = match base64::decode_config(payload, base64::URL_SAFE_NO_PAD) {
+        Ok(json) => serde_json::from_slice(&json)?,
+        Err(e) => bail!("id token has invalid claims: {}", e),
+    };
+    match claims["exp"].as_i64() {
+        Some(exp) => Ok(Utc.timestamp(exp, 0)),
+        None => bail!("id token has no expiry"),
+    }
+}
+
+#[async_trait]
+impl LoginProvider for Oidc {
+    fn name(&self) -> &'static str {
+        "oidc"
+    }
+
+    fn entries(&self) -> (String, String) {
+        (self.cluster.clone(), self.user())
+    }
+
+    async fn status(&self, kubeconfig: &Kubeconfig) -> Result<LoginStatus> {
+        let cfg = match kubeconfig.user(&self.user()) {
+            Some(u) => &u["auth-provider"]["config"],
+            None => return Ok(LoginStatus::Missing),
+        };
+        let exp = match cfg["id-token"].as_str() {
+            Some(t) => jwt_expiry(t)?,
+            None => return Ok(LoginStatus::Missing),
+        };
+        if exp > Utc::now() {
+            Ok(LoginStatus::Valid(Some(exp)))
+        } else if cfg["refresh-token"].as_str().is_some() {
+            // kubectl refreshes it on the next request
+            Ok(LoginStatus::Valid(None))
+        } else {
+            Ok(LoginStatus::Expired)
+        }
+    }
+
+    async fn login(&self, kubeconfig: &mut Kubeconfig) -> Result<()> {
+        let tokens = self.device_flow().await?;
+        let mut cfg = Mapping::new();
+        cfg.insert("idp-issuer-url".into(), self.cfg.issuer.as_str().into());
+        cfg.insert("client-id".into(), self.cfg.clientId.as_str().into());
+        cfg.insert("id-token".into(), tokens.id_token.into());
+        if let Some(rt) = tokens.refresh_token {
+            cfg.insert("refresh-token".into(), rt.into());
+        }
+        let mut provider = Mapping::new();
+        provider.insert("name".into(), "oidc".into());
+        provider.insert("config".into(), Value::Mapping(cfg));
+        let mut user = Mapping::new();
+        user.insert("auth-provider".into(), Value::Mapping(provider));
+
+        kubeconfig.set_cluster(
+            &self.cluster,
+            cluster_entry(&self.api, &self.cfg.certificateAuthorityData),
+        );
+        kubeconfig.set_user(&self.user(), Value::Mapping(user));
+        Ok(())
+    }
+}
+
+// ----------------------------------------------------------------------------------
+
+/// Credentials from a client-go exec plugin
+///
+/// kubectl runs the plugin itself, shipcat only writes the entries and checks the plugin works.
+pub struct Exec {
+    cfg: ExecLogin,
+    cluster: String,
+    api: String,
+}
 
-            if let Some(clustername) = &cluster.clustername {
-                args.push(format!("--cluster={}", &clustername));
-                args.push(format!("--user={}", &clustername));
+impl Exec {
+    pub fn new(cfg: ExecLogin, cluster: &Cluster) -> Self {
+        Exec {
+            cfg,
+            cluster: cluster.name.clone(),
+            api: cluster.api.clone(),
+        }
+    }
+
+    fn user(&self) -> String {
+        format!("exec-{}", self.cluster)
+    }
+
+    fn user_entry(&self) -> Value {
+        let mut exec = Mapping::new();
+        exec.insert("apiVersion".into(), self.cfg.apiVersion.as_str().into());
+        exec.insert("command".into(), self.cfg.command.as_str().into());
+        if !self.cfg.args.is_empty() {
+            let args = self.cfg.args.iter().map(|a| Value::from(a.as_str())).collect();
+            exec.insert("args".into(), Value::Sequence(args));
+        }
+        if !self.cfg.env.is_empty() {
+            let env = self
+                .cfg
+                .env
+                .iter()
+                .map(|(k, v)| {
+                    let mut e = Mapping::new();
+                    e.insert("name".into(), k.as_str().into());
+                    e.insert("value".into(), v.as_str().into());
+                    Value::Mapping(e)
+                })
+                .collect();
+            exec.insert("env".into(), Value::Sequence(env));
+        }
+        let mut user = Mapping::new();
+        user.insert("exec".into(), Value::Mapping(exec));
+        Value::Mapping(user)
+    }
+}
+
+/// Login status from the output of an exec credential plugin
+pub fn exec_credential_status(json: &str, now: DateTime<Utc>) -> Result<LoginStatus> {
+    let cred: serde_json::Value = serde_json::from_str(json)?;
+    let status = &cred["status"];
+    if status["token"].is_null() && status["clientCertificateData"].is_null() {
+        return Ok(LoginStatus::Missing);
+    }
+    match status["expirationTimestamp"].as_str() {
+        Some(ts) => {
+            let exp = DateTime::parse_from_rfc3339(ts)?.with_timezone(&Utc);
+            if exp > now {
+                Ok(LoginStatus::Valid(Some(exp)))
             } else {
-                args.push(format!("--cluster={}", &teleport));
-                args.push(format!("--user={}", &teleport));
+                Ok(LoginStatus::Expired)
             }
-            kubectl::set_context(&region.name, args).await?;
-            kubectl::use_context(&region.name).await?;
-        } else {
-            // We assume there's an external way to for users to create kube contexts
-            // if not teleport url is set on the owning cluster.
-            info!(
-                "Reusing {} context for non-teleport region {}",
-                region.cluster, region.name
+        }
+        None => Ok(LoginStatus::Valid(None)),
+    }
+}
+
+#[async_trait]
+impl LoginProvider for Exec {
+    fn name(&self) -> &'static str {
+        "exec"
+    }
+
+    fn entries(&self) -> (String, String) {
+        (self.cluster.clone(), self.user())
+    }
+
+    async fn status(&self, kubeconfig: &Kubeconfig) -> Result<LoginStatus> {
+        if kubeconfig.user(&self.user()) != Some(&self.user_entry()) {
+            // missing or written from an older config
+            return Ok(LoginStatus::Missing);
+        }
+        let s = Command::new(&self.cfg.command)
+            .args(&self.cfg.args)
+            .envs(&self.cfg.env)
+            .output()
+            .await
+            .chain_err(|| format!("Failed to run credential plugin {}", self.cfg.command))?;
+        if !s.status.success() {
+            debug!("{}: {}", self.cfg.command, String::from_utf8_lossy(&s.stderr));
+            return Ok(LoginStatus::Expired);
+        }
+        exec_credential_status(&String::from_utf8_lossy(&s.stdout), Utc::now())
+    }
+
+    async fn login(&self, kubeconfig: &mut Kubeconfig) -> Result<()> {
+        if which::which(&self.cfg.command).is_err() {
+            bail!("credential plugin {} not found in PATH", self.cfg.command);
+        }
+        kubeconfig.set_cluster(
+            &self.cluster,
+            cluster_entry(&self.api, &self.cfg.certificateAuthorityData),
+        );
+        kubeconfig.set_user(&self.user(), self.user_entry());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        exec_credential_status, jwt_expiry, login_with, teleport_status, Kubeconfig, LoginProvider,
+        LoginStatus,
+    };
+    use crate::{Region, Result};
+    use async_trait::async_trait;
+    use chrono::{TimeZone, Utc};
+    use serde_yaml::Value;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Provider that logs in by writing static entries
+    struct Fake {
+        status: LoginStatus,
+        logins: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl LoginProvider for Fake {
+        fn name(&self) -> &'static str {
+            "fake"
+        }
+
+        fn entries(&self) -> (String, String) {
+            ("kind-shipcat".into(), "fake-user".into())
+        }
+
+        async fn status(&self, _: &Kubeconfig) -> Result<LoginStatus> {
+            Ok(self.status.clone())
+        }
+
+        async fn login(&self, kc: &mut Kubeconfig) -> Result<()> {
+            self.logins.fetch_add(1, Ordering::SeqCst);
+            kc.set_cluster(
+                "kind-shipcat",
+                serde_yaml::from_str("server: https://127.0.0.1:6443")?,
             );
-            kubectl::use_context(&region.cluster).await?;
+            kc.set_user("fake-user", serde_yaml::from_str("token: abc")?);
+            Ok(())
         }
-    } else {
-        bail!("Region {} does not have a cluster", region.name);
     }
-    Ok(())
+
+    fn region() -> Region {
+        serde_json::from_value(serde_json::json!({
+            "name": "dev-uk",
+            "namespace": "dev",
+            "environment": "dev",
+            "cluster": "kind-shipcat",
+            "versioningScheme": "GitShaOrSemver",
+            "vault": { "url": "http://localhost:8200", "folder": "dev-uk" },
+        }))
+        .unwrap()
+    }
+
+    const EXISTING: &str = "
+apiVersion: v1
+kind: Config
+clusters:
+- name: other
+  cluster:
+    server: https://other.example.com
+users: []
+contexts: []
+preferences:
+  colors: true
+";
+
+    #[tokio::test]
+    async fn login_flow_with_fake_provider() {
+        let fake = Fake {
+            status: LoginStatus::Expired,
+            logins: AtomicUsize::new(0),
+        };
+        let mut kc = Kubeconfig::parse(EXISTING).unwrap();
+        login_with(&fake, &mut kc, &region(), false).await.unwrap();
+        assert_eq!(fake.logins.load(Ordering::SeqCst), 1);
+        assert_eq!(kc.current_context(), Some("dev-uk"));
+        let ctx = kc.context("dev-uk").unwrap();
+        assert_eq!(ctx["cluster"], Value::from("kind-shipcat"));
+        assert_eq!(ctx["user"], Value::from("fake-user"));
+        assert_eq!(ctx["namespace"], Value::from("dev"));
+        // unrelated entries are preserved
+        assert!(kc.cluster("other").is_some());
+        assert_eq!(kc.doc[&Value::from("preferences")]["colors"], Value::from(true));
+
+        // valid sessions are reused unless forced
+        let fake = Fake {
+            status: LoginStatus::Valid(None),
+            logins: AtomicUsize::new(0),
+        };
+        login_with(&fake, &mut kc, &region(), false).await.unwrap();
+        assert_eq!(fake.logins.load(Ordering::SeqCst), 0);
+        login_with(&fake, &mut kc, &region(), true).await.unwrap();
+        assert_eq!(fake.logins.load(Ordering::SeqCst), 1);
+        assert_eq!(kc.doc[&Value::from("clusters")].as_sequence().unwrap().len(), 2);
+
+        // a reused session still needs the provider's entries
+        let mut empty = Kubeconfig::default();
+        assert!(login_with(&fake, &mut empty, &region(), false).await.is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn kubeconfig_saved_private() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = std::env::temp_dir().join(format!("shipcat-auth-{}", std::process::id()));
+        let path = dir.join("config");
+        let mut kc = Kubeconfig::read(path.clone()).unwrap();
+        kc.set_user("fake-user", serde_yaml::from_str("token: abc").unwrap());
+        kc.save().unwrap();
+        // saving again replaces the file
+        kc.save().unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert!(Kubeconfig::read(path).unwrap().user("fake-user").is_some());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn login_status_parsing() {
+        let now = Utc.ymd(2020, 11, 1).and_hms(12, 0, 0);
+        let tsh = r#"{
+          "active": {
+            "profile_url": "https://teleport.example.com:3080",
+            "username": "clux",
+            "valid_until": "2020-11-01T20:00:00Z"
+          },
+          "profiles": [
+            { "profile_url": "https://old.example.com:3080", "valid_until": "2020-10-30T20:00:00Z" }
+          ]
+        }"#;
+        assert_eq!(
+            teleport_status(tsh, "teleport.example.com", now).unwrap(),
+            LoginStatus::Valid(Some(Utc.ymd(2020, 11, 1).and_hms(20, 0, 0)))
+        );
+        assert_eq!(
+            teleport_status(tsh, "https://old.example.com", now).unwrap(),
+            LoginStatus::Expired
+        );
+        assert_eq!(
+            teleport_status(tsh, "missing.example.com", now).unwrap(),
+            LoginStatus::Missing
+        );
+
+        let cred = r#"{
+          "kind": "ExecCredential",
+          "apiVersion": "client.authentication.k8s.io/v1beta1",
+          "status": { "token": "k8s-aws-v1.abc", "expirationTimestamp": "2020-11-01T12:15:00Z" }
+        }"#;
+        assert!(matches!(
+            exec_credential_status(cred, now).unwrap(),
+            LoginStatus::Valid(Some(_))
+        ));
+        assert_eq!(
+            exec_credential_status(r#"{"status": {}}"#, now).unwrap(),
+            LoginStatus::Missing
+        );
+
+        // {"alg":"none"}.{"sub":"clux","exp":1604232000}.
+        let jwt = "eyJhbGciOiJub25lIn0.eyJzdWIiOiJjbHV4IiwiZXhwIjoxNjA0MjMyMDAwfQ.";
+        assert_eq!(jwt_expiry(jwt).unwrap(), Utc.timestamp(1_604_232_000, 0));
+        assert!(jwt_expiry("garbage").is_err());
+    }
 }
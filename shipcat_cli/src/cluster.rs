@@ -6,8 +6,11 @@ use super::{kubectl, Error, ErrorKind, Result};
 use crate::{
     apply, diff, helm,
     kubeapi::ShipKube,
+    metrics::{MetricsSink, ReconcileReport, ServiceOutcome},
     webhooks::{self, UpgradeState},
 };
+use chrono::Utc;
+use std::time::Instant;
 
 struct DiffResult {
     name: String,
@@ -144,9 +147,26 @@ pub async fn crd_install(reg: &Region) -> Result<()> {
 /// Apply all services in the region
 ///
 /// Helper that shells out to kubectl apply in parallel.
-pub async fn mass_crd(conf_sec: &Config, conf_base: &Config, reg: &Region, n_workers: usize) -> Result<()> {
+/// Per-service outcomes are exported to the metrics sink when one is configured.
+pub async fn mass_crd(
+    conf_sec: &Config,
+    conf_base: &Config,
+    reg: &Region,
+    n_workers: usize,
+    freeze_override: Option<String>,
+    metrics: MetricsSink,
+) -> Result<()> {
     let svcs = shipcat_filebacked::available(conf_base, reg).await?;
-    crd_reconcile(svcs, conf_sec, conf_base, &reg.name, n_workers).await
+    crd_reconcile(
+        svcs,
+        conf_sec,
+        conf_base,
+        &reg.name,
+        n_workers,
+        freeze_override,
+        metrics,
+    )
+    .await
 }
 
 async fn crd_reconcile(
@@ -155,6 +175,8 @@ async fn crd_reconcile(
     config_base: &Config,
     region: &str,
     n_workers: usize,
+    freeze_override: Option<String>,
+    metrics: MetricsSink,
 ) -> Result<()> {
     // NB: This needs config_base for base crd application
     // shipcatconfig crd should not have secrets when applied
@@ -175,6 +197,10 @@ async fn crd_reconcile(
         .unwrap()
         .clone();
 
+    // During a deploy freeze services are refused individually once their diff is known,
+    // so only services that would change fail the reconcile
+    let frozen = apply::check_freeze(config_sec, &region_sec, freeze_override.as_deref()).is_err();
+
     webhooks::reconcile_event(UpgradeState::Pending, &region_sec).await;
     // Always reconcile the CRDs (definitions themselves) first
     crd_install(&region_base).await?;
@@ -192,12 +218,20 @@ async fn crd_reconcile(
     // Single instruction kubectl delete shipcat manifests .... of excess ones
     let svc_names = svcs.iter().map(|x| x.base.name.to_string()).collect::<Vec<_>>();
     let excess = kubectl::find_redundant_manifests(&region_sec.namespace, &svc_names).await?;
-    if !excess.is_empty() {
-        info!("Will remove excess manifests: {:?}", excess);
-    }
-    for svc in excess {
-        // NB: doing deletion sequentially...
-        apply::delete(&svc, &region_sec, &config_sec).await?;
+    if frozen && !excess.is_empty() {
+        // deleting is never a no-op
+        warn!(
+            "Not removing excess manifests during a deploy freeze: {:?}",
+            excess
+        );
+    } else {
+        if !excess.is_empty() {
+            info!("Will remove excess manifests: {:?}", excess);
+        }
+        for svc in excess {
+            // NB: doing deletion sequentially...
+            apply::delete(&svc, &region_sec, &config_sec).await?;
+        }
     }
 
     info!(
@@ -209,24 +243,43 @@ async fn crd_reconcile(
     webhooks::reconcile_event(UpgradeState::Started, &region_sec).await;
     // then parallel apply the remaining ones
     let force = std::env::var("SHIPCAT_MASS_RECONCILE").unwrap_or("0".into()) == "1";
-    let wait_for_rollout = true;
-
     let conf = config_sec.clone();
     let reg = region_sec.clone();
     let mut buffered = stream::iter(svcs)
         .map(|mf| {
             debug!("Running CRD reconcile for {:?}", mf.base.name);
-            apply::apply(mf.base.name, force, &reg, &conf, wait_for_rollout, None)
+            let name = mf.base.name.clone();
+            let start = Instant::now();
+            let upgrade = apply::reconcile(mf.base.name, force, &reg, &conf, freeze_override.clone());
+            async move { (name, upgrade.await, start.elapsed()) }
         })
         .buffer_unordered(n_workers);
 
     let mut errs = vec![];
-    while let Some(r) = buffered.next().await {
-        if let Err(e) = r {
-            warn!("{}", e);
-            errs.push(e);
-        }
+    let mut outcomes = vec![];
+    while let Some((service, r, duration)) = buffered.next().await {
+        let (reason, failed) = match r {
+            Ok(info) => (info.and_then(|i| i.reason), false),
+            Err(e) => {
+                warn!("{}", e);
+                errs.push(e);
+                (None, true)
+            }
+        };
+        outcomes.push(ServiceOutcome {
+            service,
+            duration,
+            reason,
+            failed,
+        });
+    }
+    ReconcileReport {
+        region: region_sec.name.clone(),
+        finished: Utc::now(),
+        outcomes,
     }
+    .export(&metrics)
+    .await;
 
     // propagate first non-ignorable error if exists
     for e in errs {
@@ -0,0 +1,337 @@
+//- Drift of manifests across regions
+use super::{Config, Error, Manifest, Result};
+use serde_json::Value;
+use std::{collections::BTreeMap, str::FromStr};
+
+/// How to present a drift report
+pub enum DriftFormat {
+    /// Human readable table
+    Table,
+    /// Standalone html page
+    Html,
+    /// Machine readable json
+    Json,
+}
+
+impl FromStr for DriftFormat {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Self> {
+        match input {
+            "table" => Ok(Self::Table),
+            "html" => Ok(Self::Html),
+            "json" => Ok(Self::Json),
+            _ => bail!("Drift format must be table, html or json"),
+        }
+    }
+}
+
+/// A key of a service that differs from the reference region
+#[derive(Serialize, Debug, PartialEq)]
+pub struct DriftRow {
+    pub service: String,
+    pub key: String,
+    /// Value in the reference region (None if unset)
+    pub reference: Option<String>,
+    /// Values in the other regions the service is deployed to
+    pub values: BTreeMap<String, Option<String>>,
+}
+
+/// Everything that differs from a reference region
+#[derive(Serialize, Debug)]
+pub struct DriftReport {
+    pub reference: String,
+    pub regions: Vec<String>,
+    pub rows: Vec<DriftRow>,
+}
+
+/// Flatten json into dot separated keys (lists are kept whole)
+fn flatten(prefix: &str, v: &Value, out: &mut BTreeMap<String, String>) {
+    match v {
+        Value::Object(o) => {
+            for (k, v) in o {
+                flatten(&format!("{}.{}", prefix, k), v, out);
+            }
+        }
+        Value::Null => {}
+        Value::String(s) => {
+            out.insert(prefix.to_string(), s.clone());
+        }
+        _ => {
+            out.insert(prefix.to_string(), v.to_string());
+        }
+    }
+}
+
+/// The keys of a manifest compared by `shipcat drift`
+///
+/// Covers the version, image, env, resources and kong settings.
+/// Secret env vars are compared by name only.
+pub fn drift_keys(mf: &Manifest) -> Result<BTreeMap<String, String>> {
+    let mut out = BTreeMap::new();
+    if let Some(v) = &mf.version {
+        out.insert("version".into(), v.clone());
+    }
+    if let Some(i) = &mf.image {
+        out.insert("image".into(), i.clone());
+    }
+    for (k, v) in &mf.env.plain {
+        out.insert(format!("env.{}", k), v.clone());
+    }
+    for k in &mf.env.secrets {
+        out.insert(format!("env.{}", k), "<secret>".into());
+    }
+    if let Some(r) = &mf.resources {
+        flatten("resources", &serde_json::to_value(r)?, &mut out);
+    }
+    for k in &mf.kongApis {
+        flatten(&format!("kong.{}", k.name), &serde_json::to_value(k)?, &mut out);
+    }
+    Ok(out)
+}
+
+/// Whether a key is covered by an ignore pattern
+fn is_ignored(key: &str, ignored: &[String]) -> bool {
+    let segments = key.split('.').collect::<Vec<_>>();
+    ignored.iter().any(|pattern| {
+        let parts = pattern.split('.').collect::<Vec<_>>();
+        parts.len() <= segments.len() && parts.iter().zip(&segments).all(|(p, s)| *p == "*" || p == s)
+    })
+}
+
+/// Compare the keys of one service across regions against the reference region
+fn compare(
+    service: &str,
+    reference: &BTreeMap<String, String>,
+    regions: &BTreeMap<String, BTreeMap<String, String>>,
+    ignored: &[String],
+) -> Vec<DriftRow> {
+    let mut keys = reference.keys().collect::<Vec<_>>();
+    for kv in regions.values() {
+        keys.extend(kv.keys());
+    }
+    keys.sort();
+    keys.dedup();
+
+    let mut rows = vec![];
+    for key in keys.into_iter().filter(|k| !is_ignored(k, ignored)) {
+        let refval = reference.get(key);
+        let values = regions
+            .iter()
+            .map(|(r, kv)| (r.clone(), kv.get(key).cloned()))
+            .collect::<BTreeMap<_, _>>();
+        if values.values().any(|v| v.as_ref() != refval) {
+            rows.push(DriftRow {
+                service: service.to_string(),
+                key: key.clone(),
+                reference: refval.cloned(),
+                values,
+            });
+        }
+    }
+    rows
+}
+
+/// Compute drift of every service in the reference region
+///
+/// Takes an unfiltered config. Regions that a service is not deployed to are skipped.
+pub async fn report(conf: &Config, reference: &str, regions: &[String]) -> Result<DriftReport> {
+    let refreg = match conf.get_region_unchecked(reference) {
+        Some(r) => r.clone(),
+        None => bail!("Reference region {} is not defined in shipcat.conf", reference),
+    };
+    let mut compared = vec![];
+    for r in regions.iter().filter(|r| *r != reference) {
+        match conf.get_region_unchecked(r) {
+            Some(reg) => compared.push(reg.clone()),
+            None => bail!("Region {} is not defined in shipcat.conf", r),
+        }
+    }
+
+    let mut rows = vec![];
+    for base in shipcat_filebacked::all(conf).await? {
+        if !base.regions.contains(&refreg.name) {
+            continue;
+        }
+        let refmf = shipcat_filebacked::load_manifest(&base.name, conf, &refreg).await?;
+        let refkeys = drift_keys(&refmf)?;
+        let mut others = BTreeMap::new();
+        for reg in compared.iter().filter(|r| base.regions.contains(&r.name)) {
+            let mf = shipcat_filebacked::load_manifest(&base.name, conf, reg).await?;
+            others.insert(reg.name.clone(), drift_keys(&mf)?);
+        }
+        rows.extend(compare(&base.name, &refkeys, &others, &conf.driftIgnoredKeys));
+    }
+    Ok(DriftReport {
+        reference: refreg.name,
+        regions: compared.into_iter().map(|r| r.name).collect(),
+        rows,
+    })
+}
+
+fn cell(v: Option<&String>) -> String {
+    v.cloned().unwrap_or_else(|| "-".into())
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Render a drift report as a standalone html page
+pub fn render_html(report: &DriftReport) -> String {
+    let mut html = String::new();
+    html += "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n";
+    html += &format!("<title>Drift from {}</title>\n", escape(&report.reference));
+    html += "<style>\ntable { border-collapse: collapse; font-family: monospace; }\n";
+    html += "td, th { border: 1px solid #ccc; padding: 4px 8px; }\n";
+    html += ".drift { background: #fce4e4; }\n</style>\n</head>\n<body>\n";
+    html += &format!("<h1>Drift from {}</h1>\n<table>\n<tr>", escape(&report.reference));
+    html += &format!(
+        "<th>Service</th><th>Key</th><th>{}</th>",
+        escape(&report.reference)
+    );
+    for r in &report.regions {
+        html += &format!("<th>{}</th>", escape(r));
+    }
+    html += "</tr>\n";
+    for row in &report.rows {
+        html += &format!(
+            "<tr><td>{}</td><td>{}</td><td>{}</td>",
+            escape(&row.service),
+            escape(&row.key),
+            escape(&cell(row.reference.as_ref()))
+        );
+        for r in &report.regions {
+            html += &match row.values.get(r) {
+                // service not deployed to this region
+                None => "<td></td>".to_string(),
+                Some(v) if v == &row.reference => format!("<td>{}</td>", escape(&cell(v.as_ref()))),
+                Some(v) => format!("<td class=\"drift\">{}</td>", escape(&cell(v.as_ref()))),
+            };
+        }
+        html += "</tr>\n";
+    }
+    html += "</table>\n</body>\n</html>\n";
+    html
+}
+
+fn print_table(report: &DriftReport) {
+    let mut header = format!("{0:<32} {1:<40} {2:<24}", "SERVICE", "KEY", report.reference);
+    for r in &report.regions {
+        header += &format!(" {:<24}", r);
+    }
+    println!("{}", header);
+    for row in &report.rows {
+        let mut line = format!(
+            "{0:<32} {1:<40} {2:<24}",
+            row.service,
+            row.key,
+            cell(row.reference.as_ref())
+        );
+        for r in &report.regions {
+            let v = row.values.get(r).map(|v| cell(v.as_ref())).unwrap_or_default();
+            line += &format!(" {:<24}", v);
+        }
+        println!("{}", line);
+    }
+}
+
+/// Entry point for `shipcat drift`
+///
+/// Compares `regions` (all regions when empty) against the `reference` region.
+pub async fn drift(
+    conf: &Config,
+    reference: &str,
+    regions: Vec<String>,
+    fmt: DriftFormat,
+) -> Result<DriftReport> {
+    let regions = if regions.is_empty() {
+        conf.get_regions().into_iter().map(|r| r.name).collect()
+    } else {
+        regions
+    };
+    let report = report(conf, reference, &regions).await?;
+    match fmt {
+        DriftFormat::Table => print_table(&report),
+        DriftFormat::Html => print!("{}", render_html(&report)),
+        DriftFormat::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compare, drift_keys, is_ignored, render_html, DriftReport};
+    use crate::Manifest;
+    use std::collections::BTreeMap;
+
+    fn keys(kv: &[(&str, &str)]) -> BTreeMap<String, String> {
+        kv.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn drift_ignore_patterns() {
+        let ignored = vec!["env.BASE_URL".to_string(), "kong.*.upstream_url".to_string()];
+        assert!(is_ignored("env.BASE_URL", &ignored));
+        assert!(!is_ignored("env.BASE", &ignored));
+        assert!(is_ignored("kong.webapp.upstream_url", &ignored));
+        assert!(!is_ignored("kong.webapp.uris", &ignored));
+        assert!(is_ignored("resources.limits.cpu", &["resources".to_string()]));
+    }
+
+    #[test]
+    fn drift_compare() {
+        let reference = keys(&[("version", "1.2.0"), ("env.MODE", "prod"), ("env.URL", "a")]);
+        let mut regions = BTreeMap::new();
+        regions.insert(
+            "prod-uk".to_string(),
+            keys(&[("version", "1.1.0"), ("env.MODE", "prod"), ("env.URL", "b")]),
+        );
+        regions.insert(
+            "prod-us".to_string(),
+            keys(&[
+                ("version", "1.2.0"),
+                ("env.MODE", "prod"),
+                ("env.DEBUG", "1"),
+                ("env.URL", "c"),
+            ]),
+        );
+        let rows = compare("webapp", &reference, &regions, &["env.URL".into()]);
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].key, "env.DEBUG");
+        assert_eq!(rows[0].reference, None);
+        assert_eq!(rows[0].values["prod-us"], Some("1".into()));
+        assert_eq!(rows[1].key, "version");
+        assert_eq!(rows[1].values["prod-uk"], Some("1.1.0".into()));
+        assert_eq!(rows[1].values["prod-us"], Some("1.2.0".into()));
+    }
+
+    #[test]
+    fn drift_manifest_keys() {
+        let mut mf = Manifest::test("fake-svc");
+        mf.env.plain.insert("MODE".into(), "dev".into());
+        mf.env.secrets.insert("TOKEN".into());
+        let kv = drift_keys(&mf).unwrap();
+        assert_eq!(kv["version"], "1.0.0");
+        assert_eq!(kv["env.MODE"], "dev");
+        assert_eq!(kv["env.TOKEN"], "<secret>");
+    }
+
+    #[test]
+    fn drift_html() {
+        let reference = keys(&[("env.MODE", "<prod>")]);
+        let mut regions = BTreeMap::new();
+        regions.insert("prod-uk".to_string(), keys(&[("env.MODE", "dev")]));
+        let report = DriftReport {
+            reference: "staging-uk".into(),
+            regions: vec!["prod-uk".into(), "prod-us".into()],
+            rows: compare("webapp", &reference, &regions, &[]),
+        };
+        let html = render_html(&report);
+        assert!(html.contains("<th>staging-uk</th><th>prod-uk</th><th>prod-us</th>"));
+        assert!(html.contains("<td>&lt;prod&gt;</td><td class=\"drift\">dev</td><td></td>"));
+    }
+}
@@ -1,7 +1,17 @@
-use super::{Config, Region};
-use std::collections::BTreeMap;
+use super::{Config, Manifest, Region};
+use petgraph::{
+    dot,
+    graph::{DiGraph, NodeIndex},
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::{self, Debug},
+};
 
-use super::{structs::security::DataHandling, Result};
+use super::{
+    structs::security::{DataHandling, InformationClassification},
+    Result,
+};
 
 /// GdprOutput across manifests
 #[derive(Serialize)]
@@ -38,3 +48,573 @@ pub async fn show(svc: Option<String>, conf: &Config, region: &Region) -> Result
     println!("{}", out);
     Ok(())
 }
+
+/// The node type in `DataFlowGraph` representing a service
+#[derive(Serialize, Deserialize, Clone)]
+pub struct FlowNode {
+    pub name: String,
+    /// Classification declared in `dataHandling.informationClassification`
+    pub declared: Option<InformationClassification>,
+    /// Most sensitive classification flowing into the service
+    pub received: Option<InformationClassification>,
+}
+// Debug is used for the `dot` interface - nice to have a minimal output for that
+impl Debug for FlowNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+/// How data flows along a `FlowEdge`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum FlowKind {
+    /// Fields declared in `dataHandling.processes` of the receiver
+    Processes,
+    /// Responses from a service in the `dependencies` of the receiver
+    Dependency,
+}
+
+/// The edge type in `DataFlowGraph` from the source of the data to its receiver
+#[derive(Serialize, Deserialize, Clone)]
+pub struct FlowEdge {
+    pub kind: FlowKind,
+    /// Fields carried (only for `Processes` edges)
+    pub fields: Vec<String>,
+    /// Most sensitive classification carried
+    pub classification: Option<InformationClassification>,
+}
+impl Debug for FlowEdge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let class = self
+            .classification
+            .as_ref()
+            .map(|c| c.to_string())
+            .unwrap_or_else(|| "unclassified".into());
+        if self.fields.is_empty() {
+            write!(f, "{}", class)
+        } else {
+            write!(f, "{} ({})", self.fields.join(", "), class)
+        }
+    }
+}
+
+/// Graph of services with the data they pass to each other as edges
+pub type DataFlowGraph = DiGraph<FlowNode, FlowEdge>;
+
+fn max_classification(
+    a: Option<InformationClassification>,
+    b: Option<InformationClassification>,
+) -> Option<InformationClassification> {
+    match (a, b) {
+        (Some(x), Some(y)) => Some(x.max(y)),
+        (x, None) => x,
+        (None, y) => y,
+    }
+}
+
+/// Classification of a field as held by a service
+///
+/// Follows the field back through `processes` to the service that stores it,
+/// falling back to the declared classification of the last service found.
+pub fn field_classification(
+    mfs: &BTreeMap<String, &Manifest>,
+    svc: &str,
+    field: &str,
+) -> Option<InformationClassification> {
+    let mut visited = BTreeSet::new();
+    let mut current = svc.to_string();
+    let mut fallback = None;
+    while visited.insert(current.clone()) {
+        let dh = match mfs.get(&current).and_then(|mf| mf.dataHandling.as_ref()) {
+            Some(dh) => dh,
+            None => break,
+        };
+        fallback = dh.declared_classification().or(fallback);
+        if let Some(store) = dh.store_for(field) {
+            return store.informationClassification.clone().or(fallback);
+        }
+        match dh.source_for(field) {
+            Some(src) => current = src.to_string(),
+            None => break,
+        }
+    }
+    fallback
+}
+
+/// Build the data flow graph for a set of manifests
+///
+/// Classifications propagate along `processes` edges through `field_classification`,
+/// whereas `dependencies` only carry the declared classification of the dependency.
+pub fn build_flow(mfs: &[Manifest]) -> DataFlowGraph {
+    let by_name = mfs
+        .iter()
+        .map(|mf| (mf.name.clone(), mf))
+        .collect::<BTreeMap<_, _>>();
+    let mut graph = DataFlowGraph::new();
+    let mut idx = BTreeMap::new();
+    for mf in mfs {
+        let declared = mf
+            .dataHandling
+            .as_ref()
+            .and_then(|dh| dh.declared_classification());
+        let node = FlowNode {
+            name: mf.name.clone(),
+            declared,
+            received: None,
+        };
+        idx.insert(mf.name.clone(), graph.add_node(node));
+    }
+    for mf in mfs {
+        let to = idx[&mf.name];
+        if let Some(dh) = &mf.dataHandling {
+            let mut by_source: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+            for p in &dh.processes {
+                by_source.entry(&p.source).or_default().push(p.field.clone());
+            }
+            for (src, fields) in by_source {
+                let from = match idx.get(src) {
+                    Some(i) => *i,
+                    None => continue, // not in this region
+                };
+                let classification = fields
+                    .iter()
+                    .map(|f| field_classification(&by_name, src, f))
+                    .fold(None, max_classification);
+                let edge = FlowEdge {
+                    kind: FlowKind::Processes,
+                    fields,
+                    classification,
+                };
+                graph.add_edge(from, to, edge);
+            }
+        }
+        for dep in &mf.dependencies {
+            if let Some(from) = idx.get(&dep.name) {
+                let edge = FlowEdge {
+                    kind: FlowKind::Dependency,
+                    fields: vec![],
+                    classification: graph[*from].declared.clone(),
+                };
+                graph.add_edge(*from, to, edge);
+            }
+        }
+    }
+    for to in graph.node_indices() {
+        let received = graph
+            .edges_directed(to, petgraph::Direction::Incoming)
+            .map(|e| e.weight().classification.clone())
+            .fold(None, max_classification);
+        graph[to].received = received;
+    }
+    graph
+}
+
+/// A data protection problem found in the manifests
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub enum Finding {
+    /// A service processes a field more sensitive than its declared classification
+    UndeclaredClassification {
+        service: String,
+        field: String,
+        classification: InformationClassification,
+    },
+    /// A service processes a field from a service that does not have it
+    UnknownSource {
+        service: String,
+        field: String,
+        source: String,
+    },
+    /// A classified field is stored without encryption
+    Unencrypted {
+        service: String,
+        backend: String,
+        field: String,
+    },
+    /// A field is stored without a retention period
+    NoRetentionPeriod {
+        service: String,
+        backend: String,
+        field: String,
+    },
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Finding::UndeclaredClassification {
+                service,
+                field,
+                classification,
+            } => write!(
+                f,
+                "{} processes {} field {} without declaring it in informationClassification",
+                service,
+                classification.to_string(),
+                field
+            ),
+            Finding::UnknownSource {
+                service,
+                field,
+                source,
+            } => write!(
+                f,
+                "{} processes {} from {} which neither stores nor processes it",
+                service, field, source
+            ),
+            Finding::Unencrypted {
+                service,
+                backend,
+                field,
+            } => {
+                write!(
+                    f,
+                    "{} stores classified field {} unencrypted in {}",
+                    service, field, backend
+                )
+            }
+            Finding::NoRetentionPeriod {
+                service,
+                backend,
+                field,
+            } => write!(
+                f,
+                "{} stores {} in {} without a retentionPeriod",
+                service, field, backend
+            ),
+        }
+    }
+}
+
+/// Find data protection problems in a set of manifests
+pub fn check(mfs: &[Manifest]) -> Vec<Finding> {
+    let by_name = mfs
+        .iter()
+        .map(|mf| (mf.name.clone(), mf))
+        .collect::<BTreeMap<_, _>>();
+    let mut findings = vec![];
+    for mf in mfs {
+        let dh = match &mf.dataHandling {
+            Some(dh) => dh,
+            None => continue,
+        };
+        let declared = dh.declared_classification().map_or(0, |c| c.level());
+        for p in &dh.processes {
+            if let Some(srcmf) = by_name.get(&p.source) {
+                let known = srcmf.dataHandling.as_ref().map_or(false, |sdh| {
+                    sdh.store_for(&p.field).is_some() || sdh.source_for(&p.field).is_some()
+                });
+                if !known {
+                    findings.push(Finding::UnknownSource {
+                        service: mf.name.clone(),
+                        field: p.field.clone(),
+                        source: p.source.clone(),
+                    });
+                }
+            }
+            if let Some(class) = field_classification(&by_name, &p.source, &p.field) {
+                if class.level() > declared {
+                    findings.push(Finding::UndeclaredClassification {
+                        service: mf.name.clone(),
+                        field: p.field.clone(),
+                        classification: class,
+                    });
+                }
+            }
+        }
+        for store in &dh.stores {
+            let class = store
+                .informationClassification
+                .clone()
+                .or_else(|| dh.declared_classification());
+            let classified = class.map_or(true, |c| c.level() > 0);
+            for f in &store.fields {
+                let encrypted = f.encrypted.or(store.encrypted).unwrap_or(false);
+                if classified && !encrypted {
+                    findings.push(Finding::Unencrypted {
+                        service: mf.name.clone(),
+                        backend: store.backend.clone(),
+                        field: f.name.clone(),
+                    });
+                }
+                if f.retentionPeriod.is_none() && store.retentionPeriod.is_none() {
+                    findings.push(Finding::NoRetentionPeriod {
+                        service: mf.name.clone(),
+                        backend: store.backend.clone(),
+                        field: f.name.clone(),
+                    });
+                }
+            }
+        }
+    }
+    findings
+}
+
+/// A row in a Record of Processing Activities
+#[derive(Serialize, Clone, Debug)]
+pub struct RopaEntry {
+    pub service: String,
+    pub team: String,
+    /// `store` or `process`
+    pub activity: String,
+    pub field: String,
+    /// Storage backend for stores, source service for processes
+    pub location: String,
+    pub classification: String,
+    pub encrypted: String,
+    pub retentionPeriod: String,
+}
+
+/// Create the Record of Processing Activities for a set of manifests
+pub fn ropa(mfs: &[Manifest]) -> Vec<RopaEntry> {
+    let by_name = mfs
+        .iter()
+        .map(|mf| (mf.name.clone(), mf))
+        .collect::<BTreeMap<_, _>>();
+    let class_str =
+        |c: Option<InformationClassification>| c.map(|c| c.to_string()).unwrap_or_else(|| "unknown".into());
+    let mut rows = vec![];
+    for mf in mfs {
+        let dh = match &mf.dataHandling {
+            Some(dh) => dh,
+            None => continue,
+        };
+        let team = mf.metadata.as_ref().map(|md| md.team.clone()).unwrap_or_default();
+        for store in &dh.stores {
+            let class = store
+                .informationClassification
+                .clone()
+                .or_else(|| dh.declared_classification());
+            for f in &store.fields {
+                let encrypted = f.encrypted.or(store.encrypted).unwrap_or(false);
+                rows.push(RopaEntry {
+                    service: mf.name.clone(),
+                    team: team.clone(),
+                    activity: "store".into(),
+                    field: f.name.clone(),
+                    location: store.backend.clone(),
+                    classification: class_str(class.clone()),
+                    encrypted: if encrypted { "yes" } else { "no" }.into(),
+                    retentionPeriod: f
+                        .retentionPeriod
+                        .clone()
+                        .or_else(|| store.retentionPeriod.clone())
+                        .unwrap_or_default(),
+                });
+            }
+        }
+        for p in &dh.processes {
+            rows.push(RopaEntry {
+                service: mf.name.clone(),
+                team: team.clone(),
+                activity: "process".into(),
+                field: p.field.clone(),
+                location: p.source.clone(),
+                classification: class_str(field_classification(&by_name, &p.source, &p.field)),
+                encrypted: String::new(),
+                retentionPeriod: String::new(),
+            });
+        }
+    }
+    rows
+}
+
+const ROPA_HEADER: [&str; 8] = [
+    "Service",
+    "Team",
+    "Activity",
+    "Field",
+    "Location",
+    "Classification",
+    "Encrypted",
+    "Retention Period",
+];
+
+fn ropa_columns(r: &RopaEntry) -> [&str; 8] {
+    [
+        &r.service,
+        &r.team,
+        &r.activity,
+        &r.field,
+        &r.location,
+        &r.classification,
+        &r.encrypted,
+        &r.retentionPeriod,
+    ]
+}
+
+/// Format a Record of Processing Activities as a markdown document
+pub fn ropa_markdown(region: &str, rows: &[RopaEntry]) -> String {
+    let mut out = format!("# Record of Processing Activities: {}\n\n", region);
+    out += &format!("| {} |\n", ROPA_HEADER.join(" | "));
+    out += &format!("|{}\n", "---|".repeat(ROPA_HEADER.len()));
+    for r in rows {
+        let cols = ropa_columns(r)
+            .iter()
+            .map(|c| c.replace('|', "\\|"))
+            .collect::<Vec<_>>();
+        out += &format!("| {} |\n", cols.join(" | "));
+    }
+    out
+}
+
+fn csv_escape(s: &str) -> String {
+    if s.contains(|c| c == ',' || c == '"' || c == '\n') {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+/// Format a Record of Processing Activities as CSV
+pub fn ropa_csv(region: &str, rows: &[RopaEntry]) -> String {
+    let mut out = format!("Region,{}\n", ROPA_HEADER.join(","));
+    for r in rows {
+        let cols = ropa_columns(r).iter().map(|c| csv_escape(c)).collect::<Vec<_>>();
+        out += &format!("{},{}\n", csv_escape(region), cols.join(","));
+    }
+    out
+}
+
+async fn load_region(conf: &Config, region: &Region) -> Result<Vec<Manifest>> {
+    let mut mfs = vec![];
+    for s in shipcat_filebacked::available(conf, region).await? {
+        mfs.push(shipcat_filebacked::load_manifest(&s.base.name, conf, region).await?);
+    }
+    Ok(mfs)
+}
+
+/// Generate the data flow graph for a region
+///
+/// Prints it as yaml, or as graphviz dot.
+pub async fn flow(dot: bool, conf: &Config, region: &Region) -> Result<DataFlowGraph> {
+    let graph = build_flow(&load_region(conf, region).await?);
+    let out = if dot {
+        format!("{:?}", dot::Dot::with_config(&graph, &[]))
+    } else {
+        serde_yaml::to_string(&graph)?
+    };
+    println!("{}", out);
+    Ok(graph)
+}
+
+/// Check the data handling of all services in a region
+///
+/// Fails if any problems are found.
+pub async fn verify(conf: &Config, region: &Region) -> Result<()> {
+    let findings = check(&load_region(conf, region).await?);
+    for f in &findings {
+        warn!("{}", f);
+    }
+    if !findings.is_empty() {
+        bail!(
+            "Found {} data handling problems in {}",
+            findings.len(),
+            region.name
+        );
+    }
+    Ok(())
+}
+
+/// Print the Record of Processing Activities for a region
+pub async fn report(csv: bool, conf: &Config, region: &Region) -> Result<()> {
+    let rows = ropa(&load_region(conf, region).await?);
+    if csv {
+        print!("{}", ropa_csv(&region.name, &rows));
+    } else {
+        print!("{}", ropa_markdown(&region.name, &rows));
+    }
+    Ok(())
+}
+
+/// Find the node of a service in a `DataFlowGraph`
+pub fn flow_idx(name: &str, graph: &DataFlowGraph) -> Option<NodeIndex> {
+    graph.node_indices().find(|i| graph[*i].name == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build_flow, check, csv_escape, flow_idx, ropa, ropa_csv, Finding};
+    use shipcat_definitions::{structs::security::InformationClassification, Manifest};
+
+    fn manifests() -> Vec<Manifest> {
+        let mut users = Manifest::test("users");
+        users.dataHandling = serde_yaml::from_str(
+            "
+informationClassification:
+  highestProcessed: confidentialPatientData
+stores:
+- backend: MySQL
+  encrypted: true
+  retentionPeriod: 7y
+  fields:
+  - name: DateOfBirth
+  - name: EmailAddress
+",
+        )
+        .unwrap();
+        let mut mailer = Manifest::test("mailer");
+        mailer.dataHandling = serde_yaml::from_str(
+            "
+informationClassification:
+  highestProcessed: protectedInternal
+stores:
+- backend: Redis
+  fields:
+  - name: DateOfBirth
+processes:
+- field: DateOfBirth
+  source: users
+- field: PhoneNumber
+  source: users
+",
+        )
+        .unwrap();
+        mailer.dependencies = serde_yaml::from_str("- name: users").unwrap();
+        vec![users, mailer]
+    }
+
+    #[test]
+    fn gdpr_flow_propagates_classification() {
+        let graph = build_flow(&manifests());
+        let mailer = flow_idx("mailer", &graph).unwrap();
+        let users = flow_idx("users", &graph).unwrap();
+        assert_eq!(graph.edges_connecting(users, mailer).count(), 2);
+        assert_eq!(
+            graph[mailer].received,
+            Some(InformationClassification::ConfidentialPatientData)
+        );
+        assert_eq!(graph[users].received, None);
+    }
+
+    #[test]
+    fn gdpr_check_findings() {
+        let findings = check(&manifests());
+        assert!(findings.contains(&Finding::UndeclaredClassification {
+            service: "mailer".into(),
+            field: "DateOfBirth".into(),
+            classification: InformationClassification::ConfidentialPatientData,
+        }));
+        assert!(findings.contains(&Finding::UnknownSource {
+            service: "mailer".into(),
+            field: "PhoneNumber".into(),
+            source: "users".into(),
+        }));
+        assert!(findings.contains(&Finding::Unencrypted {
+            service: "mailer".into(),
+            backend: "Redis".into(),
+            field: "DateOfBirth".into(),
+        }));
+        // users stores everything properly
+        assert!(findings.iter().all(|f| !format!("{}", f).starts_with("users")));
+    }
+
+    #[test]
+    fn gdpr_ropa_csv() {
+        let rows = ropa(&manifests());
+        assert_eq!(rows.len(), 5);
+        let csv = ropa_csv("dev-uk", &rows);
+        assert!(csv.starts_with("Region,Service,Team,"));
+        assert!(csv.contains("dev-uk,users,doves,store,DateOfBirth,MySQL,confidentialPatientData,yes,7y"));
+        assert_eq!(csv_escape("a,b"), "\"a,b\"");
+    }
+}
@@ -10,6 +10,7 @@ use tokio::{
 };
 
 use super::Result;
+use crate::strimzi;
 use shipcat_definitions::{Manifest, ReconciliationMode, Region};
 
 pub fn hexists() -> Result<()> {
@@ -107,11 +108,19 @@ pub async fn template(mf: &Manifest, output: Option<PathBuf>) -> Result<String>
         hfile.clone(),
     ];
     // NB: this call does NOT need --tiller-namespace (offline call)
-    let (tpl, tplerr, success) = hout(tplvec.clone()).await?;
+    let (mut tpl, tplerr, success) = hout(tplvec.clone()).await?;
     if !success {
         warn!("{} stderr: {}", tplvec.join(" "), tplerr);
         bail!("helm template failed");
     }
+    // kafka resources are rendered by shipcat rather than the chart
+    let kafka = strimzi::template(&mf)?;
+    if !kafka.is_empty() {
+        if !tpl.ends_with('\n') {
+            tpl.push('\n');
+        }
+        tpl += &kafka;
+    }
     if let Some(o) = &output {
         let pth = Path::new(".").join(o);
         debug!("Writing helm template for {} to {}", mf.name, pth.display());
@@ -0,0 +1,340 @@
+//- Audit history of services kept in the cluster
+use crate::{apply::UpgradeInfo, kubeapi::ShipKube, track::format_duration, webhooks::UpgradeState, Result};
+use chrono::{DateTime, Duration, Utc};
+use k8s_openapi::{api::core::v1::ConfigMap, apimachinery::pkg::apis::meta::v1::ObjectMeta};
+use shipcat_definitions::{status::Applier, Region};
+use std::{collections::BTreeMap, fmt};
+
+/// Number of entries kept per service
+pub const HISTORY_LIMIT: usize = 50;
+/// Key of the entries inside the history ConfigMap
+const HISTORY_KEY: &str = "history";
+
+/// The type of action recorded
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum HistoryAction {
+    Deployment,
+    /// An apply made while reconciling the whole region
+    Reconciliation,
+    Deletion,
+}
+
+/// Line counts of the kubernetes diff of an action
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+pub struct DiffSummary {
+    pub additions: usize,
+    pub deletions: usize,
+}
+
+impl DiffSummary {
+    /// Count changed lines of a unified diff (ignoring file headers)
+    pub fn new(diff: &str) -> Self {
+        let mut summary = DiffSummary::default();
+        for l in diff.lines() {
+            if l.starts_with("+++") || l.starts_with("---") {
+                continue;
+            }
+            if l.starts_with('+') {
+                summary.additions += 1;
+            } else if l.starts_with('-') {
+                summary.deletions += 1;
+            }
+        }
+        summary
+    }
+}
+
+impl fmt::Display for DiffSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "+{} -{}", self.additions, self.deletions)
+    }
+}
+
+/// A finished action against a service
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryEntry {
+    pub timestamp: DateTime<Utc>,
+    pub service: String,
+    pub region: String,
+    pub action: HistoryAction,
+    pub version: String,
+    pub applier: Applier,
+    /// Why the action happened (an `UpgradeReason`) if known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    pub outcome: UpgradeState,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub diff: Option<DiffSummary>,
+}
+
+impl HistoryEntry {
+    pub fn new(action: HistoryAction, us: &UpgradeState, info: &UpgradeInfo, applier: Applier) -> Self {
+        HistoryEntry {
+            timestamp: Utc::now(),
+            service: info.name.clone(),
+            region: info.region.clone(),
+            action,
+            version: info.version.clone(),
+            applier,
+            reason: info.reason.clone(),
+            outcome: us.clone(),
+            diff: info.diff.as_ref().map(|d| DiffSummary::new(d)),
+        }
+    }
+}
+
+/// Append to a ring buffer of entries, dropping the oldest beyond `limit`
+fn push(entries: &mut Vec<HistoryEntry>, entry: HistoryEntry, limit: usize) {
+    entries.push(entry);
+    if entries.len() > limit {
+        let excess = entries.len() - limit;
+        entries.drain(..excess);
+    }
+}
+
+/// Criteria for `shipcat history`
+#[derive(Default)]
+pub struct HistoryFilter {
+    /// Only entries from this region
+    pub region: Option<String>,
+    /// Only entries newer than this
+    pub since: Option<DateTime<Utc>>,
+}
+
+impl HistoryFilter {
+    fn matches(&self, e: &HistoryEntry) -> bool {
+        if let Some(r) = &self.region {
+            if &e.region != r {
+                return false;
+            }
+        }
+        if let Some(since) = self.since {
+            if e.timestamp < since {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Matching entries, newest first
+    pub fn apply<'a>(&self, entries: &'a [HistoryEntry]) -> Vec<&'a HistoryEntry> {
+        entries.iter().rev().filter(|e| self.matches(e)).collect()
+    }
+}
+
+/// Parse a `--since` argument relative to `now`
+///
+/// Accepts an RFC3339 timestamp, or a duration like `30m`, `12h`, `7d`.
+pub fn parse_since(since: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>> {
+    if let Ok(ts) = DateTime::parse_from_rfc3339(since) {
+        return Ok(ts.with_timezone(&Utc));
+    }
+    let (num, unit) = since.split_at(since.len().saturating_sub(1));
+    let n: i64 = match num.parse() {
+        Ok(n) => n,
+        Err(_) => bail!(
+            "Invalid time '{}' - use a timestamp or a duration like 12h",
+            since
+        ),
+    };
+    let dur = match unit {
+        "s" => Duration::seconds(n),
+        "m" => Duration::minutes(n),
+        "h" => Duration::hours(n),
+        "d" => Duration::days(n),
+        "w" => Duration::weeks(n),
+        _ => bail!("Invalid duration unit in '{}' - use one of s, m, h, d, w", since),
+    };
+    Ok(now - dur)
+}
+
+fn configmap_name(svc: &str) -> String {
+    format!("shipcat-history-{}", svc)
+}
+
+fn parse_entries(cm: &ConfigMap) -> Result<Vec<HistoryEntry>> {
+    match cm.data.as_ref().and_then(|d| d.get(HISTORY_KEY)) {
+        Some(raw) => Ok(serde_json::from_str(raw)?),
+        None => Ok(vec![]),
+    }
+}
+
+/// Record a finished action in the history ConfigMap of the service
+///
+/// The ConfigMap is deliberately not owned by the ShipcatManifest,
+/// so that history survives the deletion of a service.
+/// Uses the client of the action being recorded (scoped to the service's namespace).
+pub async fn record(
+    kube: &ShipKube,
+    action: HistoryAction,
+    us: &UpgradeState,
+    info: &UpgradeInfo,
+) -> Result<()> {
+    let entry = HistoryEntry::new(action, us, info, kube.applier.clone());
+    let name = configmap_name(&info.name);
+    // optimistic concurrency; re-read on conflicting writes
+    for _ in 0..3 {
+        let mut cm = match kube.get_configmap(&name).await? {
+            Some(cm) => cm,
+            None => {
+                let mut labels = BTreeMap::new();
+                labels.insert("shipcat-history-for".to_string(), info.name.clone());
+                ConfigMap {
+                    metadata: Some(ObjectMeta {
+                        name: Some(name.clone()),
+                        namespace: Some(info.namespace.clone()),
+                        labels: Some(labels),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }
+            }
+        };
+        let mut entries = parse_entries(&cm)?;
+        push(&mut entries, entry.clone(), HISTORY_LIMIT);
+        cm.data
+            .get_or_insert_with(BTreeMap::new)
+            .insert(HISTORY_KEY.into(), serde_json::to_string(&entries)?);
+        if kube.write_configmap(&cm).await? {
+            return Ok(());
+        }
+        debug!("Conflict writing {}, retrying", name);
+    }
+    bail!(
+        "Failed to record history for {} due to conflicting writes",
+        info.name
+    )
+}
+
+/// Fetch the recorded history of a service in a namespace
+pub async fn fetch(svc: &str, namespace: &str) -> Result<Vec<HistoryEntry>> {
+    let kube = ShipKube::new_within(svc, namespace).await?;
+    match kube.get_configmap(&configmap_name(svc)).await? {
+        Some(cm) => parse_entries(&cm),
+        None => Ok(vec![]),
+    }
+}
+
+/// Entry point for `shipcat history`
+pub async fn show(svc: &str, reg: &Region, filter: &HistoryFilter) -> Result<()> {
+    let entries = fetch(svc, &reg.namespace).await?;
+    let matching = filter.apply(&entries);
+    if matching.is_empty() {
+        info!("No recorded history for {} in {}", svc, reg.name);
+        return Ok(());
+    }
+    let now = Utc::now();
+    println!(
+        "{0:<6} {1:<14} {2:<14} {3:<12} {4:<10} {5:<16} {6:<12} {7}",
+        "AGE", "REGION", "ACTION", "VERSION", "OUTCOME", "REASON", "DIFF", "APPLIER"
+    );
+    for e in matching {
+        println!(
+            "{0:<6} {1:<14} {2:<14} {3:<12} {4:<10} {5:<16} {6:<12} {7}",
+            format_duration(now - e.timestamp),
+            e.region,
+            format!("{:?}", e.action),
+            e.version,
+            format!("{:?}", e.outcome),
+            e.reason.clone().unwrap_or_else(|| "-".into()),
+            e.diff
+                .as_ref()
+                .map(|d| d.to_string())
+                .unwrap_or_else(|| "-".into()),
+            e.applier.name
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_since, push, DiffSummary, HistoryAction, HistoryEntry, HistoryFilter};
+    use crate::webhooks::UpgradeState;
+    use chrono::{Duration, TimeZone, Utc};
+    use shipcat_definitions::status::Applier;
+
+    fn entry(version: &str, region: &str, hours_ago: i64) -> HistoryEntry {
+        HistoryEntry {
+            timestamp: Utc.ymd(2020, 3, 1).and_hms(12, 0, 0) - Duration::hours(hours_ago),
+            service: "webapp".into(),
+            region: region.into(),
+            action: HistoryAction::Deployment,
+            version: version.into(),
+            applier: Applier {
+                name: "deploy#12".into(),
+                url: None,
+            },
+            reason: Some("VersionChange".into()),
+            outcome: UpgradeState::Completed,
+            diff: None,
+        }
+    }
+
+    #[test]
+    fn history_ring_buffer() {
+        let mut entries = vec![];
+        for i in 0..5 {
+            push(&mut entries, entry(&format!("1.0.{}", i), "dev-uk", 0), 3);
+        }
+        let versions = entries.iter().map(|e| e.version.as_str()).collect::<Vec<_>>();
+        assert_eq!(versions, vec!["1.0.2", "1.0.3", "1.0.4"]);
+    }
+
+    #[test]
+    fn history_filter() {
+        let entries = vec![
+            entry("1.0.0", "dev-uk", 48),
+            entry("1.0.1", "dev-ie", 5),
+            entry("1.0.2", "dev-uk", 1),
+        ];
+        let now = Utc.ymd(2020, 3, 1).and_hms(12, 0, 0);
+        let filter = HistoryFilter {
+            region: Some("dev-uk".into()),
+            since: Some(parse_since("1d", now).unwrap()),
+        };
+        let res = filter.apply(&entries);
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].version, "1.0.2");
+        // newest first without criteria
+        let all = HistoryFilter::default().apply(&entries);
+        assert_eq!(all[0].version, "1.0.2");
+        assert_eq!(all.len(), 3);
+    }
+
+    #[test]
+    fn history_since() {
+        let now = Utc.ymd(2020, 3, 1).and_hms(12, 0, 0);
+        assert_eq!(parse_since("30m", now).unwrap(), now - Duration::minutes(30));
+        assert_eq!(parse_since("2w", now).unwrap(), now - Duration::weeks(2));
+        assert_eq!(
+            parse_since("2020-02-28T10:00:00Z", now).unwrap(),
+            Utc.ymd(2020, 2, 28).and_hms(10, 0, 0)
+        );
+        assert!(parse_since("yesterday", now).is_err());
+        assert!(parse_since("5y", now).is_err());
+        assert!(parse_since("", now).is_err());
+    }
+
+    #[test]
+    fn history_diff_summary() {
+        let diff =
+            "--- /tmp/a\n+++ /tmp/b\n@@ -1,2 +1,2 @@\n-  image: a:1\n+  image: a:2\n+  foo: bar\n   name: a";
+        let summary = DiffSummary::new(diff);
+        assert_eq!(summary, DiffSummary {
+            additions: 2,
+            deletions: 1
+        });
+        assert_eq!(summary.to_string(), "+2 -1");
+    }
+
+    #[test]
+    fn history_entry_roundtrip() {
+        let e = entry("1.0.0", "dev-uk", 0);
+        let encoded = serde_json::to_string(&e).unwrap();
+        assert!(encoded.contains("\"outcome\":\"COMPLETED\""));
+        let decoded: HistoryEntry = serde_json::from_str(&encoded).unwrap();
+        assert_eq!(decoded, e);
+    }
+}
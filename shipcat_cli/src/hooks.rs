@@ -0,0 +1,407 @@
+//- kubeapi module to run jobs around an apply
+use crate::{kubeapi::ShipKube, track::PodSummary, ErrorKind, Manifest, Result};
+use futures::StreamExt;
+use futures_timer::Delay;
+use k8s_openapi::{
+    api::{
+        batch::v1::{Job, JobSpec},
+        core::v1::{Container, EnvVar, EnvVarSource, PodSpec, PodTemplateSpec, Secret, SecretKeySelector},
+    },
+    apimachinery::pkg::apis::meta::v1::{ObjectMeta, OwnerReference},
+};
+use shipcat_definitions::structs::HookJob;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    convert::TryFrom,
+    fmt,
+    time::Duration,
+};
+
+/// When a hook runs relative to an apply
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HookStage {
+    /// Before the new version is applied
+    PreApply,
+    /// After the new version has rolled out
+    PostApply,
+}
+
+impl fmt::Display for HookStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HookStage::PreApply => write!(f, "preApply"),
+            HookStage::PostApply => write!(f, "postApply"),
+        }
+    }
+}
+
+impl HookStage {
+    /// Status condition reason for a failed hook of this stage
+    pub fn failure_reason(self) -> &'static str {
+        match self {
+            HookStage::PreApply => "PreApplyHookFailure",
+            HookStage::PostApply => "PostApplyHookFailure",
+        }
+    }
+
+    /// What a failed hook of this stage means for the upgrade
+    ///
+    /// Pre apply hooks abort the upgrade before it is applied,
+    /// while post apply hooks fail an upgrade that has already rolled out.
+    pub fn failure_outcome(self, svc: &str) -> String {
+        match self {
+            HookStage::PreApply => format!(
+                "aborted before applying: {} is still on its previous version",
+                svc
+            ),
+            HookStage::PostApply => format!("failed after rolling out: {} is running the new version", svc),
+        }
+    }
+}
+
+/// How a job ended
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum JobOutcome {
+    Succeeded,
+    /// Failed with the reason of its `Failed` condition
+    Failed(String),
+    /// Still running after the timeout
+    TimedOut(u32),
+}
+
+impl fmt::Display for JobOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JobOutcome::Succeeded => write!(f, "succeeded"),
+            JobOutcome::Failed(reason) => write!(f, "failed ({})", reason),
+            JobOutcome::TimedOut(secs) => write!(f, "timed out after {}s", secs),
+        }
+    }
+}
+
+fn stage_hooks(mf: &Manifest, stage: HookStage) -> &[HookJob] {
+    match stage {
+        HookStage::PreApply => &mf.hooks.preApply,
+        HookStage::PostApply => &mf.hooks.postApply,
+    }
+}
+
+/// Name of the secret holding the secrets referenced by hooks
+///
+/// This is separate from the chart's secret because pre apply hooks
+/// need secrets for the version that is not yet applied.
+fn secret_name(mf: &Manifest) -> String {
+    format!("{}-hook-secrets", mf.name)
+}
+
+fn labels(mf: &Manifest, stage: HookStage) -> BTreeMap<String, String> {
+    // NB: no `app` label so hook pods are not mistaken for the main workload
+    let mut labels = BTreeMap::new();
+    labels.insert("shipcat-hook-for".to_string(), mf.name.clone());
+    labels.insert("shipcat-hook-stage".to_string(), stage.to_string());
+    labels
+}
+
+/// Metadata for objects owned by the ShipcatManifest (when it has a uid)
+pub(crate) fn owned_metadata(mf: &Manifest, name: &str, labels: BTreeMap<String, String>) -> ObjectMeta {
+    let owner_references = mf.uid.clone().map(|uid| {
+        vec![OwnerReference {
+            api_version: "babylontech.co.uk/v1".into(),
+            kind: "ShipcatManifest".into(),
+            name: mf.name.clone(),
+            uid,
+            controller: Some(false),
+            block_owner_deletion: None,
+        }]
+    });
+    ObjectMeta {
+        name: Some(name.to_string()),
+        namespace: Some(mf.namespace.clone()),
+        labels: Some(labels),
+        owner_references,
+        ..Default::default()
+    }
+}
+
+fn metadata(mf: &Manifest, name: &str, stage: HookStage) -> ObjectMeta {
+    owned_metadata(mf, name, labels(mf, stage))
+}
+
+/// Create the secret for the hooks of a stage
+///
+/// Returns None if the hooks do not reference any secrets.
+fn make_secret(mf: &Manifest, stage: HookStage) -> Option<Secret> {
+    let keys = stage_hooks(mf, stage)
+        .iter()
+        .flat_map(|h| h.container.env.secrets.iter())
+        .collect::<BTreeSet<_>>();
+    if keys.is_empty() {
+        return None;
+    }
+    let data = keys
+        .into_iter()
+        .filter_map(|k| mf.secrets.get(k).map(|v| (k.clone(), v.clone())))
+        .collect();
+    Some(Secret {
+        metadata: Some(metadata(mf, &secret_name(mf), stage)),
+        string_data: Some(data),
+        ..Default::default()
+    })
+}
+
+/// Create the kubernetes Job for a hook
+fn make_job(mf: &Manifest, hook: &HookJob, stage: HookStage) -> Result<Job> {
+    let c = &hook.container;
+    let image = format!(
+        "{}:{}",
+        c.image.clone().or_else(|| mf.image.clone()).unwrap_or_default(),
+        c.version
+            .clone()
+            .or_else(|| mf.version.clone())
+            .unwrap_or_default()
+    );
+    let mut env = c
+        .env
+        .plain
+        .iter()
+        .map(|(k, v)| EnvVar {
+            name: k.clone(),
+            value: Some(v.clone()),
+            value_from: None,
+        })
+        .collect::<Vec<_>>();
+    for k in &c.env.secrets {
+        env.push(EnvVar {
+            name: k.clone(),
+            value: None,
+            value_from: Some(EnvVarSource {
+                secret_key_ref: Some(SecretKeySelector {
+                    name: Some(secret_name(mf)),
+                    key: k.clone(),
+                    optional: None,
+                }),
+                ..Default::default()
+            }),
+        });
+    }
+    // Shipcat structs for these are straight translations of the kube ones
+    let resources = match &c.resources {
+        Some(r) => Some(serde_json::from_value(serde_json::to_value(r)?)?),
+        None => None,
+    };
+    let volume_mounts = serde_json::from_value(serde_json::to_value(&c.volume_mounts)?)?;
+    let mounted = c.volume_mounts.iter().map(|vm| &vm.name).collect::<BTreeSet<_>>();
+    let volumes = mf
+        .volumes
+        .iter()
+        .filter(|v| mounted.contains(&v.name))
+        .collect::<Vec<_>>();
+    let volumes = serde_json::from_value(serde_json::to_value(&volumes)?)?;
+
+    let container = Container {
+        name: c.name.clone(),
+        image: Some(image),
+        command: if c.command.is_empty() {
+            None
+        } else {
+            Some(c.command.clone())
+        },
+        env: Some(env),
+        resources,
+        volume_mounts: Some(volume_mounts),
+        ..Default::default()
+    };
+    let annotations = if hook.podAnnotations.is_empty() {
+        None
+    } else {
+        Some(hook.podAnnotations.clone())
+    };
+    Ok(Job {
+        metadata: Some(metadata(mf, &c.name, stage)),
+        spec: Some(JobSpec {
+            active_deadline_seconds: Some(hook.timeout().into()),
+            backoff_limit: hook.backoffLimit.map(i32::from),
+            template: PodTemplateSpec {
+                metadata: Some(ObjectMeta {
+                    labels: Some(labels(mf, stage)),
+                    annotations,
+                    ..Default::default()
+                }),
+                spec: Some(PodSpec {
+                    containers: vec![container],
+                    volumes: Some(volumes),
+                    restart_policy: Some("Never".into()),
+                    ..Default::default()
+                }),
+            },
+            ..Default::default()
+        }),
+        ..Default::default()
+    })
+}
+
+/// Wait for any previous run of a job to be removed
+pub(crate) async fn remove_previous(kube: &ShipKube, name: &str) -> Result<()> {
+    kube.delete_job(name).await?;
+    for _ in 0..60 {
+        if kube.get_job(name).await?.is_none() {
+            return Ok(());
+        }
+        Delay::new(Duration::from_secs(1)).await;
+    }
+    bail!("Timed out waiting for previous job {} to be removed", name)
+}
+
+/// Poll a job until it succeeds, fails, or times out
+pub(crate) async fn wait_for_job(kube: &ShipKube, name: &str, timeout: u32) -> Result<JobOutcome> {
+    let poll = 2;
+    for _ in 0..(timeout / poll + 1) {
+        Delay::new(Duration::from_secs(poll.into())).await;
+        let status = match kube.get_job(name).await? {
+            Some(j) => j.status.unwrap_or_default(),
+            None => bail!("Job {} disappeared", name),
+        };
+        if status.succeeded.unwrap_or(0) > 0 {
+            return Ok(JobOutcome::Succeeded);
+        }
+        let failed = status
+            .conditions
+            .unwrap_or_default()
+            .into_iter()
+            .find(|c| c.type_ == "Failed" && c.status == "True");
+        if let Some(c) = failed {
+            let reason = c.reason.or(c.message).unwrap_or_else(|| "unknown".into());
+            return Ok(JobOutcome::Failed(reason));
+        }
+    }
+    warn!("Timed out waiting {}s for job {}", timeout, name);
+    Ok(JobOutcome::TimedOut(timeout))
+}
+
+/// Log the pods of a failed hook along with their logs
+async fn debug_job(kube: &ShipKube, name: &str) -> Result<()> {
+    for pod in kube.get_pods_by_job(name).await? {
+        let podstate = PodSummary::try_from(pod)?;
+        warn!("{:?}", podstate);
+        let mut lines = match kube.stream_container_logs(&podstate.name, name).await {
+            Ok(lines) => lines,
+            Err(e) => {
+                warn!("Failed to get logs from {}: {}", podstate.name, e);
+                continue;
+            }
+        };
+        while let Some(line) = lines.next().await {
+            match line {
+                Ok(l) => info!("{}", l),
+                Err(e) => warn!("Log stream from {} ended: {}", podstate.name, e),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Run all the hooks of a stage in order
+///
+/// Stops at the first hook that does not complete.
+pub async fn run(mf: &Manifest, kube: &ShipKube, stage: HookStage) -> Result<()> {
+    let hooks = stage_hooks(mf, stage);
+    if hooks.is_empty() {
+        return Ok(());
+    }
+    if let Some(secret) = make_secret(mf, stage) {
+        kube.recreate_secret(&secret).await?;
+    }
+    for h in hooks {
+        let name = &h.container.name;
+        let job = make_job(mf, h, stage)?;
+        remove_previous(kube, name).await?;
+        info!("Running {} hook {} for {}", stage, name, mf.name);
+        kube.create_job(&job).await?;
+        let outcome = wait_for_job(kube, name, h.timeout()).await?;
+        if outcome != JobOutcome::Succeeded {
+            let _ = debug_job(kube, name).await;
+            let reason = format!("{} - {}", outcome, stage.failure_outcome(&mf.name));
+            return Err(
+                ErrorKind::HookFailure(mf.name.clone(), name.clone(), stage.to_string(), reason).into(),
+            );
+        }
+        info!("Completed {} hook {}", stage, name);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{make_job, make_secret, HookStage, JobOutcome};
+    use crate::ErrorKind;
+    use shipcat_definitions::{
+        structs::{Container, HookJob},
+        Manifest,
+    };
+
+    #[test]
+    fn hook_job_spec() {
+        let mut mf = Manifest::test("webapp");
+        mf.namespace = "apps".into();
+        mf.image = Some("quay.io/babylonhealth/webapp".into());
+        mf.version = Some("1.2.3".into());
+        mf.secrets
+            .insert("DATABASE_URL".into(), "postgres://secret".into());
+        let mut container = Container {
+            name: "webapp-migrate".into(),
+            command: vec!["migrate".into()],
+            ..Default::default()
+        };
+        container.env.plain.insert("MODE".into(), "up".into());
+        container.env.secrets.insert("DATABASE_URL".into());
+        mf.hooks.preApply.push(HookJob {
+            container,
+            ..Default::default()
+        });
+
+        let job = make_job(&mf, &mf.hooks.preApply[0], HookStage::PreApply).unwrap();
+        let job = serde_json::to_value(job).unwrap();
+        assert_eq!(job["metadata"]["name"], "webapp-migrate");
+        assert!(job["metadata"]["labels"].get("app").is_none());
+        assert_eq!(job["spec"]["activeDeadlineSeconds"], 600);
+        let c = &job["spec"]["template"]["spec"]["containers"][0];
+        assert_eq!(c["image"], "quay.io/babylonhealth/webapp:1.2.3");
+        assert_eq!(c["env"][0]["value"], "up");
+        assert_eq!(
+            c["env"][1]["valueFrom"]["secretKeyRef"]["name"],
+            "webapp-hook-secrets"
+        );
+
+        let secret = make_secret(&mf, HookStage::PreApply).unwrap();
+        assert_eq!(secret.string_data.unwrap()["DATABASE_URL"], "postgres://secret");
+        assert!(make_secret(&mf, HookStage::PostApply).is_none());
+    }
+
+    #[test]
+    fn hook_failure_reasons() {
+        let failure = |stage: HookStage, outcome: JobOutcome| {
+            let reason = format!("{} - {}", outcome, stage.failure_outcome("webapp"));
+            ErrorKind::HookFailure(
+                "webapp".into(),
+                "webapp-migrate".into(),
+                stage.to_string(),
+                reason,
+            )
+            .to_string()
+        };
+        assert_eq!(
+            failure(
+                HookStage::PreApply,
+                JobOutcome::Failed("BackoffLimitExceeded".into())
+            ),
+            "preApply hook webapp-migrate of webapp failed (BackoffLimitExceeded) - \
+             aborted before applying: webapp is still on its previous version"
+        );
+        assert_eq!(
+            failure(HookStage::PostApply, JobOutcome::TimedOut(600)),
+            "postApply hook webapp-migrate of webapp timed out after 600s - \
+             failed after rolling out: webapp is running the new version"
+        );
+        assert_eq!(HookStage::PreApply.failure_reason(), "PreApplyHookFailure");
+    }
+}
@@ -1,10 +1,15 @@
-use crate::{ErrorKind, Manifest, Result};
+use crate::{logs, ErrorKind, Manifest, Result};
+use futures::stream::{BoxStream, StreamExt};
 use k8s_openapi::api::{
     apps::v1::{Deployment, ReplicaSet, StatefulSet},
-    core::v1::Pod,
+    batch::v1::Job,
+    core::v1::{ConfigMap, Pod, Secret},
 };
 use kube::{
-    api::{Api, DeleteParams, ListParams, LogParams, Object, ObjectList, PatchParams, Resource},
+    api::{
+        Api, DeleteParams, ListParams, LogParams, Meta, Object, ObjectList, PatchParams, PostParams,
+        PropagationPolicy, Resource,
+    },
     client::APIClient,
 };
 use shipcat_definitions::{
@@ -15,7 +20,7 @@ use shipcat_definitions::{
 /// Client creator
 ///
 /// TODO: embed inside shipcat::apply when needed for other things
-async fn make_client() -> Result<APIClient> {
+pub(crate) async fn make_client() -> Result<APIClient> {
     let config = if let Ok(cfg) = kube::config::incluster_config() {
         cfg
     } else {
@@ -25,6 +30,18 @@ async fn make_client() -> Result<APIClient> {
     };
     Ok(kube::client::APIClient::new(config))
 }
+
+/// Client creator for an explicit kube context
+async fn make_context_client(context: &str) -> Result<APIClient> {
+    let options = kube::config::ConfigOptions {
+        context: Some(context.to_string()),
+        ..Default::default()
+    };
+    let config = kube::config::load_kube_config_with(options)
+        .await
+        .map_err(ErrorKind::KubeError)?;
+    Ok(kube::client::APIClient::new(config))
+}
 #[derive(Clone, Serialize, Deserialize)]
 pub struct MinimalManifest {
     pub name: String,
@@ -47,17 +64,27 @@ impl ShipKube {
     pub async fn new_within(svc: &str, ns: &str) -> Result<Self> {
         // hide the client in here -> Api resource for now (not needed elsewhere)
         let client = make_client().await?;
+        Ok(Self::from_client(client, svc, ns))
+    }
+
+    /// Constructor for a kube context other than the current one
+    pub async fn new_in_context(svc: &str, ns: &str, context: &str) -> Result<Self> {
+        let client = make_context_client(context).await?;
+        Ok(Self::from_client(client, svc, ns))
+    }
+
+    fn from_client(client: APIClient, svc: &str, ns: &str) -> Self {
         let mfs = Resource::namespaced::<ShipcatManifest>(ns);
         let api = Api::namespaced(client.clone(), ns);
 
-        Ok(Self {
+        Self {
             name: svc.to_string(),
             namespace: ns.to_string(),
             applier: Applier::infer(),
             api,
             client,
             mfs,
-        })
+        }
     }
 
     pub async fn new(mf: &Manifest) -> Result<Self> {
@@ -147,18 +174,164 @@ impl ShipKube {
         Ok(pods)
     }
 
+    // helper to get statefulset pods by controller revision
+    pub async fn get_pods_by_revision(&self, revision: &str) -> Result<ObjectList<Pod>> {
+        let api: Api<Pod> = Api::namespaced(self.client.clone(), &self.namespace);
+        let lp = ListParams {
+            label_selector: Some(format!("app={},controller-revision-hash={}", self.name, revision)),
+            ..Default::default()
+        };
+        let pods = api.list(&lp).await.map_err(ErrorKind::KubeError)?;
+        Ok(pods)
+    }
+
+    // helper to delete a pod (so its controller replaces it)
+    pub async fn delete_pod(&self, podname: &str) -> Result<()> {
+        let api: Api<Pod> = Api::namespaced(self.client.clone(), &self.namespace);
+        let dp = DeleteParams::default();
+        api.delete(podname, &dp).await.map_err(ErrorKind::KubeError)?;
+        Ok(())
+    }
+
     // helper to get pod logs
     pub async fn get_pod_logs(&self, podname: &str) -> Result<String> {
+        self.get_container_logs(podname, &self.name).await
+    }
+
+    // helper to get logs from a specific container in a pod
+    pub async fn get_container_logs(&self, podname: &str, container: &str) -> Result<String> {
         let api: Api<Pod> = Api::namespaced(self.client.clone(), &self.namespace);
         let lp = LogParams {
             tail_lines: Some(30),
-            container: Some(self.name.to_string()),
+            container: Some(container.to_string()),
             ..Default::default()
         };
         let logs = api.logs(podname, &lp).await.map_err(ErrorKind::KubeError)?;
         Ok(logs)
     }
 
+    // helper to stream all the logs from a specific container in a pod as prefixed lines
+    pub async fn stream_container_logs(
+        &self,
+        podname: &str,
+        container: &str,
+    ) -> Result<BoxStream<'static, Result<String>>> {
+        let api: Api<Pod> = Api::namespaced(self.client.clone(), &self.namespace);
+        let lp = LogParams {
+            container: Some(container.to_string()),
+            ..Default::default()
+        };
+        let chunks = api.log_stream(podname, &lp).await.map_err(ErrorKind::KubeError)?;
+        let prefix = format!("{}/{}", podname, container);
+        Ok(logs::prefixed_lines(chunks, prefix).boxed())
+    }
+
+    // helper to get the full logs of the latest pod created by a job
+    pub async fn get_job_logs(&self, job: &str) -> Result<String> {
+        let api: Api<Pod> = Api::namespaced(self.client.clone(), &self.namespace);
+        let pods = self.get_pods_by_job(job).await?;
+        let latest = pods
+            .into_iter()
+            .filter_map(|p| p.metadata)
+            .max_by_key(|md| md.creation_timestamp.as_ref().map(|t| t.0));
+        let name = match latest.and_then(|md| md.name) {
+            Some(n) => n,
+            None => bail!("No pods found for job {}", job),
+        };
+        let logs = api
+            .logs(&name, &LogParams::default())
+            .await
+            .map_err(ErrorKind::KubeError)?;
+        Ok(logs)
+    }
+
+    // helper to get pods created by a job
+    pub async fn get_pods_by_job(&self, job: &str) -> Result<ObjectList<Pod>> {
+        let api: Api<Pod> = Api::namespaced(self.client.clone(), &self.namespace);
+        let lp = ListParams {
+            label_selector: Some(format!("job-name={}", job)),
+            ..Default::default()
+        };
+        let pods = api.list(&lp).await.map_err(ErrorKind::KubeError)?;
+        Ok(pods)
+    }
+
+    // helper to get a job (if it exists)
+    pub async fn get_job(&self, name: &str) -> Result<Option<Job>> {
+        let api: Api<Job> = Api::namespaced(self.client.clone(), &self.namespace);
+        match api.get(name).await {
+            Ok(j) => Ok(Some(j)),
+            Err(kube::Error::Api(e)) if e.code == 404 => Ok(None),
+            Err(e) => Err(ErrorKind::KubeError(e).into()),
+        }
+    }
+
+    // helper to create a job
+    pub async fn create_job(&self, job: &Job) -> Result<()> {
+        let api: Api<Job> = Api::namespaced(self.client.clone(), &self.namespace);
+        api.create(&PostParams::default(), job)
+            .await
+            .map_err(ErrorKind::KubeError)?;
+        Ok(())
+    }
+
+    // helper to delete a job along with its pods (if it exists)
+    pub async fn delete_job(&self, name: &str) -> Result<()> {
+        let api: Api<Job> = Api::namespaced(self.client.clone(), &self.namespace);
+        let dp = DeleteParams {
+            propagation_policy: Some(PropagationPolicy::Background),
+            ..Default::default()
+        };
+        match api.delete(name, &dp).await {
+            Ok(_) => Ok(()),
+            Err(kube::Error::Api(e)) if e.code == 404 => Ok(()),
+            Err(e) => Err(ErrorKind::KubeError(e).into()),
+        }
+    }
+
+    // helper to replace a secret wholesale
+    pub async fn recreate_secret(&self, secret: &Secret) -> Result<()> {
+        let api: Api<Secret> = Api::namespaced(self.client.clone(), &self.namespace);
+        let name = Meta::name(secret);
+        match api.delete(&name, &DeleteParams::default()).await {
+            Ok(_) => {}
+            Err(kube::Error::Api(e)) if e.code == 404 => {}
+            Err(e) => return Err(ErrorKind::KubeError(e).into()),
+        }
+        api.create(&PostParams::default(), secret)
+            .await
+            .map_err(ErrorKind::KubeError)?;
+        Ok(())
+    }
+
+    // helper to get a configmap (if it exists)
+    pub async fn get_configmap(&self, name: &str) -> Result<Option<ConfigMap>> {
+        let api: Api<ConfigMap> = Api::namespaced(self.client.clone(), &self.namespace);
+        match api.get(name).await {
+            Ok(cm) => Ok(Some(cm)),
+            Err(kube::Error::Api(e)) if e.code == 404 => Ok(None),
+            Err(e) => Err(ErrorKind::KubeError(e).into()),
+        }
+    }
+
+    // helper to write a configmap
+    //
+    // Replaces if it carries a resourceVersion, creates otherwise.
+    // Returns false on a write conflict so callers can re-read and retry.
+    pub async fn write_configmap(&self, cm: &ConfigMap) -> Result<bool> {
+        let api: Api<ConfigMap> = Api::namespaced(self.client.clone(), &self.namespace);
+        let res = if Meta::resource_ver(cm).is_some() {
+            api.replace(&Meta::name(cm), &PostParams::default(), cm).await
+        } else {
+            api.create(&PostParams::default(), cm).await
+        };
+        match res {
+            Ok(_) => Ok(true),
+            Err(kube::Error::Api(e)) if e.code == 409 => Ok(false),
+            Err(e) => Err(ErrorKind::KubeError(e).into()),
+        }
+    }
+
     // helper to get rs data
     pub async fn get_rs(&self) -> Result<ObjectList<ReplicaSet>> {
         let api: Api<ReplicaSet> = Api::namespaced(self.client.clone(), &self.namespace);
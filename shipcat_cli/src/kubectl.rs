@@ -1,4 +1,5 @@
 use super::{ErrorKind, Manifest, Result};
+use crate::logs::{LogSource, PodSelector};
 use kube::{
     api::{Api, PostParams},
     client::APIClient,
@@ -141,18 +142,29 @@ pub async fn use_context(context: &str) -> Result<String> {
 }
 
 /// Shell into a pod associated with a workload
-pub async fn shell(mf: &Manifest, cmd: Option<Vec<&str>>) -> Result<()> {
+///
+/// The container can be a sidecar, or a worker to shell into the worker's pods.
+/// Runs `kubectl exec` (unlike `logs::stream` which uses the kube api).
+pub async fn shell(mf: &Manifest, container: Option<&str>, cmd: Option<Vec<&str>>) -> Result<()> {
     // TODO: kubectl auth can-i create pods/exec
+    // TODO: exec/attach through kube - blocked: kube 0.30 has no websocket support,
+    // and kube's `ws` feature (0.51+) needs tokio 1 and k8s-openapi 0.11 across the workspace
+
+    let src = LogSource::resolve(mf, None, container)?.remove(0);
+    let target = match &src.pods {
+        PodSelector::App(app) if app == &mf.name => format!("{}/{}", mf.workload.to_string(), mf.name),
+        PodSelector::App(worker) => format!("deployment/{}", worker),
+        PodSelector::CronJob(cj) => bail!("Cannot shell into cronjob {}", cj),
+    };
+    debug!("Shelling into {} ({})", target, src.container);
 
-    let target = format!("{}/{}", mf.workload.to_string(), mf.name);
-    debug!("Shelling into {}", target);
-
-    // kubectl exec -it deployment/$pod sh
+    // kubectl exec -it deployment/$pod -c $container sh
     let mut execargs = vec![
         "exec".into(),
         format!("-n={}", mf.namespace),
         "-it".into(),
         target.clone(),
+        format!("-c={}", src.container),
     ];
     if let Some(cmdu) = cmd.clone() {
         for c in cmdu {
@@ -163,6 +175,7 @@ pub async fn shell(mf: &Manifest, cmd: Option<Vec<&str>>) -> Result<()> {
             "exec".into(),
             format!("-n={}", mf.namespace),
             target,
+            format!("-c={}", src.container),
             "which".into(),
             "bash".into(),
         ];
@@ -187,7 +200,8 @@ pub async fn shell(mf: &Manifest, cmd: Option<Vec<&str>>) -> Result<()> {
 
 /// Port forward a port to localhost
 ///
-/// Useful because we have autocomplete on manifest names in shipcat
+/// Useful because we have autocomplete on manifest names in shipcat.
+/// Runs `kubectl port-forward`.
 pub async fn port_forward(mf: &Manifest) -> Result<()> {
     let access_request = AccessReviewRequest {
         namespace: mf.namespace.clone(),
@@ -201,6 +215,8 @@ pub async fn port_forward(mf: &Manifest) -> Result<()> {
     };
 
     // TODO: kubectl auth can-i create pods/portforward first
+    // TODO: portforward through kube - blocked: no kube release usable with tokio 0.2
+    // and rust 1.49 (as on ci) supports portforward
     let port_offset = 7777;
     let mut ps: Vec<_> = mf.ports.iter().map(|mp| mp.port).collect();
 
@@ -242,6 +258,17 @@ pub async fn port_forward(mf: &Manifest) -> Result<()> {
     Ok(())
 }
 
+/// Undo the last rollout of the main workload
+pub async fn rollout_undo(mf: &Manifest) -> Result<()> {
+    let args = vec![
+        format!("-n={}", mf.namespace),
+        "rollout".into(),
+        "undo".into(),
+        format!("{}/{}", mf.workload.to_string(), mf.name),
+    ];
+    kexec(args).await
+}
+
 /// Apply the kube object an applyable file
 ///
 /// CRDs itself, Manifest and Config typically.
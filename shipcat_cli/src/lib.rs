@@ -37,6 +37,10 @@ error_chain! {
             description("SLACK_SHIPCAT_CHANNEL not specified")
             display("SLACK_SHIPCAT_CHANNEL not specified")
         }
+        MissingStatuscakeCredentials {
+            description("STATUSCAKE_USERNAME or STATUSCAKE_APIKEY not specified")
+            display("STATUSCAKE_USERNAME or STATUSCAKE_APIKEY not specified")
+        }
         Url(url: reqwest::Url) {
             description("could not access URL")
             display("could not access URL '{}'", &url)
@@ -69,6 +73,18 @@ error_chain! {
             description("upgrade timed out")
             display("{} upgrade timed out waiting {}s for deployment(s) to come online", &svc, secs)
         }
+        DeployFreeze(region: String, freeze: String, reason: String) {
+            description("deploy freeze in effect")
+            display("{} is frozen by {} ({}) - use --freeze-override to apply anyway", &region, &freeze, &reason)
+        }
+        HookFailure(svc: String, hook: String, stage: String, reason: String) {
+            description("apply hook failed")
+            display("{} hook {} of {} {}", &stage, &hook, &svc, &reason)
+        }
+        SmokeTestFailure(svc: String, check: String, reason: String) {
+            description("smoke test failed")
+            display("smoke check {} of {} failed: {}", &check, &svc, &reason)
+        }
         SlackSendFailure(hook: String) {
             description("slack message send failed")
             display("Failed to send the slack message to '{}' ", &hook)
@@ -112,6 +128,9 @@ pub mod kubectl;
 /// A newer API kubernetes interface
 pub mod kubeapi;
 
+/// Merged log streams from the pods of a service
+pub mod logs;
+
 /// A newer upgrade tracking interface
 pub mod track;
 
@@ -121,6 +140,15 @@ pub mod status;
 /// Apply logic
 pub mod apply;
 
+/// Pre and post apply jobs
+pub mod hooks;
+
+/// Smoke tests after rollouts
+pub mod smoke;
+
+/// Strimzi kafka resources
+pub mod strimzi;
+
 /// A small CLI helm template interface
 pub mod helm;
 
@@ -130,6 +158,9 @@ pub mod kong;
 /// A small CLI Statuscake config generator interface
 pub mod statuscake;
 
+/// Vendor independent uptime monitoring
+pub mod uptime;
+
 /// A graph generator for manifests using `petgraph`
 pub mod graph;
 
@@ -143,12 +174,18 @@ pub use top::{OutputFormat, ResourceOrder};
 /// Diffing module for values
 pub mod diff;
 
+/// Drift of manifests across regions
+pub mod drift;
+
 /// Git stuff
 pub mod git;
 
 /// Env module for sourcing secrets
 pub mod env;
 
+/// Generic outbound webhooks
+pub mod notify;
+
 /// Webhook mux/demux
 pub mod webhooks;
 pub use webhooks::UpgradeState;
@@ -156,6 +193,15 @@ pub use webhooks::UpgradeState;
 /// Simple printers
 pub mod show;
 
+/// Audit history of services kept in the cluster
+pub mod history;
+
+/// Promotion of versions between regions
+pub mod promote;
+
+/// Prometheus metrics for reconciles
+pub mod metrics;
+
 /// Cluster auth
 pub mod auth;
 
@@ -0,0 +1,309 @@
+use crate::{kubeapi, ErrorKind, Manifest, Result};
+use chrono::{DateTime, Utc};
+use futures::{future, stream, Stream, StreamExt};
+use k8s_openapi::api::core::v1::Pod;
+use kube::api::{Api, ListParams, LogParams, Meta};
+
+/// Pods to read logs from
+#[derive(Debug, Clone, PartialEq)]
+pub enum PodSelector {
+    /// Pods with an `app` label (the main workload or a worker)
+    App(String),
+    /// Pods of the jobs created by a cronjob
+    CronJob(String),
+}
+
+/// A container in a set of pods
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogSource {
+    pub pods: PodSelector,
+    pub container: String,
+}
+
+impl LogSource {
+    /// Resolve the pods and container to read for a service
+    ///
+    /// Defaults to the main container in every pod of the service (including workers and cronjobs).
+    /// A `worker` restricts this to a worker or cronjob, and a `container` picks a sidecar (or a worker) by name.
+    pub fn resolve(mf: &Manifest, worker: Option<&str>, container: Option<&str>) -> Result<Vec<LogSource>> {
+        let is_worker = |name: &str| mf.workers.iter().any(|w| w.container.name == name);
+        let is_cronjob = |name: &str| mf.cronJobs.iter().any(|c| c.container.name == name);
+        let is_container = |name: &str| name == mf.name || mf.sidecars.iter().any(|s| s.name == name);
+
+        let (worker, container) = match (worker, container) {
+            // a worker named as a container
+            (None, Some(c)) if !is_container(c) && is_worker(c) => (Some(c), None),
+            wc => wc,
+        };
+        let container = match container {
+            None => mf.name.clone(),
+            Some(c) if is_container(c) => c.to_string(),
+            Some(c) => {
+                let mut names = vec![mf.name.clone()];
+                names.extend(mf.sidecars.iter().map(|s| s.name.clone()));
+                names.extend(mf.workers.iter().map(|w| w.container.name.clone()));
+                bail!(
+                    "{} has no container named {} (has: {})",
+                    mf.name,
+                    c,
+                    names.join(", ")
+                )
+            }
+        };
+        let pods = match worker {
+            Some(w) if is_worker(w) => vec![PodSelector::App(w.to_string())],
+            Some(w) if is_cronjob(w) => vec![PodSelector::CronJob(w.to_string())],
+            Some(w) => {
+                let names = mf
+                    .workers
+                    .iter()
+                    .map(|w| &w.container)
+                    .chain(mf.cronJobs.iter().map(|c| &c.container))
+                    .map(|c| c.name.clone())
+                    .collect::<Vec<_>>();
+                bail!(
+                    "{} has no worker or cronjob named {} (has: {})",
+                    mf.name,
+                    w,
+                    names.join(", ")
+                )
+            }
+            None => {
+                let mut pods = vec![PodSelector::App(mf.name.clone())];
+                pods.extend(
+                    mf.workers
+                        .iter()
+                        .map(|w| PodSelector::App(w.container.name.clone())),
+                );
+                pods.extend(
+                    mf.cronJobs
+                        .iter()
+                        .map(|c| PodSelector::CronJob(c.container.name.clone())),
+                );
+                pods
+            }
+        };
+        Ok(pods
+            .into_iter()
+            .map(|pods| LogSource {
+                pods,
+                container: container.clone(),
+            })
+            .collect())
+    }
+
+    async fn list_pods(&self, api: &Api<Pod>) -> Result<Vec<Pod>> {
+        let (selector, prefix) = match &self.pods {
+            PodSelector::App(app) => (format!("app={}", app), None),
+            // jobs of a cronjob are named {cronjob}-{timestamp}
+            PodSelector::CronJob(cj) => ("job-name".to_string(), Some(format!("{}-", cj))),
+        };
+        let lp = ListParams {
+            label_selector: Some(selector),
+            ..Default::default()
+        };
+        let pods = api.list(&lp).await.map_err(ErrorKind::KubeError)?;
+        Ok(pods
+            .items
+            .into_iter()
+            .filter(|p| {
+                prefix.as_ref().map_or(true, |pre| {
+                    p.metadata
+                        .as_ref()
+                        .and_then(|m| m.labels.as_ref())
+                        .and_then(|l| l.get("job-name"))
+                        .map_or(false, |job| job.starts_with(pre.as_str()))
+                })
+            })
+            .collect())
+    }
+}
+
+/// How much of the logs to show
+#[derive(Debug, Clone, Default)]
+pub struct LogOptions {
+    /// Keep streaming new lines
+    pub follow: bool,
+    /// Only lines newer than this
+    pub since: Option<DateTime<Utc>>,
+    /// Number of lines to show from the end of each container's logs
+    pub tail: Option<i64>,
+}
+
+/// Splits chunks of a log stream into lines
+#[derive(Default)]
+struct LineBuffer {
+    partial: Vec<u8>,
+}
+
+impl LineBuffer {
+    /// Complete lines in the buffer after adding a chunk
+    fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.partial.extend_from_slice(chunk);
+        let mut lines = vec![];
+        while let Some(i) = self.partial.iter().position(|&b| b == b'\n') {
+            let line = self.partial.drain(..=i).collect::<Vec<_>>();
+            lines.push(
+                String::from_utf8_lossy(&line[..i])
+                    .trim_end_matches('\r')
+                    .to_string(),
+            );
+        }
+        lines
+    }
+
+    /// Any unterminated line at the end of the stream
+    fn finish(&mut self) -> Vec<String> {
+        if self.partial.is_empty() {
+            return vec![];
+        }
+        let line = String::from_utf8_lossy(&self.partial).to_string();
+        self.partial.clear();
+        vec![line]
+    }
+}
+
+/// Prefixed lines from a log stream of chunks
+pub(crate) fn prefixed_lines<S, B>(chunks: S, prefix: String) -> impl Stream<Item = Result<String>>
+where
+    S: Stream<Item = kube::Result<B>>,
+    B: AsRef<[u8]>,
+{
+    let mut buf = LineBuffer::default();
+    // None marks the end of the stream to flush an unterminated line
+    chunks
+        .map(Some)
+        .chain(stream::once(future::ready(None)))
+        .map(move |chunk| {
+            let lines = match chunk {
+                Some(Ok(c)) => buf.push(c.as_ref()),
+                Some(Err(e)) => return stream::iter(vec![Err(ErrorKind::KubeError(e).into())]),
+                None => buf.finish(),
+            };
+            let lines = lines.into_iter().map(|l| Ok(format!("{} {}", prefix, l)));
+            stream::iter(lines.collect::<Vec<_>>())
+        })
+        .flatten()
+}
+
+/// Ansi colours cycled through for the pod prefixes
+const COLOURS: &[u8] = &[32, 33, 34, 35, 36, 92, 93, 94, 95, 96];
+
+fn paint(idx: usize, text: &str, colour: bool) -> String {
+    if colour {
+        format!("\x1b[{}m{}\x1b[0m", COLOURS[idx % COLOURS.len()], text)
+    } else {
+        text.to_string()
+    }
+}
+
+/// Stream logs from every pod of the sources as one stream of lines
+///
+/// Lines are prefixed with `pod/container`, coloured per pod when writing to a terminal.
+pub async fn stream(mf: &Manifest, sources: &[LogSource], opts: &LogOptions) -> Result<()> {
+    let client = kubeapi::make_client().await?;
+    let api: Api<Pod> = Api::namespaced(client, &mf.namespace);
+    let colour = unsafe { libc::isatty(libc::STDOUT_FILENO) == 1 };
+    let since_seconds = opts.since.map(|s| (Utc::now() - s).num_seconds().max(1));
+
+    let mut streams = vec![];
+    for src in sources {
+        for pod in src.list_pods(&api).await? {
+            let name = Meta::name(&pod);
+            let lp = LogParams {
+                container: Some(src.container.clone()),
+                follow: opts.follow,
+                since_seconds,
+                tail_lines: opts.tail,
+                ..Default::default()
+            };
+            let prefix = paint(streams.len(), &format!("{}/{}", name, src.container), colour);
+            debug!("Streaming logs from {}/{}", name, src.container);
+            match api.log_stream(&name, &lp).await {
+                Ok(s) => streams.push(prefixed_lines(s, prefix).boxed()),
+                // e.g. pods still pulling images
+                Err(e) => warn!("Cannot read logs from {}/{}: {}", name, src.container, e),
+            }
+        }
+    }
+    if streams.is_empty() {
+        bail!("No pods with logs found for {}", mf.name);
+    }
+    let mut lines = stream::select_all(streams);
+    while let Some(line) = lines.next().await {
+        match line {
+            Ok(l) => println!("{}", l),
+            Err(e) => warn!("Log stream ended: {}", e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{prefixed_lines, LineBuffer, LogSource, PodSelector};
+    use crate::Manifest;
+    use futures::{stream, StreamExt};
+
+    fn manifest() -> Manifest {
+        serde_json::from_value(serde_json::json!({
+            "name": "webapp",
+            "sidecars": [{ "name": "redis" }],
+            "workers": [{ "name": "webapp-worker", "replicaCount": 1 }],
+            "cronJobs": [{ "name": "webapp-cleanup", "schedule": "0 * * * *" }],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn log_sources() {
+        let mf = manifest();
+        let all = LogSource::resolve(&mf, None, None).unwrap();
+        assert_eq!(all.iter().map(|s| s.pods.clone()).collect::<Vec<_>>(), vec![
+            PodSelector::App("webapp".into()),
+            PodSelector::App("webapp-worker".into()),
+            PodSelector::CronJob("webapp-cleanup".into()),
+        ]);
+        assert!(all.iter().all(|s| s.container == "webapp"));
+
+        let worker = LogSource::resolve(&mf, Some("webapp-worker"), None).unwrap();
+        assert_eq!(worker.len(), 1);
+        assert_eq!(worker[0].pods, PodSelector::App("webapp-worker".into()));
+        // workers can also be picked as a container
+        assert_eq!(
+            LogSource::resolve(&mf, None, Some("webapp-worker")).unwrap(),
+            worker
+        );
+
+        let cron = LogSource::resolve(&mf, Some("webapp-cleanup"), None).unwrap();
+        assert_eq!(cron[0].pods, PodSelector::CronJob("webapp-cleanup".into()));
+
+        let redis = LogSource::resolve(&mf, Some("webapp-worker"), Some("redis")).unwrap();
+        assert_eq!(redis[0].container, "redis");
+
+        assert!(LogSource::resolve(&mf, Some("nope"), None).is_err());
+        assert!(LogSource::resolve(&mf, None, Some("nope")).is_err());
+    }
+
+    #[tokio::test]
+    async fn log_lines_across_chunks() {
+        let mut buf = LineBuffer::default();
+        assert_eq!(buf.push(b"first\nsec"), vec!["first".to_string()]);
+        assert!(buf.push(b"ond").is_empty());
+        assert_eq!(buf.push(b"\r\nthird\n"), vec![
+            "second".to_string(),
+            "third".to_string()
+        ]);
+        assert!(buf.finish().is_empty());
+
+        let chunks = stream::iter(vec![
+            Ok::<_, kube::Error>(b"a\nb".to_vec()),
+            Ok(b"\nunterminated".to_vec()),
+        ]);
+        let lines = prefixed_lines(chunks, "pod/c".into())
+            .map(|l| l.unwrap())
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(lines, vec!["pod/c a", "pod/c b", "pod/c unterminated"]);
+    }
+}
@@ -3,7 +3,7 @@
 
 use clap::{App, AppSettings, Arg, ArgMatches, Shell, SubCommand};
 use shipcat::{kubeapi::ShipKube, *};
-use std::{process, str::FromStr};
+use std::{path::PathBuf, process, str::FromStr};
 
 fn print_error_debug(e: &Error) {
     use std::env;
@@ -69,9 +69,42 @@ fn build_cli() -> App<'static, 'static> {
             .arg(Arg::with_name("service")
                 .required(true)
                 .help("Service name"))
+            .arg(Arg::with_name("container")
+                .short("c")
+                .long("container")
+                .takes_value(true)
+                .help("Sidecar or worker to shell into"))
             .setting(AppSettings::TrailingVarArg)
             .arg(Arg::with_name("cmd").multiple(true)))
 
+        .subcommand(SubCommand::with_name("logs")
+            .about("Stream logs from all pods of a service (including workers and cronjobs)")
+            .arg(Arg::with_name("service")
+                .required(true)
+                .help("Service name"))
+            .arg(Arg::with_name("follow")
+                .short("f")
+                .long("follow")
+                .help("Keep streaming new log lines"))
+            .arg(Arg::with_name("worker")
+                .short("w")
+                .long("worker")
+                .takes_value(true)
+                .help("Only show logs from a worker or cronjob"))
+            .arg(Arg::with_name("container")
+                .short("c")
+                .long("container")
+                .takes_value(true)
+                .help("Sidecar (or worker) to show logs from"))
+            .arg(Arg::with_name("since")
+                .long("since")
+                .takes_value(true)
+                .help("Only show lines newer than a timestamp or a duration (e.g. 30m, 2h)"))
+            .arg(Arg::with_name("tail")
+                .long("tail")
+                .takes_value(true)
+                .help("Number of lines to show from the end of each container's logs")))
+
         .subcommand(SubCommand::with_name("port-forward")
             .about("Port forwards a service to localhost")
             .arg(Arg::with_name("service")
@@ -131,6 +164,18 @@ fn build_cli() -> App<'static, 'static> {
         .subcommand(SubCommand::with_name("gdpr")
               .arg(Arg::with_name("service")
                 .help("Service names to show"))
+              .subcommand(SubCommand::with_name("flow")
+                .arg(Arg::with_name("dot")
+                  .long("dot")
+                  .help("Generate dot output for graphviz"))
+                .about("Graph how classified data flows between services"))
+              .subcommand(SubCommand::with_name("check")
+                .about("Check data handling for undeclared or unprotected data"))
+              .subcommand(SubCommand::with_name("ropa")
+                .arg(Arg::with_name("csv")
+                  .long("csv")
+                  .help("Generate csv output rather than markdown"))
+                .about("Generate the Record of Processing Activities for a region"))
               .about("Reduce data handling structs"))
 
         .subcommand(SubCommand::with_name("get")
@@ -173,7 +218,19 @@ fn build_cli() -> App<'static, 'static> {
                 .help("Generate Kong config URL")))
         // Statuscake helper
         .subcommand(SubCommand::with_name("statuscake")
+            .subcommand(SubCommand::with_name("sync")
+                .arg(Arg::with_name("dry-run")
+                    .long("dry-run")
+                    .help("Only show what would change"))
+                .about("Reconcile uptime tests for the region against the StatusCake API"))
             .about("Generate Statuscake config"))
+        .subcommand(SubCommand::with_name("uptime")
+            .arg(Arg::with_name("provider")
+                .long("provider")
+                .takes_value(true)
+                .possible_values(&["statuscake", "blackbox", "json"])
+                .help("Override the uptime provider configured for the region"))
+            .about("Generate uptime monitoring config for the region"))
         // dependency graphing
         .subcommand(SubCommand::with_name("graph")
               .arg(Arg::with_name("service")
@@ -206,6 +263,21 @@ fn build_cli() -> App<'static, 'static> {
                 .subcommand(SubCommand::with_name("install")
                     .about("Install the Shipcat related CRDs"))
                 .subcommand(SubCommand::with_name("reconcile")
+                    .arg(Arg::with_name("freeze-override")
+                        .long("freeze-override")
+                        .takes_value(true)
+                        .value_name("reason")
+                        .help("Reconcile during a deploy freeze, recording the reason"))
+                    .arg(Arg::with_name("pushgateway")
+                        .long("pushgateway")
+                        .takes_value(true)
+                        .value_name("url")
+                        .help("Push reconcile metrics to a prometheus pushgateway"))
+                    .arg(Arg::with_name("metrics-file")
+                        .long("metrics-file")
+                        .takes_value(true)
+                        .value_name("path")
+                        .help("Write reconcile metrics in prometheus text format to a file"))
                     .about("Reconcile shipcat custom resource definitions with local state")))
             .subcommand(SubCommand::with_name("vault-policy")
                 .arg(Arg::with_name("num-jobs")
@@ -233,6 +305,48 @@ fn build_cli() -> App<'static, 'static> {
                 .help("Service to check"))
               .about("Show kubernetes status for all the resources for a service"))
 
+        .subcommand(SubCommand::with_name("history")
+              .arg(Arg::with_name("service")
+                .required(true)
+                .help("Service to show history for"))
+              .arg(Arg::with_name("since")
+                .long("since")
+                .takes_value(true)
+                .help("Only show actions newer than a timestamp or a duration (e.g. 12h, 7d)"))
+              .arg(Arg::with_name("all-regions")
+                .long("all-regions")
+                .help("Include actions recorded from other regions sharing the namespace"))
+              .about("Show the recorded deployments and deletions of a service"))
+
+        .subcommand(SubCommand::with_name("promote")
+              .arg(Arg::with_name("services")
+                .multiple(true)
+                .required_unless("all")
+                .conflicts_with("all")
+                .help("Services to promote"))
+              .arg(Arg::with_name("all")
+                .long("all")
+                .help("Promote every service available in both regions"))
+              .arg(Arg::with_name("from")
+                .long("from")
+                .takes_value(true)
+                .required(true)
+                .help("Region to promote versions from"))
+              .arg(Arg::with_name("to")
+                .long("to")
+                .takes_value(true)
+                .required(true)
+                .help("Region to promote versions to"))
+              .arg(Arg::with_name("soak")
+                .long("soak")
+                .takes_value(true)
+                .default_value("1h")
+                .help("Minimum time since the rollout in the source region (e.g. 30m, 1d)"))
+              .arg(Arg::with_name("dry-run")
+                .long("dry-run")
+                .help("Only show the promotion plan"))
+              .about("Promote rolled out versions from one region to another"))
+
         .subcommand(SubCommand::with_name("version")
               .arg(Arg::with_name("service")
                 .required(true)
@@ -293,6 +407,11 @@ fn build_cli() -> App<'static, 'static> {
               .arg(Arg::with_name("force")
                     .long("force")
                     .help("Apply template even if no changes are detected"))
+              .arg(Arg::with_name("freeze-override")
+                    .long("freeze-override")
+                    .takes_value(true)
+                    .value_name("reason")
+                    .help("Apply during a deploy freeze, recording the reason"))
               .arg(Arg::with_name("service")
                 .required(true)
                 .help("Service to apply"))
@@ -302,6 +421,9 @@ fn build_cli() -> App<'static, 'static> {
               .arg(Arg::with_name("no-wait")
                     .long("no-wait")
                     .help("Do not wait for service timeout"))
+              .arg(Arg::with_name("force")
+                    .long("force")
+                    .help("Restart even if the statefulset cannot be restarted safely"))
               .arg(Arg::with_name("service")
                 .required(true)
                 .help("Service to restart"))
@@ -377,11 +499,29 @@ fn build_cli() -> App<'static, 'static> {
                 .about("Verify the parsed config")))
 
         .subcommand(SubCommand::with_name("login")
-            .about("Login to a region (using teleport if possible)")
+            .about("Login to a region (using the login provider of its cluster)")
             .arg(Arg::with_name("force")
                 .long("force")
                 .short("f")
-                .help("Remove the old tsh state file to force a login")))
+                .help("Discard existing credentials to force a login")))
+
+        .subcommand(SubCommand::with_name("drift")
+            .about("Show manifest differences across regions relative to a reference region")
+            .arg(Arg::with_name("reference")
+                .long("reference")
+                .takes_value(true)
+                .required(true)
+                .help("Region to compare other regions against"))
+            .arg(Arg::with_name("regions")
+                .multiple(true)
+                .help("Regions to compare (defaults to all regions)"))
+            .arg(Arg::with_name("output")
+                .takes_value(true)
+                .default_value("table")
+                .possible_values(&["table", "html", "json"])
+                .long("output")
+                .short("o")
+                .help("Output format to print")))
 
         .subcommand(SubCommand::with_name("top")
             .about("Show top requests from manifests on disk")
@@ -601,6 +741,16 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
         if let Some(_) = a.subcommand_matches("kafkatopics") {
             return shipcat::get::kafkatopics(&conf, &region).await;
         }
+    } else if let Some(a) = args.subcommand_matches("drift") {
+        let fmt = shipcat::drift::DriftFormat::from_str(a.value_of("output").unwrap())?;
+        let regions = a
+            .values_of("regions")
+            .map(|vs| vs.map(String::from).collect())
+            .unwrap_or_default();
+        let rawconf = Config::read().await?;
+        return shipcat::drift::drift(&rawconf, a.value_of("reference").unwrap(), regions, fmt)
+            .await
+            .map(void);
     } else if let Some(a) = args.subcommand_matches("top") {
         let sort = top::ResourceOrder::from_str(a.value_of("sort").unwrap())?;
         let fmt = top::OutputFormat::from_str(a.value_of("output").unwrap())?;
@@ -684,6 +834,34 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
         let svc = a.value_of("service").map(String::from).unwrap();
         let (conf, region) = resolve_config(a, ConfigState::Base).await?;
         return shipcat::status::show(&svc, &conf, &region).await;
+    } else if let Some(a) = args.subcommand_matches("history") {
+        let svc = a.value_of("service").unwrap();
+        let (_conf, region) = resolve_config(a, ConfigState::Base).await?;
+        let since = if let Some(s) = a.value_of("since") {
+            Some(shipcat::history::parse_since(s, chrono::Utc::now())?)
+        } else {
+            None
+        };
+        let filter = shipcat::history::HistoryFilter {
+            region: if a.is_present("all-regions") {
+                None
+            } else {
+                Some(region.name.clone())
+            },
+            since,
+        };
+        return shipcat::history::show(svc, &region, &filter).await;
+    } else if let Some(a) = args.subcommand_matches("promote") {
+        let services = a
+            .values_of("services")
+            .map(|vs| vs.map(String::from).collect::<Vec<_>>());
+        let source = Config::new(ConfigState::Base, a.value_of("from").unwrap()).await?;
+        let target = Config::new(ConfigState::Base, a.value_of("to").unwrap()).await?;
+        let opts = shipcat::promote::PromoteOptions {
+            soak: a.value_of("soak").unwrap().into(),
+            dry_run: a.is_present("dry-run"),
+        };
+        return shipcat::promote::promote(services, &source, &target, &opts).await;
     } else if let Some(a) = args.subcommand_matches("graph") {
         let dot = a.is_present("dot");
         let (conf, region) = resolve_config(a, ConfigState::Base).await?;
@@ -883,7 +1061,21 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
         };
     } else if let Some(a) = args.subcommand_matches("statuscake") {
         let (conf, region) = resolve_config(a, ConfigState::Base).await?;
+        if let Some(b) = a.subcommand_matches("sync") {
+            let client = shipcat::statuscake::StatuscakeClient::from_env()?;
+            return shipcat::statuscake::sync(&conf, &region, &client, b.is_present("dry-run"))
+                .await
+                .map(void);
+        }
         return shipcat::statuscake::output(&conf, &region).await;
+    } else if let Some(a) = args.subcommand_matches("uptime") {
+        let (conf, region) = resolve_config(a, ConfigState::Base).await?;
+        let provider = a.value_of("provider").map(|p| match p {
+            "blackbox" => shipcat_definitions::UptimeProviderKind::Blackbox,
+            "json" => shipcat_definitions::UptimeProviderKind::Json,
+            _ => shipcat_definitions::UptimeProviderKind::Statuscake,
+        });
+        return shipcat::uptime::output(&conf, &region, provider).await;
     }
     // ------------------------------------------------------------------------------
     // everything below needs a kube context!
@@ -894,8 +1086,9 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
         let wait = !a.is_present("no-wait");
         let force = a.is_present("force");
         let ver = a.value_of("tag").map(String::from); // needed for some subcommands
+        let freeze_override = a.value_of("freeze-override").map(String::from);
         assert!(conf.has_secrets()); // sanity on cluster disruptive commands
-        return shipcat::apply::apply(svc, force, &region, &conf, wait, ver)
+        return shipcat::apply::apply(svc, force, &region, &conf, wait, ver, freeze_override)
             .await
             .map(void);
     } else if let Some(a) = args.subcommand_matches("restart") {
@@ -903,7 +1096,9 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
         let (conf, region) = resolve_config(a, ConfigState::Base).await?;
         let mf = shipcat_filebacked::load_manifest(&svc, &conf, &region).await?;
         let wait = !a.is_present("no-wait");
-        return shipcat::apply::restart(&mf, wait).await.map(void);
+        return shipcat::apply::restart(&mf, wait, a.is_present("force"))
+            .await
+            .map(void);
     } else if let Some(a) = args.subcommand_matches("delete") {
         let svc = a.value_of("service").map(String::from).unwrap();
         let (conf, region) = resolve_config(a, ConfigState::Base).await?;
@@ -921,8 +1116,24 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
             if let Some(_) = b.subcommand_matches("install") {
                 return shipcat::cluster::crd_install(&region_base).await;
             }
-            if let Some(_) = b.subcommand_matches("reconcile") {
-                return shipcat::cluster::mass_crd(&conf_sec, &conf_base, &region_base, jobs).await;
+            if let Some(c) = b.subcommand_matches("reconcile") {
+                let freeze_override = c.value_of("freeze-override").map(String::from);
+                let metrics = shipcat::metrics::MetricsSink {
+                    pushgateway: c
+                        .value_of("pushgateway")
+                        .map(String::from)
+                        .or_else(|| std::env::var("SHIPCAT_PUSHGATEWAY_URL").ok()),
+                    file: c.value_of("metrics-file").map(PathBuf::from),
+                };
+                return shipcat::cluster::mass_crd(
+                    &conf_sec,
+                    &conf_base,
+                    &region_base,
+                    jobs,
+                    freeze_override,
+                    metrics,
+                )
+                .await;
             }
         }
         if let Some(_b) = a.subcommand_matches("diff") {
@@ -966,13 +1177,30 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
             .await?
             .stub(&region)
             .await?;
-        return shipcat::kubectl::shell(&mf, cmd).await;
+        return shipcat::kubectl::shell(&mf, a.value_of("container"), cmd).await;
     } else if let Some(a) = args.subcommand_matches("version") {
         let svc = a.value_of("service").map(String::from).unwrap();
         let (_conf, region) = resolve_config(a, ConfigState::Base).await?;
         let res = shipcat::kubectl::get_running_version(&svc, &region.namespace).await?;
         println!("{}", res);
         return Ok(());
+    } else if let Some(a) = args.subcommand_matches("logs") {
+        let (conf, region) = resolve_config(args, ConfigState::Base).await?;
+        let service = a.value_of("service").unwrap();
+        let mf = shipcat_filebacked::load_manifest(service, &conf, &region)
+            .await?
+            .stub(&region)
+            .await?;
+        let sources = shipcat::logs::LogSource::resolve(&mf, a.value_of("worker"), a.value_of("container"))?;
+        let opts = shipcat::logs::LogOptions {
+            follow: a.is_present("follow"),
+            since: match a.value_of("since") {
+                Some(s) => Some(shipcat::history::parse_since(s, chrono::Utc::now())?),
+                None => None,
+            },
+            tail: a.value_of("tail").map(|t| t.parse()).transpose()?,
+        };
+        return shipcat::logs::stream(&mf, &sources, &opts).await;
     } else if let Some(a) = args.subcommand_matches("port-forward") {
         let (conf, region) = resolve_config(args, ConfigState::Base).await?;
         let service = a.value_of("service").unwrap();
@@ -1000,6 +1228,17 @@ async fn dispatch_commands(args: &ArgMatches<'_>) -> Result<()> {
         return shipcat::slack::send_dumb(msg).await;
     } else if let Some(a) = args.subcommand_matches("gdpr") {
         let (conf, region) = resolve_config(args, ConfigState::Base).await?;
+        if let Some(b) = a.subcommand_matches("flow") {
+            return shipcat::gdpr::flow(b.is_present("dot"), &conf, &region)
+                .await
+                .map(void);
+        }
+        if a.subcommand_matches("check").is_some() {
+            return shipcat::gdpr::verify(&conf, &region).await;
+        }
+        if let Some(b) = a.subcommand_matches("ropa") {
+            return shipcat::gdpr::report(b.is_present("csv"), &conf, &region).await;
+        }
         let svc = a.value_of("service").map(String::from);
         return shipcat::gdpr::show(svc, &conf, &region).await;
     }
@@ -0,0 +1,244 @@
+use std::{
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use reqwest::header::CONTENT_TYPE;
+
+use super::{ErrorKind, Result, ResultExt};
+
+/// Job label used when pushing to a pushgateway
+const PUSH_JOB: &str = "shipcat_reconcile";
+
+/// Where reconcile outcomes are exported
+#[derive(Clone, Debug, Default)]
+pub struct MetricsSink {
+    /// Prometheus pushgateway base url
+    pub pushgateway: Option<String>,
+    /// Text file (e.g. for the node exporter textfile collector)
+    pub file: Option<PathBuf>,
+}
+
+impl MetricsSink {
+    pub fn is_empty(&self) -> bool {
+        self.pushgateway.is_none() && self.file.is_none()
+    }
+}
+
+/// Outcome of reconciling a single service
+#[derive(Clone, Debug)]
+pub struct ServiceOutcome {
+    pub service: String,
+    pub duration: Duration,
+    /// `UpgradeReason` if the service was upgraded
+    pub reason: Option<String>,
+    pub failed: bool,
+}
+
+/// Outcomes of a full reconcile of a region
+#[derive(Clone, Debug)]
+pub struct ReconcileReport {
+    pub region: String,
+    pub finished: DateTime<Utc>,
+    pub outcomes: Vec<ServiceOutcome>,
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn header(out: &mut String, name: &str, help: &str) -> Result<()> {
+    writeln!(out, "# HELP {} {}", name, help)?;
+    writeln!(out, "# TYPE {} gauge", name)?;
+    Ok(())
+}
+
+impl ReconcileReport {
+    pub fn failures(&self) -> usize {
+        self.outcomes.iter().filter(|o| o.failed).count()
+    }
+
+    /// Render the report in the prometheus text exposition format
+    pub fn render(&self) -> Result<String> {
+        let region = escape(&self.region);
+        let mut outcomes = self.outcomes.iter().collect::<Vec<_>>();
+        outcomes.sort_by(|a, b| a.service.cmp(&b.service));
+        let mut out = String::new();
+
+        header(
+            &mut out,
+            "shipcat_reconcile_last_run_timestamp_seconds",
+            "When the last reconcile finished",
+        )?;
+        writeln!(
+            out,
+            "shipcat_reconcile_last_run_timestamp_seconds{{region=\"{}\"}} {}",
+            region,
+            self.finished.timestamp()
+        )?;
+        header(&mut out, "shipcat_reconcile_services", "Services reconciled")?;
+        writeln!(
+            out,
+            "shipcat_reconcile_services{{region=\"{}\"}} {}",
+            region,
+            self.outcomes.len()
+        )?;
+        header(
+            &mut out,
+            "shipcat_reconcile_failures",
+            "Services that failed to reconcile",
+        )?;
+        writeln!(
+            out,
+            "shipcat_reconcile_failures{{region=\"{}\"}} {}",
+            region,
+            self.failures()
+        )?;
+
+        header(
+            &mut out,
+            "shipcat_reconcile_service_duration_seconds",
+            "Time taken to reconcile a service",
+        )?;
+        for o in &outcomes {
+            writeln!(
+                out,
+                "shipcat_reconcile_service_duration_seconds{{region=\"{}\",service=\"{}\"}} {:.3}",
+                region,
+                escape(&o.service),
+                o.duration.as_secs_f64()
+            )?;
+        }
+        header(
+            &mut out,
+            "shipcat_reconcile_service_failed",
+            "Whether a service failed to reconcile",
+        )?;
+        for o in &outcomes {
+            writeln!(
+                out,
+                "shipcat_reconcile_service_failed{{region=\"{}\",service=\"{}\"}} {}",
+                region,
+                escape(&o.service),
+                o.failed as u8
+            )?;
+        }
+        header(
+            &mut out,
+            "shipcat_reconcile_service_upgraded",
+            "Services upgraded by the reconcile and why",
+        )?;
+        for o in &outcomes {
+            if let Some(reason) = &o.reason {
+                writeln!(
+                    out,
+                    "shipcat_reconcile_service_upgraded{{region=\"{}\",service=\"{}\",reason=\"{}\"}} 1",
+                    region,
+                    escape(&o.service),
+                    escape(reason)
+                )?;
+            }
+        }
+        Ok(out)
+    }
+
+    /// Export the report to the configured sinks
+    ///
+    /// Export failures are logged rather than failing the reconcile.
+    pub async fn export(&self, sink: &MetricsSink) {
+        if sink.is_empty() {
+            return;
+        }
+        let body = match self.render() {
+            Ok(b) => b,
+            Err(e) => {
+                warn!("Failed to render reconcile metrics: {}", e);
+                return;
+            }
+        };
+        if let Some(path) = &sink.file {
+            if let Err(e) = write_file(path, &body) {
+                warn!("Failed to write reconcile metrics to {}: {}", path.display(), e);
+            }
+        }
+        if let Some(url) = &sink.pushgateway {
+            if let Err(e) = push(url, &self.region, body).await {
+                warn!("Failed to push reconcile metrics: {}", e);
+            }
+        }
+    }
+}
+
+/// Write metrics atomically (the textfile collector may read at any time)
+fn write_file(path: &Path, body: &str) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, body)?;
+    fs::rename(&tmp, path)?;
+    debug!("Wrote reconcile metrics to {}", path.display());
+    Ok(())
+}
+
+/// Replace the metrics of this region's reconcile group on a pushgateway
+async fn push(base: &str, region: &str, body: String) -> Result<()> {
+    let url = reqwest::Url::parse(base)?.join(&format!("metrics/job/{}/region/{}", PUSH_JOB, region))?;
+    debug!("Pushing reconcile metrics to {}", url);
+    let res = reqwest::Client::new()
+        .put(url.clone())
+        .header(CONTENT_TYPE, "text/plain; version=0.0.4")
+        .body(body)
+        .send()
+        .await
+        .chain_err(|| ErrorKind::Url(url.clone()))?;
+    if !res.status().is_success() {
+        bail!("pushgateway returned {}", res.status());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ReconcileReport, ServiceOutcome};
+    use chrono::{TimeZone, Utc};
+    use std::time::Duration;
+
+    #[test]
+    fn reconcile_metrics_render() {
+        let report = ReconcileReport {
+            region: "dev-uk".into(),
+            finished: Utc.timestamp(1_600_000_000, 0),
+            outcomes: vec![
+                ServiceOutcome {
+                    service: "webapp".into(),
+                    duration: Duration::from_millis(12_500),
+                    reason: Some("VersionChange".into()),
+                    failed: false,
+                },
+                ServiceOutcome {
+                    service: "auth".into(),
+                    duration: Duration::from_secs(300),
+                    reason: None,
+                    failed: true,
+                },
+            ],
+        };
+        let text = report.render().unwrap();
+        assert!(text.contains("shipcat_reconcile_last_run_timestamp_seconds{region=\"dev-uk\"} 1600000000\n"));
+        assert!(text.contains("shipcat_reconcile_failures{region=\"dev-uk\"} 1\n"));
+        assert!(text.contains(
+            "shipcat_reconcile_service_duration_seconds{region=\"dev-uk\",service=\"webapp\"} 12.500\n"
+        ));
+        assert!(text.contains("shipcat_reconcile_service_failed{region=\"dev-uk\",service=\"auth\"} 1\n"));
+        assert!(text.contains(
+            "shipcat_reconcile_service_upgraded{region=\"dev-uk\",service=\"webapp\",reason=\"VersionChange\"} 1\n"
+        ));
+        // services are sorted and every metric is typed once
+        assert!(text.find("service=\"auth\"").unwrap() < text.find("service=\"webapp\"").unwrap());
+        assert_eq!(text.matches("# TYPE").count(), 6);
+    }
+}
@@ -0,0 +1,333 @@
+//- Generic outbound webhooks
+use super::Result;
+use crate::{apply::UpgradeInfo, webhooks::UpgradeState};
+use chrono::{SecondsFormat, Utc};
+use reqwest::{header::CONTENT_TYPE, RequestBuilder, StatusCode};
+use ring::hmac;
+use serde_json::json;
+use shipcat_definitions::region::{ChatWebhook, CloudEventsWebhook, HttpWebhook, RetryPolicy, Webhook};
+use std::{fmt, time::Duration};
+use url::Url;
+use uuid::Uuid;
+
+/// Header carrying the signature of `HttpWebhook` bodies
+pub const SIGNATURE_HEADER: &str = "X-Shipcat-Signature";
+
+/// What an event is about
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum EventAction {
+    Apply,
+    Delete,
+}
+impl fmt::Display for EventAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", format!("{:?}", self).to_lowercase())
+    }
+}
+
+/// Event body shared by the generic webhooks
+#[derive(Serialize, Clone, Debug)]
+pub struct Event {
+    pub action: EventAction,
+    pub state: UpgradeState,
+    pub service: String,
+    pub region: String,
+    pub namespace: String,
+    pub version: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diff: Option<String>,
+    /// RFC 3339
+    pub timestamp: String,
+}
+
+impl Event {
+    pub fn new(action: EventAction, us: &UpgradeState, info: &UpgradeInfo) -> Self {
+        Event {
+            action,
+            state: us.clone(),
+            service: info.name.clone(),
+            region: info.region.clone(),
+            namespace: info.namespace.clone(),
+            version: info.version.clone(),
+            reason: info.reason.clone(),
+            diff: info.diff.clone(),
+            timestamp: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        }
+    }
+
+    fn state_name(&self) -> String {
+        format!("{:?}", self.state).to_lowercase()
+    }
+
+    /// One line human readable description
+    fn headline(&self) -> String {
+        let verb = match (self.action, &self.state) {
+            (EventAction::Apply, UpgradeState::Pending) => "preparing to apply",
+            (EventAction::Apply, UpgradeState::Started) => "applying",
+            (EventAction::Apply, UpgradeState::Completed) => "applied",
+            (EventAction::Apply, UpgradeState::Failed) => "failed to apply",
+            (EventAction::Apply, UpgradeState::Cancelled) => "cancelled applying",
+            (EventAction::Delete, UpgradeState::Completed) => "deleted",
+            (EventAction::Delete, UpgradeState::Failed) => "failed to delete",
+            (EventAction::Delete, _) => "deleting",
+        };
+        format!("{} `{}` in `{}`", verb, self.service, self.region)
+    }
+
+    fn color(&self) -> &'static str {
+        match self.state {
+            UpgradeState::Completed => "2EB886",
+            UpgradeState::Failed => "A30200",
+            _ => "DAA038",
+        }
+    }
+
+    fn facts(&self) -> Vec<(&'static str, String)> {
+        let mut facts = vec![("Version", self.version.clone())];
+        if let Some(r) = &self.reason {
+            facts.push(("Reason", r.clone()));
+        }
+        facts
+    }
+}
+
+/// Hex encoded HMAC-SHA256 of a body
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    hex::encode(hmac::sign(&key, body).as_ref())
+}
+
+/// Structured mode CloudEvent wrapping an `Event`
+fn cloudevent(ev: &Event, source: &str) -> serde_json::Value {
+    json!({
+        "specversion": "1.0",
+        "id": Uuid::new_v4().to_string(),
+        "source": source,
+        "type": format!("io.shipcat.{}.{}", ev.action, ev.state_name()),
+        "subject": ev.service,
+        "time": ev.timestamp,
+        "datacontenttype": "application/json",
+        "data": ev,
+    })
+}
+
+/// Microsoft Teams MessageCard
+fn teams_card(ev: &Event) -> serde_json::Value {
+    let facts = ev
+        .facts()
+        .into_iter()
+        .map(|(name, value)| json!({ "name": name, "value": value }))
+        .collect::<Vec<_>>();
+    json!({
+        "@type": "MessageCard",
+        "@context": "https://schema.org/extensions",
+        "themeColor": ev.color(),
+        "summary": ev.headline(),
+        "title": ev.headline(),
+        "sections": [{ "facts": facts }],
+    })
+}
+
+/// Mattermost (slack compatible) incoming webhook message
+fn mattermost_message(ev: &Event) -> serde_json::Value {
+    let fields = ev
+        .facts()
+        .into_iter()
+        .map(|(title, value)| json!({ "title": title, "value": value, "short": true }))
+        .collect::<Vec<_>>();
+    json!({
+        "username": "shipcat",
+        "attachments": [{
+            "fallback": ev.headline(),
+            "color": format!("#{}", ev.color()),
+            "text": ev.headline(),
+            "fields": fields,
+        }],
+    })
+}
+
+/// Send a request until it succeeds
+///
+/// Connection errors, 429s and 5xxs are retried with exponential backoff.
+/// Other responses are not going to improve, so they fail immediately.
+async fn deliver<F>(url: &Url, retry: &RetryPolicy, make: F) -> Result<()>
+where
+    F: Fn() -> RequestBuilder,
+{
+    let mut backoff = retry.backoff_ms;
+    let mut attempt = 1;
+    loop {
+        let failure = match make().send().await {
+            Ok(r) if r.status().is_success() => return Ok(()),
+            Ok(r) => {
+                let s = r.status();
+                if !s.is_server_error() && s != StatusCode::TOO_MANY_REQUESTS {
+                    bail!("{} rejected event with {}", url, s);
+                }
+                s.to_string()
+            }
+            Err(e) => e.to_string(),
+        };
+        if attempt >= retry.attempts {
+            bail!("{} failed after {} attempts: {}", url, attempt, failure);
+        }
+        debug!(
+            "Delivery to {} failed ({}), retrying in {}ms",
+            url, failure, backoff
+        );
+        tokio::time::delay_for(Duration::from_millis(backoff)).await;
+        backoff *= 2;
+        attempt += 1;
+    }
+}
+
+/// Send a signed JSON event
+pub async fn http(ev: &Event, h: &HttpWebhook) -> Result<()> {
+    let body = serde_json::to_vec(ev)?;
+    let signature = format!("sha256={}", sign(&h.secret, &body));
+    let client = reqwest::Client::new();
+    deliver(&h.url, &h.retry, || {
+        client
+            .post(h.url.clone())
+            .header(CONTENT_TYPE, "application/json")
+            .header(SIGNATURE_HEADER, signature.clone())
+            .body(body.clone())
+    })
+    .await
+}
+
+/// Send a CloudEvent in structured mode
+pub async fn cloudevents(ev: &Event, h: &CloudEventsWebhook) -> Result<()> {
+    let source = h
+        .source
+        .clone()
+        .unwrap_or_else(|| format!("shipcat/{}", ev.region));
+    let body = serde_json::to_vec(&cloudevent(ev, &source))?;
+    let client = reqwest::Client::new();
+    deliver(&h.url, &h.retry, || {
+        client
+            .post(h.url.clone())
+            .header(CONTENT_TYPE, "application/cloudevents+json")
+            .body(body.clone())
+    })
+    .await
+}
+
+async fn chat(body: serde_json::Value, h: &ChatWebhook) -> Result<()> {
+    let client = reqwest::Client::new();
+    deliver(&h.url, &h.retry, || client.post(h.url.clone()).json(&body)).await
+}
+
+/// Send an event to a generic webhook
+///
+/// Audit webhooks have their own payloads and are ignored here.
+pub async fn send(wh: &Webhook, ev: &Event) -> Result<()> {
+    match wh {
+        Webhook::Audit(_) => Ok(()),
+        Webhook::Http(h) => http(ev, h).await,
+        Webhook::CloudEvents(h) => cloudevents(ev, h).await,
+        Webhook::Teams(h) => chat(teams_card(ev), h).await,
+        Webhook::Mattermost(h) => chat(mattermost_message(ev), h).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{cloudevent, mattermost_message, sign, teams_card, Event, EventAction};
+    use crate::{apply::UpgradeInfo, notify, Manifest, Result, UpgradeState};
+    use shipcat_definitions::region::{HttpWebhook, RetryPolicy};
+    use url::Url;
+
+    fn event(us: UpgradeState) -> Event {
+        let mut info = UpgradeInfo::new(&Manifest::test("fake-svc"));
+        info.reason = Some("VersionChange".into());
+        Event::new(EventAction::Apply, &us, &info)
+    }
+
+    fn http_hook(path: &str) -> HttpWebhook {
+        HttpWebhook {
+            url: Url::parse(&format!("{}{}", mockito::server_url(), path)).unwrap(),
+            secret: "hunter2".into(),
+            states: vec![],
+            retry: RetryPolicy {
+                attempts: 3,
+                backoff_ms: 1,
+            },
+        }
+    }
+
+    #[test]
+    fn notify_signature() {
+        assert_eq!(
+            sign("key", b"The quick brown fox jumps over the lazy dog"),
+            "f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+    }
+
+    #[test]
+    fn notify_payloads() {
+        let ev = event(UpgradeState::Completed);
+        let ce = cloudevent(&ev, "shipcat/dev-uk");
+        assert_eq!(ce["specversion"], "1.0");
+        assert_eq!(ce["type"], "io.shipcat.apply.completed");
+        assert_eq!(ce["subject"], "fake-svc");
+        assert_eq!(ce["data"]["state"], "COMPLETED");
+        assert_eq!(ce["data"]["version"], "1.0.0");
+
+        let card = teams_card(&ev);
+        assert_eq!(card["@type"], "MessageCard");
+        assert_eq!(card["summary"], "applied `fake-svc` in `dev-uk`");
+        assert_eq!(card["sections"][0]["facts"][1]["value"], "VersionChange");
+
+        let failed = mattermost_message(&event(UpgradeState::Failed));
+        assert_eq!(
+            failed["attachments"][0]["text"],
+            "failed to apply `fake-svc` in `dev-uk`"
+        );
+        assert_eq!(failed["attachments"][0]["color"], "#A30200");
+    }
+
+    #[tokio::test]
+    async fn notify_http_signed() -> Result<()> {
+        let ev = event(UpgradeState::Started);
+        let body = serde_json::to_vec(&ev)?;
+        let mocked = mockito::mock("POST", "/signed")
+            .match_header("content-type", "application/json")
+            .match_header(
+                notify::SIGNATURE_HEADER,
+                format!("sha256={}", sign("hunter2", &body)).as_str(),
+            )
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({
+                "action": "apply",
+                "state": "STARTED",
+                "service": "fake-svc",
+            })))
+            .expect(1)
+            .create();
+        notify::http(&ev, &http_hook("/signed")).await?;
+        mocked.assert();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn notify_http_retries() {
+        let ev = event(UpgradeState::Failed);
+        let unavailable = mockito::mock("POST", "/unavailable")
+            .with_status(503)
+            .expect(3)
+            .create();
+        assert!(notify::http(&ev, &http_hook("/unavailable")).await.is_err());
+        unavailable.assert();
+
+        // client errors are not retried
+        let rejected = mockito::mock("POST", "/rejected")
+            .with_status(400)
+            .expect(1)
+            .create();
+        assert!(notify::http(&ev, &http_hook("/rejected")).await.is_err());
+        rejected.assert();
+    }
+}
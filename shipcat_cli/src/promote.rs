@@ -0,0 +1,376 @@
+//- Promotion of versions between regions
+use crate::{apply, history::parse_since, kubeapi::ShipKube, kubectl, Config, ConfigState, Region, Result};
+use chrono::{DateTime, Utc};
+use shipcat_definitions::{region::VersionScheme, status::ManifestStatus};
+use std::path::PathBuf;
+use tokio::fs;
+
+/// Outcome of evaluating a promotion
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verdict {
+    /// Source version can be promoted
+    Promote,
+    /// Target already has the source version
+    UpToDate,
+    /// Source is not in a promotable state
+    Blocked(String),
+}
+
+/// A planned promotion of a service
+#[derive(Debug, Clone)]
+pub struct Promotion {
+    pub service: String,
+    /// Version pinned in the target region (if any)
+    pub current: Option<String>,
+    /// Version rolled out in the source region (if known)
+    pub candidate: Option<String>,
+    pub verdict: Verdict,
+}
+
+/// Parameters for `shipcat promote`
+pub struct PromoteOptions {
+    /// Minimum time since the source rollout completed (e.g. 1h, 2d)
+    pub soak: String,
+    /// Only print the plan
+    pub dry_run: bool,
+}
+
+/// Check the status of a source ShipcatManifest for a promotable version
+///
+/// The rollout of `version` must have succeeded, and done so before `cutoff`.
+fn soaked_version(
+    version: &str,
+    status: Option<&ManifestStatus>,
+    cutoff: DateTime<Utc>,
+) -> std::result::Result<(), String> {
+    let status = status.ok_or_else(|| "no status in source region".to_string())?;
+    let rolled = match &status.conditions.rolledout {
+        Some(c) if c.status => c,
+        Some(c) => {
+            return Err(format!(
+                "rollout failed: {}",
+                c.message.clone().unwrap_or_else(|| "unknown reason".into())
+            ))
+        }
+        None => return Err("not rolled out".into()),
+    };
+    let last_good = status
+        .summary
+        .as_ref()
+        .and_then(|s| s.last_successful_rollout_version.as_ref());
+    if last_good.map(String::as_str) != Some(version) {
+        return Err(format!("{} has not finished rolling out", version));
+    }
+    let when = rolled
+        .last_transition
+        .parse::<DateTime<Utc>>()
+        .map_err(|e| format!("invalid rollout timestamp: {}", e))?;
+    if when > cutoff {
+        return Err(format!("rolled out {} - still soaking", rolled.last_transition));
+    }
+    Ok(())
+}
+
+/// Decide what to do with a service given the source crd state
+fn evaluate(
+    service: &str,
+    current: Option<String>,
+    source: Option<(String, Option<ManifestStatus>)>,
+    cutoff: DateTime<Utc>,
+) -> Promotion {
+    let (candidate, verdict) = match source {
+        None => (None, Verdict::Blocked("not found in source region".into())),
+        Some((version, status)) => {
+            let verdict = match soaked_version(&version, status.as_ref(), cutoff) {
+                Err(reason) => Verdict::Blocked(reason),
+                Ok(_) if current.as_ref() == Some(&version) => Verdict::UpToDate,
+                Ok(_) => Verdict::Promote,
+            };
+            (Some(version), verdict)
+        }
+    };
+    Promotion {
+        service: service.to_string(),
+        current,
+        candidate,
+        verdict,
+    }
+}
+
+/// Kube contexts that can reach a region (the region name first, then aliases)
+fn region_contexts(conf: &Config, region: &Region) -> Vec<String> {
+    let mut res = vec![region.name.clone()];
+    for (alias, target) in &conf.contextAliases {
+        if target == &region.name {
+            res.push(alias.clone());
+        }
+    }
+    res
+}
+
+/// Fetch the spec version and status of a service in the source region
+async fn fetch_source(
+    svc: &str,
+    conf: &Config,
+    source: &Region,
+) -> Result<Option<(String, Option<ManifestStatus>)>> {
+    let mut last_err = None;
+    for ctx in region_contexts(conf, source) {
+        let kube = match ShipKube::new_in_context(svc, &source.namespace, &ctx).await {
+            Ok(k) => k,
+            Err(e) => {
+                debug!("Cannot use kube context {}: {}", ctx, e);
+                last_err = Some(e);
+                continue;
+            }
+        };
+        return match kube.get_minimal().await {
+            Ok(crd) => Ok(Some((crd.spec.version, crd.status))),
+            Err(e) => {
+                debug!("No shipcatmanifest for {} in {}: {}", svc, source.name, e);
+                Ok(None)
+            }
+        };
+    }
+    match last_err {
+        Some(e) => Err(e),
+        None => bail!("No kube context found for {}", source.name),
+    }
+}
+
+/// Compute the promotion of a service between two regions
+///
+/// Takes the (filtered) configs of the source and target region.
+pub async fn plan(
+    svc: &str,
+    source: &(Config, Region),
+    target: &(Config, Region),
+    soak: &str,
+) -> Result<Promotion> {
+    let cutoff = parse_since(soak, Utc::now())?;
+    let current = shipcat_filebacked::load_metadata(svc, &target.0, &target.1)
+        .await?
+        .version;
+    let src = fetch_source(svc, &source.0, &source.1).await?;
+    Ok(evaluate(svc, current, src, cutoff))
+}
+
+fn print_plan(source: &Region, target: &Region, plans: &[Promotion]) {
+    println!("Promotion plan {} -> {}", source.name, target.name);
+    println!(
+        "{0:<32} {1:<16} {2:<16} {3}",
+        "SERVICE", "CURRENT", "CANDIDATE", "ACTION"
+    );
+    for p in plans {
+        let action = match &p.verdict {
+            Verdict::Promote => "promote".to_string(),
+            Verdict::UpToDate => "up to date".to_string(),
+            Verdict::Blocked(reason) => format!("blocked ({})", reason),
+        };
+        println!(
+            "{0:<32} {1:<16} {2:<16} {3}",
+            p.service,
+            p.current.clone().unwrap_or_else(|| "-".into()),
+            p.candidate.clone().unwrap_or_else(|| "-".into()),
+            action
+        );
+    }
+}
+
+/// Set the top level `version` key of an override file
+///
+/// Edits the line in place to preserve comments and ordering in the file.
+fn pin_version(data: &str, version: &str) -> String {
+    let pin = format!("version: {}", version);
+    let mut found = false;
+    let mut lines = data
+        .lines()
+        .map(|l| {
+            if !found && l.starts_with("version:") {
+                found = true;
+                pin.clone()
+            } else {
+                l.to_string()
+            }
+        })
+        .collect::<Vec<_>>();
+    if !found {
+        lines.insert(0, pin);
+    }
+    lines.join("\n") + "\n"
+}
+
+/// Write a version into the override file of the target region
+async fn write_override(svc: &str, target: &Region, version: &str) -> Result<PathBuf> {
+    let path = PathBuf::from(".")
+        .join("services")
+        .join(svc)
+        .join(format!("{}.yml", target.name));
+    let data = if path.is_file() {
+        fs::read_to_string(&path).await?
+    } else {
+        String::new()
+    };
+    fs::write(&path, pin_version(&data, version)).await?;
+    Ok(path)
+}
+
+/// Carry out a promotion
+///
+/// Rolling environments (not locking versions) are upgraded directly,
+/// whereas other regions get their manifest override updated.
+async fn execute(p: &Promotion, target: &Region) -> Result<()> {
+    let version = p.candidate.clone().unwrap();
+    if let VersionScheme::GitShaOrSemver = target.versioningScheme {
+        let ctx = kubectl::current_context().await?;
+        let (conf, region) = Config::new(ConfigState::Filtered, &ctx).await?;
+        if region.name != target.name {
+            bail!(
+                "Promoting {} directly needs the {} kube context (currently {})",
+                p.service,
+                target.name,
+                ctx
+            );
+        }
+        apply::apply(
+            p.service.clone(),
+            false,
+            &region,
+            &conf,
+            true,
+            Some(version),
+            None,
+        )
+        .await?;
+    } else {
+        let path = write_override(&p.service, target, &version).await?;
+        info!("Pinned {} to {} in {}", p.service, version, path.display());
+    }
+    Ok(())
+}
+
+/// Entry point for `shipcat promote`
+///
+/// Services are promoted when the source rollout succeeded and has soaked.
+/// Without `services`, every service in both regions is considered.
+pub async fn promote(
+    services: Option<Vec<String>>,
+    source: &(Config, Region),
+    target: &(Config, Region),
+    opts: &PromoteOptions,
+) -> Result<()> {
+    let svcs = match services {
+        Some(s) => s,
+        None => {
+            let in_source = shipcat_filebacked::available(&source.0, &source.1)
+                .await?
+                .into_iter()
+                .map(|mf| mf.base.name)
+                .collect::<Vec<_>>();
+            shipcat_filebacked::available(&target.0, &target.1)
+                .await?
+                .into_iter()
+                .map(|mf| mf.base.name)
+                .filter(|s| in_source.contains(s))
+                .collect()
+        }
+    };
+    let mut plans = vec![];
+    for svc in &svcs {
+        plans.push(plan(svc, source, target, &opts.soak).await?);
+    }
+    print_plan(&source.1, &target.1, &plans);
+    if opts.dry_run {
+        return Ok(());
+    }
+    let mut blocked = vec![];
+    for p in &plans {
+        match &p.verdict {
+            Verdict::Promote => execute(p, &target.1).await?,
+            Verdict::UpToDate => {}
+            Verdict::Blocked(_) => blocked.push(p.service.clone()),
+        }
+    }
+    // a single explicit promotion should fail loudly
+    if svcs.len() == 1 && !blocked.is_empty() {
+        bail!("Cannot promote {} from {}", blocked[0], source.1.name);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{evaluate, pin_version, Verdict};
+    use chrono::{TimeZone, Utc};
+    use shipcat_definitions::status::ManifestStatus;
+
+    fn status(ok: bool, version: &str, when: &str) -> ManifestStatus {
+        serde_json::from_value(serde_json::json!({
+            "conditions": {
+                "rolledout": {
+                    "status": ok,
+                    "lastTransitionTime": when,
+                    "message": "timed out",
+                }
+            },
+            "summary": {
+                "lastSuccessfulRolloutVersion": version,
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn promote_verdicts() {
+        let cutoff = Utc.ymd(2020, 3, 1).and_hms(12, 0, 0);
+        let soaked = Some((
+            "1.2.0".to_string(),
+            Some(status(true, "1.2.0", "2020-03-01T10:00:00Z")),
+        ));
+        let p = evaluate("webapp", Some("1.1.0".into()), soaked.clone(), cutoff);
+        assert_eq!(p.verdict, Verdict::Promote);
+        assert_eq!(p.candidate, Some("1.2.0".into()));
+        let p = evaluate("webapp", Some("1.2.0".into()), soaked, cutoff);
+        assert_eq!(p.verdict, Verdict::UpToDate);
+
+        let soaking = Some((
+            "1.2.0".to_string(),
+            Some(status(true, "1.2.0", "2020-03-01T12:30:00Z")),
+        ));
+        let p = evaluate("webapp", None, soaking, cutoff);
+        assert!(matches!(p.verdict, Verdict::Blocked(ref r) if r.contains("soaking")));
+
+        let failed = Some((
+            "1.2.0".to_string(),
+            Some(status(false, "1.1.0", "2020-03-01T10:00:00Z")),
+        ));
+        let p = evaluate("webapp", None, failed, cutoff);
+        assert_eq!(p.verdict, Verdict::Blocked("rollout failed: timed out".into()));
+
+        // spec changed but the rollout of it has not completed
+        let rolling = Some((
+            "1.3.0".to_string(),
+            Some(status(true, "1.2.0", "2020-03-01T10:00:00Z")),
+        ));
+        let p = evaluate("webapp", None, rolling, cutoff);
+        assert!(matches!(p.verdict, Verdict::Blocked(_)));
+
+        let p = evaluate("webapp", None, None, cutoff);
+        assert_eq!(p.candidate, None);
+        assert!(matches!(p.verdict, Verdict::Blocked(_)));
+    }
+
+    #[test]
+    fn promote_pin_version() {
+        let data = "# pinned by ci\nversion: 1.1.0\nenv:\n  FOO: bar\n";
+        assert_eq!(
+            pin_version(data, "1.2.0"),
+            "# pinned by ci\nversion: 1.2.0\nenv:\n  FOO: bar\n"
+        );
+        assert_eq!(
+            pin_version("env:\n  FOO: bar", "1.2.0"),
+            "version: 1.2.0\nenv:\n  FOO: bar\n"
+        );
+        assert_eq!(pin_version("", "1.2.0"), "version: 1.2.0\n");
+    }
+}
@@ -1,10 +1,15 @@
 use semver::Version;
+use serde_json::json;
 use slack_hook2::{
-    AttachmentBuilder, PayloadBuilder, Slack, SlackLink, SlackText,
+    AttachmentBuilder, Payload, PayloadBuilder, Slack, SlackLink, SlackText,
     SlackTextContent::{self, Link, Text, User},
     SlackUserLink,
 };
-use std::{collections::BTreeMap, env};
+use std::{
+    collections::BTreeMap,
+    env,
+    time::{Duration, Instant},
+};
 
 use super::{ErrorKind, Result};
 use crate::diff;
@@ -59,6 +64,12 @@ pub fn env_channel() -> Result<String> {
 fn env_username() -> String {
     env::var("SLACK_SHIPCAT_NAME").unwrap_or_else(|_| "shipcat".into())
 }
+fn env_token() -> Option<String> {
+    env::var("SLACK_SHIPCAT_TOKEN").ok()
+}
+fn env_api_url() -> String {
+    env::var("SLACK_SHIPCAT_API_URL").unwrap_or_else(|_| "https://slack.com/api".into())
+}
 
 /// Basic check to see that slack credentials is working
 ///
@@ -134,14 +145,31 @@ pub async fn send_dumb(msg: DumbMessage) -> Result<()> {
     Ok(())
 }
 
-/// Send a `Message` to a configured slack destination
-async fn send_internal(msg: Message, chan: String, owners: &Owners) -> Result<()> {
-    let hook_url: &str = &env_hook_url()?;
+/// Channels a `Message` goes to
+fn channels(msg: &Message) -> Result<Vec<String>> {
+    let mut chans = vec![env_channel()?];
+    if let Some(chan) = &msg.metadata.notifications {
+        chans.push(chan.to_string());
+    }
+    Ok(chans)
+}
+
+/// Whether a diff says more than the version change already linked
+fn is_meaningful_diff(diff: &str) -> bool {
+    if let Some((v1, v2)) = diff::infer_version_change(diff) {
+        !diff::is_version_only(diff, (&v1, &v2))
+    } else {
+        true
+    }
+}
+
+/// Build the slack payload for a `Message`
+///
+/// The diff is put in a second attachment unless `with_code` is false.
+fn build_payload(msg: &Message, chan: String, owners: &Owners, with_code: bool) -> Result<Payload> {
     let hook_user: String = env_username();
     let md = &msg.metadata;
 
-    let slack = Slack::new(hook_url)?;
-
     let mut p = PayloadBuilder::new()
         .channel(chan)
         .icon_emoji(":shipcat:")
@@ -157,33 +185,29 @@ async fn send_internal(msg: Message, chan: String, owners: &Owners) -> Result<()
     // First attachment is main text + main link + CCs
     // Fallbacktext is in constructor here (shown in OSD notifies)
     let mut a = AttachmentBuilder::new(msg.text.clone()); // fallback
-    if let Some(c) = msg.color {
-        a = a.color(c)
+    if let Some(c) = &msg.color {
+        a = a.color(c.clone())
     }
     // All text constructed for first attachment goes in this vec:
-    let mut texts = vec![Text(msg.text.into())];
+    let mut texts = vec![Text(msg.text.clone().into())];
 
     let mut codeattach = None;
-    if let Some(diff) = msg.code {
+    if let Some(diff) = &msg.code {
         // does the diff contain versions?
-        let is_version_only = if let Some((v1, v2)) = diff::infer_version_change(&diff) {
-            let lnk = create_github_compare_url(&md, (&v1, &v2));
-            texts.push(lnk);
-            diff::is_version_only(&diff, (&v1, &v2))
-        } else {
-            false
-        };
+        if let Some((v1, v2)) = diff::infer_version_change(&diff) {
+            texts.push(create_github_compare_url(&md, (&v1, &v2)));
+        }
         // is diff otherwise meaningful?
-        if !is_version_only {
+        if with_code && is_meaningful_diff(diff) {
             codeattach = Some(
                 AttachmentBuilder::new(diff.clone())
                     .color("#439FE0")
-                    .text(vec![Text(diff.into())].as_slice())
+                    .text(vec![Text(diff.clone().into())].as_slice())
                     .build()?,
             )
         }
-    } else if let Some(v) = msg.version {
-        texts.push(infer_metadata_single_link(md, v));
+    } else if let Some(v) = &msg.version {
+        texts.push(infer_metadata_single_link(md, v.clone()));
     }
 
     // Automatic CI originator link
@@ -214,12 +238,174 @@ async fn send_internal(msg: Message, chan: String, owners: &Owners) -> Result<()
         // Pass attachment vector
     }
     p = p.attachments(ax);
+    Ok(p.build()?)
+}
+
+/// Send a `Message` to a configured slack destination
+async fn send_internal(msg: Message, chan: String, owners: &Owners) -> Result<()> {
+    let hook_url: &str = &env_hook_url()?;
+    let slack = Slack::new(hook_url)?;
+    let payload = build_payload(&msg, chan, owners, true)?;
 
     // Send everything. Phew.
     if msg.mode != NotificationMode::Silent {
-        slack.send(&p.build()?).await?;
+        slack.send(&payload).await?;
+    }
+
+    Ok(())
+}
+
+/// A message posted through the Web API, edited as a rollout progresses
+#[derive(Debug, Clone)]
+pub struct Thread {
+    /// Channel id returned by slack
+    pub channel: String,
+    /// Timestamp id of the parent message
+    pub ts: String,
+    started: Instant,
+    diff_posted: bool,
+}
+
+/// Slack Web API client
+///
+/// Used instead of the incoming webhook when `SLACK_SHIPCAT_TOKEN` is set.
+pub struct WebApi {
+    client: reqwest::Client,
+    url: String,
+    token: String,
+}
+
+impl WebApi {
+    pub fn new(url: &str, token: &str) -> Self {
+        WebApi {
+            client: reqwest::Client::new(),
+            url: url.trim_end_matches('/').to_string(),
+            token: token.to_string(),
+        }
+    }
+
+    pub fn from_env() -> Option<Self> {
+        env_token().map(|t| WebApi::new(&env_api_url(), &t))
+    }
+
+    async fn call(&self, method: &str, body: serde_json::Value) -> Result<serde_json::Value> {
+        let url = format!("{}/{}", self.url, method);
+        let res: serde_json::Value = self
+            .client
+            .post(&url)
+            .bearer_auth(&self.token)
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        // the web api signals errors in the body
+        if res["ok"].as_bool() != Some(true) {
+            bail!(
+                "slack {} failed: {}",
+                method,
+                res["error"].as_str().unwrap_or("unknown error")
+            );
+        }
+        Ok(res)
     }
 
+    /// Post a new message and start a thread on it
+    pub async fn post(&self, payload: &Payload) -> Result<Thread> {
+        let res = self
+            .call("chat.postMessage", serde_json::to_value(payload)?)
+            .await?;
+        match (res["channel"].as_str(), res["ts"].as_str()) {
+            (Some(channel), Some(ts)) => Ok(Thread {
+                channel: channel.into(),
+                ts: ts.into(),
+                started: Instant::now(),
+                diff_posted: false,
+            }),
+            _ => bail!("slack chat.postMessage did not return a message id"),
+        }
+    }
+
+    /// Replace the parent message of a thread
+    pub async fn update(&self, thread: &Thread, payload: &Payload) -> Result<()> {
+        let mut body = serde_json::to_value(payload)?;
+        body["channel"] = json!(thread.channel);
+        body["ts"] = json!(thread.ts);
+        self.call("chat.update", body).await?;
+        Ok(())
+    }
+
+    /// Add a reply to a thread
+    pub async fn reply(&self, thread: &Thread, text: &str) -> Result<()> {
+        let body = json!({
+            "channel": thread.channel,
+            "thread_ts": thread.ts,
+            "text": text,
+            "username": env_username(),
+            "icon_emoji": ":shipcat:",
+        });
+        self.call("chat.postMessage", body).await?;
+        Ok(())
+    }
+}
+
+fn format_elapsed(dur: Duration) -> String {
+    let secs = dur.as_secs();
+    if secs >= 60 {
+        format!("{}m{}s", secs / 60, secs % 60)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
+/// Post or edit the message of a rollout
+///
+/// The first call posts one message per channel; later calls edit them in place.
+/// The diff is posted once in the threads, and the duration when `finished`.
+pub async fn rollout(
+    api: &WebApi,
+    msg: Message,
+    threads: &mut Vec<Thread>,
+    finished: bool,
+    owners: &Owners,
+) -> Result<()> {
+    if msg.mode == NotificationMode::Silent {
+        return Ok(());
+    }
+    if threads.is_empty() {
+        for chan in channels(&msg)? {
+            let payload = build_payload(&msg, chan, owners, false)?;
+            threads.push(api.post(&payload).await?);
+        }
+    } else {
+        for t in threads.iter() {
+            let payload = build_payload(&msg, t.channel.clone(), owners, false)?;
+            api.update(t, &payload).await?;
+        }
+    }
+    if let Some(diff) = msg.code.as_ref().filter(|d| is_meaningful_diff(d)) {
+        for t in threads.iter_mut().filter(|t| !t.diff_posted) {
+            api.reply(t, &format!("```{}```", diff)).await?;
+            t.diff_posted = true;
+        }
+    }
+    if finished {
+        for t in threads.iter() {
+            let took = format!("finished after {}", format_elapsed(t.started.elapsed()));
+            api.reply(t, &took).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Add details to the threads of a rollout (if using the Web API)
+pub async fn reply_all(threads: &[Thread], text: &str) -> Result<()> {
+    if let Some(api) = WebApi::from_env() {
+        for t in threads {
+            api.reply(t, text).await?;
+        }
+    }
     Ok(())
 }
 
@@ -295,3 +481,70 @@ fn infer_ci_links() -> SlackTextContent {
         Text(SlackText::new("via unknown user".to_string()))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{build_payload, format_elapsed, rollout, Message, WebApi};
+    use crate::{Manifest, Result};
+    use shipcat_definitions::{structs::NotificationMode, teams::Owners};
+    use std::time::Duration;
+
+    fn message(text: &str, code: Option<&str>) -> Message {
+        Message {
+            text: text.into(),
+            metadata: Manifest::test("fake-svc").metadata.unwrap(),
+            mode: NotificationMode::MessageOnly,
+            color: Some("good".into()),
+            code: code.map(String::from),
+            version: Some("1.0.0".into()),
+        }
+    }
+
+    #[test]
+    fn slack_elapsed() {
+        assert_eq!(format_elapsed(Duration::from_secs(42)), "42s");
+        assert_eq!(format_elapsed(Duration::from_secs(133)), "2m13s");
+    }
+
+    #[test]
+    fn slack_payload_code() -> Result<()> {
+        let msg = message("applied", Some("-  replicas: 1\n+  replicas: 2"));
+        let owners = Owners::default();
+        let full = build_payload(&msg, "#deploys".into(), &owners, true)?;
+        assert_eq!(full.attachments.unwrap().len(), 2);
+        // web api mode puts the diff in the thread instead
+        let short = build_payload(&msg, "#deploys".into(), &owners, false)?;
+        assert_eq!(short.attachments.unwrap().len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn slack_rollout_threads() -> Result<()> {
+        std::env::set_var("SLACK_SHIPCAT_CHANNEL", "#deploys");
+        let posted = mockito::mock("POST", "/chat.postMessage")
+            .match_header("Authorization", "Bearer xoxb-test")
+            .with_body(r#"{"ok": true, "channel": "C012AB3CD", "ts": "1584000000.000100"}"#)
+            // parent, diff reply, and timing reply
+            .expect(3)
+            .create();
+        let updated = mockito::mock("POST", "/chat.update")
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({
+                "channel": "C012AB3CD",
+                "ts": "1584000000.000100",
+            })))
+            .with_body(r#"{"ok": true}"#)
+            .expect(1)
+            .create();
+
+        let api = WebApi::new(&mockito::server_url(), "xoxb-test");
+        let owners = Owners::default();
+        let mut threads = vec![];
+        let diff = Some("-  replicas: 1\n+  replicas: 2");
+        rollout(&api, message("applying", diff), &mut threads, false, &owners).await?;
+        assert_eq!(threads.len(), 1);
+        rollout(&api, message("applied", diff), &mut threads, true, &owners).await?;
+        posted.assert();
+        updated.assert();
+        Ok(())
+    }
+}
@@ -0,0 +1,213 @@
+//- Post rollout smoke checks
+use crate::{
+    hooks::{owned_metadata, remove_previous, wait_for_job, JobOutcome},
+    kubeapi::ShipKube,
+    ErrorKind, Manifest, Result,
+};
+use k8s_openapi::{
+    api::{
+        batch::v1::{Job, JobSpec},
+        core::v1::{Container, PodSpec, PodTemplateSpec},
+    },
+    apimachinery::pkg::apis::meta::v1::ObjectMeta,
+};
+use shipcat_definitions::structs::smoketest::{SmokeCheck, SmokeTestMode};
+use std::{collections::BTreeMap, net::TcpListener, process::Stdio, time::Duration};
+use tokio::{net::TcpStream, process::Command};
+
+/// Outcome of a single check, with a reason on failure
+type CheckResult = std::result::Result<(), String>;
+
+fn check_port(mf: &Manifest, check: &SmokeCheck) -> u32 {
+    check.port.or(mf.httpPort).unwrap_or(80)
+}
+
+/// Port the service exposes a container port on
+///
+/// The chart maps `httpPort` to port 80 on the `Service`.
+fn service_port(mf: &Manifest, port: u32) -> u32 {
+    if Some(port) == mf.httpPort {
+        80
+    } else {
+        port
+    }
+}
+
+/// Make the curl Job for a check
+fn make_job(mf: &Manifest, check: &SmokeCheck, name: &str, image: String) -> Job {
+    let url = format!(
+        "http://{}.{}.svc.cluster.local:{}{}",
+        mf.name,
+        mf.namespace,
+        service_port(mf, check_port(mf, check)),
+        check.path
+    );
+    let mut labels = BTreeMap::new();
+    labels.insert("shipcat-smoketest-for".to_string(), mf.name.clone());
+    let container = Container {
+        name: "smoketest".into(),
+        image: Some(image),
+        command: Some(vec!["curl".into()]),
+        // body followed by the status code on its own line
+        args: Some(vec![
+            "-sS".into(),
+            "-m".into(),
+            check.timeout().to_string(),
+            "-w".into(),
+            "\n%{http_code}".into(),
+            url,
+        ]),
+        ..Default::default()
+    };
+    Job {
+        metadata: Some(owned_metadata(mf, name, labels.clone())),
+        spec: Some(JobSpec {
+            backoff_limit: Some(0),
+            template: PodTemplateSpec {
+                metadata: Some(ObjectMeta {
+                    labels: Some(labels),
+                    ..Default::default()
+                }),
+                spec: Some(PodSpec {
+                    containers: vec![container],
+                    restart_policy: Some("Never".into()),
+                    ..Default::default()
+                }),
+            },
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+/// Split curl output from `make_job` into a status code and a body
+fn parse_curl_output(output: &str) -> Option<(u16, &str)> {
+    let output = output.trim_end();
+    let (body, status) = match output.rfind('\n') {
+        Some(i) => (&output[..i], &output[i + 1..]),
+        None => ("", output),
+    };
+    status.trim().parse().ok().map(|s| (s, body))
+}
+
+async fn check_via_job(
+    mf: &Manifest,
+    kube: &ShipKube,
+    check: &SmokeCheck,
+    image: String,
+) -> Result<CheckResult> {
+    let name = format!("{}-smoketest-{}", mf.name, check.name);
+    remove_previous(kube, &name).await?;
+    kube.create_job(&make_job(mf, check, &name, image)).await?;
+    // leave some time for pulling the image
+    let completed = wait_for_job(kube, &name, check.timeout() + 60).await? == JobOutcome::Succeeded;
+    let logs = kube.get_job_logs(&name).await.unwrap_or_default();
+    let res = match parse_curl_output(&logs) {
+        Some((status, body)) if completed => check.evaluate(status, body),
+        _ => Err(format!("{} request failed: {}", check.path, logs.trim())),
+    };
+    kube.delete_job(&name).await?;
+    Ok(res)
+}
+
+async fn check_via_port_forward(mf: &Manifest, check: &SmokeCheck) -> Result<CheckResult> {
+    let port = check_port(mf, check);
+    // let the OS pick a free local port for us
+    let localport = TcpListener::bind("127.0.0.1:0")?.local_addr()?.port();
+    let args = vec![
+        format!("-n={}", mf.namespace),
+        "port-forward".into(),
+        format!("{}/{}", mf.workload.to_string(), mf.name),
+        format!("{}:{}", localport, port),
+    ];
+    debug!("kubectl {}", args.join(" "));
+    let _child = Command::new("kubectl")
+        .args(&args)
+        .stdout(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    let mut listening = false;
+    for _ in 0..20 {
+        if TcpStream::connect(("127.0.0.1", localport)).await.is_ok() {
+            listening = true;
+            break;
+        }
+        tokio::time::delay_for(Duration::from_millis(500)).await;
+    }
+    if !listening {
+        bail!("Failed to port-forward to {}:{}", mf.name, port);
+    }
+
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(check.timeout().into()))
+        .build()?;
+    let url = format!("http://127.0.0.1:{}{}", localport, check.path);
+    let res = match client.get(&url).send().await {
+        Ok(resp) => {
+            let status = resp.status().as_u16();
+            let body = resp.text().await.unwrap_or_default();
+            check.evaluate(status, &body)
+        }
+        Err(e) => Err(format!("{} request failed: {}", check.path, e)),
+    };
+    Ok(res)
+}
+
+/// Run all smoke checks of a service in order
+///
+/// Stops at the first check that fails.
+pub async fn run(mf: &Manifest, kube: &ShipKube) -> Result<()> {
+    let st = match &mf.smokeTests {
+        Some(st) => st,
+        None => return Ok(()),
+    };
+    for check in &st.checks {
+        info!("Running smoke check {} for {}", check.name, mf.name);
+        let res = match st.mode {
+            SmokeTestMode::PortForward => check_via_port_forward(mf, check).await?,
+            SmokeTestMode::Job => check_via_job(mf, kube, check, st.image()).await?,
+        };
+        if let Err(reason) = res {
+            warn!("Smoke check {} failed: {}", check.name, reason);
+            return Err(ErrorKind::SmokeTestFailure(mf.name.clone(), check.name.clone(), reason).into());
+        }
+    }
+    info!("Smoke checks for {} passed", mf.name);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{make_job, parse_curl_output};
+    use shipcat_definitions::{structs::smoketest::SmokeCheck, Manifest};
+
+    #[test]
+    fn smoke_curl_output() {
+        assert_eq!(parse_curl_output("ok\n200"), Some((200, "ok")));
+        assert_eq!(parse_curl_output("{\n}\n503\n"), Some((503, "{\n}")));
+        assert_eq!(parse_curl_output("\n204"), Some((204, "")));
+        assert_eq!(parse_curl_output("curl: (7) Failed to connect"), None);
+    }
+
+    #[test]
+    fn smoke_job_url() {
+        let mut mf = Manifest::test("webapp");
+        mf.namespace = "apps".into();
+        mf.httpPort = Some(8080);
+        let check = SmokeCheck {
+            name: "health".into(),
+            path: "/health".into(),
+            ..Default::default()
+        };
+        let job = make_job(&mf, &check, "webapp-smoketest-health", "curlimages/curl".into());
+        let args = job.spec.unwrap().template.spec.unwrap().containers[0]
+            .args
+            .clone()
+            .unwrap();
+        assert_eq!(
+            args.last().unwrap(),
+            "http://webapp.apps.svc.cluster.local:80/health"
+        );
+    }
+}
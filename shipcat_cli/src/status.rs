@@ -94,6 +94,9 @@ pub async fn show(svc: &str, conf: &Config, reg: &Region) -> Result<()> {
         if let Some(ro) = &conds.rolledout {
             println!("RolledOut {}", format_condition(ro)?);
         }
+        if let Some(st) = &conds.smoketested {
+            println!("SmokeTested {}", format_condition(st)?);
+        }
     }
     println!();
 
@@ -1,10 +1,20 @@
-use super::{Config, Region, Result};
-use shipcat_definitions::{structs::Kong, BaseManifest};
+use super::{
+    uptime::{self, UptimeProvider, UptimeTarget},
+    Config, ErrorKind, Region, Result, ResultExt,
+};
+use reqwest::{header, Client, Url};
+use std::{collections::BTreeMap, env, fmt};
+
+/// Tag marking tests synced by shipcat
+///
+/// Only added by `sync`. Tests without this tag are never deleted,
+/// and are only updated when adopted by name or url.
+const MANAGED_TAG: &str = "shipcat";
 
 /// One Statuscake object
-#[derive(Serialize)]
+#[derive(Serialize, Clone, Debug, PartialEq)]
 #[serde(rename_all = "PascalCase")]
-struct StatuscakeTest {
+pub struct StatuscakeTest {
     #[serde(rename = "name")]
     pub name: String,
     pub website_name: String,
@@ -16,38 +26,20 @@ struct StatuscakeTest {
 }
 
 impl StatuscakeTest {
-    fn new(region: &Region, mf: &BaseManifest, external_svc: String, kong: Kong) -> Option<Self> {
-        let md = &mf.metadata;
-        let squad = md.squad.as_ref().expect("squad exists");
-        let tribe = md.tribe.as_ref().expect("tribe exists");
+    fn new(region: &Region, t: &UptimeTarget) -> Self {
+        let unknown = "unknown".to_string();
         // StatusCake alerts forwarded to pagerduty only includes this name
         // so we have to stuff region, service, and owners into the name :/
         let website_name = format!(
             "{} {} healthcheck squad={},tribe={}",
-            region.name, mf.name, squad, tribe
+            t.region,
+            t.service,
+            t.squad.as_ref().unwrap_or(&unknown),
+            t.tribe.as_ref().unwrap_or(&unknown)
         );
 
-        // Generate the URL to test
-        let website_url = if let Some(host) = kong.hosts.first() {
-            Some(format!("https://{}/health", host))
-        } else if let Some(uris) = kong.uris {
-            Some(format!(
-                "{}/status/{}/health",
-                external_svc,
-                uris.trim_start_matches('/')
-            ))
-        } else {
-            // No host, no uri, what's going on?
-            None
-        };
-
-        // Generate tags, both regional and environment
         // Tags are only helpful for the API part to StatusCake directly
-        let mut tags = vec![];
-        tags.push(region.name.clone());
-        tags.push(region.environment.to_string());
-        tags.push(format!("squad={}", squad));
-        tags.push(format!("tribe={}", tribe));
+        let mut tags = t.tags.clone();
 
         // Process extra region-specific config
         // Set the Contact group if available
@@ -60,52 +52,34 @@ impl StatuscakeTest {
             None
         };
 
-        Some(StatuscakeTest {
-            name: mf.name.clone(),
+        StatuscakeTest {
+            name: t.service.clone(),
             website_name,
-            website_url,
+            website_url: Some(t.url.clone()),
             contact_group,
             test_tags: tags.join(","),
-        })
+        }
     }
 }
 
 async fn generate_statuscake_output(conf: &Config, region: &Region) -> Result<Vec<StatuscakeTest>> {
-    let mut tests = Vec::new();
-
-    // Ensure the region has a base_url
-    if let Some(external_svc) = region.base_urls.get("external_services") {
-        debug!("Using base_url.external_services {:?}", external_svc);
-        // Generate list of APIs to feed to Statuscake
-        for mf in shipcat_filebacked::available(conf, region).await? {
-            debug!("Found service {:?}", mf);
-            for k in mf.kong_apis.clone() {
-                if k.name != mf.base.name {
-                    debug!(
-                        "{:?} has an additional kong configuration ({:?}), skipping",
-                        mf, k.name
-                    );
-                    continue;
-                }
-                debug!("{:?} has a main kong configuration, adding", mf);
-                if let Some(t) = StatuscakeTest::new(region, &mf.base, external_svc.to_string(), k) {
-                    tests.push(t);
-                }
-            }
-        }
-    // Extra APIs - let's not monitor them for now (too complex)
-
-    // for (name, api) in region.kong.extra_apis.clone() {
-    //    apis.insert(name, api);
-    //}
-    } else {
-        bail!(
-            "base_url.external_services is not defined for region {}",
-            region.name
-        );
-    }
+    let targets = uptime::targets(conf, region).await?;
+    Ok(targets.iter().map(|t| StatuscakeTest::new(region, t)).collect())
+}
 
-    Ok(tests)
+/// StatusCake as an `UptimeProvider`
+pub struct StatuscakeProvider {
+    pub region: Region,
+}
+
+impl UptimeProvider for StatuscakeProvider {
+    fn render(&self, targets: &[UptimeTarget]) -> Result<String> {
+        let tests = targets
+            .iter()
+            .map(|t| StatuscakeTest::new(&self.region, t))
+            .collect::<Vec<_>>();
+        Ok(serde_yaml::to_string(&tests)?)
+    }
 }
 
 /// Generate Statuscake config from a filled in global config
@@ -116,3 +90,369 @@ pub async fn output(conf: &Config, region: &Region) -> Result<()> {
 
     Ok(())
 }
+
+impl StatuscakeTest {
+    /// Stable identifier for the test of a service in a region
+    ///
+    /// The start of the `website_name` before the owners.
+    fn key(&self) -> String {
+        test_key(&self.website_name)
+    }
+
+    /// The test tagged as managed by shipcat
+    fn managed(&self) -> Self {
+        let mut t = self.clone();
+        t.test_tags = if self.test_tags.is_empty() {
+            MANAGED_TAG.to_string()
+        } else {
+            format!("{},{}", MANAGED_TAG, self.test_tags)
+        };
+        t
+    }
+}
+
+fn test_key(website_name: &str) -> String {
+    website_name
+        .split_whitespace()
+        .take(3)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// A test as returned by the StatusCake API
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "PascalCase")]
+pub struct RemoteTest {
+    #[serde(rename = "TestID")]
+    pub test_id: u64,
+    pub website_name: String,
+    #[serde(rename = "WebsiteURL", default)]
+    pub website_url: Option<String>,
+    #[serde(default)]
+    pub contact_group: Vec<String>,
+    #[serde(default)]
+    pub test_tags: Vec<String>,
+}
+
+impl RemoteTest {
+    fn is_managed(&self) -> bool {
+        self.test_tags.iter().any(|t| t == MANAGED_TAG)
+    }
+
+    fn is_managed_in(&self, region: &str) -> bool {
+        self.is_managed() && self.test_tags.iter().any(|t| t == region)
+    }
+
+    /// Whether an unmanaged test was created for the desired test before syncing
+    ///
+    /// Matches on the name, or on the url of tests tagged with the region.
+    fn adoptable_by(&self, t: &StatuscakeTest, region: &str) -> bool {
+        if self.is_managed() {
+            return false;
+        }
+        let same_url = self.website_url.is_some() && self.website_url == t.website_url;
+        test_key(&self.website_name) == t.key() || (same_url && self.test_tags.iter().any(|t| t == region))
+    }
+
+    /// Whether the remote test needs an update to match the desired test
+    fn differs_from(&self, t: &StatuscakeTest) -> bool {
+        let tags = t.test_tags.split(',').map(String::from).collect::<Vec<_>>();
+        let groups = t.contact_group.iter().cloned().collect::<Vec<_>>();
+        self.website_name != t.website_name
+            || self.website_url != t.website_url
+            || self.contact_group != groups
+            || self.test_tags != tags
+    }
+}
+
+/// A change needed to bring StatusCake in line with the manifests
+#[derive(Clone, Debug, PartialEq)]
+pub enum SyncAction {
+    Create(StatuscakeTest),
+    Update(u64, StatuscakeTest),
+    Delete(u64, String),
+}
+
+impl fmt::Display for SyncAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyncAction::Create(t) => write!(f, "create {}", t.website_name),
+            SyncAction::Update(id, t) => write!(f, "update {} ({})", t.website_name, id),
+            SyncAction::Delete(id, name) => write!(f, "delete {} ({})", name, id),
+        }
+    }
+}
+
+/// Work out the actions needed to reconcile the tests of a region
+///
+/// Only tests tagged as managed by shipcat in this region are deleted.
+/// Untagged tests created before syncing are adopted (and tagged) rather than duplicated.
+pub(crate) fn plan(desired: &[StatuscakeTest], remote: &[RemoteTest], region: &str) -> Vec<SyncAction> {
+    let mut managed = BTreeMap::new();
+    let mut unmanaged = vec![];
+    for r in remote {
+        if r.is_managed_in(region) {
+            managed.insert(test_key(&r.website_name), r);
+        } else {
+            unmanaged.push(r);
+        }
+    }
+    let mut actions = vec![];
+    for t in desired {
+        let t = t.managed();
+        match managed.remove(&t.key()) {
+            Some(r) => {
+                if r.differs_from(&t) {
+                    actions.push(SyncAction::Update(r.test_id, t));
+                }
+            }
+            None => match unmanaged.iter().position(|r| r.adoptable_by(&t, region)) {
+                Some(i) => {
+                    let r = unmanaged.remove(i);
+                    debug!("Adopting test {} for {}", r.test_id, t.key());
+                    actions.push(SyncAction::Update(r.test_id, t));
+                }
+                None => actions.push(SyncAction::Create(t)),
+            },
+        }
+    }
+    for (_, r) in managed {
+        actions.push(SyncAction::Delete(r.test_id, r.website_name.clone()));
+    }
+    actions
+}
+
+/// Response from StatusCake for changes
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ChangeResponse {
+    success: bool,
+    #[serde(default)]
+    message: Option<String>,
+}
+
+/// Minimal client for the StatusCake tests API
+pub struct StatuscakeClient {
+    client: Client,
+    url: String,
+}
+
+impl StatuscakeClient {
+    /// Create a client for the API at `url`
+    pub fn new(url: &str, username: &str, apikey: &str) -> Result<Self> {
+        let mut headers = header::HeaderMap::new();
+        match (
+            header::HeaderValue::from_str(username),
+            header::HeaderValue::from_str(apikey),
+        ) {
+            (Ok(u), Ok(k)) => {
+                headers.insert("Username", u);
+                headers.insert("API", k);
+            }
+            _ => bail!("StatusCake credentials must be valid header values"),
+        }
+        let client = Client::builder().default_headers(headers).build()?;
+        Ok(StatuscakeClient {
+            client,
+            url: url.trim_end_matches('/').to_string(),
+        })
+    }
+
+    /// Create a client from `STATUSCAKE_USERNAME` and `STATUSCAKE_APIKEY`
+    ///
+    /// The API location can be overridden with `STATUSCAKE_URL`.
+    pub fn from_env() -> Result<Self> {
+        let url = env::var("STATUSCAKE_URL").unwrap_or_else(|_| "https://app.statuscake.com/API".into());
+        match (env::var("STATUSCAKE_USERNAME"), env::var("STATUSCAKE_APIKEY")) {
+            (Ok(user), Ok(key)) => Self::new(&url, &user, &key),
+            _ => Err(ErrorKind::MissingStatuscakeCredentials.into()),
+        }
+    }
+
+    fn endpoint(&self, path: &str) -> Result<Url> {
+        Ok(Url::parse(&format!("{}{}", self.url, path))?)
+    }
+
+    /// List all tests on the account
+    pub async fn list(&self) -> Result<Vec<RemoteTest>> {
+        let url = self.endpoint("/Tests/")?;
+        let res = self
+            .client
+            .get(url.clone())
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .chain_err(|| ErrorKind::Url(url.clone()))?;
+        Ok(res.json().await?)
+    }
+
+    async fn check(&self, url: Url, res: reqwest::Result<reqwest::Response>) -> Result<()> {
+        let res = res
+            .and_then(|r| r.error_for_status())
+            .chain_err(|| ErrorKind::Url(url.clone()))?;
+        let change: ChangeResponse = res.json().await?;
+        if !change.success {
+            bail!(
+                "StatusCake rejected the change: {}",
+                change.message.unwrap_or_default()
+            );
+        }
+        Ok(())
+    }
+
+    /// Create a test, or update it when given its id
+    pub async fn upsert(&self, test: &StatuscakeTest, id: Option<u64>) -> Result<()> {
+        let url = self.endpoint("/Tests/Update")?;
+        let mut form = vec![
+            ("WebsiteName", test.website_name.clone()),
+            ("WebsiteURL", test.website_url.clone().unwrap_or_default()),
+            ("TestType", "HTTP".into()),
+            ("CheckRate", "300".into()),
+            ("TestTags", test.test_tags.clone()),
+            ("ContactGroup", test.contact_group.clone().unwrap_or_default()),
+        ];
+        if let Some(id) = id {
+            form.push(("TestID", id.to_string()));
+        }
+        let res = self.client.put(url.clone()).form(&form).send().await;
+        self.check(url, res).await
+    }
+
+    /// Delete a test
+    pub async fn delete(&self, id: u64) -> Result<()> {
+        let mut url = self.endpoint("/Tests/Details/")?;
+        url.query_pairs_mut().append_pair("TestID", &id.to_string());
+        let res = self.client.delete(url.clone()).send().await;
+        self.check(url, res).await
+    }
+
+    /// Perform a planned action
+    pub async fn execute(&self, action: &SyncAction) -> Result<()> {
+        match action {
+            SyncAction::Create(t) => self.upsert(t, None).await,
+            SyncAction::Update(id, t) => self.upsert(t, Some(*id)).await,
+            SyncAction::Delete(id, _) => self.delete(*id).await,
+        }
+    }
+}
+
+/// Reconcile the tests of a region against StatusCake
+///
+/// Prints the plan, and only performs it when not in `dry_run` mode.
+pub async fn sync(
+    conf: &Config,
+    region: &Region,
+    client: &StatuscakeClient,
+    dry_run: bool,
+) -> Result<Vec<SyncAction>> {
+    let desired = generate_statuscake_output(&conf, &region).await?;
+    let remote = client.list().await?;
+    let actions = plan(&desired, &remote, &region.name);
+    if actions.is_empty() {
+        info!("StatusCake tests for {} are up to date", region.name);
+    }
+    for a in &actions {
+        if dry_run {
+            println!("would {}", a);
+        } else {
+            info!("{}", a);
+            client.execute(a).await?;
+        }
+    }
+    Ok(actions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{plan, RemoteTest, StatuscakeClient, StatuscakeTest, SyncAction};
+    use crate::Result;
+
+    fn desired(name: &str, url: &str) -> StatuscakeTest {
+        StatuscakeTest {
+            name: name.into(),
+            website_name: format!("dev-uk {} healthcheck squad=a,tribe=b", name),
+            website_url: Some(url.into()),
+            contact_group: None,
+            test_tags: "dev-uk".into(),
+        }
+    }
+
+    fn remote(id: u64, name: &str, url: &str, tags: &[&str]) -> RemoteTest {
+        RemoteTest {
+            test_id: id,
+            website_name: format!("dev-uk {} healthcheck squad=a,tribe=b", name),
+            website_url: Some(url.into()),
+            contact_group: vec![],
+            test_tags: tags.iter().map(|t| t.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn statuscake_plan() {
+        let want = vec![
+            desired("same", "https://same/health"),
+            desired("moved", "https://new/health"),
+            desired("fresh", "https://fresh/health"),
+            desired("manual", "https://manual/health"),
+            desired("renamed", "https://renamed/health"),
+        ];
+        let have = vec![
+            remote(1, "same", "https://same/health", &["shipcat", "dev-uk"]),
+            remote(2, "moved", "https://old/health", &["shipcat", "dev-uk"]),
+            remote(3, "gone", "https://gone/health", &["shipcat", "dev-uk"]),
+            remote(4, "manual", "https://manual/health", &["dev-uk"]),
+            remote(5, "handmade", "https://handmade/health", &["dev-uk"]),
+            remote(6, "elsewhere", "https://elsewhere/health", &[
+                "shipcat", "prod-uk",
+            ]),
+            remote(7, "oldname", "https://renamed/health", &["dev-uk", "dev"]),
+        ];
+        let actions = plan(&want, &have, "dev-uk");
+        assert!(!want[0].test_tags.contains("shipcat"));
+        assert_eq!(actions, vec![
+            SyncAction::Update(2, want[1].managed()),
+            SyncAction::Create(want[2].managed()),
+            // tests created before syncing are adopted by name or url
+            SyncAction::Update(4, want[3].managed()),
+            SyncAction::Update(7, want[4].managed()),
+            SyncAction::Delete(3, have[2].website_name.clone()),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn statuscake_client_roundtrip() -> Result<()> {
+        let listed = mockito::mock("GET", "/Tests/")
+            .match_header("Username", "shipcat")
+            .match_header("API", "secret")
+            .with_body(
+                r#"[{"TestID": 3, "WebsiteName": "dev-uk gone healthcheck", "WebsiteURL": "https://gone/health",
+                     "ContactGroup": [], "TestTags": ["shipcat", "dev-uk"], "Paused": false}]"#,
+            )
+            .expect(1)
+            .create();
+        let created = mockito::mock("PUT", "/Tests/Update")
+            .match_body(mockito::Matcher::AllOf(vec![
+                mockito::Matcher::UrlEncoded("WebsiteURL".into(), "https://fresh/health".into()),
+                mockito::Matcher::UrlEncoded("TestTags".into(), "shipcat,dev-uk".into()),
+            ]))
+            .with_body(r#"{"Success": true, "Message": "Test Inserted", "InsertID": 7}"#)
+            .expect(1)
+            .create();
+        let deleted = mockito::mock("DELETE", "/Tests/Details/?TestID=3")
+            .with_body(r#"{"Success": true, "Message": "This Check Has Been Deleted"}"#)
+            .expect(1)
+            .create();
+
+        let client = StatuscakeClient::new(&mockito::server_url(), "shipcat", "secret")?;
+        let remote = client.list().await?;
+        let actions = plan(&[desired("fresh", "https://fresh/health")], &remote, "dev-uk");
+        assert_eq!(actions.len(), 2);
+        for a in &actions {
+            client.execute(a).await?;
+        }
+        listed.assert();
+        created.assert();
+        deleted.assert();
+        Ok(())
+    }
+}
@@ -0,0 +1,175 @@
+//- Strimzi KafkaTopic and KafkaUser resources
+use crate::{hooks::owned_metadata, Manifest, Result};
+use serde_json::{json, Value};
+use shipcat_definitions::structs::kafkaresources::{
+    AclDefinition, KafkaResources, KafkaUserPatternType, KafkaUserResourceType,
+};
+use std::collections::BTreeMap;
+
+const STRIMZI_API_VERSION: &str = "kafka.strimzi.io/v1beta1";
+
+fn labels(mf: &Manifest, cluster: &str) -> BTreeMap<String, String> {
+    let mut labels = BTreeMap::new();
+    labels.insert("strimzi.io/cluster".to_string(), cluster.to_string());
+    labels.insert("app.kubernetes.io/name".to_string(), mf.name.clone());
+    labels.insert("app.kubernetes.io/managed-by".to_string(), "shipcat".to_string());
+    if let Some(v) = &mf.version {
+        labels.insert("app.kubernetes.io/version".to_string(), v.clone());
+    }
+    labels
+}
+
+fn metadata(mf: &Manifest, name: &str, cluster: &str) -> Result<Value> {
+    Ok(serde_json::to_value(owned_metadata(
+        mf,
+        name,
+        labels(mf, cluster),
+    ))?)
+}
+
+/// Map an `AclDefinition` to a Strimzi `AclRule`
+///
+/// Resources default to literal topics, cluster resources have no name.
+fn acl_rule(acl: &AclDefinition) -> Result<Value> {
+    let operation = match &acl.operation {
+        Some(op) => op.clone(),
+        None => bail!("ACL for {} is missing an operation", acl.resource_name),
+    };
+    let rtype = acl.resource_type.clone().unwrap_or(KafkaUserResourceType::Topic);
+    let mut resource = json!({ "type": rtype });
+    if !matches!(rtype, KafkaUserResourceType::Cluster) {
+        resource["name"] = json!(acl.resource_name);
+        resource["patternType"] = json!(acl.pattern_type.clone().unwrap_or(KafkaUserPatternType::Literal));
+    }
+    Ok(json!({
+        "resource": resource,
+        "operation": operation,
+        "host": acl.host,
+    }))
+}
+
+/// KafkaTopic and KafkaUser objects for the kafkaResources of a manifest
+///
+/// Empty unless the region has a Strimzi cluster.
+pub fn objects(mf: &Manifest) -> Result<Vec<Value>> {
+    let (kr, cluster): (&KafkaResources, &str) = match &mf.kafkaResources {
+        Some(kr) => match &kr.cluster {
+            Some(c) => (kr, c),
+            None => return Ok(vec![]),
+        },
+        None => return Ok(vec![]),
+    };
+    let mut res = vec![];
+    for t in &kr.topics {
+        res.push(json!({
+            "apiVersion": STRIMZI_API_VERSION,
+            "kind": "KafkaTopic",
+            "metadata": metadata(mf, &t.name, cluster)?,
+            "spec": {
+                "partitions": t.partitions,
+                "replicas": t.replicas,
+                "config": t.config,
+            }
+        }));
+    }
+    for u in &kr.users {
+        let acls = u.acls.iter().map(acl_rule).collect::<Result<Vec<_>>>()?;
+        res.push(json!({
+            "apiVersion": STRIMZI_API_VERSION,
+            "kind": "KafkaUser",
+            "metadata": metadata(mf, &u.name, cluster)?,
+            "spec": {
+                "authentication": { "type": "tls" },
+                "authorization": {
+                    "type": "simple",
+                    "acls": acls,
+                }
+            }
+        }));
+    }
+    Ok(res)
+}
+
+/// Render the Strimzi objects of a manifest as a multi document yaml
+///
+/// Appended to the chart output so they get applied and diffed with the rest.
+pub fn template(mf: &Manifest) -> Result<String> {
+    let mut out = String::new();
+    for o in objects(mf)? {
+        out += &serde_yaml::to_string(&o)?;
+        out += "\n";
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{objects, template};
+    use shipcat_definitions::{structs::KafkaResources, Manifest};
+
+    fn manifest() -> Manifest {
+        let mut mf = Manifest::test("fake-svc");
+        mf.uid = Some("FAKE-GUID".into());
+        let mut kr: KafkaResources = serde_yaml::from_str(
+            r###"
+topics:
+- name: fake-topic
+  partitions: 3
+  replicas: 2
+  config:
+    retention.ms: "604800000"
+users:
+- name: fake-user
+  acls:
+  - resourceName: fake-topic
+    operation: Read
+  - resourceName: fake-
+    resourceType: group
+    patternType: prefix
+    operation: All
+  - resourceName: kafka-cluster
+    resourceType: cluster
+    operation: Describe"###,
+        )
+        .unwrap();
+        kr.cluster = Some("main".into());
+        mf.kafkaResources = Some(kr);
+        mf
+    }
+
+    #[test]
+    fn strimzi_objects() {
+        let objs = objects(&manifest()).unwrap();
+        assert_eq!(objs.len(), 2);
+        let topic = &objs[0];
+        assert_eq!(topic["kind"], "KafkaTopic");
+        assert_eq!(topic["metadata"]["name"], "fake-topic");
+        assert_eq!(topic["metadata"]["labels"]["strimzi.io/cluster"], "main");
+        assert_eq!(topic["metadata"]["ownerReferences"][0]["kind"], "ShipcatManifest");
+        assert_eq!(topic["metadata"]["ownerReferences"][0]["uid"], "FAKE-GUID");
+        assert_eq!(topic["spec"]["partitions"], 3);
+        assert_eq!(topic["spec"]["config"]["retention.ms"], "604800000");
+
+        let user = &objs[1];
+        assert_eq!(user["kind"], "KafkaUser");
+        let acls = &user["spec"]["authorization"]["acls"];
+        assert_eq!(acls[0]["resource"]["type"], "topic");
+        assert_eq!(acls[0]["resource"]["patternType"], "literal");
+        assert_eq!(acls[0]["operation"], "Read");
+        assert_eq!(acls[0]["host"], "*");
+        assert_eq!(acls[1]["resource"]["patternType"], "prefix");
+        assert_eq!(acls[1]["operation"], "All");
+        assert!(acls[2]["resource"].get("name").is_none());
+    }
+
+    #[test]
+    fn strimzi_requires_cluster() {
+        let mut mf = manifest();
+        mf.kafkaResources.as_mut().unwrap().cluster = None;
+        assert!(objects(&mf).unwrap().is_empty());
+        assert_eq!(template(&mf).unwrap(), "");
+        mf.kafkaResources.as_mut().unwrap().users[0].acls[0].operation = None;
+        mf.kafkaResources.as_mut().unwrap().cluster = Some("main".into());
+        assert!(objects(&mf).is_err());
+    }
+}
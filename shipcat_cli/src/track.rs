@@ -6,13 +6,13 @@ use k8s_openapi::api::{
     core::v1::Pod,
 };
 use kube::api::{Meta, ObjectList};
-use shipcat_definitions::{Manifest, PrimaryWorkload};
+use shipcat_definitions::{structs::statefulset::pod_ordinal, Manifest, PrimaryWorkload};
 use std::{
     convert::{TryFrom, TryInto},
     fmt::{self, Debug},
 };
 
-fn format_duration(dur: Duration) -> String {
+pub(crate) fn format_duration(dur: Duration) -> String {
     let days = dur.num_days();
     let hours = dur.num_hours();
     let mins = dur.num_minutes();
@@ -165,7 +165,7 @@ impl TryFrom<ReplicaSet> for ReplicaSetSummary {
 pub async fn debug(mf: &Manifest, kube: &ShipKube) -> Result<()> {
     match mf.workload {
         PrimaryWorkload::Deployment => debug_deployment(kube).await,
-        PrimaryWorkload::Statefulset => debug_statefulset(kube).await,
+        PrimaryWorkload::Statefulset => debug_statefulset(mf, kube).await,
     }
 }
 
@@ -190,14 +190,64 @@ async fn debug_deployment(kube: &ShipKube) -> Result<()> {
     Ok(())
 }
 
-async fn debug_statefulset(kube: &ShipKube) -> Result<()> {
-    // For now, just list the pods as if there were no replicaset to worry about
-    let pods = kube.get_pods().await?;
-    info!("Statefulset contains:");
-    debug_pods(pods, kube).await?;
+/// Debug a statefulset
+///
+/// Shows the rollout state of every ordinal
+/// Debugs the pods on the current and the update revision
+/// Tails the logs from each broken pod
+async fn debug_statefulset(mf: &Manifest, kube: &ShipKube) -> Result<()> {
+    let s = StatefulSummary::try_from(kube.get_statefulset().await?)?;
+    info!(
+        "Statefulset has {}/{} ready replicas ({} updated, partition {})",
+        s.ready, s.replicas, s.updated_replicas, s.partition
+    );
+    for o in ordinal_summaries(&mf.name, kube.get_pods().await?) {
+        let state = if o.revision == s.update_revision {
+            "updated"
+        } else if o.ordinal < s.partition as u32 {
+            "partitioned"
+        } else {
+            "outdated"
+        };
+        let ready = if o.ready { "ready" } else { "not ready" };
+        info!("Ordinal {}: {} {} ({})", o.ordinal, o.name, state, ready);
+    }
+    let mut revisions = vec![];
+    revisions.extend(s.update_revision.clone());
+    revisions.extend(
+        s.current_revision
+            .clone()
+            .filter(|r| Some(r) != s.update_revision.as_ref()),
+    );
+    for rev in revisions {
+        let pods = kube.get_pods_by_revision(&rev).await?;
+        if pods.items.is_empty() {
+            continue;
+        }
+        info!("Revision {} contains:", rev);
+        debug_pods(pods, kube).await?;
+    }
     Ok(())
 }
 
+/// Summaries of the pods that are not ready along with their last log lines
+///
+/// A condensed `debug` for notifications.
+pub async fn pod_failures(kube: &ShipKube) -> Result<Vec<String>> {
+    let mut failures = vec![];
+    for pod in kube.get_pods().await? {
+        let podstate = PodSummary::try_from(pod)?;
+        if podstate.running != podstate.containers as i32 {
+            let mut text = format!("{:?}", podstate);
+            if let Ok(logs) = kube.get_pod_logs(&podstate.name).await {
+                text += &format!("\n{}", logs);
+            }
+            failures.push(text);
+        }
+    }
+    Ok(failures)
+}
+
 async fn debug_pods(pods: ObjectList<Pod>, kube: &ShipKube) -> Result<()> {
     for pod in pods {
         let podstate = PodSummary::try_from(pod)?;
@@ -275,6 +325,8 @@ pub struct StatefulSummary {
     pub current_replicas: i32,
     pub update_revision: Option<String>,
     pub updated_replicas: i32,
+    pub partition: i32,
+    pub on_delete: bool,
 }
 
 impl TryFrom<StatefulSet> for StatefulSummary {
@@ -282,6 +334,14 @@ impl TryFrom<StatefulSet> for StatefulSummary {
 
     /// Helper to convert the openapi Statefulset to the useful info
     fn try_from(d: StatefulSet) -> Result<StatefulSummary> {
+        let mut partition = 0;
+        let mut on_delete = false;
+        if let Some(strategy) = d.spec.and_then(|s| s.update_strategy) {
+            on_delete = strategy.type_.as_ref().map(String::as_str) == Some("OnDelete");
+            if let Some(ru) = strategy.rolling_update {
+                partition = ru.partition.unwrap_or(0);
+            }
+        }
         if let Some(status) = d.status {
             let ready = status.ready_replicas.unwrap_or(0);
             let replicas = status.replicas;
@@ -298,6 +358,8 @@ impl TryFrom<StatefulSet> for StatefulSummary {
                 current_replicas,
                 update_revision,
                 updated_replicas,
+                partition,
+                on_delete,
             })
         } else {
             bail!("Missing statefulset status object")
@@ -305,6 +367,47 @@ impl TryFrom<StatefulSet> for StatefulSummary {
     }
 }
 
+/// The state of a single Statefulset ordinal
+#[derive(Debug)]
+pub struct OrdinalSummary {
+    pub ordinal: u32,
+    pub name: String,
+    pub revision: Option<String>,
+    pub ready: bool,
+}
+
+/// Summarise the pods of a Statefulset by ordinal
+///
+/// Pods not following the statefulset naming scheme are ignored.
+fn ordinal_summaries(name: &str, pods: ObjectList<Pod>) -> Vec<OrdinalSummary> {
+    let mut res = vec![];
+    for pod in pods {
+        let podname = Meta::name(&pod);
+        let ordinal = match pod_ordinal(name, &podname) {
+            Some(o) => o,
+            None => continue,
+        };
+        let revision = Meta::meta(&pod)
+            .labels
+            .as_ref()
+            .and_then(|l| l.get("controller-revision-hash").cloned());
+        let ready = pod
+            .status
+            .and_then(|s| s.conditions)
+            .unwrap_or_default()
+            .iter()
+            .any(|c| c.type_ == "Ready" && c.status == "True");
+        res.push(OrdinalSummary {
+            ordinal,
+            name: podname,
+            revision,
+            ready,
+        });
+    }
+    res.sort_by_key(|o| o.ordinal);
+    res
+}
+
 #[derive(Debug)]
 struct RolloutResult {
     progress: u32,
@@ -378,44 +481,81 @@ async fn rollout_status(mf: &Manifest, kube: &ShipKube, hash: &Option<String>) -
         PrimaryWorkload::Statefulset => {
             let ss = kube.get_statefulset().await?;
             let s = StatefulSummary::try_from(ss)?;
-            let minimum = mf.min_replicas();
-
-            let ok = s.updated_replicas >= minimum as i32
-                && s.updated_replicas == s.ready
-                && s.update_revision == *hash;
+            // only ordinals at or above the partition get replaced
+            let partition = std::cmp::max(0, s.partition) as u32;
+            let expected = mf.min_replicas().saturating_sub(partition);
+
+            // Track the pods directly so that progress only counts ready replacements
+            let ordinals = ordinal_summaries(&mf.name, kube.get_pods().await?);
+            let in_scope = ordinals.iter().filter(|o| o.ordinal >= partition);
+            let (done, pending): (Vec<_>, Vec<_>) =
+                in_scope.partition(|o| o.ready && hash.is_some() && o.revision == *hash);
+            let progress = done.len() as u32;
+
+            let ok = progress >= expected && s.update_revision == *hash;
+            // Statefulsets replace ordinals in descending order
             let message = if ok {
                 None
+            } else if let Some(o) = pending.iter().max_by_key(|o| o.ordinal) {
+                Some(format!("waiting for ordinal {}", o.ordinal))
             } else {
                 Some("Statefulset update in progress".to_string())
             };
-
-            // NB: Progress is slightly optimistic because updated_replicas increment
-            // as soon as the old replica is replaced, not when it's ready.
-            // (we can't use ready_replicas because that counts the sum of old + new)
-            // But this is OK. If it gets to 3/3 then at least 2 rolled out successfully,
-            // and the third was started. The only other way of getting around that
-            // would be tracking the pods with the new hash directly..
-
-            // Note that while progressbar is optimistic, it's not marked as ok (done)
-            // until the new revision is changed over (when sts controller thinks it's done)
-            // So this is a progressbar only inconsistency.
             Ok(RolloutResult {
-                progress: std::cmp::max(0, s.updated_replicas)
-                    .try_into()
-                    .expect("sts.updated_replicas >= 0"),
-                expected: minimum,
-                message: message,
+                progress,
+                expected,
+                message,
                 ok,
             })
         }
     }
 }
 
+/// Replace outdated pods of an OnDelete Statefulset one ordinal at a time
+///
+/// The statefulset controller does not replace pods with this strategy,
+/// so we delete them in descending ordinal order, and wait for each replacement
+/// to be ready on the update revision before moving on to the next ordinal.
+async fn replace_ordinals(mf: &Manifest, kube: &ShipKube, revision: &str) -> Result<bool> {
+    use futures_timer::Delay;
+    let one_sec = std::time::Duration::from_millis(1000);
+    let per_pod = mf.estimate_wait_time() / std::cmp::max(1, mf.estimate_rollout_iterations());
+
+    let ordinals = ordinal_summaries(&mf.name, kube.get_pods().await?);
+    for o in ordinals.iter().rev() {
+        if o.revision.as_ref().map(String::as_str) == Some(revision) {
+            debug!(
+                "Ordinal {} of {} already on revision {}",
+                o.ordinal, mf.name, revision
+            );
+            continue;
+        }
+        info!("Replacing {} (ordinal {})", o.name, o.ordinal);
+        kube.delete_pod(&o.name).await?;
+        let mut replaced = false;
+        for _ in 0..per_pod {
+            Delay::new(one_sec).await;
+            let current = ordinal_summaries(&mf.name, kube.get_pods().await?);
+            if let Some(p) = current.iter().find(|p| p.ordinal == o.ordinal) {
+                if p.ready && p.revision.as_ref().map(String::as_str) == Some(revision) {
+                    replaced = true;
+                    break;
+                }
+            }
+        }
+        if !replaced {
+            warn!("Timed out waiting {}s for {} to be replaced", per_pod, o.name);
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
 /// Track the rollout of the main workload
 pub async fn workload_rollout(mf: &Manifest, kube: &ShipKube) -> Result<bool> {
     use futures_timer::Delay;
     use indicatif::{ProgressBar, ProgressStyle};
-    let minimum = mf.min_replicas();
+    let minimum = mf.rollout_replicas();
     let waittime = mf.estimate_wait_time();
     let one_sec = std::time::Duration::from_millis(1000);
 
@@ -458,6 +598,10 @@ pub async fn workload_rollout(mf: &Manifest, kube: &ShipKube) -> Result<bool> {
             let summary = StatefulSummary::try_from(sts)?;
             if let Some(ur) = summary.update_revision {
                 debug!("Tracking statefulset {:?} for {}", ur, mf.name);
+                if summary.on_delete {
+                    // nothing gets replaced unless we delete the pods ourselves
+                    return replace_ordinals(mf, kube, &ur).await;
+                }
                 hash = Some(ur);
             }
         }
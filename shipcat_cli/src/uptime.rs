@@ -0,0 +1,300 @@
+use super::{Config, Region, Result};
+use serde_json::json;
+use shipcat_definitions::{
+    region::{BlackboxConfig, BlackboxOutput},
+    structs::Kong,
+    BaseManifest, UptimeProviderKind,
+};
+
+/// A public health endpoint of a service to monitor
+///
+/// Vendor independent, and shared by all the `UptimeProvider`s.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct UptimeTarget {
+    pub service: String,
+    pub region: String,
+    pub environment: String,
+    pub url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub squad: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tribe: Option<String>,
+    /// Regional and ownership tags
+    pub tags: Vec<String>,
+}
+
+/// Generate the health URL of a kong exposed service
+pub fn health_url(external_svc: &str, kong: &Kong) -> Option<String> {
+    if let Some(host) = kong.hosts.first() {
+        Some(format!("https://{}/health", host))
+    } else if let Some(uris) = &kong.uris {
+        Some(format!(
+            "{}/status/{}/health",
+            external_svc,
+            uris.trim_start_matches('/')
+        ))
+    } else {
+        // No host, no uri, what's going on?
+        None
+    }
+}
+
+impl UptimeTarget {
+    fn new(region: &Region, mf: &BaseManifest, external_svc: &str, kong: &Kong) -> Option<Self> {
+        let md = &mf.metadata;
+        let url = health_url(external_svc, kong)?;
+
+        // Generate tags, both regional and environment
+        let mut tags = vec![];
+        tags.push(region.name.clone());
+        tags.push(region.environment.to_string());
+        if let Some(squad) = &md.squad {
+            tags.push(format!("squad={}", squad));
+        }
+        if let Some(tribe) = &md.tribe {
+            tags.push(format!("tribe={}", tribe));
+        }
+
+        Some(UptimeTarget {
+            service: mf.name.clone(),
+            region: region.name.clone(),
+            environment: region.environment.to_string(),
+            url,
+            squad: md.squad.clone(),
+            tribe: md.tribe.clone(),
+            tags,
+        })
+    }
+}
+
+/// Find the endpoints to monitor in a region
+///
+/// One per service with a main kong configuration.
+pub async fn targets(conf: &Config, region: &Region) -> Result<Vec<UptimeTarget>> {
+    let mut targets = Vec::new();
+
+    // Ensure the region has a base_url
+    let external_svc = match region.base_urls.get("external_services") {
+        Some(e) => e,
+        None => bail!(
+            "base_url.external_services is not defined for region {}",
+            region.name
+        ),
+    };
+    debug!("Using base_url.external_services {:?}", external_svc);
+    for mf in shipcat_filebacked::available(conf, region).await? {
+        debug!("Found service {:?}", mf);
+        for k in &mf.kong_apis {
+            if k.name != mf.base.name {
+                debug!(
+                    "{:?} has an additional kong configuration ({:?}), skipping",
+                    mf, k.name
+                );
+                continue;
+            }
+            debug!("{:?} has a main kong configuration, adding", mf);
+            if let Some(t) = UptimeTarget::new(region, &mf.base, external_svc, k) {
+                targets.push(t);
+            }
+        }
+    }
+    // Extra APIs - let's not monitor them for now (too complex)
+    Ok(targets)
+}
+
+/// A vendor that can monitor `UptimeTarget`s
+pub trait UptimeProvider {
+    /// Generate the vendor configuration for a set of targets
+    fn render(&self, targets: &[UptimeTarget]) -> Result<String>;
+}
+
+/// Plain JSON list of targets for vendors without native support
+pub struct JsonProvider;
+
+impl UptimeProvider for JsonProvider {
+    fn render(&self, targets: &[UptimeTarget]) -> Result<String> {
+        Ok(serde_json::to_string_pretty(targets)?)
+    }
+}
+
+/// Prometheus blackbox-exporter probes
+pub struct BlackboxProvider {
+    pub config: BlackboxConfig,
+    /// Namespace to put `Probe` objects in
+    pub namespace: String,
+}
+
+impl BlackboxProvider {
+    fn module(&self) -> String {
+        self.config.module.clone().unwrap_or_else(|| "http_2xx".into())
+    }
+
+    fn labels(t: &UptimeTarget) -> serde_json::Value {
+        let mut labels = json!({
+            "service": t.service,
+            "region": t.region,
+            "environment": t.environment,
+        });
+        if let Some(squad) = &t.squad {
+            labels["squad"] = json!(squad);
+        }
+        if let Some(tribe) = &t.tribe {
+            labels["tribe"] = json!(tribe);
+        }
+        labels
+    }
+
+    fn probe(&self, t: &UptimeTarget) -> serde_json::Value {
+        let mut spec = json!({
+            "jobName": "uptime",
+            "module": self.module(),
+            "prober": { "url": self.config.prober },
+            "targets": {
+                "staticConfig": {
+                    "static": [t.url],
+                    "labels": Self::labels(t),
+                }
+            }
+        });
+        if let Some(interval) = &self.config.interval {
+            spec["interval"] = json!(interval);
+        }
+        json!({
+            "apiVersion": "monitoring.coreos.com/v1",
+            "kind": "Probe",
+            "metadata": {
+                "name": format!("{}-uptime", t.service),
+                "namespace": self.namespace,
+                "labels": { "app": t.service },
+            },
+            "spec": spec,
+        })
+    }
+
+    fn scrape_config(&self, targets: &[UptimeTarget]) -> serde_json::Value {
+        let static_configs = targets
+            .iter()
+            .map(|t| json!({ "targets": [t.url], "labels": Self::labels(t) }))
+            .collect::<Vec<_>>();
+        let mut job = json!({
+            "job_name": "blackbox-uptime",
+            "metrics_path": "/probe",
+            "params": { "module": [self.module()] },
+            "static_configs": static_configs,
+            "relabel_configs": [
+                { "source_labels": ["__address__"], "target_label": "__param_target" },
+                { "source_labels": ["__param_target"], "target_label": "instance" },
+                { "target_label": "__address__", "replacement": self.config.prober },
+            ],
+        });
+        if let Some(interval) = &self.config.interval {
+            job["scrape_interval"] = json!(interval);
+        }
+        json!([job])
+    }
+}
+
+impl UptimeProvider for BlackboxProvider {
+    fn render(&self, targets: &[UptimeTarget]) -> Result<String> {
+        match self.config.output {
+            BlackboxOutput::Probe => {
+                let mut docs = vec![];
+                for t in targets {
+                    docs.push(serde_yaml::to_string(&self.probe(t))?);
+                }
+                Ok(docs.join("\n"))
+            }
+            BlackboxOutput::ScrapeConfig => Ok(serde_yaml::to_string(&self.scrape_config(targets))?),
+        }
+    }
+}
+
+/// The provider a region has chosen (or an explicit override)
+pub fn provider(region: &Region, kind: Option<UptimeProviderKind>) -> Result<Box<dyn UptimeProvider>> {
+    let uptime = region.uptime.clone().unwrap_or_default();
+    let kind = kind.unwrap_or(uptime.provider);
+    Ok(match kind {
+        UptimeProviderKind::Statuscake => Box::new(crate::statuscake::StatuscakeProvider {
+            region: region.clone(),
+        }),
+        UptimeProviderKind::Json => Box::new(JsonProvider),
+        UptimeProviderKind::Blackbox => match uptime.blackbox {
+            Some(config) => {
+                let namespace = config
+                    .namespace
+                    .clone()
+                    .unwrap_or_else(|| region.namespace.clone());
+                Box::new(BlackboxProvider { config, namespace })
+            }
+            None => bail!("Region {} has no blackbox config", region.name),
+        },
+    })
+}
+
+/// Generate uptime monitoring config for a region
+pub async fn output(conf: &Config, region: &Region, kind: Option<UptimeProviderKind>) -> Result<()> {
+    let p = provider(region, kind)?;
+    let targets = targets(conf, region).await?;
+    println!("{}", p.render(&targets)?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{health_url, BlackboxProvider, UptimeProvider, UptimeTarget};
+    use shipcat_definitions::{
+        region::{BlackboxConfig, BlackboxOutput},
+        structs::Kong,
+    };
+
+    fn target() -> UptimeTarget {
+        UptimeTarget {
+            service: "webapp".into(),
+            region: "dev-uk".into(),
+            environment: "dev".into(),
+            url: "https://webapp.example.com/health".into(),
+            squad: Some("doves".into()),
+            tribe: None,
+            tags: vec!["dev-uk".into(), "dev".into(), "squad=doves".into()],
+        }
+    }
+
+    #[test]
+    fn uptime_health_url() {
+        let mut kong = Kong::default();
+        assert_eq!(health_url("https://svc.example.com", &kong), None);
+        kong.uris = Some("/webapp".into());
+        assert_eq!(
+            health_url("https://svc.example.com", &kong).unwrap(),
+            "https://svc.example.com/status/webapp/health"
+        );
+        kong.hosts = vec!["webapp.example.com".into()];
+        assert_eq!(
+            health_url("https://svc.example.com", &kong).unwrap(),
+            "https://webapp.example.com/health"
+        );
+    }
+
+    #[test]
+    fn uptime_blackbox_render() {
+        let mut bb = BlackboxProvider {
+            config: BlackboxConfig {
+                prober: "blackbox-exporter:9115".into(),
+                interval: Some("30s".into()),
+                ..Default::default()
+            },
+            namespace: "monitoring".into(),
+        };
+        let probes = bb.render(&[target()]).unwrap();
+        assert!(probes.contains("kind: Probe"));
+        assert!(probes.contains("name: webapp-uptime"));
+        assert!(probes.contains("module: http_2xx"));
+        assert!(probes.contains("squad: doves"));
+
+        bb.config.output = BlackboxOutput::ScrapeConfig;
+        let scrape = bb.render(&[target()]).unwrap();
+        assert!(scrape.contains("job_name: blackbox-uptime"));
+        assert!(scrape.contains("replacement: \"blackbox-exporter:9115\""));
+        assert!(scrape.contains("- \"https://webapp.example.com/health\""));
+    }
+}
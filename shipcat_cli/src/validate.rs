@@ -26,6 +26,7 @@ pub async fn regional_manifests(conf: &Config, reg: &Region) -> Result<()> {
     let mut used_stream_names = vec![];
     let mut used_topic_names = vec![];
     let mut used_user_names = vec![];
+    let mut topic_acls = vec![];
     while let Some(r) = buffered.next().await {
         match r {
             Err(e) => errs.push(e),
@@ -56,6 +57,11 @@ pub async fn regional_manifests(conf: &Config, reg: &Region) -> Result<()> {
                             bail!("{}, Kafka User name already exists: {}", mf.name, &user.name);
                         }
                         used_user_names.push(user.name.clone());
+                        for acl in user.acls {
+                            if acl.is_literal_topic() {
+                                topic_acls.push((mf.name.clone(), acl.resource_name));
+                            }
+                        }
                     }
                 }
             }
@@ -69,6 +75,19 @@ pub async fn regional_manifests(conf: &Config, reg: &Region) -> Result<()> {
         }
         bail!("Invalid shipcat data in {} files", errs.len());
     }
+    // topics must be declared when strimzi manages them
+    if reg.kafka.strimziCluster.is_some() {
+        for name in &used_stream_names {
+            if !used_topic_names.contains(name) {
+                bail!("eventStream {} has no topic declared in kafkaResources", name);
+            }
+        }
+        for (svc, topic) in &topic_acls {
+            if !used_topic_names.contains(topic) {
+                bail!("{}, Kafka User ACL references undeclared topic {}", svc, topic);
+            }
+        }
+    }
     // TODO: cross reference uniqueness values here
     Ok(())
 }
@@ -1,21 +1,14 @@
 use super::{Config, Region, Webhook};
-use crate::{apply::UpgradeInfo, audit, slack, Result};
+use crate::{
+    apply::UpgradeInfo,
+    audit,
+    history::{self, HistoryAction},
+    kubeapi::ShipKube,
+    notify::{self, Event, EventAction},
+    slack, Result,
+};
 
-/// The different states an upgrade can be in
-#[derive(Serialize, PartialEq, Clone)]
-#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
-pub enum UpgradeState {
-    /// Before action
-    Pending,
-    /// Action was cancelled before start
-    Cancelled,
-    // Action has started
-    Started,
-    /// No errors
-    Completed,
-    /// Errors
-    Failed,
-}
+pub use shipcat_definitions::region::UpgradeState;
 
 pub fn ensure_requirements(reg: &Region) -> Result<()> {
     for wh in &reg.webhooks {
@@ -32,6 +25,7 @@ pub async fn reconcile_event(us: UpgradeState, reg: &Region) {
         if let Ok(whc) = wh.get_configuration() {
             let res = match wh {
                 Webhook::Audit(h) => audit::reconciliation(&us, &reg.name, &h, whc).await,
+                _ => Ok(()), // generic webhooks only receive service events
             };
             if let Err(e) = res {
                 warn!("Failed to notify about reconciliation event: {}", e)
@@ -41,10 +35,16 @@ pub async fn reconcile_event(us: UpgradeState, reg: &Region) {
 }
 
 /// Throw events to configured webhooks
-pub async fn apply_event(us: UpgradeState, info: &UpgradeInfo, reg: &Region, conf: &Config) {
+pub async fn apply_event(
+    us: UpgradeState,
+    info: &mut UpgradeInfo,
+    reg: &Region,
+    conf: &Config,
+    kube: &ShipKube,
+) {
     debug!("Apply event: {:?}", info);
     // Webhooks defined in shipcat.conf for the region:
-    for wh in &reg.webhooks {
+    for wh in reg.webhooks.iter().filter(|wh| wh.accepts(&us)) {
         if let Ok(whc) = wh.get_configuration() {
             let res = match wh {
                 Webhook::Audit(h) => {
@@ -55,12 +55,19 @@ pub async fn apply_event(us: UpgradeState, info: &UpgradeInfo, reg: &Region, con
                         _ => Ok(()), // audit only sends Started / Failed / Completed
                     }
                 }
+                _ => notify::send(wh, &Event::new(EventAction::Apply, &us, info)).await,
             };
             if let Err(e) = res {
                 warn!("Failed to notify about apply event: {}", e)
             }
         }
     }
+    // in-cluster history of finished actions:
+    if let UpgradeState::Completed | UpgradeState::Failed = us {
+        if let Err(e) = history::record(kube, info.action.clone(), &us, info).await {
+            warn!("Failed to record apply history: {}", e)
+        }
+    }
     // slack notifications:
     let (color, text) = match us {
         UpgradeState::Completed => ("good", format!("applied `{}` in `{}`", info.name, info.region)),
@@ -68,6 +75,14 @@ pub async fn apply_event(us: UpgradeState, info: &UpgradeInfo, reg: &Region, con
             "danger",
             format!("failed to apply `{}` in `{}`", info.name, info.region),
         ),
+        UpgradeState::Started => (
+            "warning",
+            format!("applying `{}` in `{}`", info.name, info.region),
+        ),
+        UpgradeState::Cancelled => (
+            "good",
+            format!("nothing to apply for `{}` in `{}`", info.name, info.region),
+        ),
         _ => (
             "good",
             format!(
@@ -76,41 +91,88 @@ pub async fn apply_event(us: UpgradeState, info: &UpgradeInfo, reg: &Region, con
             ),
         ),
     };
-    match us {
-        UpgradeState::Completed | UpgradeState::Failed => {
-            let _ = slack::send(
-                slack::Message {
-                    text,
-                    code: info.diff.clone(),
-                    color: Some(String::from(color)),
-                    version: Some(info.version.clone()),
-                    mode: info.slackMode.clone(),
-                    metadata: info.metadata.clone(),
-                },
-                &conf.owners,
-            )
-            .await;
+    let msg = slack::Message {
+        text,
+        code: info.diff.clone(),
+        color: Some(String::from(color)),
+        version: Some(info.version.clone()),
+        mode: info.slackMode.clone(),
+        metadata: info.metadata.clone(),
+    };
+    if let Some(api) = slack::WebApi::from_env() {
+        // one message per rollout, edited as it progresses
+        let notify = match us {
+            UpgradeState::Pending => false,
+            UpgradeState::Cancelled => !info.slack.is_empty(),
+            _ => true,
+        };
+        if notify {
+            let finished = us == UpgradeState::Completed || us == UpgradeState::Failed;
+            if let Err(e) = slack::rollout(&api, msg, &mut info.slack, finished, &conf.owners).await {
+                warn!("Failed to notify slack about apply event: {}", e)
+            }
+        }
+    } else {
+        match us {
+            UpgradeState::Completed | UpgradeState::Failed => {
+                let _ = slack::send(msg, &conf.owners).await;
+            }
+            _ => {}
         }
-        _ => {}
     }
 }
 
 /// Throw events to configured webhooks
 ///
 /// This is the new version for shipcat apply module
-pub async fn delete_event(us: &UpgradeState, info: &UpgradeInfo, reg: &Region, conf: &Config) {
+pub async fn delete_event(
+    us: &UpgradeState,
+    info: &mut UpgradeInfo,
+    reg: &Region,
+    conf: &Config,
+    kube: &ShipKube,
+) {
     // Webhooks defined in shipcat.conf for the region:
     debug!("Delete event: {:?}", info);
-    for wh in &reg.webhooks {
+    for wh in reg.webhooks.iter().filter(|wh| wh.accepts(us)) {
         if let Ok(whc) = wh.get_configuration() {
             let res = match wh {
                 Webhook::Audit(h) => audit::deletion(&us, &info, &h, whc).await,
+                _ => notify::send(wh, &Event::new(EventAction::Delete, us, info)).await,
             };
             if let Err(e) = res {
                 warn!("Failed to notify about delete event: {}", e)
             }
         }
     }
+    if let UpgradeState::Completed | UpgradeState::Failed = us {
+        if let Err(e) = history::record(kube, HistoryAction::Deletion, us, info).await {
+            warn!("Failed to record delete history: {}", e)
+        }
+    }
+    if let Some(api) = slack::WebApi::from_env() {
+        let (color, text) = match us {
+            UpgradeState::Completed => ("good", format!("deleted `{}` in `{}`", info.name, reg.name)),
+            UpgradeState::Failed => (
+                "danger",
+                format!("failed to delete `{}` in `{}`", info.name, reg.name),
+            ),
+            _ => ("warning", format!("deleting `{}` in `{}`", info.name, reg.name)),
+        };
+        let msg = slack::Message {
+            text,
+            code: None,
+            color: Some(String::from(color)),
+            version: Some(info.version.clone()),
+            mode: info.slackMode.clone(),
+            metadata: info.metadata.clone(),
+        };
+        let finished = *us == UpgradeState::Completed || *us == UpgradeState::Failed;
+        if let Err(e) = slack::rollout(&api, msg, &mut info.slack, finished, &conf.owners).await {
+            warn!("Failed to notify slack about delete event: {}", e)
+        }
+        return;
+    }
     // slack notifies when we start the deletion only
     #[allow(clippy::single_match)] // no PartialEq for UpgradeState
     match us {
@@ -0,0 +1,38 @@
+mod common;
+use crate::common::setup;
+use shipcat::gdpr::{check, flow, flow_idx, Finding};
+use shipcat_definitions::{Config, ConfigState};
+
+#[tokio::test]
+async fn gdpr_flow() {
+    setup();
+    let (conf, reg) = Config::new(ConfigState::Base, "dev-uk").await.unwrap();
+    let graph = flow(false, &conf, &reg).await.unwrap();
+    let askidx = flow_idx("fake-ask", &graph).unwrap();
+    let strgidx = flow_idx("fake-storage", &graph).unwrap();
+    // fake-storage processes EmailAddress from fake-ask
+    let edge = graph.find_edge(askidx, strgidx).unwrap();
+    assert_eq!(graph.edge_weight(edge).unwrap().fields, vec!["EmailAddress"]);
+    // fake-ask depends on fake-storage
+    assert!(graph.find_edge(strgidx, askidx).is_some());
+}
+
+#[tokio::test]
+async fn gdpr_check() {
+    setup();
+    let (conf, reg) = Config::new(ConfigState::Base, "dev-uk").await.unwrap();
+    let mf = shipcat_filebacked::load_manifest("fake-storage", &conf, &reg)
+        .await
+        .unwrap();
+    let findings = check(&[mf]);
+    assert!(findings.contains(&Finding::Unencrypted {
+        service: "fake-storage".into(),
+        backend: "S3".into(),
+        field: "EmailAddress".into(),
+    }));
+    assert!(findings.contains(&Finding::NoRetentionPeriod {
+        service: "fake-storage".into(),
+        backend: "MySQL".into(),
+        field: "ChatHistory".into(),
+    }));
+}
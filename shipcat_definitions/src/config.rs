@@ -9,9 +9,11 @@ use crate::teams;
 
 #[allow(unused_imports)] use super::{Error, Result};
 use crate::{
+    freeze::FreezeWindow,
     region::{Environment, Region},
     states::ConfigState,
 };
+use chrono::{DateTime, Utc};
 
 /// Kubernetes cluster information
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -27,10 +29,111 @@ pub struct Cluster {
     /// Clusternmae for overriding kube config context
     #[serde(default)]
     pub clustername: Option<String>,
+    /// How users log in to the cluster
+    ///
+    /// Defaults to teleport when `teleport` is set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub login: Option<LoginConfig>,
     /// What regions this cluster control (perhaps not exclusively)
     pub regions: Vec<String>,
 }
 
+impl Cluster {
+    /// The login flow for the cluster (if shipcat manages credentials for it)
+    pub fn login_config(&self) -> Option<LoginConfig> {
+        self.login.clone().or_else(|| {
+            self.teleport
+                .clone()
+                .map(|url| LoginConfig::Teleport(TeleportLogin { url }))
+        })
+    }
+
+    fn verify_login(&self) -> Result<()> {
+        if self.login.is_some() && self.teleport.is_some() {
+            bail!("cluster {} cannot set both teleport and login", self.name);
+        }
+        match &self.login {
+            Some(LoginConfig::Teleport(t)) if t.url.is_empty() => {
+                bail!("cluster {} needs a teleport url to login", self.name)
+            }
+            Some(LoginConfig::Oidc(o)) if !o.issuer.starts_with("https://") => {
+                bail!("cluster {} needs an https oidc issuer", self.name)
+            }
+            Some(LoginConfig::Oidc(o)) if o.clientId.is_empty() => {
+                bail!("cluster {} needs an oidc clientId", self.name)
+            }
+            Some(LoginConfig::Exec(e)) if e.command.is_empty() => {
+                bail!("cluster {} needs an exec credential command", self.name)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Login flows that create kubeconfig credentials for a cluster
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "provider", deny_unknown_fields, rename_all = "snake_case")]
+pub enum LoginConfig {
+    /// Teleport proxy via `tsh login`
+    Teleport(TeleportLogin),
+    /// OIDC device-code flow (credentials are refreshed by kubectl)
+    Oidc(OidcLogin),
+    /// A client-go exec credential plugin (e.g. `aws-iam-authenticator`)
+    Exec(ExecLogin),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct TeleportLogin {
+    /// Teleport proxy url
+    pub url: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct OidcLogin {
+    /// Issuer url (must serve `.well-known/openid-configuration`)
+    pub issuer: String,
+    /// Public client allowed to use the device authorization grant
+    pub clientId: String,
+    /// Scopes to request (`openid` and `offline_access` for refresh tokens)
+    #[serde(default = "default_oidc_scopes")]
+    pub scopes: Vec<String>,
+    /// Base64 encoded CA bundle of the api server (if not publicly trusted)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub certificateAuthorityData: Option<String>,
+}
+fn default_oidc_scopes() -> Vec<String> {
+    vec![
+        "openid".into(),
+        "email".into(),
+        "groups".into(),
+        "offline_access".into(),
+    ]
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct ExecLogin {
+    /// Plugin executable
+    pub command: String,
+    /// Arguments to the plugin
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    /// Extra environment variables for the plugin
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    /// ExecCredential version the plugin speaks
+    #[serde(default = "default_exec_api_version")]
+    pub apiVersion: String,
+    /// Base64 encoded CA bundle of the api server (if not publicly trusted)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub certificateAuthorityData: Option<String>,
+}
+fn default_exec_api_version() -> String {
+    "client.authentication.k8s.io/v1beta1".into()
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct Location {
@@ -106,9 +209,40 @@ pub struct Config {
     #[serde(default)]
     pub allowedCustomMetadata: BTreeSet<String>,
 
+    /// Manifest keys that are intentionally different across regions
+    ///
+    /// Ignored by `shipcat drift`. Keys are dot separated paths,
+    /// where `*` matches any one segment, and a path matches all keys below it.
+    ///
+    /// ```yaml
+    /// driftIgnoredKeys:
+    /// - env.BASE_URL
+    /// - kong.*.upstream_url
+    /// ```
+    #[serde(default)]
+    pub driftIgnoredKeys: Vec<String>,
+
+    /// Regions aggregated by raftcat
+    ///
+    /// Service pages show these regions side by side, fetched from their raftcat.
+    /// Only useful in the unionised config, where all regions are present.
+    ///
+    /// ```yaml
+    /// federatedRegions:
+    /// - dev-uk
+    /// - staging-uk
+    /// - prod-uk
+    /// ```
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub federatedRegions: Vec<String>,
+
     /// Shipcat version pins
     pub versions: BTreeMap<Environment, Version>,
 
+    /// Deploy freeze windows per environment or region
+    #[serde(default)]
+    pub freezes: Vec<FreezeWindow>,
+
     /// Owners of services, squads, tribes
     ///
     /// Populated from teams.yml
@@ -129,6 +263,7 @@ impl Config {
                     cname
                 );
             }
+            clst.verify_login()?;
             // can't actually verify this in a smaller manifest..
             #[cfg(feature = "filesystem")]
             for r in &clst.regions {
@@ -149,6 +284,20 @@ impl Config {
             }
         }
 
+        for f in &self.freezes {
+            f.verify()?;
+        }
+
+        // can only be checked on the full config
+        #[cfg(feature = "filesystem")]
+        if self.state == ConfigState::File {
+            for r in &self.federatedRegions {
+                if !self.has_region(r) {
+                    bail!("federatedRegions contains undefined region {}", r);
+                }
+            }
+        }
+
         let mut used_kong_urls = vec![];
         for r in &self.regions {
             if r.namespace == "" {
@@ -173,10 +322,24 @@ impl Config {
                 }
                 used_kong_urls.push(kong.config_url.clone());
             }
+            if let Some(uptime) = &r.uptime {
+                uptime.verify(&r.name)?;
+            }
+            r.integrations.verify(&r.name)?;
         }
         Ok(())
     }
 
+    /// The first freeze in effect for a region (if any)
+    pub fn active_freeze(&self, region: &Region, now: DateTime<Utc>) -> Result<Option<&FreezeWindow>> {
+        for f in self.freezes.iter().filter(|f| f.applies_to(region)) {
+            if f.is_active(now)? {
+                return Ok(Some(f));
+            }
+        }
+        Ok(None)
+    }
+
     #[cfg(feature = "filesystem")]
     pub fn verify_version_pin(&self, env: &Environment) -> Result<()> {
         let pin = self.get_appropriate_version_pin(env)?;
@@ -0,0 +1,371 @@
+use chrono::{DateTime, Datelike, Duration, Timelike, Utc};
+use std::collections::BTreeSet;
+
+use super::{Region, Result};
+use crate::region::Environment;
+
+/// A period where upgrades are refused
+///
+/// Either recurring via a cron `schedule`, or a one-off between `from` and `to`.
+///
+/// ```yaml
+/// freezes:
+/// - name: weekend
+///   environments: [prod]
+///   schedule: "0 17 * * FRI"
+///   duration: 64h
+/// - name: christmas
+///   regions: [prod-uk]
+///   from: 2020-12-23T00:00:00Z
+///   to: 2021-01-04T09:00:00Z
+///   reason: "Reduced cover over the holidays"
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct FreezeWindow {
+    /// Name of the freeze
+    pub name: String,
+    /// Environments the freeze applies to
+    #[serde(default)]
+    pub environments: Vec<Environment>,
+    /// Regions the freeze applies to
+    ///
+    /// If neither regions nor environments are set, the freeze applies everywhere.
+    #[serde(default)]
+    pub regions: Vec<String>,
+    /// Cron expression (in UTC) for when a recurring freeze is in effect
+    ///
+    /// Without a `duration`, every matching minute is frozen.
+    /// With a `duration`, every match starts a freeze of that length.
+    #[serde(default)]
+    pub schedule: Option<String>,
+    /// Length of a scheduled freeze (e.g. 90m, 64h, 2d)
+    #[serde(default)]
+    pub duration: Option<String>,
+    /// Start of a one-off freeze
+    #[serde(default)]
+    pub from: Option<DateTime<Utc>>,
+    /// End of a one-off freeze
+    #[serde(default)]
+    pub to: Option<DateTime<Utc>>,
+    /// Explanation shown when refusing upgrades
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+/// A record of an upgrade allowed through an active freeze
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct FreezeOverride {
+    /// Name of the overridden freeze
+    pub freeze: String,
+    /// Why the freeze was overridden
+    pub reason: String,
+}
+
+/// Parse a duration like `90m`, `64h` or `2d`
+fn parse_duration(s: &str) -> Result<Duration> {
+    let (num, unit) = s.split_at(s.len().saturating_sub(1));
+    let n: i64 = match num.parse() {
+        Ok(n) if n > 0 => n,
+        _ => bail!("Invalid freeze duration '{}'", s),
+    };
+    Ok(match unit {
+        "m" => Duration::minutes(n),
+        "h" => Duration::hours(n),
+        "d" => Duration::days(n),
+        _ => bail!("Invalid freeze duration unit in '{}' - use one of m, h, d", s),
+    })
+}
+
+/// A parsed 5 field cron expression (minute hour day-of-month month day-of-week)
+#[derive(Debug, PartialEq)]
+pub struct CronSchedule {
+    minutes: BTreeSet<u32>,
+    hours: BTreeSet<u32>,
+    days: BTreeSet<u32>,
+    months: BTreeSet<u32>,
+    weekdays: BTreeSet<u32>,
+    /// Whether day-of-month and day-of-week were both restricted (then either matches)
+    either_day: bool,
+}
+
+const MONTHS: [&str; 12] = [
+    "JAN", "FEB", "MAR", "APR", "MAY", "JUN", "JUL", "AUG", "SEP", "OCT", "NOV", "DEC",
+];
+const WEEKDAYS: [&str; 7] = ["SUN", "MON", "TUE", "WED", "THU", "FRI", "SAT"];
+
+fn parse_value(s: &str, names: &[&str], offset: u32) -> Option<u32> {
+    let upper = s.to_uppercase();
+    if let Some(i) = names.iter().position(|n| *n == upper) {
+        return Some(i as u32 + offset);
+    }
+    s.parse().ok()
+}
+
+/// Parse one cron field into the set of values it matches
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str]) -> Result<BTreeSet<u32>> {
+    let mut values = BTreeSet::new();
+    for part in field.split(',') {
+        let (range, step) = match part.find('/') {
+            Some(i) => (&part[..i], part[i + 1..].parse::<u32>().ok()),
+            None => (part, Some(1)),
+        };
+        let step = match step {
+            Some(s) if s > 0 => s,
+            _ => bail!("Invalid step in cron field '{}'", field),
+        };
+        let (lo, hi) = if range == "*" {
+            (Some(min), Some(max))
+        } else if let Some(i) = range.find('-') {
+            (
+                parse_value(&range[..i], names, min),
+                parse_value(&range[i + 1..], names, min),
+            )
+        } else {
+            let v = parse_value(range, names, min);
+            // a single value with a step runs to the end of the range
+            (v, if part.contains('/') { Some(max) } else { v })
+        };
+        match (lo, hi) {
+            (Some(lo), Some(hi)) if min <= lo && lo <= hi && hi <= max => {
+                values.extend((lo..=hi).step_by(step as usize));
+            }
+            _ => bail!("Invalid cron field '{}' (allowed {}-{})", field, min, max),
+        }
+    }
+    Ok(values)
+}
+
+impl CronSchedule {
+    pub fn parse(expr: &str) -> Result<Self> {
+        let fields = expr.split_whitespace().collect::<Vec<_>>();
+        if fields.len() != 5 {
+            bail!("Cron expression '{}' must have 5 fields", expr);
+        }
+        // 7 is an alias for sunday
+        let mut weekdays = parse_field(fields[4], 0, 7, &WEEKDAYS)?;
+        if weekdays.remove(&7) {
+            weekdays.insert(0);
+        }
+        Ok(CronSchedule {
+            minutes: parse_field(fields[0], 0, 59, &[])?,
+            hours: parse_field(fields[1], 0, 23, &[])?,
+            days: parse_field(fields[2], 1, 31, &[])?,
+            months: parse_field(fields[3], 1, 12, &MONTHS)?,
+            weekdays,
+            either_day: fields[2] != "*" && fields[4] != "*",
+        })
+    }
+
+    /// Whether the date of a timestamp matches the schedule
+    fn matches_day(&self, t: DateTime<Utc>) -> bool {
+        let day = self.days.contains(&t.day());
+        let weekday = self.weekdays.contains(&t.weekday().num_days_from_sunday());
+        let day_ok = if self.either_day {
+            day || weekday
+        } else {
+            day && weekday
+        };
+        day_ok && self.months.contains(&t.month())
+    }
+
+    /// Whether the minute of a timestamp matches the schedule
+    pub fn matches(&self, t: DateTime<Utc>) -> bool {
+        self.matches_day(t) && self.hours.contains(&t.hour()) && self.minutes.contains(&t.minute())
+    }
+
+    /// The last matching minute at or before `t`, if it is not before `earliest`
+    ///
+    /// Skips whole days and hours that cannot match, rather than checking every minute.
+    pub fn last_match(&self, t: DateTime<Utc>, earliest: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let mut t = t.with_second(0)?.with_nanosecond(0)?;
+        while t >= earliest {
+            if !self.matches_day(t) {
+                t = t.date().and_hms(0, 0, 0) - Duration::minutes(1);
+            } else if !self.hours.contains(&t.hour()) {
+                t = t.with_minute(0)? - Duration::minutes(1);
+            } else {
+                match self.minutes.range(..=t.minute()).next_back() {
+                    Some(&m) => return Some(t.with_minute(m)?).filter(|s| *s >= earliest),
+                    None => t = t.with_minute(0)? - Duration::minutes(1),
+                }
+            }
+        }
+        None
+    }
+}
+
+impl FreezeWindow {
+    pub fn verify(&self) -> Result<()> {
+        match (&self.schedule, &self.from, &self.to) {
+            (Some(s), None, None) => {
+                CronSchedule::parse(s)?;
+                if let Some(d) = &self.duration {
+                    let dur = parse_duration(d)?;
+                    if dur > Duration::days(31) {
+                        bail!("Freeze {} duration cannot exceed 31 days", self.name);
+                    }
+                }
+            }
+            (None, Some(from), Some(to)) => {
+                if from >= to {
+                    bail!("Freeze {} must end after it starts", self.name);
+                }
+                if self.duration.is_some() {
+                    bail!("Freeze {} cannot have a duration without a schedule", self.name);
+                }
+            }
+            _ => bail!(
+                "Freeze {} needs either a schedule, or both from and to",
+                self.name
+            ),
+        }
+        Ok(())
+    }
+
+    /// Whether the freeze covers a region
+    pub fn applies_to(&self, region: &Region) -> bool {
+        if self.environments.is_empty() && self.regions.is_empty() {
+            return true;
+        }
+        self.environments.contains(&region.environment) || self.regions.contains(&region.name)
+    }
+
+    /// Whether the freeze is in effect at a point in time
+    pub fn is_active(&self, now: DateTime<Utc>) -> Result<bool> {
+        if let (Some(from), Some(to)) = (self.from, self.to) {
+            return Ok(from <= now && now < to);
+        }
+        let cron = match &self.schedule {
+            Some(s) => CronSchedule::parse(s)?,
+            None => return Ok(false),
+        };
+        match &self.duration {
+            None => Ok(cron.matches(now)),
+            Some(d) => {
+                // the last start of the freeze must be within its duration
+                let dur = parse_duration(d)?;
+                Ok(cron
+                    .last_match(now, now - dur)
+                    .map_or(false, |start| now < start + dur))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CronSchedule, FreezeWindow};
+    use crate::{region::Environment, Region};
+    use chrono::{Duration, TimeZone, Utc};
+
+    #[test]
+    fn freeze_cron_parse() {
+        let cron = CronSchedule::parse("*/15 9-17 * * MON-FRI").unwrap();
+        // 2020-03-06 is a friday
+        assert!(cron.matches(Utc.ymd(2020, 3, 6).and_hms(9, 45, 0)));
+        assert!(!cron.matches(Utc.ymd(2020, 3, 6).and_hms(9, 46, 0)));
+        assert!(!cron.matches(Utc.ymd(2020, 3, 7).and_hms(9, 45, 0)));
+        // sunday as 7, and day of month OR day of week
+        let cron = CronSchedule::parse("0 0 1 * 7").unwrap();
+        assert!(cron.matches(Utc.ymd(2020, 3, 1).and_hms(0, 0, 0)));
+        assert!(cron.matches(Utc.ymd(2020, 3, 8).and_hms(0, 0, 0)));
+        assert!(!cron.matches(Utc.ymd(2020, 3, 9).and_hms(0, 0, 0)));
+
+        // searching backwards across days and hours
+        let now = Utc.ymd(2020, 3, 9).and_hms(8, 59, 30);
+        let cron = CronSchedule::parse("0 17 * * FRI").unwrap();
+        let week_ago = now - Duration::days(7);
+        assert_eq!(
+            cron.last_match(now, week_ago),
+            Some(Utc.ymd(2020, 3, 6).and_hms(17, 0, 0))
+        );
+        assert_eq!(cron.last_match(now, now - Duration::days(2)), None);
+        let cron = CronSchedule::parse("30 8 * * *").unwrap();
+        assert_eq!(
+            cron.last_match(now, week_ago),
+            Some(Utc.ymd(2020, 3, 9).and_hms(8, 30, 0))
+        );
+
+        assert!(CronSchedule::parse("* * * *").is_err());
+        assert!(CronSchedule::parse("60 * * * *").is_err());
+        assert!(CronSchedule::parse("* * * * FUNDAY").is_err());
+        assert!(CronSchedule::parse("*/0 * * * *").is_err());
+    }
+
+    #[test]
+    fn freeze_scheduled_window() {
+        let weekend = FreezeWindow {
+            name: "weekend".into(),
+            schedule: Some("0 17 * * FRI".into()),
+            duration: Some("64h".into()),
+            ..Default::default()
+        };
+        weekend.verify().unwrap();
+        assert!(!weekend.is_active(Utc.ymd(2020, 3, 6).and_hms(16, 59, 0)).unwrap());
+        assert!(weekend.is_active(Utc.ymd(2020, 3, 6).and_hms(17, 0, 30)).unwrap());
+        assert!(weekend.is_active(Utc.ymd(2020, 3, 9).and_hms(8, 59, 0)).unwrap());
+        assert!(!weekend.is_active(Utc.ymd(2020, 3, 9).and_hms(9, 0, 0)).unwrap());
+
+        // a long freeze starting on the last day of a month
+        let month_end = FreezeWindow {
+            name: "month-end".into(),
+            schedule: Some("0 0 28-31 * *".into()),
+            duration: Some("3d".into()),
+            ..Default::default()
+        };
+        assert!(month_end
+            .is_active(Utc.ymd(2020, 3, 2).and_hms(23, 59, 0))
+            .unwrap());
+        assert!(!month_end.is_active(Utc.ymd(2020, 3, 3).and_hms(0, 0, 0)).unwrap());
+
+        // every matching minute without a duration
+        let evenings = FreezeWindow {
+            name: "evenings".into(),
+            schedule: Some("* 18-23 * * *".into()),
+            ..Default::default()
+        };
+        assert!(evenings
+            .is_active(Utc.ymd(2020, 3, 4).and_hms(23, 59, 0))
+            .unwrap());
+        assert!(!evenings.is_active(Utc.ymd(2020, 3, 5).and_hms(0, 0, 0)).unwrap());
+    }
+
+    #[test]
+    fn freeze_date_range() {
+        let xmas = FreezeWindow {
+            name: "christmas".into(),
+            from: Some(Utc.ymd(2020, 12, 23).and_hms(0, 0, 0)),
+            to: Some(Utc.ymd(2021, 1, 4).and_hms(9, 0, 0)),
+            regions: vec!["prod-uk".into()],
+            ..Default::default()
+        };
+        xmas.verify().unwrap();
+        assert!(xmas.is_active(Utc.ymd(2020, 12, 25).and_hms(12, 0, 0)).unwrap());
+        assert!(!xmas.is_active(Utc.ymd(2021, 1, 4).and_hms(9, 0, 0)).unwrap());
+
+        let mut region = Region::default();
+        region.name = "prod-uk".into();
+        assert!(xmas.applies_to(&region));
+        region.name = "prod-us".into();
+        assert!(!xmas.applies_to(&region));
+        region.environment = Environment::Prod;
+        let everywhere = FreezeWindow {
+            environments: vec![Environment::Prod],
+            ..xmas.clone()
+        };
+        assert!(everywhere.applies_to(&region));
+
+        let backwards = FreezeWindow {
+            from: xmas.to,
+            to: xmas.from,
+            ..xmas.clone()
+        };
+        assert!(backwards.verify().is_err());
+        let both = FreezeWindow {
+            schedule: Some("* * * * *".into()),
+            ..xmas
+        };
+        assert!(both.verify().is_err());
+    }
+}
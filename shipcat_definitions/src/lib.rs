@@ -77,10 +77,15 @@ error_chain! {
 
 /// Config with regional data
 pub mod region;
-pub use crate::region::{Environment, KongConfig, ReconciliationMode, Region, VaultConfig, VersionScheme};
+pub use crate::region::{
+    Environment, KongConfig, ReconciliationMode, Region, UpgradeState, UptimeProviderKind, VaultConfig,
+    VersionScheme,
+};
 /// Master config with cross-region data
 pub mod config;
-pub use crate::config::{Cluster, Config, ConfigFallback, ShipcatConfig};
+/// Deploy freeze windows
+pub mod freeze;
+pub use crate::config::{Cluster, Config, ConfigFallback, LoginConfig, ShipcatConfig};
 
 /// Structs for the manifest
 pub mod structs;
@@ -13,16 +13,19 @@ use crate::{
 
 // All structs come from the structs directory
 use super::structs::{
+    affinity::Affinity,
     autoscaling::AutoScaling,
     newrelic::Newrelic,
     security::DataHandling,
     sentry::Sentry,
     tolerations::Tolerations,
     volume::{Volume, VolumeMount},
-    ConfigMap, Container, CronJob, Dependency, DestinationRule, EnvVars, EventStream, Gate, HealthCheck,
-    HostAlias, Kafka, KafkaResources, Kong, LifeCycle, Metadata, NotificationMode, PersistentVolume, Port,
-    Probe, PrometheusAlert, Rbac, ResourceRequirements, RollingUpdate, SecurityContext, VaultOpts, Worker,
+    ConfigMap, Container, CronJob, Dependency, DestinationRule, DisruptionBudget, EnvVars, EventStream, Gate,
+    HealthCheck, Hooks, HostAlias, Kafka, KafkaResources, Kong, LifeCycle, Metadata, NotificationMode,
+    PersistentVolume, Port, Probe, PrometheusAlert, Rbac, ResourceRequirements, RollingUpdate,
+    SecurityContext, SmokeTests, StatefulSetParams, TopologySpreadConstraint, VaultOpts, Worker,
 };
+use k8s_openapi::api::core::v1::PersistentVolumeClaim;
 
 /// Main manifest, serializable from manifest.yml or the shipcat CRD.
 #[derive(CustomResource, Serialize, Deserialize, Debug, Clone, Default)]
@@ -496,6 +499,34 @@ pub struct Manifest {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub rollingUpdate: Option<RollingUpdate>,
 
+    /// StatefulSet parameters
+    ///
+    /// Controls ordering and partitioning of upgrades when using `workload: Statefulset`.
+    /// Straight from [kubernetes statefulset update strategies](https://kubernetes.io/docs/concepts/workloads/controllers/statefulset/#update-strategies).
+    ///
+    /// ```yaml
+    /// statefulSet:
+    ///   podManagementPolicy: Parallel
+    ///   updateStrategy: RollingUpdate
+    ///   partition: 2
+    /// ```
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub statefulSet: Option<StatefulSetParams>,
+
+    /// PodDisruptionBudget parameters
+    ///
+    /// Limits how many replicas voluntary disruptions (like node drains) can take down at once.
+    /// Straight from [kubernetes disruption budgets](https://kubernetes.io/docs/concepts/workloads/pods/disruptions/).
+    /// Regions (or services) setting `defaultDisruptionBudgets: true` in their defaults get one
+    /// from the `maxUnavailable` of the `rollingUpdate` for services with more than one replica.
+    ///
+    /// ```yaml
+    /// disruptionBudget:
+    ///   minAvailable: 50%
+    /// ```
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disruptionBudget: Option<DisruptionBudget>,
+
     /// `HorizontalPodAutoScaler` parameters for kubernetes
     ///
     /// Passed all parameters directly onto the `spec` of a kube HPA.
@@ -529,6 +560,51 @@ pub struct Manifest {
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub tolerations: Vec<Tolerations>,
 
+    /// Node selector for kubernetes
+    ///
+    /// Only schedule the service on nodes with all of these labels.
+    /// Straight from [kubernetes node selectors](https://kubernetes.io/docs/concepts/scheduling-eviction/assign-pod-node/#nodeselector).
+    /// Can be set as a region default.
+    ///
+    /// ```yaml
+    /// nodeSelector:
+    ///   node.kubernetes.io/instance-type: m5.2xlarge
+    /// ```
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub nodeSelector: BTreeMap<String, String>,
+
+    /// Affinity parameters for kubernetes
+    ///
+    /// Attract or repel the service from nodes or other pods.
+    /// Straight from [kubernetes affinity](https://kubernetes.io/docs/concepts/scheduling-eviction/assign-pod-node/#affinity-and-anti-affinity),
+    /// but pod affinity terms without a `labelSelector` select the pods of the service itself.
+    /// Can be set as a region default.
+    ///
+    /// ```yaml
+    /// affinity:
+    ///   podAntiAffinity:
+    ///     requiredDuringSchedulingIgnoredDuringExecution:
+    ///     - topologyKey: topology.kubernetes.io/zone
+    /// ```
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub affinity: Option<Affinity>,
+
+    /// Topology spread constraints for kubernetes
+    ///
+    /// Spread the pods of the service evenly across a topology like zones.
+    /// Straight from [kubernetes topology spread constraints](https://kubernetes.io/docs/concepts/workloads/pods/pod-topology-spread-constraints/),
+    /// but constraints without a `labelSelector` select the pods of the service itself.
+    /// Can be set as a region default.
+    ///
+    /// ```yaml
+    /// topologySpreadConstraints:
+    /// - maxSkew: 1
+    ///   topologyKey: topology.kubernetes.io/zone
+    ///   whenUnsatisfiable: ScheduleAnyway
+    /// ```
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub topologySpreadConstraints: Vec<TopologySpreadConstraint>,
+
     /// Host aliases to inject in /etc/hosts in every kubernetes `Pod`
     ///
     /// Straight from [kubernetes host aliases](https://kubernetes.io/docs/concepts/services-networking/add-entries-to-pod-etc-hosts-with-host-aliases/).
@@ -597,7 +673,10 @@ pub struct Manifest {
     /// - name: svc-cache-space
     ///   mountPath: /root/.scratch
     ///   size: 10Gi
+    ///   storageClass: gp2
     /// ```
+    ///
+    /// With `workload: Statefulset` these become per-replica `volumeClaimTemplates`.
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub persistentVolumes: Vec<PersistentVolume>,
 
@@ -615,6 +694,38 @@ pub struct Manifest {
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub cronJobs: Vec<CronJob>,
 
+    /// One-shot jobs to run as kubernetes `Job` objects around an apply
+    ///
+    /// `preApply` jobs (like database migrations) must succeed before the new version is applied,
+    /// and `postApply` jobs must succeed after it has rolled out.
+    /// Jobs use the service image and version unless overridden, and get secrets like other containers.
+    ///
+    /// ```yaml
+    /// hooks:
+    ///   preApply:
+    ///   - name: webapp-migrate
+    ///     command: ["bundle", "exec", "rake", "db:migrate"]
+    ///     timeout: 300
+    /// ```
+    #[serde(default, skip_serializing_if = "Hooks::is_empty")]
+    pub hooks: Hooks,
+
+    /// HTTP checks that must pass after a rollout before it is marked completed
+    ///
+    /// Checks reach the service through a port-forward (default) or an in-cluster curl `Job`.
+    /// If any check fails, the rollout is marked as failed, and optionally undone.
+    ///
+    /// ```yaml
+    /// smokeTests:
+    ///   rollback: true
+    ///   checks:
+    ///   - name: health
+    ///     path: /health
+    ///     bodyContains: ok
+    /// ```
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub smokeTests: Option<SmokeTests>,
+
     /// Annotations to set on `Service` objects
     ///
     /// Useful for `LoadBalancer` type `Service` objects.
@@ -748,6 +859,9 @@ pub struct Manifest {
     /// Topic Inputs: [Strimzi Kafka Topic CRD ](https://github.com/strimzi/strimzi-kafka-operator/blob/master/install/topic-operator/04-Crd-kafkatopic.yaml)
     /// User Inputs: [Strimzi Kafka User CRD ](https://github.com/strimzi/strimzi-kafka-operator/blob/master/install/user-operator/04-Crd-kafkauser.yaml)
     ///
+    /// In regions with a `kafka.strimziCluster`, these are applied as `KafkaTopic` and `KafkaUser`
+    /// objects owned by the `ShipcatManifest`.
+    ///
     /// ```yaml
     /// kafkaResources:
     ///   topics:
@@ -890,6 +1004,14 @@ pub struct Manifest {
     /// ```
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub prometheusAlerts: Vec<PrometheusAlert>,
+
+    /// Claim templates for a StatefulSet
+    ///
+    /// Generated from `persistentVolumes` when using `workload: Statefulset`.
+    ///
+    /// This is an internal property that is exposed as an output only.
+    #[serde(default, skip_deserializing, skip_serializing_if = "Vec::is_empty")]
+    pub volumeClaimTemplates: Vec<PersistentVolumeClaim>,
 }
 
 impl Manifest {
@@ -999,11 +1121,34 @@ impl Manifest {
         for tl in &self.tolerations {
             tl.verify()?;
         }
+        if let Some(ref aff) = self.affinity {
+            let max_replicas = match &self.autoScaling {
+                Some(hpa) => hpa.maxReplicas,
+                None => self.replicaCount.unwrap_or_default(),
+            };
+            let zones = if region.zones.is_empty() {
+                None
+            } else {
+                Some(region.zones.len())
+            };
+            aff.verify(&self.name, max_replicas, zones)?;
+        }
+        for tsc in &self.topologySpreadConstraints {
+            tsc.verify()?;
+        }
+        self.hooks.verify()?;
+        if let Some(st) = &self.smokeTests {
+            st.verify(self.httpPort)?;
+        }
         for r in &self.rbac {
             r.verify()?;
         }
+        let mut pv_names = BTreeSet::new();
         for pv in &self.persistentVolumes {
             pv.verify()?;
+            if !pv_names.insert(&pv.name) {
+                bail!("Duplicate persistentVolume name {}", pv.name);
+            }
         }
         if let Some(ref cmap) = self.configs {
             cmap.verify()?;
@@ -1015,9 +1160,11 @@ impl Manifest {
         }
         for es in &self.eventStreams {
             es.verify()?;
+            es.verify_region(&region.kafka)?;
         }
         if let Some(kr) = &self.kafkaResources {
             kr.verify()?;
+            kr.verify_region(&region.kafka)?;
         }
         for pa in &self.prometheusAlerts {
             pa.verify(&self.name)?;
@@ -1029,6 +1176,27 @@ impl Manifest {
         if let Some(ref ru) = &self.rollingUpdate {
             ru.verify(self.replicaCount.unwrap())?;
         }
+        if let Some(ref db) = &self.disruptionBudget {
+            db.verify(self.min_replicas())?;
+        }
+        match self.workload {
+            PrimaryWorkload::Statefulset => {
+                if let Some(ref ss) = &self.statefulSet {
+                    ss.verify(self.min_replicas())?;
+                }
+                if self.rollingUpdate.is_some() {
+                    warn!(
+                        "{} sets rollingUpdate which is ignored for statefulsets",
+                        self.name
+                    );
+                }
+            }
+            PrimaryWorkload::Deployment => {
+                if self.statefulSet.is_some() {
+                    bail!("Cannot set statefulSet parameters without `workload: Statefulset`");
+                }
+            }
+        }
 
         self.env.verify()?;
 
@@ -1089,6 +1257,14 @@ impl Manifest {
         for i in &mut self.initContainers {
             envs.push(&mut i.env);
         }
+        for h in self
+            .hooks
+            .preApply
+            .iter_mut()
+            .chain(self.hooks.postApply.iter_mut())
+        {
+            envs.push(&mut h.container.env);
+        }
         envs
     }
 
@@ -1,12 +1,12 @@
 use super::{
     structs::{rollingupdate::RollingUpdate, ResourceRequirements},
-    Manifest, Result,
+    Manifest, PrimaryWorkload, Result,
 };
 
 /// Total resource usage for a Manifest
 ///
 /// Accounting for workers, replicas, sidecars, and autoscaling policies for these.
-#[derive(Serialize, Default)]
+#[derive(Serialize, Default, Clone)]
 pub struct ResourceTotals {
     /// Sum of basic resource structs (ignoring autoscaling limits)
     pub base: ResourceRequirements<f64>,
@@ -64,12 +64,26 @@ impl Manifest {
         }
     }
 
+    /// Compute how many replicas get replaced in a rollout
+    ///
+    /// Statefulsets only replace the ordinals at or above their partition.
+    pub fn rollout_replicas(&self) -> u32 {
+        let rcount = self.min_replicas();
+        match (&self.workload, &self.statefulSet) {
+            (PrimaryWorkload::Statefulset, Some(ss)) => ss.rollout_replicas(rcount),
+            _ => rcount,
+        }
+    }
+
     /// Estimate how many iterations needed in a kube rolling upgrade
     ///
     /// Used to `estimate_wait_time` for a rollout.
     pub fn estimate_rollout_iterations(&self) -> u32 {
-        let rcount = self.min_replicas();
-        if let Some(ru) = self.rollingUpdate.clone() {
+        let rcount = self.rollout_replicas();
+        if self.workload == PrimaryWorkload::Statefulset {
+            // statefulsets replace one pod at a time regardless of podManagementPolicy
+            rcount
+        } else if let Some(ru) = self.rollingUpdate.clone() {
             ru.rollout_iterations(rcount)
         } else {
             RollingUpdate::default().rollout_iterations(rcount)
@@ -153,8 +167,8 @@ impl Manifest {
 
 #[cfg(test)]
 mod tests {
-    use super::Manifest;
-    use crate::structs::HealthCheck;
+    use super::{Manifest, PrimaryWorkload};
+    use crate::structs::{HealthCheck, StatefulSetParams};
 
     #[test]
     fn mf_wait_time_check() {
@@ -193,4 +207,20 @@ mod tests {
         mf.replicaCount = Some(1);
         assert_eq!(mf.estimate_wait_time(), 990); // lots of leeway here just in case
     }
+
+    #[test]
+    fn mf_statefulset_rollout_iterations() {
+        let mut mf = Manifest::default();
+        mf.replicaCount = Some(4);
+        mf.workload = PrimaryWorkload::Statefulset;
+        assert_eq!(mf.rollout_replicas(), 4);
+        assert_eq!(mf.estimate_rollout_iterations(), 4); // one ordinal at a time
+
+        mf.statefulSet = Some(StatefulSetParams {
+            partition: Some(3),
+            ..Default::default()
+        });
+        assert_eq!(mf.rollout_replicas(), 1);
+        assert_eq!(mf.estimate_rollout_iterations(), 1); // only ordinal 3
+    }
 }
@@ -122,6 +122,12 @@ pub struct KafkaConfig {
     /// These are injected in to the manifest.kafka struct if it's set.
     pub brokers: Vec<String>,
 
+    /// Number of brokers in the cluster (optional)
+    ///
+    /// `brokers` may only be a bootstrap list, so topic replicas are only checked against this.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub brokerCount: Option<u32>,
+
     /// Proxy urls in "hostname:port" format.
     ///
     /// These are injected in to the manifest.kafka struct if it's set.
@@ -137,6 +143,29 @@ pub struct KafkaConfig {
     /// A mapping of kafka properties to environment variables (optional)
     #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
     pub propertyEnvMapping: BTreeMap<String, String>,
+
+    /// Name of the Strimzi `Kafka` cluster managing topics and users (optional)
+    ///
+    /// When set, `kafkaResources` are rendered as `KafkaTopic` and `KafkaUser` objects
+    /// owned by the `ShipcatManifest`, and topics used by `eventStreams` must be declared.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strimziCluster: Option<String>,
+}
+
+/// The different states an upgrade can be in
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum UpgradeState {
+    /// Before action
+    Pending,
+    /// Action was cancelled before start
+    Cancelled,
+    // Action has started
+    Started,
+    /// No errors
+    Completed,
+    /// Errors
+    Failed,
 }
 
 /// Webhook types that shipcat might trigger after actions
@@ -145,6 +174,14 @@ pub struct KafkaConfig {
 pub enum Webhook {
     /// Audit webhook details
     Audit(AuditWebhook),
+    /// Signed JSON events to an arbitrary endpoint
+    Http(HttpWebhook),
+    /// CloudEvents 1.0 events in structured mode
+    CloudEvents(CloudEventsWebhook),
+    /// Microsoft Teams incoming webhook
+    Teams(ChatWebhook),
+    /// Mattermost incoming webhook
+    Mattermost(ChatWebhook),
 }
 
 /// Where / how to send audited events
@@ -157,6 +194,78 @@ pub struct AuditWebhook {
     pub token: String,
 }
 
+/// How to retry failed webhook deliveries
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct RetryPolicy {
+    /// Total number of delivery attempts
+    #[serde(default = "default_retry_attempts")]
+    pub attempts: u32,
+    /// Delay before the first retry, doubling on every subsequent retry
+    #[serde(default = "default_retry_backoff_ms")]
+    pub backoff_ms: u64,
+}
+fn default_retry_attempts() -> u32 {
+    3
+}
+fn default_retry_backoff_ms() -> u64 {
+    500
+}
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            attempts: default_retry_attempts(),
+            backoff_ms: default_retry_backoff_ms(),
+        }
+    }
+}
+
+/// Generic JSON events signed with a shared secret
+///
+/// The hex encoded HMAC-SHA256 of the body is sent in `X-Shipcat-Signature`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct HttpWebhook {
+    /// Endpoint
+    pub url: Url,
+    /// Signing secret (IN_VAULT reads WEBHOOK_HTTP_SECRET)
+    pub secret: String,
+    /// States to send events for (all if empty)
+    #[serde(default)]
+    pub states: Vec<UpgradeState>,
+    #[serde(default)]
+    pub retry: RetryPolicy,
+}
+
+/// CloudEvents 1.0 emitter
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct CloudEventsWebhook {
+    /// Endpoint
+    pub url: Url,
+    /// The `source` attribute of events (defaults to shipcat/{region})
+    #[serde(default)]
+    pub source: Option<String>,
+    /// States to send events for (all if empty)
+    #[serde(default)]
+    pub states: Vec<UpgradeState>,
+    #[serde(default)]
+    pub retry: RetryPolicy,
+}
+
+/// Chat incoming webhook
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct ChatWebhook {
+    /// Incoming webhook url
+    pub url: Url,
+    /// States to send events for (all if empty)
+    #[serde(default)]
+    pub states: Vec<UpgradeState>,
+    #[serde(default)]
+    pub retry: RetryPolicy,
+}
+
 /// Configure how CRs will be deployed on a region
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
@@ -199,6 +308,85 @@ pub struct StatuscakeConfig {
     pub extra_tags: Option<String>,
 }
 
+/// Uptime monitoring vendor for a region
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum UptimeProviderKind {
+    /// StatusCake tests (see `statuscake`)
+    Statuscake,
+    /// Prometheus blackbox-exporter probes (see `blackbox`)
+    Blackbox,
+    /// Plain JSON list of endpoints for any other vendor
+    Json,
+}
+
+impl Default for UptimeProviderKind {
+    fn default() -> Self {
+        UptimeProviderKind::Statuscake
+    }
+}
+
+/// What to generate for the Prometheus blackbox-exporter
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum BlackboxOutput {
+    /// prometheus-operator `Probe` objects
+    Probe,
+    /// A plain prometheus `scrape_configs` entry
+    ScrapeConfig,
+}
+
+impl Default for BlackboxOutput {
+    fn default() -> Self {
+        BlackboxOutput::Probe
+    }
+}
+
+/// Prometheus blackbox-exporter configuration for a region
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct BlackboxConfig {
+    /// Address of the blackbox-exporter (e.g. blackbox-exporter.monitoring:9115)
+    pub prober: String,
+    /// Blackbox module to probe with (defaults to http_2xx)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub module: Option<String>,
+    /// Probe interval (e.g. 30s)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interval: Option<String>,
+    /// Namespace for `Probe` objects (defaults to the region namespace)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+    /// What to generate
+    #[serde(default)]
+    pub output: BlackboxOutput,
+}
+
+/// Uptime monitoring configuration for a region
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct UptimeConfig {
+    /// Vendor monitoring the services of the region
+    #[serde(default)]
+    pub provider: UptimeProviderKind,
+    /// Blackbox-exporter configuration (for the Blackbox provider)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blackbox: Option<BlackboxConfig>,
+}
+
+impl UptimeConfig {
+    pub fn verify(&self, region: &str) -> Result<()> {
+        match &self.blackbox {
+            None if self.provider == UptimeProviderKind::Blackbox => {
+                bail!(
+                    "Region {} uses the Blackbox uptime provider without blackbox config",
+                    region
+                )
+            }
+            Some(bb) if bb.prober.is_empty() => bail!("Region {} needs a blackbox prober address", region),
+            _ => Ok(()),
+        }
+    }
+}
+
 /// Logz.io configuration for a region
 #[derive(Serialize, Deserialize, Clone, Debug, Default)] // TODO: better Default impl
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
@@ -227,6 +415,69 @@ pub struct SentryConfig {
     pub url: String,
 }
 
+/// PagerDuty details for a region
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct PagerDutyConfig {
+    /// Base URL of the account (e.g. https://babylon.pagerduty.com)
+    pub url: String,
+}
+
+/// Integration providers raftcat can link services to
+pub const INTEGRATION_PROVIDERS: &[&str] =
+    &["newrelic", "grafana", "logzio", "sentry", "pagerduty", "runbook"];
+
+/// Which integrations raftcat shows for services in a region
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
+pub struct IntegrationsConfig {
+    /// Providers to enable
+    ///
+    /// Defaults to every provider that is configured for the region.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub providers: Vec<String>,
+    /// Seconds between refreshes of providers backed by an external api
+    #[serde(default = "IntegrationsConfig::default_refresh")]
+    pub refreshSeconds: u64,
+}
+
+impl Default for IntegrationsConfig {
+    fn default() -> Self {
+        IntegrationsConfig {
+            providers: vec![],
+            refreshSeconds: Self::default_refresh(),
+        }
+    }
+}
+
+impl IntegrationsConfig {
+    fn default_refresh() -> u64 {
+        15 * 60
+    }
+
+    /// Whether a provider is enabled (assuming it is configured)
+    pub fn enabled(&self, provider: &str) -> bool {
+        self.providers.is_empty() || self.providers.iter().any(|p| p == provider)
+    }
+
+    pub fn verify(&self, region: &str) -> Result<()> {
+        for p in &self.providers {
+            if !INTEGRATION_PROVIDERS.contains(&p.as_str()) {
+                bail!(
+                    "Region {} enables unknown integration '{}' (known: {})",
+                    region,
+                    p,
+                    INTEGRATION_PROVIDERS.join(", ")
+                );
+            }
+        }
+        if self.refreshSeconds < 60 {
+            bail!("Region {} refreshes integrations more than once a minute", region);
+        }
+        Ok(())
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
 pub struct KongAnonymousConsumers {
@@ -289,6 +540,13 @@ impl Webhook {
                     h.token = vault.read(&vkey).await?;
                 }
             }
+            Webhook::Http(h) => {
+                if h.secret == "IN_VAULT" {
+                    let vkey = format!("{}/shipcat/WEBHOOK_HTTP_SECRET", region);
+                    h.secret = vault.read(&vkey).await?;
+                }
+            }
+            Webhook::CloudEvents(_) | Webhook::Teams(_) | Webhook::Mattermost(_) => {}
         }
         Ok(())
     }
@@ -299,11 +557,31 @@ impl Webhook {
                 let vkey = format!("{}/shipcat/WEBHOOK_AUDIT_TOKEN", region);
                 vault.read(&vkey).await?;
             }
+            Webhook::Http(h) => {
+                if h.secret == "IN_VAULT" {
+                    let vkey = format!("{}/shipcat/WEBHOOK_HTTP_SECRET", region);
+                    vault.read(&vkey).await?;
+                }
+            }
+            Webhook::CloudEvents(_) | Webhook::Teams(_) | Webhook::Mattermost(_) => {}
         }
         // TODO: when more secrets, build up a list and do a LIST on shipcat folder
         Ok(())
     }
 
+    /// Whether events in a state should be sent to this webhook
+    ///
+    /// Audit webhooks decide this themselves, the rest use their `states` filter.
+    pub fn accepts(&self, us: &UpgradeState) -> bool {
+        let states = match self {
+            Webhook::Audit(_) => return true,
+            Webhook::Http(h) => &h.states,
+            Webhook::CloudEvents(h) => &h.states,
+            Webhook::Teams(h) | Webhook::Mattermost(h) => &h.states,
+        };
+        states.is_empty() || states.contains(us)
+    }
+
     pub fn get_configuration(&self) -> Result<BTreeMap<String, String>> {
         let mut whc = BTreeMap::default();
         match self {
@@ -338,6 +616,8 @@ impl Webhook {
 
                 debug!("Audit webhook config {:?}", whc);
             }
+            // generic webhooks only need what is in shipcat.conf
+            Webhook::Http(_) | Webhook::CloudEvents(_) | Webhook::Teams(_) | Webhook::Mattermost(_) => {}
         }
 
         // TODO: when slack webhook is cfged, require this:
@@ -349,7 +629,7 @@ impl Webhook {
 
 #[cfg(test)]
 mod test_webhooks {
-    use super::{AuditWebhook, Webhook};
+    use super::{AuditWebhook, UpgradeState, Webhook};
     use regex::Regex;
     use std::env;
     use url::Url;
@@ -392,6 +672,34 @@ mod test_webhooks {
 
         assert!(cfg.is_err());
     }
+
+    #[test]
+    fn region_webhook_state_filters() {
+        let hooks: Vec<Webhook> = serde_yaml::from_str(
+            "
+- name: http
+  url: http://testnoop
+  secret: noop
+  states: [COMPLETED, FAILED]
+  retry:
+    attempts: 5
+- name: mattermost
+  url: http://testnoop
+",
+        )
+        .unwrap();
+        assert!(hooks[0].accepts(&UpgradeState::Failed));
+        assert!(!hooks[0].accepts(&UpgradeState::Started));
+        if let Webhook::Http(h) = &hooks[0] {
+            assert_eq!(h.retry.attempts, 5);
+            assert_eq!(h.retry.backoff_ms, 500);
+        } else {
+            panic!("expected an http webhook");
+        }
+        // no filter means everything
+        assert!(hooks[1].accepts(&UpgradeState::Pending));
+        assert!(hooks[1].get_configuration().unwrap().is_empty());
+    }
 }
 
 // ----------------------------------------------------------------------------------
@@ -492,6 +800,11 @@ pub struct Region {
     /// Statuscake configuration for the region
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub statuscake: Option<StatuscakeConfig>,
+    /// Uptime monitoring configuration for the region
+    ///
+    /// Defaults to generating StatusCake tests.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uptime: Option<UptimeConfig>,
     /// List of Whitelisted IPs
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub ip_whitelist: Vec<String>,
@@ -506,9 +819,20 @@ pub struct Region {
     pub grafana: Option<GrafanaConfig>,
     /// Sentry URL for the region
     pub sentry: Option<SentryConfig>,
+    /// PagerDuty account for the region
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pagerduty: Option<PagerDutyConfig>,
+    /// Integrations shown by raftcat
+    #[serde(default)]
+    pub integrations: IntegrationsConfig,
     /// List of locations the region serves
     #[serde(default)]
     pub locations: Vec<String>,
+    /// Availability zones the nodes of the region are spread across
+    ///
+    /// Used to verify that zonal scheduling constraints can be satisfied.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub zones: Vec<String>,
     /// All webhooks
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub webhooks: Vec<Webhook>,
@@ -600,6 +924,17 @@ impl Region {
         })
     }
 
+    // Get the PagerDuty URL for a given service id in this region
+    pub fn pagerduty_url(&self, id: &str) -> Option<String> {
+        self.pagerduty.clone().map(|pd| {
+            format!(
+                "{pagerduty_url}/service-directory/{id}",
+                pagerduty_url = pd.url.trim_matches('/'),
+                id = id
+            )
+        })
+    }
+
     pub fn raftcat_url(&self) -> Option<String> {
         let devops = String::from("dev-ops");
         let region_name = env::var("REGION_NAME").ok()?;
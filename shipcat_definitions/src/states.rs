@@ -1,7 +1,7 @@
 use super::{vault::Vault, Manifest, Region, Result};
 
 /// Type of primary workload that is associated with the Manifest
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
 pub enum PrimaryWorkload {
     Deployment,
     Statefulset,
@@ -1,4 +1,5 @@
 use super::Result;
+use crate::freeze::FreezeOverride;
 use chrono::{SecondsFormat, Utc};
 
 pub fn make_date() -> String {
@@ -53,6 +54,15 @@ pub struct Conditions {
     /// Best effort information given in message, but this won't replace DeploymentConditions
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub rolledout: Option<Condition>,
+
+    /// Smoke tests passed after the rollout
+    ///
+    /// Only set for manifests with `smokeTests`.
+    /// If smoketested.status is false, this might contain information about:
+    /// - which check failed and why
+    /// - whether the rollout was undone
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub smoketested: Option<Condition>,
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug)]
@@ -84,7 +94,7 @@ pub struct ConditionSummary {
 
     /// reason for last failure (if any)
     #[serde(default)]
-    last_failure_reason: Option<String>,
+    pub last_failure_reason: Option<String>,
 
     /// Best effort reason for why an apply was triggered
     #[serde(default)]
@@ -93,6 +103,10 @@ pub struct ConditionSummary {
     /// Last version that was successfully rolled out
     #[serde(default)]
     pub last_successful_rollout_version: Option<String>,
+
+    /// Last deploy freeze that was overridden to apply
+    #[serde(default)]
+    pub last_freeze_override: Option<FreezeOverride>,
 }
 
 /// Condition
@@ -227,7 +241,7 @@ mod tests {
     }
 }
 
-#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
 pub struct Applier {
     /// Human readable text describing what applied
     pub name: String,
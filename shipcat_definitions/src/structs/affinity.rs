@@ -0,0 +1,330 @@
+use super::Result;
+use std::collections::BTreeMap;
+
+/// Well known node label for the availability zone of a node
+pub const ZONE_LABELS: &[&str] = &[
+    "topology.kubernetes.io/zone",
+    "failure-domain.beta.kubernetes.io/zone",
+];
+
+/// Operator for a selector requirement
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum SelectorOperator {
+    In,
+    NotIn,
+    Exists,
+    DoesNotExist,
+    /// Only valid for node selectors
+    Gt,
+    /// Only valid for node selectors
+    Lt,
+}
+
+/// A single label requirement
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SelectorRequirement {
+    /// Label key the requirement applies to
+    pub key: String,
+    /// How the key relates to the values
+    pub operator: SelectorOperator,
+    /// Values to match against (depending on operator)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub values: Vec<String>,
+}
+
+impl SelectorRequirement {
+    pub fn verify(&self) -> Result<()> {
+        match self.operator {
+            SelectorOperator::In | SelectorOperator::NotIn => {
+                if self.values.is_empty() {
+                    bail!(
+                        "Selector on {} needs values with operator {:?}",
+                        self.key,
+                        self.operator
+                    );
+                }
+            }
+            SelectorOperator::Exists | SelectorOperator::DoesNotExist => {
+                if !self.values.is_empty() {
+                    bail!(
+                        "Selector on {} cannot have values with operator {:?}",
+                        self.key,
+                        self.operator
+                    );
+                }
+            }
+            SelectorOperator::Gt | SelectorOperator::Lt => {
+                if self.values.len() != 1 || self.values[0].parse::<i64>().is_err() {
+                    bail!(
+                        "Selector on {} needs a single integer value with operator {:?}",
+                        self.key,
+                        self.operator
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Label selector for pods
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct LabelSelector {
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub matchLabels: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub matchExpressions: Vec<SelectorRequirement>,
+}
+
+impl LabelSelector {
+    /// Selector matching all the pods of a service
+    pub fn service(name: &str) -> Self {
+        let mut matchLabels = BTreeMap::new();
+        matchLabels.insert("app".to_string(), name.to_string());
+        LabelSelector {
+            matchLabels,
+            ..Default::default()
+        }
+    }
+
+    /// Whether this selector only checks for the pods of a service
+    pub fn selects_service(&self, name: &str) -> bool {
+        self.matchLabels.get("app").map(String::as_str) == Some(name)
+    }
+
+    pub fn verify(&self) -> Result<()> {
+        for e in &self.matchExpressions {
+            if e.operator == SelectorOperator::Gt || e.operator == SelectorOperator::Lt {
+                bail!("Cannot use operator {:?} in a pod label selector", e.operator);
+            }
+            e.verify()?;
+        }
+        Ok(())
+    }
+}
+
+/// A set of node requirements that must all be satisfied
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct NodeSelectorTerm {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub matchExpressions: Vec<SelectorRequirement>,
+}
+
+/// Node requirements where one term must be satisfied
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct NodeSelector {
+    pub nodeSelectorTerms: Vec<NodeSelectorTerm>,
+}
+
+/// A weighted node preference
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PreferredSchedulingTerm {
+    /// Weight in the range 1-100
+    pub weight: u32,
+    pub preference: NodeSelectorTerm,
+}
+
+/// Node affinity scheduling rules
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct NodeAffinity {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requiredDuringSchedulingIgnoredDuringExecution: Option<NodeSelector>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub preferredDuringSchedulingIgnoredDuringExecution: Vec<PreferredSchedulingTerm>,
+}
+
+/// Pods that must (or must not) be co-located within a topology domain
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct PodAffinityTerm {
+    /// Pods to consider
+    ///
+    /// Defaults to the pods of the service itself.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub labelSelector: Option<LabelSelector>,
+    /// Namespaces to consider (defaults to the service namespace)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub namespaces: Vec<String>,
+    /// Node label defining the topology domain
+    pub topologyKey: String,
+}
+
+/// A weighted pod affinity preference
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WeightedPodAffinityTerm {
+    /// Weight in the range 1-100
+    pub weight: u32,
+    pub podAffinityTerm: PodAffinityTerm,
+}
+
+/// Pod (anti-)affinity scheduling rules
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct PodAffinity {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub requiredDuringSchedulingIgnoredDuringExecution: Vec<PodAffinityTerm>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub preferredDuringSchedulingIgnoredDuringExecution: Vec<WeightedPodAffinityTerm>,
+}
+
+/// Kubernetes affinity parameters for a service
+///
+/// Straight from [kubernetes affinity](https://kubernetes.io/docs/concepts/scheduling-eviction/assign-pod-node/#affinity-and-anti-affinity).
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Affinity {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nodeAffinity: Option<NodeAffinity>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub podAffinity: Option<PodAffinity>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub podAntiAffinity: Option<PodAffinity>,
+}
+
+fn verify_weight(weight: u32) -> Result<()> {
+    if !(1..=100).contains(&weight) {
+        bail!("Affinity weight {} must be between 1 and 100", weight);
+    }
+    Ok(())
+}
+
+impl PodAffinityTerm {
+    fn verify(&self) -> Result<()> {
+        if self.topologyKey.is_empty() {
+            bail!("Pod affinity terms need a topologyKey");
+        }
+        if let Some(ls) = &self.labelSelector {
+            ls.verify()?;
+        }
+        Ok(())
+    }
+}
+
+impl PodAffinity {
+    fn verify(&self) -> Result<()> {
+        for t in &self.requiredDuringSchedulingIgnoredDuringExecution {
+            t.verify()?;
+        }
+        for wt in &self.preferredDuringSchedulingIgnoredDuringExecution {
+            verify_weight(wt.weight)?;
+            wt.podAffinityTerm.verify()?;
+        }
+        Ok(())
+    }
+}
+
+impl Affinity {
+    /// Fill in unset label selectors with the pods of the service
+    pub fn implicits(&mut self, name: &str) {
+        for pa in self.podAffinity.iter_mut().chain(self.podAntiAffinity.iter_mut()) {
+            let required = pa.requiredDuringSchedulingIgnoredDuringExecution.iter_mut();
+            let preferred = pa
+                .preferredDuringSchedulingIgnoredDuringExecution
+                .iter_mut()
+                .map(|wt| &mut wt.podAffinityTerm);
+            for t in required.chain(preferred) {
+                if t.labelSelector.is_none() {
+                    t.labelSelector = Some(LabelSelector::service(name));
+                }
+            }
+        }
+    }
+
+    /// Verify the affinity rules are satisfiable
+    ///
+    /// `replicas` is the largest number of replicas the service may scale to,
+    /// and `zones` is the number of availability zones in the region (if known).
+    pub fn verify(&self, name: &str, replicas: u32, zones: Option<usize>) -> Result<()> {
+        if let Some(na) = &self.nodeAffinity {
+            if let Some(req) = &na.requiredDuringSchedulingIgnoredDuringExecution {
+                if req.nodeSelectorTerms.is_empty() {
+                    bail!("Required node affinity needs at least one nodeSelectorTerm");
+                }
+                for t in &req.nodeSelectorTerms {
+                    for e in &t.matchExpressions {
+                        e.verify()?;
+                    }
+                }
+            }
+            for pt in &na.preferredDuringSchedulingIgnoredDuringExecution {
+                verify_weight(pt.weight)?;
+                for e in &pt.preference.matchExpressions {
+                    e.verify()?;
+                }
+            }
+        }
+        if let Some(pa) = &self.podAffinity {
+            pa.verify()?;
+        }
+        if let Some(paa) = &self.podAntiAffinity {
+            paa.verify()?;
+            // one replica per zone is all a required zonal anti-affinity on itself allows
+            let zonal_self = paa
+                .requiredDuringSchedulingIgnoredDuringExecution
+                .iter()
+                .any(|t| {
+                    ZONE_LABELS.contains(&t.topologyKey.as_str())
+                        && t.labelSelector
+                            .as_ref()
+                            .map(|ls| ls.selects_service(name))
+                            .unwrap_or(true)
+                });
+            if zonal_self {
+                match zones {
+                    Some(z) if replicas as usize > z => bail!(
+                        "Required zone anti-affinity cannot schedule {} replicas in {} zones",
+                        replicas,
+                        z
+                    ),
+                    Some(_) => {}
+                    None => warn!(
+                        "{} uses zone anti-affinity in a region without declared zones",
+                        name
+                    ),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Affinity, LabelSelector, PodAffinity, PodAffinityTerm};
+
+    fn zonal_anti_affinity() -> Affinity {
+        Affinity {
+            podAntiAffinity: Some(PodAffinity {
+                requiredDuringSchedulingIgnoredDuringExecution: vec![PodAffinityTerm {
+                    topologyKey: "topology.kubernetes.io/zone".into(),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn affinity_zone_count() {
+        let mut aff = zonal_anti_affinity();
+        aff.implicits("webapp");
+        assert!(aff.verify("webapp", 3, Some(3)).is_ok());
+        assert!(aff.verify("webapp", 4, Some(3)).is_err());
+        assert!(aff.verify("webapp", 4, None).is_ok()); // can't tell
+
+        // spreading away from other services is fine
+        let mut other = zonal_anti_affinity();
+        other.implicits("other");
+        assert!(other.verify("webapp", 4, Some(3)).is_ok());
+    }
+
+    #[test]
+    fn affinity_implicit_selectors() {
+        let mut aff = zonal_anti_affinity();
+        aff.implicits("webapp");
+        let t = &aff
+            .podAntiAffinity
+            .unwrap()
+            .requiredDuringSchedulingIgnoredDuringExecution[0];
+        assert!(t.labelSelector.as_ref().unwrap().selects_service("webapp"));
+        assert!(!LabelSelector::default().selects_service("webapp"));
+    }
+}
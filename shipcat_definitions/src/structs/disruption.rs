@@ -1,51 +1,15 @@
-/// Source for PodDisruptionBudget representation
-///
-/// Ultimately, this is left unused and unimported due to significant limitations in kube.
-/// In particular; https://github.com/kubernetes/kubernetes/issues/45398
-/// which causes us to not reliably be able to change them.
-///
-/// This work is left here in case it becomes useful.
-/// Users may wish to look at rollingupdate.rs instead, which has a useful alternative.
-
-use super::{Result};
-
-// Untagged enum to get around the weird validation
-#[derive(Serialize, Deserialize, Debug, Clone)]
-#[serde(untagged)]
-pub enum AvailabilityPolicy {
-    Percentage(String),
-    Unsigned(u32),
-}
-// Kube has a weird hybrid type for this intstr.IntOrString: IntVal | StrVal
-// if it's a string, then '[0-9]+%!' has to parse
-impl AvailabilityPolicy {
-    fn verify(&self, name: &str, maxNumber: u32) -> Result<()> {
-        match self {
-            AvailabilityPolicy::Unsigned(ref n) => {
-                if n > &maxNumber {
-                    bail!("Cannot have {} set higher than replicaCount {}", name, maxNumber);
-                }
-            },
-            AvailabilityPolicy::Percentage(s) => {
-                if !s.ends_with('%') {
-                    bail!("{} must end with a '%' sign", name);
-                }
-                let digits = s.chars().take_while(|ch| *ch != '%').collect::<String>();
-                let res : u32 = digits.parse()?;
-                if res > 100 {
-                    bail!("Percentage value for {} cannot exceed 100", name);
-                }
-            }
-        }
-        Ok(())
-    }
-}
+use super::{
+    rollingupdate::{AvailabilityPolicy, RollingUpdate},
+    Result,
+};
 
 /// PodDisruptionBudget representation
 ///
 /// Users need to set exactly one of these to pass validation.
 /// The values are "how many replicas" when integer values are used,
 /// and "what percentage of total replicas" when a % is added to the string.
+///
+/// See [K8s disruption docs](https://kubernetes.io/docs/concepts/workloads/pods/disruptions/).
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct DisruptionBudget {
     #[serde(default, skip_serializing_if = "Option::is_none")]
@@ -55,19 +19,98 @@ pub struct DisruptionBudget {
 }
 
 impl DisruptionBudget {
-     pub fn verify(&self, replicas: u32) -> Result<()> {
+    pub fn verify(&self, replicas: u32) -> Result<()> {
         if self.minAvailable.is_none() && self.maxUnavailable.is_none() {
             bail!("Need to set one of minAvailable or maxUnavailable in disruptionBudget");
         }
         if self.minAvailable.is_some() && self.maxUnavailable.is_some() {
             bail!("Cannot set both minAvailable and maxUnavailable in disruptionBudget");
         }
+        // NB: kube rounds minAvailable percentages up, and maxUnavailable percentages down
         if let Some(ref ma) = &self.minAvailable {
             ma.verify("minAvailable", replicas)?;
+            if ma.to_replicas_ceil(replicas) >= replicas {
+                bail!(
+                    "disruptionBudget minAvailable must allow evicting one of {} replicas",
+                    replicas
+                );
+            }
         }
         if let Some(ref mu) = &self.maxUnavailable {
             mu.verify("maxUnavailable", replicas)?;
+            if mu.to_replicas_floor(replicas) == 0 {
+                bail!(
+                    "disruptionBudget maxUnavailable must allow evicting one of {} replicas",
+                    replicas
+                );
+            }
         }
         Ok(())
-     }
+    }
+
+    /// A budget allowing as many disruptions as a rolling update
+    ///
+    /// Always allows at least one eviction so that nodes can be drained.
+    /// Single replica services get no budget as it would always block evictions.
+    pub fn from_rolling_update(ru: &RollingUpdate, replicas: u32) -> Option<Self> {
+        if replicas < 2 {
+            return None;
+        }
+        let unavailable = ru
+            .maxUnavailable
+            .as_ref()
+            .map(|mu| mu.to_replicas_floor(replicas))
+            .unwrap_or(0);
+        Some(DisruptionBudget {
+            minAvailable: None,
+            maxUnavailable: Some(AvailabilityPolicy::Unsigned(std::cmp::max(1, unavailable))),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AvailabilityPolicy, DisruptionBudget, RollingUpdate};
+
+    #[test]
+    fn disruption_budget_allows_evictions() {
+        let pdb = |min: Option<&str>, max: Option<&str>| DisruptionBudget {
+            minAvailable: min.map(|s| AvailabilityPolicy::Percentage(s.into())),
+            maxUnavailable: max.map(|s| AvailabilityPolicy::Percentage(s.into())),
+        };
+        assert!(pdb(Some("50%"), None).verify(4).is_ok());
+        assert!(pdb(Some("100%"), None).verify(4).is_err());
+        assert!(pdb(Some("80%"), None).verify(4).is_err()); // rounds up to 4
+        assert!(pdb(None, Some("25%")).verify(4).is_ok());
+        assert!(pdb(None, Some("20%")).verify(4).is_err()); // rounds down to 0
+        assert!(pdb(None, None).verify(4).is_err());
+        assert!(pdb(Some("50%"), Some("50%")).verify(4).is_err());
+
+        let fixed = DisruptionBudget {
+            minAvailable: Some(AvailabilityPolicy::Unsigned(2)),
+            maxUnavailable: None,
+        };
+        assert!(fixed.verify(3).is_ok());
+        assert!(fixed.verify(2).is_err());
+    }
+
+    #[test]
+    fn disruption_budget_from_rolling_update() {
+        let ru = RollingUpdate::default();
+        assert!(DisruptionBudget::from_rolling_update(&ru, 1).is_none());
+        let pdb = DisruptionBudget::from_rolling_update(&ru, 8).unwrap();
+        assert!(pdb.verify(8).is_ok());
+        match pdb.maxUnavailable {
+            Some(AvailabilityPolicy::Unsigned(2)) => {}
+            x => panic!("unexpected maxUnavailable {:?}", x),
+        }
+
+        // surge only rollouts still allow a single eviction
+        let surge = RollingUpdate {
+            maxUnavailable: Some(AvailabilityPolicy::Unsigned(0)),
+            maxSurge: Some(AvailabilityPolicy::Percentage("50%".into())),
+        };
+        let pdb = DisruptionBudget::from_rolling_update(&surge, 3).unwrap();
+        assert!(pdb.verify(3).is_ok());
+    }
 }
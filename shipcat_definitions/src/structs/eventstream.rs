@@ -1,4 +1,5 @@
-use super::Result;
+use super::{kafkaresources::verify_topic_layout, Result};
+use crate::region::KafkaConfig;
 use std::collections::BTreeMap;
 
 #[derive(Default, Serialize, Deserialize, Clone, Debug)]
@@ -35,4 +36,21 @@ impl EventStream {
         }
         Ok(())
     }
+
+    /// Verify partitions and replicas (if configured) against the kafka cluster of a region
+    pub fn verify_region(&self, kafka: &KafkaConfig) -> Result<()> {
+        let number = |key: &str| -> Result<Option<i32>> {
+            match self.config.get(key) {
+                None => Ok(None),
+                Some(v) => match v.parse() {
+                    Ok(n) => Ok(Some(n)),
+                    Err(_) => bail!("EventStream {} has a non-numeric {}: {}", self.name, key, v),
+                },
+            }
+        };
+        if let (Some(p), Some(r)) = (number("partitions")?, number("replicas")?) {
+            verify_topic_layout(&self.name, p, r, kafka)?;
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,80 @@
+use super::{Container, Result};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// A one-shot job to run around an apply
+///
+/// Runs as a kubernetes `Job` with the service image unless `image` is overridden.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct HookJob {
+    /// Common properties for all types of container
+    #[serde(flatten)]
+    pub container: Container,
+
+    /// Maximum time to wait for the job to complete, in seconds
+    ///
+    /// Defaults to 600 seconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u32>,
+
+    /// Optional number of retries before marking the job as failed
+    /// Kubernetes default is 6
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backoffLimit: Option<u16>,
+
+    /// Metadata Annotations for pod spec templates in hook jobs
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub podAnnotations: BTreeMap<String, String>,
+}
+
+impl HookJob {
+    /// How long to wait for the job before giving up
+    pub fn timeout(&self) -> u32 {
+        self.timeout.unwrap_or(600)
+    }
+}
+
+/// Jobs to run before and after an apply
+///
+/// ```yaml
+/// hooks:
+///   preApply:
+///   - name: webapp-migrate
+///     command: ["bundle", "exec", "rake", "db:migrate"]
+///     timeout: 300
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Hooks {
+    /// Jobs that must succeed before the new version is applied
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub preApply: Vec<HookJob>,
+
+    /// Jobs that must succeed after the new version has rolled out
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub postApply: Vec<HookJob>,
+}
+
+impl Hooks {
+    pub fn is_empty(&self) -> bool {
+        self.preApply.is_empty() && self.postApply.is_empty()
+    }
+
+    pub fn verify(&self) -> Result<()> {
+        let mut names = BTreeSet::new();
+        for h in self.preApply.iter().chain(self.postApply.iter()) {
+            if !names.insert(&h.container.name) {
+                bail!("Duplicate hook name {}", h.container.name);
+            }
+            if h.timeout() == 0 {
+                bail!("Hook {} needs a non-zero timeout", h.container.name);
+            }
+            if h.container.image.is_some() != h.container.version.is_some() {
+                bail!(
+                    "Hook {} must set both image and version or neither",
+                    h.container.name
+                );
+            }
+            h.container.env.verify()?;
+        }
+        Ok(())
+    }
+}
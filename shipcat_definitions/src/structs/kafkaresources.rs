@@ -1,4 +1,5 @@
 use super::Result;
+use crate::region::KafkaConfig;
 use regex::Regex;
 use std::collections::BTreeMap;
 
@@ -70,6 +71,14 @@ fn default_host() -> String {
     "*".into()
 }
 
+impl AclDefinition {
+    /// Whether the ACL refers to a single topic by name
+    pub fn is_literal_topic(&self) -> bool {
+        matches!(self.resource_type, None | Some(KafkaUserResourceType::Topic))
+            && matches!(self.pattern_type, None | Some(KafkaUserPatternType::Literal))
+    }
+}
+
 #[derive(Default, Serialize, Deserialize, Clone, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct KafkaUsers {
@@ -85,6 +94,43 @@ pub struct KafkaResources {
 
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub users: Vec<KafkaUsers>,
+
+    /// Strimzi cluster managing these resources
+    ///
+    /// Injected from the region's `kafka.strimziCluster`, not overrideable.
+    #[serde(default)]
+    #[cfg_attr(
+        feature = "filesystem",
+        serde(skip_deserializing, skip_serializing_if = "Option::is_none")
+    )]
+    pub cluster: Option<String>,
+}
+
+/// Check the partitions and replicas of a topic against the brokers of a region
+///
+/// Replicas cannot exceed the region's `brokerCount` (when set).
+pub fn verify_topic_layout(name: &str, partitions: i32, replicas: i32, kafka: &KafkaConfig) -> Result<()> {
+    if !KafkaResources::is_VALID_PARTITIONS(&partitions) {
+        bail!(
+            "Topic {} has invalid partitions {} (allowed 1-9999)",
+            name,
+            partitions
+        );
+    }
+    if !KafkaResources::is_VALID_REPLICAS(&replicas) {
+        bail!("Topic {} has invalid replicas {}", name, replicas);
+    }
+    if let Some(brokers) = kafka.brokerCount {
+        if replicas as u32 > brokers {
+            bail!(
+                "Topic {} has {} replicas, but the region only has {} brokers",
+                name,
+                replicas,
+                brokers
+            );
+        }
+    }
+    Ok(())
 }
 
 impl KafkaResources {
@@ -147,11 +193,20 @@ impl KafkaResources {
 
         Ok(())
     }
+
+    /// Verify topic layouts against the kafka cluster of a region
+    pub fn verify_region(&self, kafka: &KafkaConfig) -> Result<()> {
+        for topic in &self.topics {
+            verify_topic_layout(&topic.name, topic.partitions, topic.replicas, kafka)?;
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::KafkaResources;
+    use crate::region::KafkaConfig;
 
     fn validKafkaResource(input: &str) -> KafkaResources {
         let kr: KafkaResources = serde_yaml::from_str(input).unwrap();
@@ -211,4 +266,26 @@ mod tests {
         let kr = validKafkaResource(&INVALID_KAFKA_RESOURCE);
         kr.verify().unwrap_err();
     }
+
+    #[test]
+    fn verifies_topic_layout_against_brokers() {
+        let kr = validKafkaResource(
+            r###"
+    topics:
+    - name: my-topic
+      partitions: 6
+      replicas: 3"###,
+        );
+        let mut kafka = KafkaConfig::default();
+        // unknown broker count, even with a bootstrap list
+        kafka.brokers = vec!["b-1:9092".into()];
+        kr.verify_region(&kafka).unwrap();
+        kafka.brokerCount = Some(2);
+        kr.verify_region(&kafka).unwrap_err();
+        kafka.brokerCount = Some(3);
+        kr.verify_region(&kafka).unwrap();
+
+        let empty = validKafkaResource("topics:\n- name: my-topic\n  replicas: 1");
+        empty.verify_region(&kafka).unwrap_err();
+    }
 }
@@ -69,6 +69,18 @@ pub mod rollingupdate;
 pub use self::rollingupdate::RollingUpdate;
 /// Kubernetes horizontal pod autoscaler
 pub mod autoscaling;
+/// Kubernetes pod disruption budgets
+pub mod disruption;
+pub use self::disruption::DisruptionBudget;
+/// Kubernetes statefulset settings
+pub mod statefulset;
+pub use self::statefulset::StatefulSetParams;
+/// Kubernetes affinity rules
+pub mod affinity;
+pub use self::affinity::Affinity;
+/// Kubernetes topology spread constraints
+pub mod topology;
+pub use self::topology::TopologySpreadConstraint;
 /// Kubernetes container lifecycle events
 mod lifecycle;
 /// Kuberneter tolerations
@@ -91,6 +103,14 @@ pub use self::vault::VaultOpts;
 pub mod cronjob;
 pub use self::cronjob::{CronJob, JobVolumeClaim};
 
+/// One-shot jobs around applies
+pub mod hooks;
+pub use self::hooks::{HookJob, Hooks};
+
+/// Post rollout smoke checks
+pub mod smoketest;
+pub use self::smoketest::SmokeTests;
+
 // Kubernetes Containers
 pub mod container;
 pub use self::container::Container;
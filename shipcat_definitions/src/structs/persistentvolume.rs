@@ -1,4 +1,9 @@
 use super::{resources::parse_memory, Result};
+use k8s_openapi::{
+    api::core::v1::{PersistentVolumeClaim, PersistentVolumeClaimSpec, ResourceRequirements},
+    apimachinery::pkg::{api::resource::Quantity, apis::meta::v1::ObjectMeta},
+};
+use std::{collections::BTreeMap, fmt};
 
 /// K8s Access modes for PVCs
 ///
@@ -10,6 +15,12 @@ pub enum VolumeAccessMode {
     ReadWriteMany,
 }
 
+impl fmt::Display for VolumeAccessMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
 impl Default for VolumeAccessMode {
     fn default() -> Self {
         Self::ReadWriteOnce // most supported mode
@@ -26,6 +37,11 @@ pub struct PersistentVolume {
     pub size: String,
     #[serde(default)]
     pub accessMode: VolumeAccessMode,
+    /// Storage class to provision the volume from
+    ///
+    /// Uses the cluster default storage class when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub storageClass: Option<String>,
 }
 
 impl PersistentVolume {
@@ -43,4 +59,51 @@ impl PersistentVolume {
         }
         Ok(())
     }
+
+    /// Convert to a claim template for a StatefulSet's `volumeClaimTemplates`
+    ///
+    /// Every StatefulSet replica gets its own claim from this template.
+    pub fn claim_template(&self) -> PersistentVolumeClaim {
+        let mut requests = BTreeMap::new();
+        requests.insert("storage".to_string(), Quantity(self.size.clone()));
+        PersistentVolumeClaim {
+            metadata: Some(ObjectMeta {
+                name: Some(self.name.clone()),
+                ..Default::default()
+            }),
+            spec: Some(PersistentVolumeClaimSpec {
+                access_modes: Some(vec![self.accessMode.to_string()]),
+                resources: Some(ResourceRequirements {
+                    requests: Some(requests),
+                    ..Default::default()
+                }),
+                storage_class_name: self.storageClass.clone(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PersistentVolume;
+
+    #[test]
+    fn pv_claim_template() {
+        let pv = PersistentVolume {
+            name: "data".into(),
+            mountPath: "/data".into(),
+            size: "10Gi".into(),
+            ..Default::default()
+        };
+        let claim = serde_yaml::to_value(pv.claim_template()).unwrap();
+        assert_eq!(claim["metadata"]["name"].as_str(), Some("data"));
+        assert_eq!(claim["spec"]["accessModes"][0].as_str(), Some("ReadWriteOnce"));
+        assert_eq!(
+            claim["spec"]["resources"]["requests"]["storage"].as_str(),
+            Some("10Gi")
+        );
+        assert!(claim["spec"]["storageClassName"].is_null());
+    }
 }
@@ -10,7 +10,7 @@ pub enum AvailabilityPolicy {
 // Kube has a weird hybrid type for this intstr.IntOrString: IntVal | StrVal
 // if it's a string, then '[0-9]+%!' has to parse
 impl AvailabilityPolicy {
-    fn verify(&self, name: &str, maxNumber: u32) -> Result<()> {
+    pub(crate) fn verify(&self, name: &str, maxNumber: u32) -> Result<()> {
         match self {
             AvailabilityPolicy::Unsigned(n) => {
                 if *n > maxNumber {
@@ -35,7 +35,7 @@ impl AvailabilityPolicy {
     /// Figure out how many the availability policy refers to
     ///
     /// This multiplies the policy with num replicas and rounds up (for maxSurge)
-    fn to_replicas_ceil(&self, replicas: u32) -> u32 {
+    pub(crate) fn to_replicas_ceil(&self, replicas: u32) -> u32 {
         match self {
             AvailabilityPolicy::Percentage(percstr) => {
                 let digits = percstr.chars().take_while(|ch| *ch != '%').collect::<String>();
@@ -49,7 +49,7 @@ impl AvailabilityPolicy {
     /// Figure out how many the availability policy refers to
     ///
     /// This multiplies the policy with num replicas and rounds down (for maxUnavailable)
-    fn to_replicas_floor(&self, replicas: u32) -> u32 {
+    pub(crate) fn to_replicas_floor(&self, replicas: u32) -> u32 {
         match self {
             AvailabilityPolicy::Percentage(percstr) => {
                 let digits = percstr.chars().take_while(|ch| *ch != '%').collect::<String>();
@@ -1,6 +1,6 @@
 use super::Result;
 use regex::Regex;
-use std::path::Path;
+use std::{fmt, path::Path};
 
 /// What sensitive data is managed and how
 ///
@@ -25,6 +25,28 @@ impl DataHandling {
             s.implicits();
         }
     }
+
+    /// The declared highest information classification processed (if any)
+    pub fn declared_classification(&self) -> Option<InformationClassification> {
+        self.informationClassification
+            .as_ref()
+            .map(|ic| ic.highestProcessed.clone())
+    }
+
+    /// The store holding a field (if this service stores it)
+    pub fn store_for(&self, field: &str) -> Option<&DataStore> {
+        self.stores
+            .iter()
+            .find(|s| s.fields.iter().any(|f| f.name == field))
+    }
+
+    /// The source service of a field (if this service processes it)
+    pub fn source_for(&self, field: &str) -> Option<&str> {
+        self.processes
+            .iter()
+            .find(|p| p.field == field)
+            .map(|p| p.source.as_str())
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
@@ -35,7 +57,7 @@ pub struct InformationClassificationData {
 }
 
 /// Possible levels of information classification of the data stored in the data store.
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 #[serde(rename_all = "camelCase")]
 pub enum InformationClassification {
     StrictlyConfidential,
@@ -51,6 +73,41 @@ impl Default for InformationClassification {
     }
 }
 
+impl InformationClassification {
+    /// How sensitive the classification is, where `Public` is 0
+    pub fn level(&self) -> u8 {
+        match self {
+            InformationClassification::Public => 0,
+            InformationClassification::ProtectedInternal => 1,
+            InformationClassification::CommercialConfidential => 2,
+            InformationClassification::ConfidentialPatientData => 3,
+            InformationClassification::StrictlyConfidential => 4,
+        }
+    }
+
+    /// The most sensitive of two classifications
+    pub fn max(self, other: Self) -> Self {
+        if other.level() > self.level() {
+            other
+        } else {
+            self
+        }
+    }
+}
+
+impl fmt::Display for InformationClassification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            InformationClassification::StrictlyConfidential => "strictlyConfidential",
+            InformationClassification::ConfidentialPatientData => "confidentialPatientData",
+            InformationClassification::CommercialConfidential => "commercialConfidential",
+            InformationClassification::ProtectedInternal => "protectedInternal",
+            InformationClassification::Public => "public",
+        };
+        f.write_str(s)
+    }
+}
+
 /// Data storage information and encryption information
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 #[cfg_attr(feature = "filesystem", serde(deny_unknown_fields))]
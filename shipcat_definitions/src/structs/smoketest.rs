@@ -0,0 +1,225 @@
+use super::Result;
+use regex::Regex;
+use std::collections::BTreeSet;
+
+/// How smoke checks reach the service
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum SmokeTestMode {
+    /// Port-forward to the workload from wherever shipcat runs
+    PortForward,
+    /// Run a curl Job inside the cluster against the service
+    Job,
+}
+
+impl Default for SmokeTestMode {
+    fn default() -> Self {
+        SmokeTestMode::PortForward
+    }
+}
+
+/// A single HTTP check against a rolled out service
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct SmokeCheck {
+    /// Name of the check
+    pub name: String,
+
+    /// Path to request, including any query string
+    pub path: String,
+
+    /// Port to request
+    ///
+    /// Defaults to the `httpPort` of the service.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u32>,
+
+    /// Status codes that count as a pass
+    ///
+    /// Defaults to 200 only.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub expectedStatus: Vec<u16>,
+
+    /// Text the response body must contain
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bodyContains: Option<String>,
+
+    /// Regex the response body must match
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bodyMatches: Option<String>,
+
+    /// Maximum time to wait for a response, in seconds
+    ///
+    /// Defaults to 10 seconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u32>,
+}
+
+impl SmokeCheck {
+    /// How long to wait for a response before failing
+    pub fn timeout(&self) -> u32 {
+        self.timeout.unwrap_or(10)
+    }
+
+    /// Status codes that count as a pass
+    pub fn expected_status(&self) -> Vec<u16> {
+        if self.expectedStatus.is_empty() {
+            vec![200]
+        } else {
+            self.expectedStatus.clone()
+        }
+    }
+
+    /// Check a response against the expectations
+    ///
+    /// Returns a one sentence reason for the failure if it did not pass.
+    pub fn evaluate(&self, status: u16, body: &str) -> std::result::Result<(), String> {
+        let expected = self.expected_status();
+        if !expected.contains(&status) {
+            return Err(format!(
+                "{} returned status {} (expected {:?})",
+                self.path, status, expected
+            ));
+        }
+        if let Some(needle) = &self.bodyContains {
+            if !body.contains(needle.as_str()) {
+                return Err(format!("{} response did not contain '{}'", self.path, needle));
+            }
+        }
+        if let Some(pattern) = &self.bodyMatches {
+            let re = Regex::new(pattern).map_err(|e| e.to_string())?;
+            if !re.is_match(body) {
+                return Err(format!("{} response did not match '{}'", self.path, pattern));
+            }
+        }
+        Ok(())
+    }
+
+    fn verify(&self) -> Result<()> {
+        if self.name.is_empty() {
+            bail!("Smoke checks need a name");
+        }
+        if !self.path.starts_with('/') {
+            bail!("Smoke check {} path must start with a slash", self.name);
+        }
+        for s in &self.expectedStatus {
+            if *s < 100 || *s > 599 {
+                bail!("Smoke check {} expects invalid status {}", self.name, s);
+            }
+        }
+        if let Some(pattern) = &self.bodyMatches {
+            if let Err(e) = Regex::new(pattern) {
+                bail!("Smoke check {} has an invalid bodyMatches: {}", self.name, e);
+            }
+        }
+        if self.timeout == Some(0) {
+            bail!("Smoke check {} needs a non-zero timeout", self.name);
+        }
+        Ok(())
+    }
+}
+
+/// Checks that must pass after a rollout before it is considered complete
+///
+/// ```yaml
+/// smokeTests:
+///   mode: PortForward
+///   rollback: true
+///   checks:
+///   - name: health
+///     path: /health
+///     bodyContains: ok
+///   - name: status
+///     path: /status
+///     expectedStatus: [200, 204]
+///     timeout: 5
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct SmokeTests {
+    /// How the checks reach the service
+    #[serde(default)]
+    pub mode: SmokeTestMode,
+
+    /// Undo the rollout if any check fails
+    #[serde(default)]
+    pub rollback: bool,
+
+    /// Image used to run checks with `mode: Job`
+    ///
+    /// Must contain `curl`. Defaults to `curlimages/curl`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+
+    /// Checks to run in order
+    pub checks: Vec<SmokeCheck>,
+}
+
+impl SmokeTests {
+    /// Image used to run checks in the cluster
+    pub fn image(&self) -> String {
+        self.image.clone().unwrap_or_else(|| "curlimages/curl".into())
+    }
+
+    pub fn verify(&self, http_port: Option<u32>) -> Result<()> {
+        if self.checks.is_empty() {
+            bail!("smokeTests need at least one check");
+        }
+        let mut names = BTreeSet::new();
+        for c in &self.checks {
+            c.verify()?;
+            if !names.insert(&c.name) {
+                bail!("Smoke check names must be unique, found {} twice", c.name);
+            }
+            if c.port.is_none() && http_port.is_none() {
+                bail!("Smoke check {} needs a port when httpPort is not set", c.name);
+            }
+        }
+        if self.image.is_some() && self.mode != SmokeTestMode::Job {
+            bail!("smokeTests image is only used with mode Job");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SmokeCheck, SmokeTests};
+
+    #[test]
+    fn smoketest_evaluate() {
+        let mut check = SmokeCheck {
+            name: "health".into(),
+            path: "/health".into(),
+            bodyContains: Some("ok".into()),
+            ..Default::default()
+        };
+        assert!(check.evaluate(200, "status: ok").is_ok());
+        assert!(check.evaluate(204, "status: ok").is_err());
+        assert!(check.evaluate(200, "status: degraded").is_err());
+
+        check.expectedStatus = vec![200, 204];
+        check.bodyContains = None;
+        check.bodyMatches = Some(r"^\{.*\}$".into());
+        assert!(check.evaluate(204, "{}").is_ok());
+        assert!(check.evaluate(204, "<html>").is_err());
+    }
+
+    #[test]
+    fn smoketest_verify() {
+        let mut st = SmokeTests {
+            checks: vec![SmokeCheck {
+                name: "health".into(),
+                path: "/health".into(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        assert!(st.verify(Some(8080)).is_ok());
+        assert!(st.verify(None).is_err()); // nowhere to send it
+
+        st.checks.push(st.checks[0].clone());
+        assert!(st.verify(Some(8080)).is_err()); // duplicate name
+
+        st.checks.pop();
+        st.checks[0].bodyMatches = Some("(".into());
+        assert!(st.verify(Some(8080)).is_err()); // bad regex
+    }
+}
@@ -0,0 +1,127 @@
+use super::Result;
+
+/// Ordering guarantees when scaling a StatefulSet
+///
+/// See [K8s pod management policies](https://kubernetes.io/docs/concepts/workloads/controllers/statefulset/#pod-management-policies).
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum PodManagementPolicy {
+    /// Pods are created in order and removed in reverse order, one at a time
+    OrderedReady,
+    /// Pods are created and removed in parallel
+    Parallel,
+}
+
+impl Default for PodManagementPolicy {
+    fn default() -> Self {
+        Self::OrderedReady // kube default
+    }
+}
+
+/// How a StatefulSet replaces its pods when the pod template changes
+///
+/// See [K8s update strategies](https://kubernetes.io/docs/concepts/workloads/controllers/statefulset/#update-strategies).
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum StatefulUpdateStrategy {
+    /// Pods are replaced in reverse ordinal order, one at a time
+    RollingUpdate,
+    /// Pods are only replaced once they are deleted
+    OnDelete,
+}
+
+impl Default for StatefulUpdateStrategy {
+    fn default() -> Self {
+        Self::RollingUpdate // kube default
+    }
+}
+
+/// StatefulSet specific parameters
+///
+/// Only valid for manifests with `workload: Statefulset`.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct StatefulSetParams {
+    /// Ordering guarantees when scaling up or down
+    #[serde(default)]
+    pub podManagementPolicy: PodManagementPolicy,
+
+    /// Strategy for replacing pods on upgrades
+    #[serde(default)]
+    pub updateStrategy: StatefulUpdateStrategy,
+
+    /// Only pods with an ordinal greater than or equal to this are upgraded
+    ///
+    /// Allows staging (canarying) an upgrade on the highest ordinals first.
+    /// Only valid with the `RollingUpdate` strategy.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub partition: Option<u32>,
+}
+
+impl StatefulSetParams {
+    pub fn verify(&self, replicas: u32) -> Result<()> {
+        if let Some(p) = self.partition {
+            if self.updateStrategy == StatefulUpdateStrategy::OnDelete {
+                bail!("Cannot set a partition with the OnDelete updateStrategy");
+            }
+            if p > replicas {
+                bail!(
+                    "Cannot have partition {} higher than replicaCount {}",
+                    p,
+                    replicas
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// The lowest ordinal that gets replaced in a rollout
+    pub fn partition(&self) -> u32 {
+        self.partition.unwrap_or(0)
+    }
+
+    /// Number of pods that a rollout will replace out of `replicas`
+    pub fn rollout_replicas(&self, replicas: u32) -> u32 {
+        replicas.saturating_sub(self.partition())
+    }
+}
+
+/// Extract the ordinal from a StatefulSet pod name
+///
+/// StatefulSet pods are named `{statefulset}-{ordinal}`.
+pub fn pod_ordinal(statefulset: &str, pod: &str) -> Option<u32> {
+    if pod.len() <= statefulset.len() + 1 || !pod.starts_with(statefulset) {
+        return None;
+    }
+    let (prefix, ordinal) = pod.split_at(statefulset.len() + 1);
+    if !prefix.ends_with('-') {
+        return None;
+    }
+    ordinal.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{pod_ordinal, StatefulSetParams, StatefulUpdateStrategy};
+
+    #[test]
+    fn statefulset_partition() {
+        let mut ss = StatefulSetParams::default();
+        assert!(ss.verify(3).is_ok());
+        assert_eq!(ss.rollout_replicas(3), 3);
+
+        ss.partition = Some(2);
+        assert!(ss.verify(3).is_ok());
+        assert_eq!(ss.rollout_replicas(3), 1);
+        assert!(ss.verify(1).is_err()); // partition above replicas
+
+        ss.updateStrategy = StatefulUpdateStrategy::OnDelete;
+        assert!(ss.verify(3).is_err()); // partition with OnDelete
+    }
+
+    #[test]
+    fn statefulset_pod_ordinals() {
+        assert_eq!(pod_ordinal("redis", "redis-0"), Some(0));
+        assert_eq!(pod_ordinal("redis", "redis-12"), Some(12));
+        assert_eq!(pod_ordinal("redis", "redis-"), None);
+        assert_eq!(pod_ordinal("redis", "redis-sentinel-0"), None);
+        assert_eq!(pod_ordinal("redis", "webapp-0"), None);
+    }
+}
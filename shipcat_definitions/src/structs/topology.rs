@@ -0,0 +1,55 @@
+use super::{affinity::LabelSelector, Result};
+
+/// What to do with a pod that does not satisfy a spread constraint
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum UnsatisfiableConstraintAction {
+    DoNotSchedule,
+    ScheduleAnyway,
+}
+
+impl Default for UnsatisfiableConstraintAction {
+    fn default() -> Self {
+        UnsatisfiableConstraintAction::DoNotSchedule // kube default
+    }
+}
+
+/// Kubernetes topology spread constraint for a service
+///
+/// Straight from [kubernetes pod topology spread constraints](https://kubernetes.io/docs/concepts/workloads/pods/pod-topology-spread-constraints/).
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TopologySpreadConstraint {
+    /// Maximum allowed difference in number of pods between topology domains
+    pub maxSkew: u32,
+    /// Node label defining the topology domain
+    pub topologyKey: String,
+    /// Whether to schedule pods that violate the constraint
+    #[serde(default)]
+    pub whenUnsatisfiable: UnsatisfiableConstraintAction,
+    /// Pods to spread
+    ///
+    /// Defaults to the pods of the service itself.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub labelSelector: Option<LabelSelector>,
+}
+
+impl TopologySpreadConstraint {
+    /// Fill in an unset label selector with the pods of the service
+    pub fn implicits(&mut self, name: &str) {
+        if self.labelSelector.is_none() {
+            self.labelSelector = Some(LabelSelector::service(name));
+        }
+    }
+
+    pub fn verify(&self) -> Result<()> {
+        if self.maxSkew == 0 {
+            bail!("topologySpreadConstraints maxSkew must be at least 1");
+        }
+        if self.topologyKey.is_empty() {
+            bail!("topologySpreadConstraints need a topologyKey");
+        }
+        if let Some(ls) = &self.labelSelector {
+            ls.verify()?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,55 @@
+use merge::Merge;
+
+use shipcat_definitions::{
+    structs::{HookJob, Hooks},
+    Result,
+};
+
+use crate::util::{Build, RelaxedString};
+use std::collections::BTreeMap;
+
+use super::source::{ContainerBuildParams, ContainerSource};
+
+#[derive(Deserialize, Merge, Clone, Default)]
+#[serde(default, rename_all = "camelCase")]
+pub struct HookJobSource {
+    pub timeout: Option<u32>,
+    pub backoff_limit: Option<u16>,
+    pub pod_annotations: BTreeMap<String, RelaxedString>,
+
+    #[serde(flatten)]
+    pub container: ContainerSource,
+}
+
+impl Build<HookJob, ContainerBuildParams> for HookJobSource {
+    fn build(self, params: &ContainerBuildParams) -> Result<HookJob> {
+        let container = self.container.build(params)?;
+        match (&container.image, &container.version) {
+            (Some(_), None) => bail!("Cannot specify image without specifying version in hook"),
+            (None, Some(_)) => bail!("Cannot specify the version without specifying an image in hook"),
+            (_, _) => (),
+        };
+        Ok(HookJob {
+            container,
+            timeout: self.timeout,
+            backoffLimit: self.backoff_limit,
+            podAnnotations: self.pod_annotations.build(&())?,
+        })
+    }
+}
+
+#[derive(Deserialize, Merge, Clone, Default)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct HooksSource {
+    pub pre_apply: Option<Vec<HookJobSource>>,
+    pub post_apply: Option<Vec<HookJobSource>>,
+}
+
+impl Build<Hooks, ContainerBuildParams> for HooksSource {
+    fn build(self, params: &ContainerBuildParams) -> Result<Hooks> {
+        Ok(Hooks {
+            preApply: self.pre_apply.unwrap_or_default().build(params)?,
+            postApply: self.post_apply.unwrap_or_default().build(params)?,
+        })
+    }
+}
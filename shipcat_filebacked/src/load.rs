@@ -200,7 +200,7 @@ mod tests {
     use std::{env, fs, path::Path};
 
     use super::ManifestSource;
-    use shipcat_definitions::Config;
+    use shipcat_definitions::{structs::rollingupdate::AvailabilityPolicy, Config};
 
     fn setup() {
         let pwd = env::current_dir().unwrap();
@@ -236,6 +236,37 @@ mod tests {
         assert_eq!(manifest.image, Some("quay.io/babylonhealth/fake-ask".into()));
     }
 
+    #[tokio::test]
+    async fn load_default_disruption_budgets() {
+        setup();
+
+        let conf = Config::read().await.unwrap();
+        let mut region = conf.get_region("dev-uk").unwrap();
+
+        // opt-in, so services without a disruptionBudget get none by default
+        let manifest = ManifestSource::load_manifest("fake-ask", &conf, &region)
+            .await
+            .unwrap();
+        assert!(manifest.disruptionBudget.is_none());
+
+        region
+            .defaultsV2
+            .as_mut()
+            .unwrap()
+            .as_mapping_mut()
+            .unwrap()
+            .insert("defaultDisruptionBudgets".into(), true.into());
+        let manifest = ManifestSource::load_manifest("fake-ask", &conf, &region)
+            .await
+            .unwrap();
+        let pdb = manifest.disruptionBudget.unwrap();
+        assert!(pdb.minAvailable.is_none());
+        match pdb.maxUnavailable {
+            Some(AvailabilityPolicy::Unsigned(1)) => {}
+            x => panic!("unexpected maxUnavailable {:?}", x),
+        }
+    }
+
     #[tokio::test]
     async fn all() {
         setup();
@@ -5,21 +5,23 @@ use std::collections::BTreeMap;
 
 use shipcat_definitions::{
     structs::{
+        affinity::Affinity,
         autoscaling::AutoScaling,
         metadata::{default_format_string, Contact, Context, Language, SlackChannel},
         security::DataHandling,
         tolerations::Tolerations,
         volume::Volume,
-        ConfigMap, Dependency, DestinationRule, EventStream, Gate, HealthCheck, HostAlias, Kafka,
-        KafkaResources, LifeCycle, Metadata, NotificationMode, PersistentVolume, Probe, PrometheusAlert,
-        Rbac, RollingUpdate, SecurityContext, VaultOpts, VolumeMount,
+        ConfigMap, Dependency, DestinationRule, DisruptionBudget, EventStream, Gate, HealthCheck, HostAlias,
+        Kafka, KafkaResources, LifeCycle, Metadata, NotificationMode, PersistentVolume, Probe,
+        PrometheusAlert, Rbac, RollingUpdate, SecurityContext, SmokeTests, StatefulSetParams,
+        TopologySpreadConstraint, VaultOpts, VolumeMount,
     },
     BaseManifest, Config, Manifest, PrimaryWorkload, Region, Result,
 };
 
 use super::{
     container::{
-        ContainerBuildParams, CronJobSource, EnvVarsSource, ImageNameSource, ImageTagSource,
+        ContainerBuildParams, CronJobSource, EnvVarsSource, HooksSource, ImageNameSource, ImageTagSource,
         InitContainerSource, PortSource, ResourceRequirementsSource, SidecarSource, WorkerSource,
     },
     kong::{KongApisBuildParams, KongApisSource, KongSource},
@@ -119,6 +121,8 @@ pub struct ManifestOverrides {
     pub liveness_probe: Option<Probe>,
     pub lifecycle: Option<LifeCycle>,
     pub rolling_update: Option<RollingUpdate>,
+    pub stateful_set: Option<StatefulSetParams>,
+    pub disruption_budget: Option<DisruptionBudget>,
     pub auto_scaling: Option<AutoScaling>,
     pub tolerations: Option<Vec<Tolerations>>,
     pub host_aliases: Option<Vec<HostAlias>>,
@@ -127,6 +131,8 @@ pub struct ManifestOverrides {
     pub volume_mounts: Option<Vec<VolumeMount>>,
     pub persistent_volumes: Option<Vec<PersistentVolume>>,
     pub cron_jobs: Option<Vec<CronJobSource>>,
+    pub hooks: HooksSource,
+    pub smoke_tests: Option<SmokeTests>,
     pub service_annotations: BTreeMap<String, String>,
     pub pod_annotations: BTreeMap<String, RelaxedString>,
     pub labels: BTreeMap<String, RelaxedString>,
@@ -158,6 +164,10 @@ pub struct ManifestDefaults {
     pub kong_apis: KongApisSource,
     // TODO: Migrate to kong_apis
     pub kong: Enabled<KongSource>,
+    pub node_selector: BTreeMap<String, String>,
+    pub affinity: Option<Affinity>,
+    pub topology_spread_constraints: Option<Vec<TopologySpreadConstraint>>,
+    pub default_disruption_budgets: Option<bool>,
 }
 
 // impl Build<Manifest, (Config, Region)> - but no need to have this as a trait
@@ -184,6 +194,48 @@ impl ManifestSource {
             .notifications
             .expect("notifications channel is always defined");
 
+        let workload = overrides.workload.unwrap_or_default();
+        let persistent_volumes = overrides.persistent_volumes.unwrap_or_default();
+        let volume_claim_templates = match workload {
+            PrimaryWorkload::Statefulset => persistent_volumes.iter().map(|pv| pv.claim_template()).collect(),
+            PrimaryWorkload::Deployment => vec![],
+        };
+        let min_replicas = overrides
+            .auto_scaling
+            .as_ref()
+            .map(|a| a.minReplicas)
+            .or(defaults.replica_count)
+            .unwrap_or_default();
+        let affinity = defaults.affinity.map(|mut a| {
+            a.implicits(&name);
+            a
+        });
+        let topology_spread_constraints = defaults
+            .topology_spread_constraints
+            .unwrap_or_default()
+            .into_iter()
+            .map(|mut tsc| {
+                tsc.implicits(&name);
+                tsc
+            })
+            .collect();
+        let budget_rollout = match workload {
+            PrimaryWorkload::Deployment => overrides.rolling_update.clone().unwrap_or_default(),
+            // statefulsets roll one replica at a time
+            PrimaryWorkload::Statefulset => RollingUpdate {
+                maxUnavailable: None,
+                maxSurge: None,
+            },
+        };
+        let default_budget = defaults.default_disruption_budgets.unwrap_or(false);
+        let disruption_budget = overrides.disruption_budget.or_else(|| {
+            if default_budget {
+                DisruptionBudget::from_rolling_update(&budget_rollout, min_replicas)
+            } else {
+                None
+            }
+        });
+
         Ok(Manifest {
             name,
             publiclyAccessible: overrides.publicly_accessible.unwrap_or_default(),
@@ -228,8 +280,13 @@ impl ManifestSource {
             livenessProbe: overrides.liveness_probe,
             lifecycle: overrides.lifecycle,
             rollingUpdate: overrides.rolling_update,
+            statefulSet: overrides.stateful_set,
+            disruptionBudget: disruption_budget,
             autoScaling: overrides.auto_scaling,
             tolerations: overrides.tolerations.unwrap_or_default(),
+            nodeSelector: defaults.node_selector,
+            affinity,
+            topologySpreadConstraints: topology_spread_constraints,
             hostAliases: overrides.host_aliases.unwrap_or_default(),
             initContainers: overrides
                 .init_containers
@@ -237,11 +294,13 @@ impl ManifestSource {
                 .build(&container_build_params)?,
             volumes: overrides.volumes.unwrap_or_default(),
             volumeMounts: overrides.volume_mounts.unwrap_or_default(),
-            persistentVolumes: overrides.persistent_volumes.unwrap_or_default(),
+            persistentVolumes: persistent_volumes,
             cronJobs: overrides
                 .cron_jobs
                 .unwrap_or_default()
                 .build(&container_build_params)?,
+            hooks: overrides.hooks.build(&container_build_params)?,
+            smokeTests: overrides.smoke_tests,
             serviceAnnotations: overrides.service_annotations,
             podAnnotations: overrides.pod_annotations.build(&())?,
             labels: overrides.labels.build(&())?,
@@ -256,7 +315,10 @@ impl ManifestSource {
                 .map(|sentry| sentry.build(&team_notifications))
                 .transpose()?,
             eventStreams: overrides.event_streams.unwrap_or_default(),
-            kafkaResources: overrides.kafka_resources,
+            kafkaResources: overrides.kafka_resources.map(|mut kr| {
+                kr.cluster = region.kafka.strimziCluster.clone();
+                kr
+            }),
             upgradeNotifications: Default::default(),
             region: region.name.clone(),
             environment: region.environment.to_string(),
@@ -264,8 +326,9 @@ impl ManifestSource {
             uid: Default::default(),
             secrets: Default::default(),
             state: Default::default(),
-            workload: overrides.workload.unwrap_or_default(),
+            workload,
             prometheusAlerts: overrides.prometheus_alerts.unwrap_or_default(),
+            volumeClaimTemplates: volume_claim_templates,
         })
     }
 }
@@ -464,7 +527,7 @@ mod tests {
     use merge::Merge;
     use std::collections::BTreeMap;
 
-    use super::ManifestDefaults;
+    use super::{ManifestDefaults, ManifestSource};
 
     #[test]
     fn merge() {
@@ -503,4 +566,84 @@ mod tests {
         expected_env.insert("c", "override-c");
         assert_eq!(merged.env, expected_env.into());
     }
+
+    #[test]
+    fn merge_default_disruption_budgets() {
+        let region = ManifestDefaults {
+            default_disruption_budgets: Some(true),
+            ..Default::default()
+        };
+        let unset = region.clone().merge(ManifestDefaults::default());
+        assert_eq!(unset.default_disruption_budgets, Some(true));
+
+        // services can opt out of a regional default
+        let service = ManifestDefaults {
+            default_disruption_budgets: Some(false),
+            ..Default::default()
+        };
+        let merged = region.merge(service);
+        assert_eq!(merged.default_disruption_budgets, Some(false));
+    }
+
+    #[test]
+    fn merge_scheduling_defaults() {
+        let region: ManifestDefaults = serde_yaml::from_str(
+            "
+nodeSelector:
+  kubernetes.io/os: linux
+  pool: general
+affinity:
+  podAntiAffinity:
+    preferredDuringSchedulingIgnoredDuringExecution:
+    - weight: 100
+      podAffinityTerm:
+        topologyKey: kubernetes.io/hostname
+topologySpreadConstraints:
+- maxSkew: 1
+  topologyKey: topology.kubernetes.io/zone
+",
+        )
+        .unwrap();
+
+        // services without scheduling settings inherit the region defaults
+        let plain: ManifestSource = serde_yaml::from_str("name: plain").unwrap();
+        let merged = region.clone().merge_source(plain).overrides.defaults;
+        assert_eq!(merged.node_selector.len(), 2);
+        assert!(merged.affinity.unwrap().podAntiAffinity.is_some());
+        assert_eq!(merged.topology_spread_constraints.unwrap().len(), 1);
+
+        // service values merge into the nodeSelector and replace affinity and spread constraints
+        let custom: ManifestSource = serde_yaml::from_str(
+            "
+name: custom
+nodeSelector:
+  pool: highmem
+affinity:
+  nodeAffinity:
+    requiredDuringSchedulingIgnoredDuringExecution:
+      nodeSelectorTerms:
+      - matchExpressions:
+        - key: instance-type
+          operator: In
+          values: [r5.large]
+topologySpreadConstraints:
+- maxSkew: 2
+  topologyKey: kubernetes.io/hostname
+  whenUnsatisfiable: ScheduleAnyway
+",
+        )
+        .unwrap();
+        let merged = region.merge_source(custom).overrides.defaults;
+        let mut expected_selector = BTreeMap::new();
+        expected_selector.insert("kubernetes.io/os".to_string(), "linux".to_string());
+        expected_selector.insert("pool".to_string(), "highmem".to_string());
+        assert_eq!(merged.node_selector, expected_selector);
+        let affinity = merged.affinity.unwrap();
+        assert!(affinity.nodeAffinity.is_some());
+        assert!(affinity.podAntiAffinity.is_none());
+        let tscs = merged.topology_spread_constraints.unwrap();
+        assert_eq!(tscs.len(), 1);
+        assert_eq!(tscs[0].maxSkew, 2);
+        assert_eq!(tscs[0].topologyKey, "kubernetes.io/hostname");
+    }
 }